@@ -52,6 +52,11 @@ mod imp {
       obj.set_accels_for_action("app.quit", &["<primary>q"]);
       obj.set_accels_for_action("win.open-file-dialog", &["<primary>o"]);
       obj.set_accels_for_action("win.reset-zoom", &["<primary>r"]);
+      obj.set_accels_for_action("win.paste-message", &["<primary><shift>v"]);
+      obj.set_accels_for_action("win.reload", &["F5"]);
+      obj.set_accels_for_action("win.show-html-view", &["<alt>1"]);
+      obj.set_accels_for_action("win.show-text-view", &["<alt>2"]);
+      obj.set_accels_for_action("win.distraction-free", &["<shift>F11"]);
     }
   }
 
@@ -90,14 +95,34 @@ mod imp {
         log::debug!("[ARGUMENT] File: {:?}, Hint : {:?}", file.path(), hint);
       }
 
-      if files.is_empty() == false {
-        if let Some(path) = files[0].path() {
-          let path = path.to_str().unwrap().to_string();
-          self.filename.replace(Some(path.clone()));
+      if let Some(file) = files.first() {
+        // gio decodes the file:// URIs of launchers and file managers, the
+        // name is passed as is (never through a shell)
+        match file.path() {
+          Some(path) => match path.to_str() {
+            Some(path) => {
+              self.filename.replace(Some(path.to_string()));
+            }
+            None => log::error!("open() => not an UTF-8 file name: {:?}", path),
+          },
+          // not a local file (e.g. an unmounted network location)
+          None => {
+            self.filename.replace(Some(file.uri().to_string()));
+          }
         }
       }
       self.activate();
     }
+
+    // app.quit doesn't close the windows, nothing else saves their geometry
+    fn shutdown(&self) {
+      for window in self.obj().windows() {
+        if let Ok(window) = window.downcast::<MailViewerWindow>() {
+          window.save_geometry();
+        }
+      }
+      self.parent_shutdown();
+    }
   }
 
   impl GtkApplicationImpl for MailViewerApplication {}
@@ -0,0 +1,85 @@
+/* avatar.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use gtk4::glib;
+
+// federated Gravatar alternative, falls back to Gravatar itself
+const AVATAR_URL: &str = "https://seccdn.libravatar.org/avatar/";
+
+/// Address part of a From header, "John <john@moon.space>" => "john@moon.space"
+pub fn email_address(from: &str) -> Option<String> {
+  let address = match (from.rfind('<'), from.rfind('>')) {
+    (Some(start), Some(end)) if start < end => &from[start + 1..end],
+    _ => from,
+  };
+  let address = address.trim();
+  match address.contains('@') && !address.contains(char::is_whitespace) {
+    true => Some(address.to_string()),
+    false => None,
+  }
+}
+
+/// Name the initials are taken from, the address when there's none
+pub fn display_name(from: &str) -> String {
+  let name = match from.rfind('<') {
+    Some(start) => from[..start].trim().trim_matches('"').trim(),
+    None => "",
+  };
+  match name.is_empty() {
+    true => email_address(from).unwrap_or(from.trim().to_string()),
+    false => name.to_string(),
+  }
+}
+
+/// Gravatar/Libravatar hash: MD5 of the trimmed, lowercased address
+pub fn hash(email: &str) -> String {
+  let email = email.trim().to_lowercase();
+  glib::compute_checksum_for_string(glib::ChecksumType::Md5, email.as_str())
+    .map(|hash| hash.to_string())
+    .unwrap_or_default()
+}
+
+/// Avatar image of `email`, answering 404 when the address has none
+pub fn url(email: &str, size: u32) -> String {
+  format!("{}{}?s={}&d=404", AVATAR_URL, hash(email), size)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn gravatar_hash() {
+    // example of the Gravatar documentation
+    assert_eq!(hash(" MyEmailAddress@example.com "), "0bc83cb571cd1c50ba6f3e8a78ef1346");
+    assert_eq!(
+      url("myemailaddress@example.com", 64),
+      "https://seccdn.libravatar.org/avatar/0bc83cb571cd1c50ba6f3e8a78ef1346?s=64&d=404"
+    );
+  }
+
+  #[test]
+  fn from_header() {
+    assert_eq!(email_address("John Doe <john@moon.space>").as_deref(), Some("john@moon.space"));
+    assert_eq!(email_address("john@moon.space").as_deref(), Some("john@moon.space"));
+    assert_eq!(email_address("Undisclosed recipients"), None);
+    assert_eq!(display_name("\"Doe, John\" <john@moon.space>"), "Doe, John");
+    assert_eq!(display_name("<john@moon.space>"), "john@moon.space");
+  }
+}
@@ -0,0 +1,420 @@
+/* cli.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::error::Error;
+use std::ffi::OsString;
+
+use gtk4::glib;
+
+use crate::html::Html;
+use crate::mailservice::MailService;
+use crate::message::mimepart::MimePart;
+use crate::message::value::Value;
+
+const INSPECT_USAGE: &str = "Usage: mailviewer inspect FILE [--field FIELD]...
+Fields: from, to, subject, date, headers, attachments, parts, body, json";
+const INSPECT_FIELDS: &[&str] = &["from", "to", "subject", "date"];
+const VERIFY_USAGE: &str = "Usage: mailviewer --verify MANIFEST FILE
+MANIFEST is a JSON object mapping attachment filenames to their SHA-256";
+const HEADERS_JSON_USAGE: &str = "Usage: mailviewer --headers-json FILE";
+
+/// Outcome of the check of one attachment against a manifest
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+  Ok,
+  Mismatch { expected: String, actual: String },
+  /// in the manifest, not in the message
+  Missing,
+  /// in the message, not in the manifest
+  Unlisted,
+}
+
+impl Verification {
+  pub fn is_failure(&self) -> bool {
+    matches!(self, Verification::Mismatch { .. } | Verification::Missing)
+  }
+}
+
+/// Runs the command line subcommands, None when `args` has none and the
+/// application should start. The file to open may have any name, only the
+/// arguments of a subcommand have to be UTF-8.
+pub fn run(args: &[OsString]) -> Option<glib::ExitCode> {
+  let subcommand: fn(&[String]) -> Result<(), Box<dyn Error>> =
+    match args.get(1).and_then(|arg| arg.to_str()) {
+      Some("inspect") => run_inspect,
+      Some("--verify") => run_verify,
+      Some("--headers-json") => run_headers_json,
+      _ => return None,
+    };
+  Some(exit_code(utf8_args(&args[2..]).and_then(|args| subcommand(&args))))
+}
+
+fn utf8_args(args: &[OsString]) -> Result<Vec<String>, Box<dyn Error>> {
+  args
+    .iter()
+    .map(|arg| {
+      arg
+        .to_str()
+        .map(|arg| arg.to_string())
+        .ok_or_else(|| format!("Not an UTF-8 argument: {:?}", arg).into())
+    })
+    .collect()
+}
+
+fn exit_code(result: Result<(), Box<dyn Error>>) -> glib::ExitCode {
+  match result {
+    Ok(_) => glib::ExitCode::SUCCESS,
+    Err(e) => {
+      eprintln!("{}", e);
+      glib::ExitCode::FAILURE
+    }
+  }
+}
+
+fn run_inspect(args: &[String]) -> Result<(), Box<dyn Error>> {
+  let mut file: Option<&str> = None;
+  let mut fields: Vec<&str> = vec![];
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--field" | "-f" => fields.push(args.next().ok_or(INSPECT_USAGE)?),
+      "--help" | "-h" => {
+        println!("{}", INSPECT_USAGE);
+        return Ok(());
+      }
+      arg if file.is_none() && !arg.starts_with('-') => file = Some(arg),
+      _ => return Err(INSPECT_USAGE.into()),
+    }
+  }
+  let service = MailService::new();
+  service.open_message(file.ok_or(INSPECT_USAGE)?)?;
+  if fields.is_empty() {
+    fields = INSPECT_FIELDS.to_vec();
+  }
+  for field in fields {
+    println!("{}", inspect(&service, field)?);
+  }
+  Ok(())
+}
+
+/// `field` of the opened message, as printed by `mailviewer inspect`
+pub fn inspect(service: &MailService, field: &str) -> Result<String, Box<dyn Error>> {
+  let value = match field.to_lowercase().as_str() {
+    "from" => service.from(),
+    "to" => service.to(),
+    "subject" => service.subject(),
+    "date" => service.date(),
+    "headers" => service
+      .headers()
+      .iter()
+      .map(|(name, value)| format!("{}: {}", name, value))
+      .collect::<Vec<String>>()
+      .join("\n"),
+    "attachments" => {
+      let mut lines: Vec<String> = vec![];
+      service.for_each_attachment(|attachment| {
+        lines.push(format!(
+          "{}\t{}\t{}",
+          attachment.filename,
+          attachment.mime_type.as_deref().unwrap_or("application/octet-stream"),
+          attachment.size()
+        ));
+      });
+      lines.join("\n")
+    }
+    "parts" => {
+      let mut lines: Vec<String> = vec![];
+      part_lines(&service.mime_tree(), 0, &mut lines);
+      lines.join("\n")
+    }
+    "body" => match (service.body_text(), service.body_html()) {
+      (Some(text), _) => text,
+      (None, Some(html)) => Html::new(&html, false).to_text(),
+      (None, None) => String::new(),
+    },
+    "json" => service.to_value().to_string(),
+    _ => return Err(format!("Unknown field \"{}\"\n{}", field, INSPECT_USAGE).into()),
+  };
+  Ok(value)
+}
+
+fn run_verify(args: &[String]) -> Result<(), Box<dyn Error>> {
+  let [manifest, file] = args else {
+    return Err(VERIFY_USAGE.into());
+  };
+  let manifest = std::fs::read_to_string(manifest)
+    .map_err(|e| format!("{}: {}", manifest, e))
+    .and_then(|text| Value::parse(&text).map_err(|e| format!("{}: {}", manifest, e)))?;
+  let service = MailService::new();
+  service.open_message(file)?;
+  let mut failures = 0;
+  for (filename, verification) in verify(&service, &manifest)? {
+    match &verification {
+      Verification::Ok => println!("OK\t{}", filename),
+      Verification::Mismatch { expected, actual } => {
+        println!("FAILED\t{} (expected {}, got {})", filename, expected, actual)
+      }
+      Verification::Missing => println!("MISSING\t{}", filename),
+      Verification::Unlisted => println!("UNLISTED\t{}", filename),
+    }
+    if verification.is_failure() {
+      failures += 1;
+    }
+  }
+  match failures {
+    0 => Ok(()),
+    n => Err(format!("{} attachment(s) failed verification", n).into()),
+  }
+}
+
+/// Checks the SHA-256 of the attachments of the opened message against
+/// `manifest`, an object of filename to hex digest. Attachments absent from
+/// the manifest are reported without failing.
+pub fn verify(
+  service: &MailService,
+  manifest: &Value,
+) -> Result<Vec<(String, Verification)>, Box<dyn Error>> {
+  let entries = manifest.as_object().ok_or(VERIFY_USAGE)?;
+  let mut digests: Vec<(String, std::io::Result<String>)> = vec![];
+  service.for_each_attachment(|attachment| {
+    digests.push((attachment.filename.clone(), attachment.sha256()));
+  });
+  // an attachment that can't be decoded can't be verified
+  let digests = digests
+    .into_iter()
+    .map(|(filename, digest)| match digest {
+      Ok(digest) => Ok((filename, digest)),
+      Err(e) => Err(format!("{}: {}", filename, e)),
+    })
+    .collect::<Result<Vec<(String, String)>, String>>()?;
+  let mut results = vec![];
+  for (filename, expected) in entries {
+    let expected = expected
+      .as_str()
+      .ok_or_else(|| format!("The digest of \"{}\" is not a string", filename))?;
+    // every attachment of that name, another one may have been added
+    let named: Vec<&String> = digests
+      .iter()
+      .filter(|(name, _)| name == filename)
+      .map(|(_, actual)| actual)
+      .collect();
+    if named.is_empty() {
+      results.push((filename.clone(), Verification::Missing));
+    }
+    for actual in named {
+      let verification = match actual.eq_ignore_ascii_case(expected.trim()) {
+        true => Verification::Ok,
+        false => Verification::Mismatch {
+          expected: expected.to_string(),
+          actual: actual.clone(),
+        },
+      };
+      results.push((filename.clone(), verification));
+    }
+  }
+  for (filename, _) in &digests {
+    if !entries.iter().any(|(name, _)| name == filename) {
+      results.push((filename.clone(), Verification::Unlisted));
+    }
+  }
+  Ok(results)
+}
+
+fn run_headers_json(args: &[String]) -> Result<(), Box<dyn Error>> {
+  let [file] = args else {
+    return Err(HEADERS_JSON_USAGE.into());
+  };
+  let service = MailService::new();
+  service.open_message(file)?;
+  println!("{}", headers_json(&service));
+  Ok(())
+}
+
+/// Every header of the opened message, in order and with the duplicates,
+/// as an array of {name, value} objects (names as written in the message).
+pub fn headers_json(service: &MailService) -> Value {
+  Value::Array(
+    service
+      .headers()
+      .into_iter()
+      .map(|(name, value)| Value::object(vec![("name", name.into()), ("value", value.into())]))
+      .collect(),
+  )
+}
+
+fn part_lines(part: &MimePart, depth: usize, lines: &mut Vec<String>) {
+  let mut line = format!("{}{}", "  ".repeat(depth), part.content_type);
+  if let Some(disposition) = &part.disposition {
+    line.push_str(&format!(" ({})", disposition));
+  }
+  line.push_str(&format!(" {}", part.size));
+  lines.push(line);
+  for child in &part.children {
+    part_lines(child, depth + 1, lines);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::os::unix::ffi::OsStringExt;
+
+  use super::*;
+  use crate::message::attachment::Attachment;
+  use crate::message::eml;
+
+  #[test]
+  fn inspect_fields() {
+    let service = MailService::new();
+    service.open_message("sample.eml").unwrap();
+
+    assert_eq!(inspect(&service, "subject").unwrap(), "Lorem ipsum");
+    assert_eq!(inspect(&service, "From").unwrap(), "John Doe <john@moon.space>");
+    assert!(inspect(&service, "headers")
+      .unwrap()
+      .lines()
+      .any(|line| line == "Subject: Lorem ipsum"));
+    let attachments = inspect(&service, "attachments").unwrap();
+    assert!(attachments.starts_with("Deus_Gnome.png\timage/png\t"));
+    let parts = inspect(&service, "parts").unwrap();
+    assert!(parts.starts_with("multipart/"));
+    assert!(parts.contains("\n  "));
+    assert!(inspect(&service, "json").unwrap().starts_with('{'));
+    assert!(inspect(&service, "color").is_err());
+  }
+
+  #[test]
+  fn verify_manifest() {
+    let service = MailService::new();
+    service.open_message("sample.eml").unwrap();
+    let digest = "7bb53b9516aa45f7607fff1fcc3146d573041f3e3a857fbd1f621ac373c30723";
+
+    let manifest = Value::parse(&format!(r#"{{"Deus_Gnome.png": "{}"}}"#, digest)).unwrap();
+    assert_eq!(
+      verify(&service, &manifest).unwrap(),
+      vec![("Deus_Gnome.png".to_string(), Verification::Ok)]
+    );
+
+    let wrong = digest.replace('7', "8");
+    let manifest = Value::parse(&format!(
+      r#"{{"Deus_Gnome.png": "{}", "report.pdf": "{}"}}"#,
+      wrong, digest
+    ))
+    .unwrap();
+    let results = verify(&service, &manifest).unwrap();
+    assert_eq!(
+      results,
+      vec![
+        (
+          "Deus_Gnome.png".to_string(),
+          Verification::Mismatch {
+            expected: wrong,
+            actual: digest.to_string()
+          }
+        ),
+        ("report.pdf".to_string(), Verification::Missing),
+      ]
+    );
+    assert!(results.iter().all(|(_, verification)| verification.is_failure()));
+
+    let results = verify(&service, &Value::parse("{}").unwrap()).unwrap();
+    assert_eq!(results, vec![("Deus_Gnome.png".to_string(), Verification::Unlisted)]);
+    assert!(!results[0].1.is_failure());
+    assert!(verify(&service, &Value::parse("[]").unwrap()).is_err());
+  }
+
+  #[test]
+  fn verify_same_names() {
+    let attachment = |body: &[u8]| Attachment {
+      filename: "report.txt".to_string(),
+      content_id: "none".to_string(),
+      body: body.to_vec(),
+      mime_type: Some("text/plain".to_string()),
+      description: None,
+      encoded: None,
+    };
+    let (listed, tampered) = (attachment(b"Q3 figures"), attachment(b"Q3 figures, updated"));
+    let headers = [("Subject".to_string(), "Report".to_string())];
+    let eml = eml::build(&headers, Some("See attached"), None, &[listed.clone(), tampered.clone()])
+      .unwrap();
+    let service = MailService::new();
+    service.open_from_reader(eml.as_slice()).unwrap();
+
+    let digest = listed.sha256().unwrap();
+    let manifest = Value::parse(&format!(r#"{{"report.txt": "{}"}}"#, digest)).unwrap();
+    let results = verify(&service, &manifest).unwrap();
+    assert_eq!(
+      results,
+      vec![
+        ("report.txt".to_string(), Verification::Ok),
+        (
+          "report.txt".to_string(),
+          Verification::Mismatch {
+            expected: listed.sha256().unwrap(),
+            actual: tampered.sha256().unwrap()
+          }
+        ),
+      ]
+    );
+  }
+
+  #[test]
+  fn headers_as_json() {
+    let service = MailService::new();
+    service.open_message("sample.eml").unwrap();
+    let json = headers_json(&service);
+    let subject = Value::object(vec![
+      ("name", "Subject".into()),
+      ("value", "Lorem ipsum".into()),
+    ]);
+    assert!(matches!(&json, Value::Array(headers) if headers.contains(&subject)));
+    assert!(json
+      .to_string()
+      .contains(r#"{"name":"Subject","value":"Lorem ipsum"}"#));
+  }
+
+  #[test]
+  fn subcommands() {
+    let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<OsString>>();
+    assert!(run(&args(&["mailviewer", "sample.eml"])).is_none());
+    assert!(run(&args(&["mailviewer"])).is_none());
+    assert_eq!(
+      run(&args(&["mailviewer", "inspect", "sample.eml", "--field", "subject"])),
+      Some(glib::ExitCode::SUCCESS)
+    );
+    assert_eq!(
+      run(&args(&["mailviewer", "inspect", "--field"])),
+      Some(glib::ExitCode::FAILURE)
+    );
+    assert_eq!(
+      run(&args(&["mailviewer", "--verify", "sample.eml"])),
+      Some(glib::ExitCode::FAILURE)
+    );
+    assert_eq!(
+      run(&args(&["mailviewer", "--headers-json", "sample.eml"])),
+      Some(glib::ExitCode::SUCCESS)
+    );
+
+    // a file name that isn't UTF-8 still starts the application
+    let latin1 = OsString::from_vec(b"r\xe9union.eml".to_vec());
+    assert!(run(&[OsString::from("mailviewer"), latin1.clone()]).is_none());
+    assert_eq!(
+      run(&[OsString::from("mailviewer"), OsString::from("inspect"), latin1]),
+      Some(glib::ExitCode::FAILURE)
+    );
+}
@@ -17,6 +17,9 @@
  *
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
+use std::collections::HashSet;
+
+use gtk4::glib;
 use nipper::{Document, Node};
 
 pub const CSS: &str = r#"
@@ -30,9 +33,164 @@ pub const CSS: &str = r#"
 </style>
 "#;
 
+/// CSS forced while the system prefers a dark style
+pub const DARK_CSS: &str = r#"
+<style>
+  * {
+    color: #deddda;
+    background-color: #1e1e1e;
+    font-family: Poppins, Roboto, sans-serif;
+    font-size: 20px;
+  }
+  a {
+    color: #78aeed;
+  }
+</style>
+"#;
+
+pub const VISITED_CSS: &str = r#"
+<style>
+  a[data-visited] {
+    color: #681da8 !important;
+  }
+</style>
+"#;
+
+/// Stylesheet of the reader view, plain text in a readable column
+pub const READER_CSS: &str = r#"
+<style>
+  body {
+    max-width: 40em;
+    margin: 1em auto;
+    padding: 0 1em;
+    line-height: 1.5;
+  }
+  img {
+    max-width: 100%;
+    height: auto;
+  }
+</style>
+"#;
+
+/// Elements the reader view drops whatever their content
+const READER_BOILERPLATE: &str = "head,script,style,noscript,nav,header,footer,aside,form,button,\
+  input,select,textarea,svg,[role=navigation],[role=banner],[role=contentinfo]";
+
+/// Attributes kept by the reader view, layout and styling are dropped
+const READER_ATTRIBUTES: [&str; 6] = ["href", "src", "alt", "title", "colspan", "rowspan"];
+
+/// Href of the click-to-load image placeholders, followed by the escaped
+/// image URL.
+pub const IMAGE_SCHEME: &str = "mailviewer-image:";
+
+const PLACEHOLDER_CSS: &str = r#"
+<style>
+  a.mailviewer-image {
+    display: inline-block;
+    padding: 1em;
+    border: 1px dashed #888;
+    color: #555;
+    font: 12px sans-serif;
+    text-decoration: none;
+  }
+</style>
+"#;
+
+/// Share of the text removed by safe() from which the HTML isn't worth
+/// reading anymore
+const HEAVY_SANITIZATION: f64 = 0.8;
+
+/// What safe() removed from the message
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Sanitization {
+  /// scripts, forms, embedded objects... not the metadata also removed
+  pub removed_elements: usize,
+  /// visible characters (whitespace and source code excluded) before and
+  /// after
+  pub text_before: usize,
+  pub text_after: usize,
+}
+
+impl Sanitization {
+  /// Share of the text removed, from 0 to 1
+  pub fn removed_ratio(&self) -> f64 {
+    match self.text_before {
+      0 => 0.0,
+      before => 1.0 - self.text_after.min(before) as f64 / before as f64,
+    }
+  }
+
+  /// Too little is left of the HTML for it to be worth reading, or it had
+  /// no text but what its scripts would write
+  pub fn is_heavy(&self) -> bool {
+    self.removed_elements > 0
+      && (self.text_before == 0 || self.removed_ratio() >= HEAVY_SANITIZATION)
+  }
+}
+
+/// Links opened from the current message, so they can be rendered as
+/// visited. Cleared whenever another message is displayed.
+#[derive(Debug, Default)]
+pub struct VisitedLinks {
+  uris: HashSet<String>,
+}
+
+impl VisitedLinks {
+  /// Records `uri`, returns false if it was already visited.
+  pub fn visit(&mut self, uri: &str) -> bool {
+    self.uris.insert(Self::normalize(uri).to_string())
+  }
+
+  pub fn contains(&self, uri: &str) -> bool {
+    self.uris.contains(Self::normalize(uri))
+  }
+
+  pub fn clear(&mut self) {
+    self.uris.clear();
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.uris.is_empty()
+  }
+
+  // WebKit hands us "https://example.com/" for href="https://example.com"
+  fn normalize(uri: &str) -> &str {
+    uri.trim().trim_end_matches('/')
+  }
+
+  /// Script marking the links to `uri` already rendered in the page.
+  pub fn mark_script(uri: &str) -> String {
+    let mut escaped = String::with_capacity(uri.len() + 2);
+    for c in Self::normalize(uri).chars() {
+      match c {
+        '\\' | '\'' | '"' | '<' | '>' | '\n' | '\r' | '\u{2028}' | '\u{2029}' => {
+          escaped.push_str(&format!("\\u{:04x}", c as u32))
+        }
+        _ => escaped.push(c),
+      }
+    }
+    format!(
+      "document.querySelectorAll('a[href]').forEach(a => {{ \
+       if (a.href.replace(/\\/+$/, '') === '{}') a.setAttribute('data-visited', ''); }});",
+      escaped
+    )
+  }
+}
+
 pub struct Html {
   body: String,
   strip_css: bool,
+  strip_comments: bool,
+  image_proxy: Option<String>,
+  language: Option<String>,
+  visited: Vec<String>,
+  custom_css: Option<String>,
+  dark: bool,
+  max_width: u32,
+  reader: bool,
+  stylesheets: Vec<(String, String)>,
+  image_placeholder: Option<String>,
+  loaded_images: Vec<String>,
 }
 
 impl Html {
@@ -40,26 +198,780 @@ impl Html {
     Self {
       body: body.to_string(),
       strip_css,
+      strip_comments: false,
+      image_proxy: None,
+      language: None,
+      visited: vec![],
+      custom_css: None,
+      dark: false,
+      max_width: 0,
+      reader: false,
+      stylesheets: vec![],
+      image_placeholder: None,
+      loaded_images: vec![],
     }
   }
 
+  /// Also drop HTML comments, including Outlook conditional blocks
+  /// (`<!--[if mso]>...<![endif]-->`).
+  pub fn with_strip_comments(mut self, strip_comments: bool) -> Self {
+    self.strip_comments = strip_comments;
+    self
+  }
+
+  /// Routes remote images through `proxy`, "%s" being replaced by the
+  /// escaped image URL (appended when absent). Empty means no proxy.
+  pub fn with_image_proxy(mut self, proxy: Option<&str>) -> Self {
+    self.image_proxy = proxy.filter(|p| !p.trim().is_empty()).map(|p| p.trim().to_string());
+    self
+  }
+
+  /// Sets `<html lang>` (hyphenation, spell checking) unless the message
+  /// already does.
+  pub fn with_language(mut self, language: Option<&str>) -> Self {
+    self.language = language.map(|l| l.to_string());
+    self
+  }
+
+  /// Stylesheet applied instead of the built-in one when CSS is forced.
+  /// Empty means the built-in one.
+  pub fn with_custom_css(mut self, css: Option<&str>) -> Self {
+    self.custom_css = css.filter(|css| !css.trim().is_empty()).map(|css| css.to_string());
+    self
+  }
+
+  /// Forces the dark built-in stylesheet rather than the light one
+  pub fn with_dark(mut self, dark: bool) -> Self {
+    self.dark = dark;
+    self
+  }
+
+  /// Centers the content in a column of `chars` characters, 0 keeps the full
+  /// width.
+  pub fn with_max_width(mut self, chars: u32) -> Self {
+    self.max_width = chars;
+    self
+  }
+
+  /// Renders reader_content() rather than the message as is
+  pub fn with_reader(mut self, reader: bool) -> Self {
+    self.reader = reader;
+    self
+  }
+
+  /// Stylesheets of the message, by "cid:" URL or Content-Location, inlined
+  /// in place of the `<link rel="stylesheet">` referencing them.
+  pub fn with_stylesheets(mut self, stylesheets: &[(String, String)]) -> Self {
+    self.stylesheets = stylesheets.to_vec();
+    self
+  }
+
+  /// Replaces remote images with a link labelled `label` loading that image
+  /// on click (see IMAGE_SCHEME), except the `loaded` ones. None keeps them.
+  pub fn with_image_placeholders(mut self, label: Option<&str>, loaded: &[String]) -> Self {
+    self.image_placeholder = label.map(|label| label.to_string());
+    self.loaded_images = loaded.to_vec();
+    self
+  }
+
+  pub fn with_visited(mut self, visited: &VisitedLinks) -> Self {
+    self.visited = visited.uris.iter().cloned().collect();
+    self
+  }
+
   pub fn safe(&self) -> String {
-    let document = Document::from(&self.body);
+    self.safe_report().0
+  }
+
+  /// safe() along with how much it removed
+  pub fn safe_report(&self) -> (String, Sanitization) {
+    let document = match (self.reader, self.strip_comments) {
+      (true, _) => Document::from(&self.reader_content()),
+      (false, true) => Document::from(&Self::remove_comments(&self.body)),
+      (false, false) => Document::from(&self.body),
+    };
+    let mut sanitization = Sanitization {
+      text_before: Self::visible_length(&document),
+      ..Default::default()
+    };
+    self.inline_stylesheets(&document);
     document
-      .select("script,meta,audio,video,iframe,link,object,embed,applet,form")
+      .select("script,audio,video,iframe,object,embed,applet,form")
       .iter()
       .for_each(|mut node| {
+        sanitization.removed_elements += 1;
         node.remove();
       });
+    document.select("meta,link").iter().for_each(|mut node| {
+      node.remove();
+    });
+    sanitization.text_after = Self::visible_length(&document);
     self.parse(&document.root());
+    self.replace_images(&document);
+    self.proxy_images(&document);
+    if let Some(language) = &self.language {
+      let mut root = document.select("html");
+      if root.attr("lang").is_none() {
+        root.set_attr("lang", language);
+      }
+    }
+    self.mark_visited(&document);
+    document
+      .select("html")
+      .select("head")
+      .first()
+      .append_html(VISITED_CSS);
+    if self.max_width > 0 {
+      document
+        .select("html")
+        .select("head")
+        .first()
+        .append_html(Self::max_width_css(self.max_width));
+    }
     if self.strip_css {
+      let css = match &self.custom_css {
+        // can't close the <style> element
+        Some(css) => format!("<style>\n{}\n</style>", css.replace("</", "<\\/")),
+        None if self.dark => DARK_CSS.to_string(),
+        None => CSS.to_string(),
+      };
       document
         .select("html")
         .select("head")
         .first()
-        .append_html(CSS);
+        .append_html(css);
     }
-    document.html().to_string()
+    (document.html().to_string(), sanitization)
+  }
+
+  // after the message stylesheets, which often set the body margins
+  fn max_width_css(chars: u32) -> String {
+    format!(
+      "<style>\n  body {{\n    max-width: {}ch;\n    margin-left: auto !important;\n    \
+       margin-right: auto !important;\n  }}\n</style>",
+      chars
+    )
+  }
+
+  // the source of scripts (JSON-LD included) and stylesheets isn't text
+  fn visible_length(document: &Document) -> usize {
+    let length = |text: &str| text.chars().filter(|c| !c.is_whitespace()).count();
+    let all = length(&document.select("html").text());
+    all.saturating_sub(length(&document.select("script,style,template,title").text()))
+  }
+
+  /// Simplified HTML of the main content of the message: menus, social and
+  /// footer links, hidden text and the layout are dropped, the text, links,
+  /// lists, tables and images are kept.
+  pub fn reader_content(&self) -> String {
+    let document = Document::from(&Self::remove_comments(&self.body));
+    document.select(READER_BOILERPLATE).iter().for_each(|mut node| {
+      node.remove();
+    });
+    document.select("[style],[hidden]").iter().for_each(|mut node| {
+      let style = node.attr("style").map(|s| s.to_string()).unwrap_or_default();
+      if node.attr("hidden").is_some() || Self::is_hidden_style(&style) {
+        node.remove();
+      }
+    });
+    // innermost first, a cluster inside the content doesn't make it one.
+    // A short message made of links is no boilerplate.
+    let total = Self::text_length(&document.select("body").text());
+    let blocks = document.select("p,li,ul,ol,td,tr,table,div,section,center");
+    blocks.iter().collect::<Vec<_>>().into_iter().rev().for_each(|mut block| {
+      let links = block.select("a");
+      let text = block.text();
+      if Self::is_link_cluster(&text, &links.text(), links.length())
+        && Self::text_length(&text) * 2 < total
+      {
+        block.remove();
+      }
+    });
+    Self::simplify(&document.root());
+
+    // the smallest block holding most of the text, unless the message says
+    // where its content is
+    let total = Self::text_length(&document.select("body").text());
+    let main = match document.select("article,main,[role=main]").first() {
+      main if main.exists() && Self::text_length(&main.text()) > 0 => main.html().to_string(),
+      _ => document
+        .select("article,main,section,div,td,table,body")
+        .iter()
+        .filter(|block| Self::text_length(&block.text()) * 10 >= total * 8)
+        .map(|block| block.html().to_string())
+        .min_by_key(|html| html.len())
+        .unwrap_or_default(),
+    };
+    format!(
+      "<html><head>{}</head><body><article>{}</article></body></html>",
+      READER_CSS, main
+    )
+  }
+
+  // mostly short links, as many menus, social networks and footers are
+  fn is_link_cluster(text: &str, link_text: &str, links: usize) -> bool {
+    let text = Self::text_length(text);
+    let link_text = Self::text_length(link_text);
+    links >= 2 && link_text * 10 >= text * 7 && text <= links * 30
+  }
+
+  fn text_length(text: &str) -> usize {
+    text.chars().filter(|c| !c.is_whitespace()).count()
+  }
+
+  fn simplify(root: &Node) {
+    root.children().iter().for_each(|node| {
+      if node.node_name().is_some() {
+        let attrs_to_remove: Vec<String> = node
+          .attrs()
+          .iter()
+          .map(|attr| attr.name.local.as_ref().to_lowercase())
+          .filter(|name| !READER_ATTRIBUTES.contains(&name.as_str()))
+          .collect();
+        for attr_name in attrs_to_remove {
+          node.remove_attr(&attr_name);
+        }
+      }
+      Self::simplify(node);
+    });
+  }
+
+  /// Plain text rendering, used when the HTML view is unavailable.
+  pub fn to_text(&self) -> String {
+    let document = Document::from(&self.body);
+    document.select("script,style,head").iter().for_each(|mut node| {
+      node.remove();
+    });
+    document
+      .select("body")
+      .text()
+      .lines()
+      .map(|line| line.trim())
+      .collect::<Vec<&str>>()
+      .join("\n")
+      .trim()
+      .to_string()
+  }
+
+  /// Markdown rendering for pasting into docs and wikis, elements without a
+  /// Markdown equivalent degrade to their text content.
+  pub fn to_markdown(&self) -> String {
+    let document = Document::from(&self.body);
+    document.select("script,style,head").iter().for_each(|mut node| {
+      node.remove();
+    });
+    let markdown: String = document
+      .root()
+      .children()
+      .iter()
+      .map(|node| Self::markdown(node, &mut vec![]))
+      .collect();
+    Self::collapse_blank_lines(&markdown)
+  }
+
+  // also drops the indentation left by whitespace between elements, only
+  // list items and code are indented on purpose
+  fn collapse_blank_lines(text: &str) -> String {
+    let mut lines: Vec<&str> = vec![];
+    let mut code = false;
+    for line in text.lines().map(|line| line.trim_end()) {
+      if line.trim_start().starts_with("```") {
+        code = !code;
+      }
+      let item = line.trim_start().starts_with("- ")
+        || line
+          .trim_start()
+          .split_once(". ")
+          .is_some_and(|(number, _)| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()));
+      let line = if code || item { line } else { line.trim_start() };
+      let blank = line.trim().is_empty();
+      if blank && matches!(lines.last(), None | Some(&"")) {
+        continue;
+      }
+      lines.push(if blank { "" } else { line });
+    }
+    lines.join("\n").trim().to_string()
+  }
+
+  // `lists` holds the enclosing lists, None for unordered ones and the next
+  // item number for ordered ones
+  fn markdown(node: &Node, lists: &mut Vec<Option<usize>>) -> String {
+    let Some(name) = node.node_name() else {
+      // text, comments and doctype have none
+      let text = node.text();
+      let words = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+      if words.is_empty() {
+        return if text.is_empty() { String::new() } else { " ".to_string() };
+      }
+      let leading = if text.starts_with(char::is_whitespace) { " " } else { "" };
+      let trailing = if text.ends_with(char::is_whitespace) { " " } else { "" };
+      return format!("{leading}{words}{trailing}");
+    };
+    let name = name.to_lowercase();
+    let inner = |lists: &mut Vec<Option<usize>>| -> String {
+      node
+        .children()
+        .iter()
+        .map(|child| Self::markdown(child, lists))
+        .collect()
+    };
+    match name.as_str() {
+      "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+        let level = name[1..].parse::<usize>().unwrap_or(1);
+        let title = inner(lists);
+        format!("\n\n{} {}\n\n", "#".repeat(level), title.trim())
+      }
+      "p" => format!("\n\n{}\n\n", inner(lists).trim()),
+      "div" | "tr" => format!("\n{}\n", inner(lists).trim()),
+      "br" => "\n".to_string(),
+      "hr" => "\n\n---\n\n".to_string(),
+      "strong" | "b" => Self::emphasis(&inner(lists), "**"),
+      "em" | "i" => Self::emphasis(&inner(lists), "*"),
+      "code" => Self::emphasis(&node.text(), "`"),
+      "pre" => format!("\n\n```\n{}\n```\n\n", node.text().trim_end()),
+      "a" => {
+        let text = inner(lists);
+        match node.attr("href") {
+          Some(href) if !text.trim().is_empty() && !href.starts_with('#') => {
+            format!("[{}]({})", text.trim(), href.trim())
+          }
+          _ => text,
+        }
+      }
+      "img" => node.attr("alt").map(|alt| alt.to_string()).unwrap_or_default(),
+      "ul" | "ol" => {
+        lists.push(if name == "ol" { Some(1) } else { None });
+        let items = inner(lists);
+        lists.pop();
+        match lists.is_empty() {
+          true => format!("\n\n{}\n\n", items),
+          false => format!("\n{}\n", items),
+        }
+      }
+      "li" => {
+        let indent = "  ".repeat(lists.len().saturating_sub(1));
+        let marker = match lists.last_mut() {
+          Some(Some(number)) => {
+            *number += 1;
+            format!("{}. ", *number - 1)
+          }
+          _ => "- ".to_string(),
+        };
+        let item = inner(lists);
+        let item = item
+          .trim()
+          .lines()
+          .filter(|line| !line.trim().is_empty())
+          .collect::<Vec<&str>>()
+          .join("\n");
+        format!("\n{indent}{marker}{item}")
+      }
+      "blockquote" => {
+        let quoted = Self::collapse_blank_lines(&inner(lists))
+          .lines()
+          .map(|line| match line.trim().is_empty() {
+            true => ">".to_string(),
+            false => format!("> {}", line),
+          })
+          .collect::<Vec<String>>()
+          .join("\n");
+        format!("\n\n{}\n\n", quoted)
+      }
+      _ => inner(lists),
+    }
+  }
+
+  fn emphasis(text: &str, marker: &str) -> String {
+    match text.trim().is_empty() {
+      true => text.to_string(),
+      false => format!("{marker}{}{marker}", text.trim()),
+    }
+  }
+
+  /// Text hidden from the reader (preheaders, spam keywords...): display:none,
+  /// visibility:hidden, zero size or opacity, or same text and background
+  /// color.
+  pub fn hidden_text(&self) -> Vec<String> {
+    let document = Document::from(&self.body);
+    let mut hidden: Vec<String> = vec![];
+    document.select("[style],[hidden]").iter().for_each(|node| {
+      let style = node.attr("style").map(|s| s.to_string()).unwrap_or_default();
+      if node.attr("hidden").is_none() && !Self::is_hidden_style(&style) {
+        return;
+      }
+      let text = node.text().split_whitespace().collect::<Vec<&str>>().join(" ");
+      // children of an already reported element
+      if !text.is_empty() && !hidden.iter().any(|h| h.contains(&text)) {
+        hidden.push(text);
+      }
+    });
+    hidden
+  }
+
+  /// (text, href) of the links showing an URL to another host than the one
+  /// they lead to, a phishing classic.
+  pub fn deceptive_links(&self) -> Vec<(String, String)> {
+    let document = Document::from(&self.body);
+    let mut links: Vec<(String, String)> = vec![];
+    document.select("a[href]").iter().for_each(|node| {
+      let href = node.attr("href").map(|h| h.trim().to_string()).unwrap_or_default();
+      let text = node.text().trim().to_string();
+      let lowercase = text.to_lowercase();
+      let looks_like_url = lowercase.starts_with("http://")
+        || lowercase.starts_with("https://")
+        || lowercase.starts_with("www.");
+      if !looks_like_url {
+        return;
+      }
+      match (Self::host(&text), Self::host(&href)) {
+        (Some(shown), Some(target)) if shown != target => links.push((text, href)),
+        _ => (),
+      }
+    });
+    links
+  }
+
+  /// Distinct hosts of the links, mailto: and relative ones left out
+  pub fn link_hosts(&self) -> Vec<String> {
+    let document = Document::from(&self.body);
+    let mut hosts: Vec<String> = vec![];
+    document.select("a[href]").iter().for_each(|node| {
+      let href = node.attr("href").map(|h| h.to_string()).unwrap_or_default();
+      if let Some(host) = Self::host(&href) {
+        if !hosts.contains(&host) {
+          hosts.push(host);
+        }
+      }
+    });
+    hosts
+  }
+
+  /// Remote images of at most 1x1 pixel, the usual read receipts
+  pub fn tracking_pixels(&self) -> usize {
+    let document = Document::from(&self.body);
+    let tiny = |value: Option<String>| {
+      value.is_some_and(|v| matches!(v.trim().trim_end_matches("px"), "0" | "1"))
+    };
+    document
+      .select("img[src]")
+      .iter()
+      .filter(|node| {
+        let src = node.attr("src").map(|s| s.to_lowercase()).unwrap_or_default();
+        (src.starts_with("http://") || src.starts_with("https://"))
+          && tiny(node.attr("width").map(|w| w.to_string()))
+          && tiny(node.attr("height").map(|h| h.to_string()))
+      })
+      .count()
+  }
+
+  // lowercase host without "www.", None for mailto: and relative links
+  fn host(url: &str) -> Option<String> {
+    let url = url.trim().to_lowercase();
+    let rest = match url.split_once("://") {
+      Some(("http" | "https", rest)) => rest.to_string(),
+      Some(_) => return None,
+      None if url.starts_with("www.") => url,
+      None => return None,
+    };
+    let host = rest.split(['/', '?', '#']).next()?;
+    let host = host.rsplit('@').next()?.split(':').next()?;
+    match host.is_empty() {
+      true => None,
+      false => Some(host.trim_start_matches("www.").to_string()),
+    }
+  }
+
+  /// Background color of the message (body, or the wrapper table/div most
+  /// newsletters use), as a CSS color safe to embed in a stylesheet.
+  pub fn background_color(&self) -> Option<String> {
+    let document = Document::from(&self.body);
+    let candidates = [
+      document.select("body"),
+      document.select("body > table, body > div, body > center").first(),
+    ];
+    for node in candidates.iter().filter(|n| n.exists()) {
+      if let Some(color) = node.attr("bgcolor").and_then(|c| Self::css_color(&c)) {
+        return Some(color);
+      }
+      let style = node.attr("style").map(|s| s.to_string()).unwrap_or_default();
+      let declarations = Self::style_declarations(&style);
+      let color = declarations
+        .iter()
+        .rev()
+        .filter(|(k, _)| k == "background-color" || k == "background")
+        .find_map(|(_, v)| Self::css_color(v));
+      if color.is_some() {
+        return color;
+      }
+    }
+    None
+  }
+
+  /// Black or white, whichever reads best on a `#rrggbb`/`#rgb` background.
+  pub fn foreground_for(background: &str) -> Option<&'static str> {
+    let hex = background.strip_prefix('#')?;
+    let hex: String = match hex.len() {
+      3 => hex.chars().flat_map(|c| [c, c]).collect(),
+      6 => hex.to_string(),
+      _ => return None,
+    };
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok().map(f64::from);
+    let luminance = 0.299 * channel(0)? + 0.587 * channel(2)? + 0.114 * channel(4)?;
+    Some(if luminance > 140.0 { "#000000" } else { "#ffffff" })
+  }
+
+  // first color of a (possibly shorthand) CSS value, None for anything that
+  // could escape the stylesheet it's put in
+  fn css_color(value: &str) -> Option<String> {
+    let value = value.replace("!important", "").trim().to_lowercase();
+    if let Some(start) = value.find("rgb").or(value.find("hsl")) {
+      let end = value[start..].find(')')? + start + 1;
+      let color: String = value[start..end].chars().filter(|c| !c.is_whitespace()).collect();
+      let valid = color
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "(),.%".contains(c));
+      return valid.then_some(color);
+    }
+    value.split_whitespace().find_map(|token| {
+      if let Some(hex) = token.strip_prefix('#') {
+        let valid = [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit());
+        return valid.then(|| token.to_string());
+      }
+      // other background shorthand keywords are not colors
+      let keyword = matches!(
+        token,
+        "none"
+          | "transparent"
+          | "inherit"
+          | "initial"
+          | "unset"
+          | "currentcolor"
+          | "center"
+          | "top"
+          | "bottom"
+          | "left"
+          | "right"
+          | "repeat"
+          | "fixed"
+          | "scroll"
+      );
+      let named = !token.is_empty() && token.chars().all(|c| c.is_ascii_alphabetic());
+      (named && !keyword).then(|| token.to_string())
+    })
+  }
+
+  fn style_declarations(style: &str) -> Vec<(String, String)> {
+    style
+      .split(';')
+      .filter_map(|d| d.split_once(':'))
+      .map(|(k, v)| {
+        (
+          k.trim().to_lowercase(),
+          v.replace("!important", "").trim().to_lowercase(),
+        )
+      })
+      .collect()
+  }
+
+  fn is_hidden_style(style: &str) -> bool {
+    let declarations = Self::style_declarations(style);
+    let get = |name: &str| {
+      declarations
+        .iter()
+        .rev()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.as_str())
+    };
+    let is_zero = |v: Option<&str>| {
+      v.is_some_and(|v| v.trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '%') == "0")
+    };
+
+    get("display") == Some("none")
+      || get("visibility") == Some("hidden")
+      || is_zero(get("opacity"))
+      || is_zero(get("font-size"))
+      || (is_zero(get("max-height")) && get("overflow") == Some("hidden"))
+      || matches!(
+        (get("color"), get("background-color").or(get("background"))),
+        (Some(color), Some(background)) if Self::same_color(color, background)
+      )
+  }
+
+  fn same_color(a: &str, b: &str) -> bool {
+    let normalize = |c: &str| match c.replace(' ', "").as_str() {
+      "white" | "#fff" | "#ffffff" | "rgb(255,255,255)" => "#ffffff".to_string(),
+      "black" | "#000" | "#000000" | "rgb(0,0,0)" => "#000000".to_string(),
+      c => c.to_string(),
+    };
+    normalize(a) == normalize(b)
+  }
+
+  fn remove_comments(body: &str) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut rest = body;
+    loop {
+      let comment = rest.find("<!--");
+      // downlevel-revealed conditionals (<![if !mso]>...<![endif]>) keep
+      // their content, only the markers go
+      let marker = [rest.find("<![if "), rest.find("<![endif]>")]
+        .into_iter()
+        .flatten()
+        .min();
+      match (comment, marker) {
+        (Some(start), None) => rest = Self::skip_comment(&mut out, rest, start),
+        (Some(start), Some(marker)) if start < marker => {
+          rest = Self::skip_comment(&mut out, rest, start)
+        }
+        (_, Some(start)) => {
+          out.push_str(&rest[..start]);
+          rest = match rest[start..].find('>') {
+            Some(end) => &rest[start + end + 1..],
+            None => "",
+          };
+        }
+        (None, None) => {
+          out.push_str(rest);
+          return out;
+        }
+      }
+    }
+  }
+
+  fn skip_comment<'a>(out: &mut String, rest: &'a str, start: usize) -> &'a str {
+    out.push_str(&rest[..start]);
+    // an unterminated comment runs to the end of the document
+    match rest[start + 4..].find("-->") {
+      Some(end) => &rest[start + 4 + end + 3..],
+      None => "",
+    }
+  }
+
+  // the links to anything else (remote stylesheets) are removed with the
+  // other <link> elements
+  fn inline_stylesheets(&self, document: &Document) {
+    if self.stylesheets.is_empty() {
+      return;
+    }
+    document.select("link[href]").iter().for_each(|mut node| {
+      let rel = node.attr("rel").unwrap_or_default().to_lowercase();
+      if !rel.split_whitespace().any(|rel| rel == "stylesheet") {
+        return;
+      }
+      let href = node.attr("href").unwrap_or_default();
+      let href = href.trim();
+      let css = self
+        .stylesheets
+        .iter()
+        .find(|(reference, _)| reference.eq_ignore_ascii_case(href))
+        .map(|(_, css)| css);
+      if let Some(css) = css {
+        // can't close the <style> element
+        node.replace_with_html(format!("<style>\n{}\n</style>", css.replace("</", "<\\/")));
+      }
+    });
+  }
+
+  fn replace_images(&self, document: &Document) {
+    let Some(label) = &self.image_placeholder else {
+      return;
+    };
+    let mut replaced = false;
+    document.select("img").iter().for_each(|mut node| {
+      // would load other remote images
+      node.remove_attr("srcset");
+      let Some(src) = node.attr("src").map(|src| src.trim().to_string()) else {
+        return;
+      };
+      let src = match src.starts_with("//") {
+        true => format!("https:{}", src),
+        false => src,
+      };
+      let lowercase = src.to_lowercase();
+      let remote = lowercase.starts_with("http://") || lowercase.starts_with("https://");
+      if !remote {
+        return;
+      }
+      if self.loaded_images.contains(&src) {
+        node.set_attr("src", &src);
+        return;
+      }
+      node.replace_with_html(format!(
+        r#"<a class="mailviewer-image" href="{}{}" title="{}">{}</a>"#,
+        IMAGE_SCHEME,
+        glib::Uri::escape_string(&src, None, false),
+        glib::markup_escape_text(&src),
+        glib::markup_escape_text(label),
+      ));
+      replaced = true;
+    });
+    if replaced {
+      document
+        .select("html")
+        .select("head")
+        .first()
+        .append_html(PLACEHOLDER_CSS);
+    }
+    // blocks the remote images of CSS, background attributes and the like,
+    // the message <meta> elements are removed and the body comes after
+    document
+      .select("html")
+      .select("head")
+      .first()
+      .append_html(self.image_policy());
+  }
+
+  /// Content-Security-Policy allowing the inline images and the loaded ones
+  fn image_policy(&self) -> String {
+    let mut sources = vec!["data:".to_string()];
+    for url in &self.loaded_images {
+      // paths are matched without the query, a source can't hold separators
+      let url = url.split(['?', '#']).next().unwrap_or_default();
+      if !url.is_empty() && !url.contains(char::is_whitespace) {
+        sources.push(url.replace(';', "%3B").replace(',', "%2C"));
+      }
+    }
+    format!(
+      r#"<meta http-equiv="Content-Security-Policy" content="img-src {}">"#,
+      glib::markup_escape_text(&sources.join(" "))
+    )
+  }
+
+  fn proxy_images(&self, document: &Document) {
+    let Some(proxy) = &self.image_proxy else {
+      return;
+    };
+    document.select("img").iter().for_each(|mut node| {
+      // srcset would bypass the proxy
+      node.remove_attr("srcset");
+      if let Some(src) = node.attr("src") {
+        let lowercase = src.to_lowercase();
+        if lowercase.starts_with("http://") || lowercase.starts_with("https://") {
+          node.set_attr("src", &Self::proxied(proxy, &src));
+        }
+      }
+    });
+  }
+
+  fn proxied(proxy: &str, url: &str) -> String {
+    let url = glib::Uri::escape_string(url, None, false);
+    if proxy.contains("%s") {
+      proxy.replace("%s", &url)
+    } else {
+      format!("{}{}", proxy, url)
+    }
+  }
+
+  fn mark_visited(&self, document: &Document) {
+    if self.visited.is_empty() {
+      return;
+    }
+    document.select("a[href]").iter().for_each(|mut node| {
+      if let Some(href) = node.attr("href") {
+        if self.visited.iter().any(|v| v == VisitedLinks::normalize(&href)) {
+          node.set_attr("data-visited", "");
+        }
+      }
+    });
   }
 
   fn parse(&self, root: &Node) {
@@ -97,6 +1009,8 @@ mod tests {
   use std::error::Error;
   use std::fs;
 
+  use super::*;
+
   #[test]
   fn html() -> Result<(), Box<dyn Error>> {
     let html = crate::html::Html::new(&fs::read_to_string("tests/test.html")?, true);
@@ -124,4 +1038,360 @@ mod tests {
 
     Ok(())
   }
+
+  #[test]
+  fn dark_css() {
+    let html = Html::new("<p>Hello</p>", true).with_dark(true).safe();
+    assert!(html.contains(DARK_CSS.trim()));
+    assert!(!html.contains(CSS.trim()));
+    let html = Html::new("<p>Hello</p>", false).with_dark(true).safe();
+    assert!(!html.contains(DARK_CSS.trim()));
+  }
+
+  #[test]
+  fn related_stylesheets() {
+    let body = r#"<html><head>
+      <link rel="stylesheet" href="cid:style@moon.space">
+      <link rel="stylesheet" href="https://tracker.example/style.css">
+      </head><body><p class="title">Hello</p></body></html>"#;
+    let stylesheets = vec![(
+      "cid:style@moon.space".to_string(),
+      ".title { color: navy; }".to_string(),
+    )];
+    let html = Html::new(body, false).with_stylesheets(&stylesheets).safe();
+    assert!(html.contains("<style>\n.title { color: navy; }\n</style>"));
+    assert!(!html.contains("<link"));
+    assert!(!html.contains("tracker.example"));
+  }
+
+  #[test]
+  fn reader_content() {
+    let newsletter = r#"<html><head><style>p { color: red; }</style></head><body>
+      <div style="display:none">Preheader you weren't meant to see</div>
+      <table width="600"><tr><td>
+        <div class="menu"><a href="/">Home</a> | <a href="/news">News</a> |
+          <a href="/shop">Shop</a></div>
+        <div class="content" style="padding: 20px">
+          <h1>Spring release</h1>
+          <p style="font-size: 14px">The new version is out with a faster search, offline
+            support and <a href="https://example.com/notes">detailed release notes</a>.</p>
+          <p>Upgrade from the settings of the application, your data is kept.</p>
+        </div>
+        <div><a href="https://example.com/unsubscribe">Unsubscribe</a>
+          <a href="https://twitter.com/example">Twitter</a>
+          <a href="https://example.com/prefs">Preferences</a></div>
+      </td></tr></table>
+    </body></html>"#;
+    let reader = Html::new(newsletter, false).reader_content();
+    assert!(reader.contains("Spring release"));
+    assert!(reader.contains("your data is kept"));
+    assert!(reader.contains(r#"<a href="https://example.com/notes">"#));
+    assert!(!reader.contains("Preheader"));
+    assert!(!reader.contains("Shop"));
+    assert!(!reader.contains("Unsubscribe"));
+    assert!(!reader.contains("Twitter"));
+    assert!(!reader.contains("padding"));
+    assert!(!reader.contains("color: red"));
+    assert!(reader.contains(READER_CSS.trim()));
+
+    // nothing else to show
+    let links = r#"<p><a href="https://a.example">First link</a>
+      <a href="https://b.example">Second link</a></p>"#;
+    let reader = Html::new(links, false).reader_content();
+    assert!(reader.contains("https://a.example"));
+
+    let safe = Html::new(newsletter, false).with_reader(true).safe();
+    assert!(safe.contains("Spring release"));
+    assert!(!safe.contains("Twitter"));
+  }
+
+  #[test]
+  fn max_width() {
+    let html = Html::new("<p>Hello</p>", false).with_max_width(80).safe();
+    assert!(html.contains("max-width: 80ch;"));
+    assert!(html.contains("margin-left: auto !important;"));
+    let html = Html::new("<p>Hello</p>", true).with_max_width(0).safe();
+    assert!(!html.contains("max-width"));
+  }
+
+  #[test]
+  fn custom_css() {
+    let css = "p { color: navy; }";
+    let html = Html::new("<p>Hello</p>", true).with_custom_css(Some(css)).safe();
+    assert!(html.contains(css));
+    assert!(!html.contains("font-family: Poppins"));
+
+    // not applied unless CSS is forced
+    let html = Html::new("<p>Hello</p>", false).with_custom_css(Some(css)).safe();
+    assert!(!html.contains(css));
+
+    // falls back to the built-in stylesheet
+    let html = Html::new("<p>Hello</p>", true).with_custom_css(Some(" ")).safe();
+    assert!(html.contains("font-family: Poppins"));
+
+    let html = Html::new("<p>Hello</p>", true)
+      .with_custom_css(Some("p {}</style><script>evil()</script>"))
+      .safe();
+    assert!(!html.contains("</style><script>"));
+  }
+
+  #[test]
+  fn strip_comments() {
+    let body = r#"<html><body><!-- tracking: 1234 --><p>Hello</p><!--[if mso]><table><tr><td>Outlook</td></tr></table><![endif]--><![if !mso]><p>World</p><![endif]></body></html>"#;
+    let html = Html::new(body, false).with_strip_comments(true).safe();
+    assert!(!html.contains("<!--"));
+    assert!(!html.contains("tracking"));
+    assert!(!html.contains("Outlook"));
+    assert!(!html.contains("<![if"));
+    assert!(html.contains("<p>Hello</p><p>World</p>"));
+
+    let html = Html::new(body, false).safe();
+    assert!(html.contains("<!-- tracking: 1234 -->"));
+  }
+
+  #[test]
+  fn image_placeholders() {
+    let body = r#"<p><img src="https://tracker.example/pixel.gif?id=1&amp;u=2"><img src="https://cdn.example/logo.png"><img src="data:image/png;base64,AAAA"></p>"#;
+    let loaded = vec!["https://cdn.example/logo.png".to_string()];
+    let html = Html::new(body, false)
+      .with_image_placeholders(Some("Click to load image"), &loaded)
+      .safe();
+    assert!(html.contains(
+      r#"<a class="mailviewer-image" href="mailviewer-image:https%3A%2F%2Ftracker.example%2Fpixel.gif%3Fid%3D1%26u%3D2""#
+    ));
+    assert!(html.contains(">Click to load image</a>"));
+    assert!(!html.contains(r#"<img src="https://tracker.example"#));
+    assert!(html.contains(r#"<img src="https://cdn.example/logo.png">"#));
+    assert!(html.contains(r#"<img src="data:image/png;base64,AAAA">"#));
+
+    assert!(html.contains(
+      r#"<meta http-equiv="Content-Security-Policy" content="img-src data: https://cdn.example/logo.png">"#
+    ));
+
+    let html = Html::new(body, false).with_image_placeholders(None, &[]).safe();
+    assert!(!html.contains("mailviewer-image"));
+    assert!(!html.contains("Content-Security-Policy"));
+  }
+
+  #[test]
+  fn image_placeholders_other_sources() {
+    let body = r#"<body background="https://tracker.example/bg.gif"><p style="background: url(https://tracker.example/css.gif)"><img src="//tracker.example/pixel.gif"><img src="//cdn.example/logo.png"><img src="data:image/png;base64,AAAA" srcset="https://tracker.example/2x.gif 2x"></p></body>"#;
+    let loaded = vec!["https://cdn.example/logo.png".to_string()];
+    let html = Html::new(body, false)
+      .with_image_placeholders(Some("Click to load image"), &loaded)
+      .safe();
+    assert!(html.contains(r#"href="mailviewer-image:https%3A%2F%2Ftracker.example%2Fpixel.gif""#));
+    assert!(html.contains(r#"<img src="https://cdn.example/logo.png">"#));
+    assert!(!html.contains("srcset"));
+    // left to the policy
+    assert!(html.contains("content=\"img-src data: https://cdn.example/logo.png\""));
+    assert!(html.contains(r#"background="https://tracker.example/bg.gif""#));
+  }
+
+  #[test]
+  fn image_proxy() {
+    let body = r#"<p><img src="https://tracker.example/pixel.gif?id=1&amp;u=2" srcset="https://tracker.example/2x.gif 2x"><img src="data:image/png;base64,AAAA"></p>"#;
+    let html = Html::new(body, false)
+      .with_image_proxy(Some("https://proxy.example/?url=%s"))
+      .safe();
+    assert!(html.contains(
+      r#"src="https://proxy.example/?url=https%3A%2F%2Ftracker.example%2Fpixel.gif%3Fid%3D1%26u%3D2""#
+    ));
+    assert!(!html.contains("srcset"));
+    assert!(html.contains(r#"src="data:image/png;base64,AAAA""#));
+
+    let html = Html::new(body, false).with_image_proxy(Some("")).safe();
+    assert!(html.contains("https://tracker.example/pixel.gif"));
+    assert!(html.contains("srcset"));
+  }
+
+  #[test]
+  fn hidden_text() {
+    let html = Html::new(
+      r#"<body>
+        <span style="display: none !important">Preheader: 50% off everything</span>
+        <div style="color:#FFF; background-color: white">cheap pills</div>
+        <p style="font-size:0px">tiny</p>
+        <div hidden><span>hidden attribute</span></div>
+        <p style="color: red">Visible text</p>
+      </body>"#,
+      false,
+    );
+    assert_eq!(
+      html.hidden_text(),
+      vec![
+        "Preheader: 50% off everything",
+        "cheap pills",
+        "tiny",
+        "hidden attribute"
+      ]
+    );
+  }
+
+  #[test]
+  fn sanitization() {
+    let (_, report) = Html::new(
+      "<html><head><script>document.write(atob('PGgxPkhlbGxvPC9oMT4='))</script></head></html>",
+      false,
+    )
+    .safe_report();
+    assert_eq!(report.removed_elements, 1);
+    assert_eq!(report.text_after, 0);
+    assert!(report.is_heavy());
+
+    let body = "<style>p { color: red }</style><p>A long enough paragraph</p><script>x()</script>";
+    let (_, report) = Html::new(body, false).safe_report();
+    assert_eq!(report.removed_elements, 1);
+    assert_eq!(report.removed_ratio(), 0.0);
+    assert!(!report.is_heavy());
+
+    // structured data and metadata are neither text nor dangerous
+    let body = format!(
+      "<head><meta charset=\"utf-8\"><link rel=\"icon\" href=\"icon.png\">\
+       <script type=\"application/ld+json\">{}</script></head><p>Your order has shipped</p>",
+      r#"{"@type": "Order", "orderNumber": "1234"}"#.repeat(50)
+    );
+    let (_, report) = Html::new(&body, false).safe_report();
+    assert_eq!(report.removed_elements, 1);
+    assert_eq!(report.text_before, report.text_after);
+    assert!(!report.is_heavy());
+
+    let (_, report) = Html::new("<img src=\"cid:logo\">", false).safe_report();
+    assert_eq!(report, Sanitization::default());
+    assert!(!report.is_heavy());
+  }
+
+  #[test]
+  fn security_signals() {
+    let html = Html::new(
+      r#"<body>
+        <a href="https://evil.example/login">https://www.bank.example/login</a>
+        <a href="https://www.bank.example/help">bank.example/help</a>
+        <a href="https://bank.example/">www.bank.example</a>
+        <a href="mailto:john@moon.space">https://moon.space</a>
+        <img src="https://tracker.example/open.gif" width="1" height="1px">
+        <img src="https://cdn.example/logo.png" width="1" height="40">
+        <img src="data:image/gif;base64,R0lGOD" width="1" height="1">
+      </body>"#,
+      false,
+    );
+    assert_eq!(
+      html.deceptive_links(),
+      vec![(
+        "https://www.bank.example/login".to_string(),
+        "https://evil.example/login".to_string()
+      )]
+    );
+    assert_eq!(html.tracking_pixels(), 1);
+    assert_eq!(html.link_hosts(), vec!["evil.example", "bank.example"]);
+  }
+
+  #[test]
+  fn language() {
+    let html = Html::new("<p>Bonjour</p>", false).with_language(Some("fr-FR")).safe();
+    assert!(html.contains(r#"<html lang="fr-FR">"#));
+    let html = Html::new(r#"<html lang="en"><p>Hello</p></html>"#, false)
+      .with_language(Some("fr-FR"))
+      .safe();
+    assert!(html.contains(r#"<html lang="en">"#));
+  }
+
+  #[test]
+  fn background_color() {
+    let html = Html::new(
+      r#"<html><body style="margin: 0; background-color: #F4F1EA !important"><p>Hi</p></body></html>"#,
+      false,
+    );
+    assert_eq!(html.background_color().as_deref(), Some("#f4f1ea"));
+    let html = Html::new(
+      r#"<body><table bgcolor="navy" width="100%"><tr><td>Hi</td></tr></table></body>"#,
+      false,
+    );
+    assert_eq!(html.background_color().as_deref(), Some("navy"));
+    let html = Html::new(
+      r#"<body style="background: url(bg.png) no-repeat rgb(10, 20, 30)">Hi</body>"#,
+      false,
+    );
+    assert_eq!(html.background_color().as_deref(), Some("rgb(10,20,30)"));
+    let html = Html::new(r#"<body style="background-color: red}*{x:y">Hi</body>"#, false);
+    assert_eq!(html.background_color(), None);
+    assert_eq!(Html::new("<p>Hi</p>", false).background_color(), None);
+
+    assert_eq!(Html::foreground_for("#f4f1ea"), Some("#000000"));
+    assert_eq!(Html::foreground_for("#000080"), Some("#ffffff"));
+    assert_eq!(Html::foreground_for("navy"), None);
+  }
+
+  #[test]
+  fn to_markdown() {
+    let html = Html::new(
+      r#"<html><head><title>Hi</title></head><body>
+        <h2>News</h2>
+        <p>See <a href="https://example.com">the <b>site</b></a>, <em>now</em>.</p>
+        <ul>
+          <li>One</li>
+          <li>Two
+            <ol><li>Two.1</li><li>Two.2</li></ol>
+          </li>
+        </ul>
+        <blockquote><p>Quoted</p><p>text</p></blockquote>
+        <span>Plain <font color="red">text</font></span>
+      </body></html>"#,
+      false,
+    );
+    assert_eq!(
+      html.to_markdown(),
+      "## News\n\
+       \n\
+       See [the **site**](https://example.com), *now*.\n\
+       \n\
+       - One\n\
+       - Two\n  \
+       1. Two.1\n  \
+       2. Two.2\n\
+       \n\
+       > Quoted\n\
+       >\n\
+       > text\n\
+       \n\
+       Plain text"
+    );
+  }
+
+  #[test]
+  fn to_text() {
+    let html = Html::new(
+      "<html><head><style>p { color: red; }</style></head><body><p>Hello</p>\n  <p>World</p></body></html>",
+      false,
+    );
+    assert_eq!(html.to_text(), "Hello\nWorld");
+  }
+
+  #[test]
+  fn visited_links() {
+    let mut visited = VisitedLinks::default();
+    assert!(visited.is_empty());
+    assert!(visited.visit("https://example.com"));
+    assert!(!visited.visit("https://example.com/"));
+    assert!(visited.contains("https://example.com/"));
+    assert!(!visited.contains("https://example.org"));
+
+    let html = Html::new(
+      r#"<a href="https://example.com">one</a><a href="https://example.org">two</a>"#,
+      false,
+    )
+    .with_visited(&visited)
+    .safe();
+    assert!(html.contains(r#"<a href="https://example.com" data-visited="">"#));
+    assert!(html.contains(r#"<a href="https://example.org">"#));
+
+    visited.clear();
+    assert!(!visited.contains("https://example.com"));
+  }
+
+  #[test]
+  fn mark_script_escaping() {
+    let script = VisitedLinks::mark_script("https://example.com/?q='a'</script>");
+    assert!(script.contains(r"https://example.com/?q=\u0027a\u0027\u003c/script\u003e"));
+  }
 }
@@ -0,0 +1,300 @@
+/* linkify.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::ops::Range;
+
+use gtk4::glib;
+
+// last word of an english street address, "1600 Amphitheatre Parkway"
+const STREET_SUFFIXES: &[&str] = &[
+  "street", "st", "avenue", "ave", "road", "rd", "boulevard", "blvd", "drive", "dr", "lane", "ln",
+  "way", "parkway", "court", "ct", "place", "pl", "square", "sq",
+];
+// first word of a french one, "12 rue de la Paix"
+const STREET_TYPES: &[&str] = &[
+  "rue", "avenue", "boulevard", "bd", "place", "chemin", "impasse", "allée", "quai", "cours",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkKind {
+  Phone,
+  Address,
+}
+
+/// Phone number or postal address found in a plain text body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+  pub kind: LinkKind,
+  /// byte range in the text
+  pub range: Range<usize>,
+  /// dialable number ("+15551234567") or the address as written
+  pub target: String,
+}
+
+impl Link {
+  pub fn uri(&self) -> String {
+    match self.kind {
+      LinkKind::Phone => format!("tel:{}", self.target),
+      LinkKind::Address => {
+        format!("maps:q={}", glib::Uri::escape_string(&self.target, None, false))
+      }
+    }
+  }
+}
+
+/// Phone numbers and street addresses, sorted by position. Detection is
+/// conservative: order numbers, dates, amounts or IP addresses shouldn't
+/// turn into links, at the cost of missing unusual formats.
+pub fn find_links(text: &str) -> Vec<Link> {
+  let mut links = find_phones(text);
+  for address in find_addresses(text) {
+    if !links.iter().any(|link| overlaps(&link.range, &address.range)) {
+      links.push(address);
+    }
+  }
+  links.sort_by_key(|link| link.range.start);
+  links
+}
+
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+  a.start < b.end && b.start < a.end
+}
+
+fn find_phones(text: &str) -> Vec<Link> {
+  let bytes = text.as_bytes();
+  let mut links = vec![];
+  let mut i = 0;
+  while i < bytes.len() {
+    let c = bytes[i];
+    let starts = (c == b'+' || c == b'(' || c.is_ascii_digit())
+      && (i == 0 || !is_word_byte(bytes[i - 1]));
+    if starts {
+      if let Some((end, link)) = phone_at(text, i) {
+        links.push(link);
+        i = end;
+        continue;
+      }
+    }
+    // skip the rest of the word, "A1234" or "1.5" aren't phone starts
+    match is_word_byte(c) {
+      true => {
+        while i < bytes.len() && is_word_byte(bytes[i]) {
+          i += 1;
+        }
+      }
+      false => i += 1,
+    }
+  }
+  links
+}
+
+fn is_word_byte(c: u8) -> bool {
+  c.is_ascii_alphanumeric() || c == b'.' || c == b'/' || c == b'-' || c == b'_' || c >= 0x80
+}
+
+// "+1 555 123 4567", "(555) 123-4567", "555.123.4567", "+33 1 23 45 67 89"
+fn phone_at(text: &str, start: usize) -> Option<(usize, Link)> {
+  let bytes = text.as_bytes();
+  let international = bytes[start] == b'+';
+  let mut i = start + international as usize;
+  let mut groups: Vec<usize> = vec![];
+  let mut separators: Vec<u8> = vec![];
+  let mut digits = String::new();
+  let mut parenthesis = false;
+  loop {
+    if i < bytes.len() && bytes[i] == b'(' && !parenthesis {
+      parenthesis = true;
+      i += 1;
+    }
+    let group_start = i;
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+      i += 1;
+    }
+    if i == group_start {
+      return None;
+    }
+    groups.push(i - group_start);
+    digits.push_str(&text[group_start..i]);
+    if parenthesis && i < bytes.len() && bytes[i] == b')' {
+      i += 1;
+      if i < bytes.len() && bytes[i] == b' ' {
+        i += 1;
+      }
+      separators.push(b' ');
+      continue;
+    }
+    // a single separator followed by another group of digits
+    match (bytes.get(i), bytes.get(i + 1)) {
+      (Some(&separator), Some(next))
+        if (separator == b' ' || separator == b'-' || separator == b'.')
+          && (next.is_ascii_digit() || *next == b'(') =>
+      {
+        separators.push(separator);
+        i += 1;
+      }
+      _ => break,
+    }
+  }
+  if i < bytes.len() && is_word_byte(bytes[i]) && bytes[i] != b'.' {
+    return None;
+  }
+  let valid = match international {
+    true => (8..=15).contains(&digits.len()) && groups[0] <= 3 && groups.len() >= 2,
+    // unformatted digits are more likely a reference than a number
+    false => digits.len() == 10 && groups.len() >= 3,
+  };
+  // dotted numbers are versions or IP addresses unless shaped like 555.123.4567
+  let dotted = separators.contains(&b'.');
+  if !valid || (dotted && groups != [3, 3, 4]) || groups.iter().any(|&group| group > 4) {
+    return None;
+  }
+  let target = match international {
+    true => format!("+{}", digits),
+    false => digits,
+  };
+  let link = Link { kind: LinkKind::Phone, range: start..i, target };
+  Some((i, link))
+}
+
+fn find_addresses(text: &str) -> Vec<Link> {
+  let mut links = vec![];
+  let mut offset = 0;
+  for line in text.split_inclusive('\n') {
+    let words = words(line);
+    for (n, &(start, word)) in words.iter().enumerate() {
+      if !is_street_number(word) {
+        continue;
+      }
+      if let Some(end) = street_end(&words[n + 1..]) {
+        let address = &line[start..end];
+        links.push(Link {
+          kind: LinkKind::Address,
+          range: offset + start..offset + end,
+          target: address.to_string(),
+        });
+        break;
+      }
+    }
+    offset += line.len();
+  }
+  links
+}
+
+// words with their byte offset, punctuation kept
+fn words(line: &str) -> Vec<(usize, &str)> {
+  let mut words = vec![];
+  let mut start = None;
+  for (i, c) in line.char_indices() {
+    match (c.is_whitespace(), start) {
+      (true, Some(s)) => {
+        words.push((s, &line[s..i]));
+        start = None;
+      }
+      (false, None) => start = Some(i),
+      _ => (),
+    }
+  }
+  if let Some(s) = start {
+    words.push((s, &line[s..]));
+  }
+  words
+}
+
+// "12", "221B", "12," (french style)
+fn is_street_number(word: &str) -> bool {
+  let word = word.trim_end_matches(',');
+  let digits = word.chars().take_while(|c| c.is_ascii_digit()).count();
+  let rest = &word[digits..];
+  (1..=5).contains(&digits) && rest.chars().count() <= 1 && rest.chars().all(char::is_alphabetic)
+}
+
+// end of "Baker Street" or "rue de la Paix" following the number
+fn street_end(words: &[(usize, &str)]) -> Option<usize> {
+  let (_, first) = words.first()?;
+  if STREET_TYPES.contains(&first.to_lowercase().as_str()) {
+    // up to the end of the line or the first comma, at most 6 words
+    let mut end = None;
+    for &(start, word) in words.iter().skip(1).take(5) {
+      let trimmed = word.trim_end_matches([',', '.', ';']);
+      end = Some(start + trimmed.len());
+      if trimmed.len() != word.len() {
+        break;
+      }
+    }
+    return end.filter(|_| words.len() > 1);
+  }
+  // capitalized words up to a street suffix, at most 5 words
+  for &(start, word) in words.iter().take(5) {
+    let trimmed = word.trim_end_matches([',', '.', ';']);
+    if !trimmed.starts_with(|c: char| c.is_uppercase()) {
+      return None;
+    }
+    if start > words[0].0 && STREET_SUFFIXES.contains(&trimmed.to_lowercase().as_str()) {
+      return Some(start + trimmed.len());
+    }
+    if trimmed.len() != word.len() {
+      return None;
+    }
+  }
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn phone() {
+    let text = "Call me at +1 555 123 4567 tomorrow.";
+    let links = find_links(text);
+    assert_eq!(links.len(), 1);
+    assert_eq!(links[0].kind, LinkKind::Phone);
+    assert_eq!(links[0].range, 11..26);
+    assert_eq!(&text[links[0].range.clone()], "+1 555 123 4567");
+    assert_eq!(links[0].uri(), "tel:+15551234567");
+
+    let text = "Office: (555) 123-4567, fax 555.123.4568, Paris +33 1 23 45 67 89.";
+    let targets: Vec<String> = find_links(text).into_iter().map(|link| link.target).collect();
+    assert_eq!(targets, ["5551234567", "5551234568", "+33123456789"]);
+  }
+
+  #[test]
+  fn not_phone() {
+    for text in [
+      "Order 1234567890 shipped",
+      "On 2024-10-21 at 10:00",
+      "Server 192.168.100.200 is down",
+      "Version 1.2.3 costs 1 234 euros",
+      "Ref A-555-123-4567",
+      "ISBN 978-3-16-148410-0",
+    ] {
+      assert_eq!(find_links(text), vec![], "{}", text);
+    }
+  }
+
+  #[test]
+  fn address() {
+    let text = "Meet me at 221B Baker Street, London.\nOr 12 rue de la Paix, Paris\n";
+    let links = find_links(text);
+    let addresses: Vec<&str> = links.iter().map(|link| &text[link.range.clone()]).collect();
+    assert_eq!(addresses, ["221B Baker Street", "12 rue de la Paix"]);
+    assert_eq!(links[0].kind, LinkKind::Address);
+    assert_eq!(find_links("I have 3 Apples and 2 Pears"), vec![]);
+  }
+}
@@ -17,42 +17,160 @@
  *
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::io::{self, Read, Write};
 use std::path::Path;
 
+use gtk4::glib;
+
 use crate::config::VERSION;
+use crate::html::Html;
+use crate::message::alternative::Alternative;
+use crate::message::archive;
 use crate::message::attachment::Attachment;
+use crate::message::authresults::AuthResults;
+use crate::message::dsn::DeliveryStatus;
+use crate::message::eml;
+use crate::message::folder::Folder;
+use crate::message::mbox::{Mailbox, Mbox};
 use crate::message::message::{Message, MessageParser};
+use crate::message::mimepart::MimePart;
+use crate::message::partial::{self, Fragment};
+use crate::message::search::SearchMatch;
+use crate::message::security::Finding;
+use crate::message::value::Value;
+use crate::message::zip;
+
+/// Size of the reads of open_message(), the progress is reported after each
+const OPEN_CHUNK_SIZE: usize = 1024 * 1024;
 
 pub struct MailService {
   parser: RefCell<Option<MessageParser>>,
+  /// mbox file or folder of message files, when several messages were opened
+  mailbox: RefCell<Option<Box<dyn Mailbox>>>,
+  selected: Cell<usize>,
   full_path: RefCell<Option<String>>,
   show_file_name: RefCell<bool>,
   signal_title_changed: RefCell<Option<Box<dyn Fn(&Self, &str) + 'static>>>,
+  signal_open_progress: RefCell<Option<Box<dyn Fn(&Self, u64, u64) + 'static>>>,
 }
 
 impl MailService {
   pub fn new() -> Self {
     Self {
       parser: RefCell::new(None),
+      mailbox: RefCell::new(None),
+      selected: Cell::new(0),
       full_path: RefCell::new(None),
       show_file_name: RefCell::new(true),
       signal_title_changed: RefCell::new(None),
+      signal_open_progress: RefCell::new(None),
     }
   }
 
   pub fn open_message(&self, fullpath: &str) -> Result<(), Box<dyn std::error::Error>> {
-    if Path::new(fullpath).exists() == false {
-      return Err(format!("File not found : {}", fullpath).into());
+    let file = read_message_file(fullpath, |read, total| self.open_progress(read, total))
+      .map_err(|e| e.to_string())?;
+    self.open_read(fullpath, file)
+  }
+
+  /// Displays what read_message_file() read from `fullpath`
+  pub fn open_read(
+    &self,
+    fullpath: &str,
+    file: MessageFile,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let fullpath = &local_path(fullpath);
+    match file {
+      MessageFile::Folder(folder) => {
+        // an unparseable file doesn't keep the next ones from being read
+        let (index, parser) = (0..folder.count())
+          .find_map(|index| match folder.parse(index) {
+            Ok(parser) => Some((index, parser)),
+            Err(e) => {
+              log::warn!("open_read() => skipped {:?} : {}", folder.path(index), e);
+              None
+            }
+          })
+          .ok_or("No readable message in the folder")?;
+        self.mailbox.borrow_mut().replace(Box::new(folder));
+        self.selected.set(index);
+        self.parser.borrow_mut().replace(parser);
+      }
+      MessageFile::Mbox(mbox) => {
+        self.mailbox.borrow_mut().replace(Box::new(mbox));
+        self.select_message(0)?;
+      }
+      MessageFile::Message(data) => {
+        let parser = MessageParser::from_bytes(&data)?;
+        self.mailbox.borrow_mut().take();
+        self.selected.set(0);
+        self.parser.borrow_mut().replace(parser);
+      }
     }
     self.full_path.borrow_mut().replace(fullpath.to_string());
-    let mut parser = MessageParser::new(fullpath);
-    parser.parse()?;
+    self.update_title();
+    Ok(())
+  }
+
+  /// Forgets the current message and its file
+  pub fn clear(&self) {
+    self.parser.borrow_mut().take();
+    self.mailbox.borrow_mut().take();
+    self.selected.set(0);
+    self.full_path.borrow_mut().take();
+    self.update_title();
+  }
+
+  /// Number of messages in the opened file, more than one for a mailbox
+  pub fn message_count(&self) -> usize {
+    match self.mailbox.borrow().as_ref() {
+      Some(mailbox) => mailbox.count(),
+      None => self.parser.borrow().is_some() as usize,
+    }
+  }
+
+  pub fn selected_message(&self) -> usize {
+    self.selected.get()
+  }
+
+  /// Parses the message at `index` of the opened mailbox
+  pub fn select_message(&self, index: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let parser = match self.mailbox.borrow().as_ref() {
+      Some(mailbox) => mailbox.parse(index)?,
+      None => return Err("No mailbox opened".into()),
+    };
+    self.selected.set(index);
+    self.parser.borrow_mut().replace(parser);
+    Ok(())
+  }
+
+  pub fn open_from_reader<R: Read>(&self, mut reader: R) -> Result<(), Box<dyn std::error::Error>> {
+    let mut data: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let parser = MessageParser::from_bytes(&data)?;
+    self.full_path.borrow_mut().take();
+    self.mailbox.borrow_mut().take();
+    self.selected.set(0);
     self.parser.borrow_mut().replace(parser);
     self.update_title();
     Ok(())
   }
 
+  pub fn reload(&self) -> Result<(), Box<dyn std::error::Error>> {
+    let selected = self.selected.get();
+    match self.get_fullpath() {
+      Some(fullpath) => {
+        self.open_message(&fullpath)?;
+        if selected > 0 && selected < self.message_count() {
+          self.select_message(selected)?;
+        }
+        Ok(())
+      }
+      None => Err("No file to reload".into()),
+    }
+  }
+
   pub fn from(&self) -> String {
     if let Some(parser) = self.parser.borrow().as_ref() {
       return parser.from();
@@ -88,6 +206,14 @@ impl MailService {
     None
   }
 
+  /// body_text() with format=flowed soft line breaks joined
+  pub fn body_text_reflowed(&self) -> Option<String> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.body_text_reflowed();
+    }
+    None
+  }
+
   pub fn body_html(&self) -> Option<String> {
     if let Some(parser) = self.parser.borrow().as_ref() {
       return parser.body_html();
@@ -95,6 +221,178 @@ impl MailService {
     None
   }
 
+  /// Sanitizer for an HTML body of the current message
+  pub fn html(&self, body: &str, force_css: bool) -> Html {
+    Html::new(body, force_css)
+      .with_language(self.language().as_deref())
+      .with_stylesheets(&self.stylesheets())
+  }
+
+  /// Sanitized HTML body, None when the message has no HTML part
+  #[allow(dead_code)]
+  pub fn safe_html(&self, force_css: bool) -> Option<String> {
+    let html = self.body_html()?;
+    Some(self.html(&html, force_css).safe())
+  }
+
+  pub fn is_html_only(&self) -> bool {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.is_html_only();
+    }
+    false
+  }
+
+  pub fn is_text_only(&self) -> bool {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.is_text_only();
+    }
+    false
+  }
+
+  pub fn has_both(&self) -> bool {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.has_both();
+    }
+    false
+  }
+
+  pub fn search(&self, query: &str, include_attachments: bool) -> Vec<SearchMatch> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.search(query, include_attachments);
+    }
+    vec![]
+  }
+
+  pub fn headers(&self) -> Vec<(String, String)> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.headers();
+    }
+    vec![]
+  }
+
+  pub fn language(&self) -> Option<String> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.language();
+    }
+    None
+  }
+
+  /// Sender header when it differs from From, shown as "via ..."
+  pub fn sent_via(&self) -> Option<String> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.sent_via();
+    }
+    None
+  }
+
+  pub fn organization(&self) -> Option<String> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.organization();
+    }
+    None
+  }
+
+  pub fn charset(&self) -> Option<String> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.charset();
+    }
+    None
+  }
+
+  pub fn raw_part(&self, index: usize) -> Option<Vec<u8>> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.raw_part(index);
+    }
+    None
+  }
+
+  pub fn mime_tree(&self) -> MimePart {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.mime_tree();
+    }
+    MimePart::default()
+  }
+
+  pub fn is_truncated(&self) -> bool {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.is_truncated();
+    }
+    false
+  }
+
+  pub fn stylesheets(&self) -> Vec<(String, String)> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.stylesheets();
+    }
+    vec![]
+  }
+
+  pub fn is_bulk(&self) -> bool {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.is_bulk();
+    }
+    false
+  }
+
+  pub fn warnings(&self) -> Vec<String> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.warnings();
+    }
+    vec![]
+  }
+
+  pub fn auth_results(&self) -> Vec<AuthResults> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.auth_results();
+    }
+    vec![]
+  }
+
+  /// The message as .eml bytes, None when none is opened
+  pub fn to_eml(&self) -> Option<io::Result<Vec<u8>>> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return Some(parser.to_eml());
+    }
+    None
+  }
+
+  /// A new message with the displayed one attached (message/rfc822)
+  pub fn forward_eml(&self) -> Option<io::Result<Vec<u8>>> {
+    self
+      .to_eml()
+      .map(|original| Ok(eml::forward(&self.subject(), &original?)))
+  }
+
+  /// Every security signal of the message (authentication, spoofing, links,
+  /// tracking, attachments), most severe first.
+  pub fn security_findings(&self) -> Vec<Finding> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.security_findings();
+    }
+    vec![]
+  }
+
+  pub fn to_value(&self) -> Value {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.to_value();
+    }
+    Value::Null
+  }
+
+  pub fn delivery_status(&self) -> Vec<DeliveryStatus> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.delivery_status();
+    }
+    vec![]
+  }
+
+  pub fn alternatives(&self) -> Vec<Alternative> {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      return parser.alternatives();
+    }
+    vec![]
+  }
+
   pub fn attachments(&self) -> Vec<Attachment> {
     if let Some(parser) = self.parser.borrow().as_ref() {
       return parser.attachments().clone();
@@ -102,6 +400,43 @@ impl MailService {
     vec![]
   }
 
+  /// Calls `f` on every attachment without cloning them. The message is
+  /// borrowed meanwhile, `f` must not open another one.
+  pub fn for_each_attachment<F: FnMut(&Attachment)>(&self, mut f: F) {
+    if let Some(parser) = self.parser.borrow().as_ref() {
+      parser.attachments_ref().iter().for_each(|attachment| f(attachment));
+    }
+  }
+
+  /// Writes all the attachments as a zip archive to `writer`
+  pub fn write_attachments_zip<W: Write>(&self, writer: W) -> io::Result<W> {
+    match self.parser.borrow().as_ref() {
+      Some(parser) => zip::write(writer, parser.attachments_ref()),
+      None => zip::write(writer, &[]),
+    }
+  }
+
+  /// Writes the plain text body (extracted from the HTML when there is none)
+  /// to a .txt file of the temporary folder, removed on exit like the
+  /// opened attachments.
+  pub fn write_body_to_tmp(&self) -> Result<String, Box<dyn std::error::Error>> {
+    let text = match (self.body_text(), self.body_html()) {
+      (Some(text), _) => text,
+      (None, Some(html)) => Html::new(&html, false).to_text(),
+      (None, None) => return Err("The message has no body".into()),
+    };
+    let subject: String = self.subject().trim().chars().take(100).collect();
+    let body = Attachment {
+      filename: format!("{}.txt", if subject.is_empty() { "message" } else { &subject }),
+      content_id: "none".to_string(),
+      body: text.into_bytes(),
+      mime_type: Some("text/plain".to_string()),
+      description: None,
+      encoded: None,
+    };
+    body.write_to_tmp()
+  }
+
   pub fn set_show_file_name(&self, show_file_name: bool) {
     log::debug!("set_show_file_name({})", show_file_name);
     self.show_file_name.replace(show_file_name);
@@ -116,12 +451,22 @@ impl MailService {
     self.signal_title_changed.borrow_mut().replace(Box::new(f));
   }
 
+  /// Called while open_message() reads the file with the bytes read so far
+  /// and the file size.
+  pub fn connect_open_progress<F: Fn(&Self, u64, u64) + 'static>(&self, f: F) {
+    self.signal_open_progress.borrow_mut().replace(Box::new(f));
+  }
+
+  fn open_progress(&self, read: u64, total: u64) {
+    if let Some(callback) = self.signal_open_progress.borrow().as_ref() {
+      callback(self, read, total);
+    }
+  }
+
   fn update_title(&self) {
     if let Some(callback) = self.signal_title_changed.borrow().as_ref() {
-      if let Some(fullpath) = self.full_path.borrow().as_ref() {
-        let title = self.get_title(fullpath);
-        callback(self, &title);
-      }
+      let title = self.get_title(self.full_path.borrow().as_deref().unwrap_or_default());
+      callback(self, &title);
     }
   }
 
@@ -135,6 +480,97 @@ impl MailService {
   }
 }
 
+/// Path of a file given as a path or as a file:// URI (launchers, drag and
+/// drop), percent-decoded. Other strings are returned as is.
+pub fn local_path(file: &str) -> String {
+  if !file.starts_with("file://") {
+    return file.to_string();
+  }
+  match glib::filename_from_uri(file) {
+    Ok((path, _)) => path.to_string_lossy().to_string(),
+    Err(e) => {
+      log::warn!("local_path({}) => {}", file, e);
+      file.to_string()
+    }
+  }
+}
+
+/// What read_message_file() found at a path
+pub enum MessageFile {
+  Folder(Folder),
+  Mbox(Mbox),
+  Message(Vec<u8>),
+}
+
+/// The reading part of open_message(), without the parsing: it can run on
+/// another thread, calling `progress(read, total)` as it goes.
+pub fn read_message_file<F: FnMut(u64, u64)>(
+  fullpath: &str,
+  progress: F,
+) -> Result<MessageFile, Box<dyn std::error::Error + Send + Sync>> {
+  let fullpath = &local_path(fullpath);
+  // a message of a zip archive ("archive.zip!inner/message.eml"), read
+  // without extracting it
+  if let Some((zip, entry)) = archive::split_zip_path(fullpath) {
+    if Path::new(zip).exists() == false {
+      return Err(format!("File not found : {}", zip).into());
+    }
+    let data = archive::zip_entry(&std::fs::read(zip)?, entry).map_err(|e| e.to_string())?;
+    return Ok(MessageFile::Message(data));
+  }
+  if Path::new(fullpath).exists() == false {
+    return Err(format!("File not found : {}", fullpath).into());
+  }
+  if Path::new(fullpath).is_dir() {
+    let folder = Folder::open(Path::new(fullpath)).map_err(|e| e.to_string())?;
+    if folder.count() == 0 {
+      return Err("No message file in the folder".into());
+    }
+    return Ok(MessageFile::Folder(folder));
+  }
+  let mut head = [0u8; 5];
+  let read = std::fs::File::open(fullpath)?.read(&mut head)?;
+  if Mbox::looks_like_mbox(&head[..read]) {
+    // large archives: index now, parse on selection
+    let mbox =
+      Mbox::open_with_progress(Path::new(fullpath), progress).map_err(|e| e.to_string())?;
+    if mbox.count() == 0 {
+      return Err("Empty mailbox".into());
+    }
+    return Ok(MessageFile::Mbox(mbox));
+  }
+  let size = std::fs::metadata(fullpath)?.len();
+  let data = read_chunked(std::fs::File::open(fullpath)?, size, OPEN_CHUNK_SIZE, progress)?;
+  let data = match Fragment::parse(&data) {
+    // message/partial, rebuilt from the fragments next to it
+    Some(fragment) => {
+      partial::reassemble_from_dir(Path::new(fullpath), &fragment).map_err(|e| e.to_string())?
+    }
+    None => data,
+  };
+  Ok(MessageFile::Message(data))
+}
+
+/// Reads all of `reader`, `size` bytes being expected, `chunk_size` bytes at
+/// a time, calling `progress(read, size)` after each chunk. The whole content
+/// is returned in memory, `size` only reserves the first allocation.
+pub fn read_chunked<R: Read, F: FnMut(u64, u64)>(
+  mut reader: R,
+  size: u64,
+  chunk_size: usize,
+  mut progress: F,
+) -> io::Result<Vec<u8>> {
+  let mut data: Vec<u8> = Vec::with_capacity(size as usize);
+  loop {
+    let read = (&mut reader).take(chunk_size as u64).read_to_end(&mut data)?;
+    if read == 0 {
+      break;
+    }
+    progress(data.len() as u64, size.max(data.len() as u64));
+  }
+  Ok(data)
+}
+
 impl std::fmt::Debug for MailService {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.debug_struct("MailService")
@@ -148,7 +584,10 @@ impl std::fmt::Debug for MailService {
 mod tests {
   use std::rc::Rc;
 
-  use crate::mailservice::MailService;
+  use gtk4::glib;
+
+  use crate::mailservice::{local_path, read_chunked, read_message_file, MailService, MessageFile};
+  use crate::message::message::{Message, MessageParser};
 
   #[test]
   fn new_mail_service() {
@@ -185,6 +624,148 @@ mod tests {
     );
   }
 
+  #[test]
+  fn open_from_reader() {
+    let service = MailService::new();
+    let data = std::fs::read("sample.eml").unwrap();
+
+    assert!(service.open_from_reader(data.as_slice()).is_ok());
+    assert!(service.get_fullpath().is_none());
+    assert_eq!(service.from(), "John Doe <john@moon.space>");
+    assert_eq!(service.subject(), "Lorem ipsum");
+  }
+
+  #[test]
+  fn reload() {
+    let service = MailService::new();
+
+    assert!(service.reload().is_err());
+    service.open_message("sample.eml").unwrap();
+    assert!(service.reload().is_ok());
+    assert_eq!(service.get_fullpath().unwrap(), "sample.eml");
+    assert_eq!(service.from(), "John Doe <john@moon.space>");
+    assert_eq!(service.subject(), "Lorem ipsum");
+    assert_eq!(service.attachments().len(), 1);
+  }
+
+  #[test]
+  fn attachments_zip() {
+    let service = MailService::new();
+
+    service.open_message("sample.eml").unwrap();
+    let zip = service.write_attachments_zip(Vec::new()).unwrap();
+    assert_eq!(&zip[..4], b"PK\x03\x04");
+    let entry = b"Deus_Gnome.png";
+    assert_eq!(zip.windows(entry.len()).filter(|w| w == entry).count(), 2);
+  }
+
+  #[test]
+  fn body_to_tmp() {
+    let service = MailService::new();
+
+    assert!(service.write_body_to_tmp().is_err());
+    service.open_message("sample.eml").unwrap();
+    let path = service.write_body_to_tmp().unwrap();
+    assert!(path.ends_with("/Lorem ipsum.txt"));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), service.body_text().unwrap());
+  }
+
+  #[test]
+  fn safe_html() {
+    let service = MailService::new();
+
+    assert!(service.safe_html(false).is_none());
+    service.open_message("sample.eml").unwrap();
+    let html = service.safe_html(false).unwrap();
+    assert!(html.contains("Hello Lucas,"));
+    assert!(html.to_lowercase().contains("<script") == false);
+  }
+
+  #[test]
+  fn clear() {
+    let service = MailService::new();
+
+    service.open_message("tests/sample.mbox").unwrap();
+    service.select_message(1).unwrap();
+    service.clear();
+    assert!(service.parser.borrow().is_none());
+    assert!(service.get_fullpath().is_none());
+    assert_eq!(service.message_count(), 0);
+    assert_eq!(service.selected_message(), 0);
+    assert_eq!(service.subject(), "");
+    assert!(service.body_html().is_none());
+    assert!(service.safe_html(false).is_none());
+    assert!(service.reload().is_err());
+  }
+
+  #[test]
+  fn open_mbox() {
+    let service = MailService::new();
+
+    assert_eq!(service.message_count(), 0);
+    assert!(service.select_message(0).is_err());
+    service.open_message("tests/sample.mbox").unwrap();
+    assert_eq!(service.message_count(), 3);
+    assert_eq!(service.subject(), "First message");
+    service.select_message(2).unwrap();
+    assert_eq!(service.selected_message(), 2);
+    assert_eq!(service.subject(), "Third message");
+    assert!(service.select_message(3).is_err());
+    assert_eq!(service.subject(), "Third message");
+    service.reload().unwrap();
+    assert_eq!(service.subject(), "Third message");
+
+    service.open_message("sample.eml").unwrap();
+    assert_eq!(service.message_count(), 1);
+    assert!(service.select_message(1).is_err());
+  }
+
+  #[test]
+  fn open_folder() {
+    let service = MailService::new();
+    service.open_message("tests/folder").unwrap();
+    assert_eq!(service.message_count(), 3);
+    assert_eq!(service.subject(), "Quarterly figures");
+    service.select_message(2).unwrap();
+    assert_eq!(service.subject(), "Lorem ipsum");
+    assert_eq!(service.get_fullpath().as_deref(), Some("tests/folder"));
+    assert!(service.open_message("src/css").is_err());
+
+    // the unparseable first file is skipped
+    let dir = std::env::temp_dir().join(format!("mailviewer-folder-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.eml"), b"").unwrap();
+    std::fs::copy("sample.eml", dir.join("b.eml")).unwrap();
+    let opened = service.open_message(dir.to_str().unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+    opened.unwrap();
+    assert_eq!(service.message_count(), 2);
+    assert_eq!(service.selected_message(), 1);
+    assert_eq!(service.subject(), "Lorem ipsum");
+  }
+
+  #[test]
+  fn open_partial() {
+    let service = MailService::new();
+    service.open_message("tests/partial/part2.eml").unwrap();
+    assert_eq!(service.subject(), "Holiday pictures");
+    assert_eq!(service.from(), "John Doe <john@moon.space>");
+    assert_eq!(
+      service.body_text().unwrap().trim_end(),
+      "Hello Lucas,\n\nThe first half of the body, then the second half."
+    );
+  }
+
+  #[test]
+  fn reload_without_path() {
+    let service = MailService::new();
+    let data = std::fs::read("sample.eml").unwrap();
+
+    service.open_from_reader(data.as_slice()).unwrap();
+    assert!(service.reload().is_err());
+    assert_eq!(service.subject(), "Lorem ipsum");
+  }
+
   #[test]
   fn get_text() {
     let service = MailService::new();
@@ -214,6 +795,49 @@ mod tests {
     assert_eq!(attachments[0].filename, "Deus_Gnome.png");
   }
 
+  #[test]
+  fn for_each_attachment() {
+    let service = MailService::new();
+    let mut total = 0;
+    service.for_each_attachment(|_| total += 1);
+    assert_eq!(total, 0);
+
+    service.open_message("sample.eml").unwrap();
+    let mut names: Vec<String> = vec![];
+    service.for_each_attachment(|attachment| names.push(attachment.filename.clone()));
+    assert_eq!(names, vec!["Deus_Gnome.png"]);
+  }
+
+  #[test]
+  fn open_zip_entry() {
+    let service = MailService::new();
+    service.open_message("tests/messages.zip!mail/organization.eml").unwrap();
+    assert_eq!(service.subject(), "Quarterly figures");
+    assert_eq!(
+      service.get_fullpath().as_deref(),
+      Some("tests/messages.zip!mail/organization.eml")
+    );
+    assert!(service.open_message("tests/messages.zip!mail/missing.eml").is_err());
+    assert!(service.open_message("tests/missing.zip!mail/organization.eml").is_err());
+  }
+
+  #[test]
+  fn open_unicode_path() {
+    let file = "tests/R\u{e9}union d\u{2019}\u{e9}t\u{e9} 2024.eml";
+    let service = MailService::new();
+    service.open_message(file).unwrap();
+    assert_eq!(service.subject(), "R\u{e9}union d\u{2019}\u{e9}t\u{e9}");
+
+    // as handed out by launchers and drag and drop
+    let path = std::fs::canonicalize(file).unwrap();
+    let uri = glib::filename_to_uri(&path, None).unwrap();
+    assert!(uri.contains("%20"));
+    let service = MailService::new();
+    service.open_message(&uri).unwrap();
+    assert_eq!(service.get_fullpath().as_deref(), path.to_str());
+    assert_eq!(local_path("sample.eml"), "sample.eml");
+  }
+
   #[test]
   fn update_title_with_show_file_name() {
     let service = MailService::new();
@@ -232,6 +856,60 @@ mod tests {
     );
   }
 
+  #[test]
+  fn chunked_reading() {
+    let data = std::fs::read("sample.eml").unwrap();
+    let mut reports: Vec<(u64, u64)> = vec![];
+    let read = read_chunked(data.as_slice(), data.len() as u64, 1024, |read, size| {
+      reports.push((read, size))
+    })
+    .unwrap();
+
+    assert_eq!(read, data);
+    assert_eq!(reports.len(), data.len().div_ceil(1024));
+    assert_eq!(reports[0], (1024, data.len() as u64));
+    assert_eq!(reports.last(), Some(&(data.len() as u64, data.len() as u64)));
+    assert_eq!(MessageParser::from_bytes(&read).unwrap().subject(), "Lorem ipsum");
+  }
+
+  #[test]
+  fn connect_open_progress() {
+    let service = MailService::new();
+    let reports = Rc::new(std::cell::RefCell::new(vec![]));
+    let reports_clone = Rc::clone(&reports);
+    service.connect_open_progress(move |_, read, total| {
+      reports_clone.borrow_mut().push((read, total));
+    });
+    service.open_message("sample.eml").unwrap();
+
+    let size = std::fs::metadata("sample.eml").unwrap().len();
+    assert_eq!(reports.borrow().last(), Some(&(size, size)));
+    assert_eq!(service.subject(), "Lorem ipsum");
+
+    reports.borrow_mut().clear();
+    service.open_message("tests/sample.mbox").unwrap();
+    assert!(!reports.borrow().is_empty());
+  }
+
+  #[test]
+  fn read_then_open() {
+    let mut reports = 0;
+    let file = read_message_file("tests/sample.mbox", |_, _| reports += 1).unwrap();
+    assert!(matches!(file, MessageFile::Mbox(_)));
+    assert!(reports > 0);
+    assert!(matches!(read_message_file("tests/folder", |_, _| ()), Ok(MessageFile::Folder(_))));
+    assert!(read_message_file("tests/missing.eml", |_, _| ()).is_err());
+
+    let service = MailService::new();
+    let file = read_message_file("sample.eml", |_, _| ()).unwrap();
+    service.open_read("sample.eml", file).unwrap();
+    assert_eq!(service.subject(), "Lorem ipsum");
+    // not a message, the opened one is kept
+    let file = MessageFile::Message(vec![]);
+    assert!(service.open_read("empty.eml", file).is_err());
+    assert_eq!(service.get_fullpath().as_deref(), Some("sample.eml"));
+  }
+
   #[test]
   fn connect_title_changed() {
     let service = MailService::new();
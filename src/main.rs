@@ -18,11 +18,15 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 mod application;
+mod avatar;
+mod cli;
 mod config;
 mod gmimeinit;
 mod html;
+mod linkify;
 mod mailservice;
 mod message;
+mod quote;
 mod window;
 
 use config::{APP_ID, GETTEXT_PACKAGE, LOCALEDIR, PKGDATADIR};
@@ -36,6 +40,10 @@ use self::window::MailViewerWindow;
 
 fn main() -> glib::ExitCode {
   env_logger::init();
+  if let Some(code) = cli::run(&std::env::args_os().collect::<Vec<_>>()) {
+    MessageParser::cleanup();
+    return code;
+  }
   // Set up gettext translations
   bindtextdomain(GETTEXT_PACKAGE, LOCALEDIR).expect("Unable to bind the text domain");
   bind_textdomain_codeset(GETTEXT_PACKAGE, "UTF-8")
@@ -0,0 +1,116 @@
+/* alternative.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/// One representation of the body (text/plain, text/html, AMP, ...).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternative {
+  pub content_type: String,
+  pub body: String,
+}
+
+impl Alternative {
+  pub fn new(content_type: &str, body: &str) -> Self {
+    Self {
+      content_type: content_type.to_lowercase(),
+      body: body.to_string(),
+    }
+  }
+
+  pub fn is_text(&self) -> bool {
+    self.content_type == "text/plain"
+  }
+
+  /// AMP for Email (text/x-amp-html) relies on scripts, never pick it by default.
+  pub fn is_safe(&self) -> bool {
+    !self.content_type.contains("amp")
+  }
+}
+
+/// Index of the representation rendered by default: the richest safe one,
+/// i.e. the last HTML part (RFC 2046 orders alternatives by fidelity),
+/// falling back to the last safe part of any kind.
+pub fn preferred(alternatives: &[Alternative]) -> Option<usize> {
+  let safe = || alternatives.iter().enumerate().rev().filter(|(_, a)| a.is_safe());
+  safe()
+    .find(|(_, a)| a.content_type == "text/html")
+    .or_else(|| safe().next())
+    .map(|(i, _)| i)
+}
+
+/// Content types of the "html-types" setting, e.g. "text/html, text/x-amp-html"
+pub fn allowlist(setting: &str) -> Vec<String> {
+  setting
+    .split([',', ' ', ';'])
+    .map(|t| t.trim().to_lowercase())
+    .filter(|t| !t.is_empty())
+    .collect()
+}
+
+pub fn is_allowed(content_type: &str, allowlist: &[String]) -> bool {
+  allowlist.iter().any(|t| t.eq_ignore_ascii_case(content_type))
+}
+
+/// Representations that may be displayed: plain text always, the others
+/// (rendered by the WebView) only when their type is in the allowlist.
+pub fn allowed(alternatives: Vec<Alternative>, allowlist: &[String]) -> Vec<Alternative> {
+  alternatives
+    .into_iter()
+    .filter(|a| a.is_text() || is_allowed(&a.content_type, allowlist))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn preferred_alternative() {
+    let alternatives = vec![
+      Alternative::new("text/plain", "plain"),
+      Alternative::new("text/html", "<p>html</p>"),
+      Alternative::new("text/x-amp-html", "<html amp4email></html>"),
+    ];
+    assert_eq!(preferred(&alternatives), Some(1));
+    assert_eq!(preferred(&alternatives[..1]), Some(0));
+    assert_eq!(preferred(&alternatives[2..]), None);
+    assert_eq!(preferred(&[]), None);
+  }
+
+  #[test]
+  fn allowlisted_alternatives() {
+    let alternatives = vec![
+      Alternative::new("text/plain", "plain"),
+      Alternative::new("text/html", "<p>html</p>"),
+      Alternative::new("text/x-amp-html", "<html amp4email></html>"),
+    ];
+    let list = allowlist("text/html, text/x-amp-html");
+    assert_eq!(list, vec!["text/html", "text/x-amp-html"]);
+    assert_eq!(allowed(alternatives.clone(), &list).len(), 3);
+
+    // text/html excluded: the text alternative is shown instead
+    let strict = allowed(alternatives.clone(), &allowlist("text/x-amp-html"));
+    assert_eq!(strict.len(), 2);
+    assert!(!is_allowed("text/html", &allowlist("text/x-amp-html")));
+    let shown = allowed(alternatives, &allowlist(""));
+    assert_eq!(shown.len(), 1);
+    let text = preferred(&shown).map(|i| shown[i].content_type.as_str());
+    assert_eq!(text, Some("text/plain"));
+  }
+}
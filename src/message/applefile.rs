@@ -0,0 +1,116 @@
+/* applefile.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+const APPLE_SINGLE: u32 = 0x0005_1600;
+const APPLE_DOUBLE: u32 = 0x0005_1607;
+const DATA_FORK: u32 = 1;
+const REAL_NAME: u32 = 3;
+
+/// An application/applefile part: AppleSingle (the whole file) or the
+/// AppleDouble header of a multipart/appledouble, which only holds the
+/// resource fork and Finder info, the data fork is the next part.
+#[derive(Debug, Default, PartialEq)]
+pub struct AppleFile {
+  pub real_name: Option<String>,
+  pub data_fork: Option<Vec<u8>>,
+}
+
+impl AppleFile {
+  pub fn parse(data: &[u8]) -> Option<Self> {
+    let u32_at = |offset: usize| -> Option<u32> {
+      let bytes = data.get(offset..offset + 4)?;
+      Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    };
+    let magic = u32_at(0)?;
+    if magic != APPLE_SINGLE && magic != APPLE_DOUBLE {
+      return None;
+    }
+    // magic, version, 16 bytes of filler then the entries count
+    let count = u16::from_be_bytes(data.get(24..26)?.try_into().ok()?) as usize;
+    let mut file = AppleFile::default();
+    for i in 0..count {
+      let entry = 26 + i * 12;
+      let (id, offset, length) = (u32_at(entry)?, u32_at(entry + 4)?, u32_at(entry + 8)?);
+      let start = offset as usize;
+      let Some(content) = start
+        .checked_add(length as usize)
+        .and_then(|end| data.get(start..end))
+      else {
+        log::warn!("AppleFile entry {} out of bounds", id);
+        continue;
+      };
+      match id {
+        DATA_FORK if magic == APPLE_SINGLE => file.data_fork = Some(content.to_vec()),
+        // Mac Roman, ASCII in practice
+        REAL_NAME => file.real_name = Some(String::from_utf8_lossy(content).to_string()),
+        _ => {}
+      }
+    }
+    Some(file)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn applefile(magic: u32, entries: &[(u32, &[u8])]) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![];
+    data.extend(magic.to_be_bytes());
+    data.extend(0x0002_0000u32.to_be_bytes());
+    data.extend([0u8; 16]);
+    data.extend((entries.len() as u16).to_be_bytes());
+    let mut offset = 26 + entries.len() * 12;
+    for (id, content) in entries {
+      data.extend(id.to_be_bytes());
+      data.extend((offset as u32).to_be_bytes());
+      data.extend((content.len() as u32).to_be_bytes());
+      offset += content.len();
+    }
+    for (_, content) in entries {
+      data.extend(*content);
+    }
+    data
+  }
+
+  #[test]
+  fn apple_single() {
+    let data = applefile(
+      APPLE_SINGLE,
+      &[(REAL_NAME, b"notes.txt"), (2, b"resource"), (DATA_FORK, b"Hello")],
+    );
+    let file = AppleFile::parse(&data).unwrap();
+    assert_eq!(file.real_name.as_deref(), Some("notes.txt"));
+    assert_eq!(file.data_fork.as_deref(), Some(&b"Hello"[..]));
+  }
+
+  #[test]
+  fn apple_double() {
+    let data = applefile(APPLE_DOUBLE, &[(REAL_NAME, b"notes.txt"), (2, b"resource")]);
+    let file = AppleFile::parse(&data).unwrap();
+    assert_eq!(file.real_name.as_deref(), Some("notes.txt"));
+    assert_eq!(file.data_fork, None);
+
+    assert_eq!(AppleFile::parse(b"Hello"), None);
+    let mut truncated = applefile(APPLE_SINGLE, &[(DATA_FORK, b"Hello")]);
+    truncated.truncate(truncated.len() - 2);
+    assert_eq!(AppleFile::parse(&truncated), Some(AppleFile::default()));
+  }
+}
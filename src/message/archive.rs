@@ -0,0 +1,375 @@
+/* archive.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::error::Error;
+use std::io::Read;
+
+use gtk4::prelude::*;
+use gtk4::{gio, glib};
+
+use super::attachment::Attachment;
+
+const ZIP_LOCAL_HEADER: &[u8] = b"PK\x03\x04";
+const ZIP_CENTRAL_HEADER: &[u8] = b"PK\x01\x02";
+const ZIP_END: &[u8] = b"PK\x05\x06";
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const TAR_BLOCK: usize = 512;
+
+/// Most bytes decompressed from an archive or a compressed message, past it
+/// the data is refused (decompression bombs)
+pub const MAX_DECOMPRESSED: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+  Zip,
+  Tar,
+  TarGz,
+}
+
+/// A file (or directory) listed in an archive attachment
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+  pub name: String,
+  /// uncompressed size
+  pub size: u64,
+  pub is_dir: bool,
+  // where extract() finds the data
+  offset: usize,
+  compressed_size: usize,
+  method: u16,
+}
+
+impl ArchiveEntry {
+  /// Last component of the name, what the file is saved as
+  pub fn basename(&self) -> &str {
+    self.name.trim_end_matches('/').rsplit('/').next().unwrap_or_default()
+  }
+}
+
+/// Kind of archive of an attachment, from its type or name, confirmed by
+/// its content.
+pub fn kind(attachment: &Attachment) -> Option<ArchiveKind> {
+  let name = attachment.filename.to_lowercase();
+  let mime_type = attachment.mime_type.as_deref().unwrap_or_default().to_lowercase();
+  let body = attachment.head(TAR_BLOCK);
+  if (mime_type.contains("zip") || name.ends_with(".zip")) && body.starts_with(ZIP_LOCAL_HEADER) {
+    Some(ArchiveKind::Zip)
+  } else if (name.ends_with(".tar.gz") || name.ends_with(".tgz")) && body.starts_with(GZIP_MAGIC) {
+    Some(ArchiveKind::TarGz)
+  } else if body.get(257..262) == Some(b"ustar") {
+    Some(ArchiveKind::Tar)
+  } else {
+    None
+  }
+}
+
+/// An archive attachment read once: the zip, or the tar decompressed
+#[derive(Debug)]
+pub struct Archive {
+  kind: ArchiveKind,
+  data: Vec<u8>,
+  entries: Vec<ArchiveEntry>,
+}
+
+impl Archive {
+  /// Lists the files and directories of `attachment`, without extracting them
+  pub fn open(attachment: &Attachment) -> Result<Self, Box<dyn Error>> {
+    let kind = kind(attachment)
+      .ok_or_else(|| format!("{} is not a supported archive", attachment.filename))?;
+    let data = match kind {
+      ArchiveKind::Zip | ArchiveKind::Tar => attachment.bytes()?.to_vec(),
+      ArchiveKind::TarGz => gunzip(attachment.bytes()?)?,
+    };
+    let entries = match kind {
+      ArchiveKind::Zip => zip_entries(&data)?,
+      ArchiveKind::Tar | ArchiveKind::TarGz => tar_entries(&data)?,
+    };
+    Ok(Self {
+      kind,
+      data,
+      entries,
+    })
+  }
+
+  pub fn entries(&self) -> &[ArchiveEntry] {
+    &self.entries
+  }
+
+  /// Content of one of the entries()
+  pub fn extract(&self, entry: &ArchiveEntry) -> Result<Vec<u8>, Box<dyn Error>> {
+    if self.kind == ArchiveKind::Zip {
+      return zip_extract(&self.data, entry);
+    }
+    let end = entry.offset + entry.size as usize;
+    Ok(self.data.get(entry.offset..end).ok_or("Truncated archive")?.to_vec())
+  }
+}
+
+/// "messages.zip!inner/message.eml" => ("messages.zip", "inner/message.eml")
+pub fn split_zip_path(path: &str) -> Option<(&str, &str)> {
+  // ASCII only, a Unicode lowercase may change the byte offsets
+  let end = path.to_ascii_lowercase().find(".zip!")? + ".zip".len();
+  let entry = &path[end + 1..];
+  match entry.is_empty() {
+    true => None,
+    false => Some((&path[..end], entry)),
+  }
+}
+
+/// Content of the file `name` of a zip archive
+pub fn zip_entry(data: &[u8], name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+  let name = name.trim_start_matches('/');
+  let entry = zip_entries(data)?
+    .into_iter()
+    .find(|entry| !entry.is_dir && entry.name == name)
+    .ok_or_else(|| format!("No {} in the archive", name))?;
+  zip_extract(data, &entry)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+  Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+  Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn zip_entries(data: &[u8]) -> Result<Vec<ArchiveEntry>, Box<dyn Error>> {
+  // the end record is last, followed by a comment of at most 64 KiB
+  let end = (0..data.len().saturating_sub(21))
+    .rev()
+    .take(0xffff + 22)
+    .find(|&i| data[i..].starts_with(ZIP_END))
+    .ok_or("Zip end record not found")?;
+  let count = u16_at(data, end + 10).ok_or("Truncated zip")? as usize;
+  let mut offset = u32_at(data, end + 16).ok_or("Truncated zip")? as usize;
+  let mut entries: Vec<ArchiveEntry> = vec![];
+  for _ in 0..count {
+    let header = data.get(offset..).ok_or("Truncated zip")?;
+    if !header.starts_with(ZIP_CENTRAL_HEADER) {
+      return Err("Invalid zip central directory".into());
+    }
+    let field = |at: usize| u16_at(header, at).map(|v| v as usize).ok_or("Truncated zip");
+    let (name_length, extra_length, comment_length) = (field(28)?, field(30)?, field(32)?);
+    let name = header.get(46..46 + name_length).ok_or("Truncated zip")?;
+    let name = String::from_utf8_lossy(name).to_string();
+    entries.push(ArchiveEntry {
+      is_dir: name.ends_with('/'),
+      name,
+      size: u32_at(header, 24).ok_or("Truncated zip")? as u64,
+      offset: u32_at(header, 42).ok_or("Truncated zip")? as usize,
+      compressed_size: u32_at(header, 20).ok_or("Truncated zip")? as usize,
+      method: u16_at(header, 10).ok_or("Truncated zip")?,
+    });
+    offset += 46 + name_length + extra_length + comment_length;
+  }
+  Ok(entries)
+}
+
+fn zip_extract(data: &[u8], entry: &ArchiveEntry) -> Result<Vec<u8>, Box<dyn Error>> {
+  let header = data.get(entry.offset..).ok_or("Truncated zip")?;
+  if !header.starts_with(ZIP_LOCAL_HEADER) {
+    return Err("Invalid zip entry".into());
+  }
+  let name_length = u16_at(header, 26).ok_or("Truncated zip")? as usize;
+  let extra_length = u16_at(header, 28).ok_or("Truncated zip")? as usize;
+  let start = 30 + name_length + extra_length;
+  let content = header
+    .get(start..start + entry.compressed_size)
+    .ok_or("Truncated zip")?;
+  if entry.size > MAX_DECOMPRESSED {
+    return Err(format!("{} is too large to extract", entry.name).into());
+  }
+  match entry.method {
+    0 => Ok(content.to_vec()),
+    8 => decompress(content, gio::ZlibCompressorFormat::Raw, entry.size),
+    method => Err(format!("Unsupported zip compression method {}", method).into()),
+  }
+}
+
+fn tar_entries(data: &[u8]) -> Result<Vec<ArchiveEntry>, Box<dyn Error>> {
+  let mut entries: Vec<ArchiveEntry> = vec![];
+  let mut offset = 0;
+  // GNU long name of the next entry
+  let mut long_name: Option<String> = None;
+  while let Some(header) = data.get(offset..offset + TAR_BLOCK) {
+    if header.iter().all(|b| *b == 0) {
+      break;
+    }
+    let text = |range: std::ops::Range<usize>| {
+      let field = &header[range];
+      let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+      String::from_utf8_lossy(&field[..end]).to_string()
+    };
+    let size = u64::from_str_radix(text(124..136).trim(), 8).map_err(|_| "Invalid tar header")?;
+    let content = offset + TAR_BLOCK;
+    let name = match (text(345..500), text(0..100)) {
+      (prefix, name) if !prefix.is_empty() && &header[257..262] == b"ustar" => {
+        format!("{}/{}", prefix, name)
+      }
+      (_, name) => name,
+    };
+    match header[156] {
+      b'L' => {
+        let value = data.get(content..content + size as usize).ok_or("Truncated tar")?;
+        long_name = Some(String::from_utf8_lossy(value).trim_end_matches('\0').to_string());
+      }
+      // pax headers, links, devices...
+      b'x' | b'g' | b'1' | b'2' | b'3' | b'4' | b'6' => (),
+      typeflag => {
+        let name = long_name.take().unwrap_or(name);
+        entries.push(ArchiveEntry {
+          is_dir: typeflag == b'5' || name.ends_with('/'),
+          name,
+          size,
+          offset: content,
+          compressed_size: size as usize,
+          method: 0,
+        });
+      }
+    }
+    offset = content + (size as usize).div_ceil(TAR_BLOCK) * TAR_BLOCK;
+  }
+  Ok(entries)
+}
+
+/// Decompresses gzip `data`, up to MAX_DECOMPRESSED bytes
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+  decompress(data, gio::ZlibCompressorFormat::Gzip, MAX_DECOMPRESSED)
+}
+
+// fails rather than going over `limit` bytes
+fn decompress(
+  data: &[u8],
+  format: gio::ZlibCompressorFormat,
+  limit: u64,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+  let input = gio::MemoryInputStream::from_bytes(&glib::Bytes::from(data));
+  let stream = gio::ConverterInputStream::new(&input, &gio::ZlibDecompressor::new(format));
+  let mut decompressed: Vec<u8> = vec![];
+  stream.into_read().take(limit + 1).read_to_end(&mut decompressed)?;
+  if decompressed.len() as u64 > limit {
+    return Err(format!("Decompressed data over {} bytes", limit).into());
+  }
+  Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::message::zip;
+
+  fn attachment(filename: &str, body: Vec<u8>) -> Attachment {
+    Attachment {
+      filename: filename.to_string(),
+      content_id: "none".to_string(),
+      body,
+      mime_type: None,
+      description: None,
+      encoded: None,
+    }
+  }
+
+  #[test]
+  fn zip_archive() {
+    let files = [
+      attachment("hello.txt", b"Hello Lucas".to_vec()),
+      attachment("moon.csv", b"month,total\njuly,10\n".to_vec()),
+    ];
+    let archive = attachment("bundle.zip", zip::write(Vec::new(), &files).unwrap());
+    assert_eq!(kind(&archive), Some(ArchiveKind::Zip));
+    let opened = Archive::open(&archive).unwrap();
+    let listed = opened.entries();
+    let names: Vec<(&str, u64)> = listed.iter().map(|e| (e.name.as_str(), e.size)).collect();
+    assert_eq!(names, vec![("hello.txt", 11), ("moon.csv", 20)]);
+    assert_eq!(opened.extract(&listed[1]).unwrap(), files[1].body);
+
+    // deflated entries, made by Info-ZIP
+    let archive = attachment("deflated.zip", std::fs::read("tests/archive.zip").unwrap());
+    let opened = Archive::open(&archive).unwrap();
+    let listed = opened.entries();
+    let names: Vec<&str> = listed.iter().map(|e| e.basename()).collect();
+    assert_eq!(names, vec!["docs", "lorem.txt"]);
+    assert!(listed[0].is_dir);
+    let lorem = opened.extract(&listed[1]).unwrap();
+    assert_eq!(lorem.len() as u64, listed[1].size);
+    assert!(lorem.starts_with(b"Lorem ipsum"));
+
+    // an entry inflating past its declared size
+    let mut bomb = listed[1].clone();
+    bomb.size = 100;
+    assert!(opened.extract(&bomb).is_err());
+  }
+
+  #[test]
+  fn zip_path() {
+    assert_eq!(
+      split_zip_path("/tmp/Mail.ZIP!inner/message.eml"),
+      Some(("/tmp/Mail.ZIP", "inner/message.eml"))
+    );
+    assert_eq!(split_zip_path("messages.zip"), None);
+    assert_eq!(split_zip_path("messages.zip!"), None);
+    assert_eq!(split_zip_path("Hello!.eml"), None);
+    assert_eq!(
+      split_zip_path("/home/İlkay/Été.zip!courrier/été.eml"),
+      Some(("/home/İlkay/Été.zip", "courrier/été.eml"))
+    );
+
+    let data = std::fs::read("tests/messages.zip").unwrap();
+    assert!(zip_entry(&data, "mail/organization.eml")
+      .unwrap()
+      .starts_with(b"MIME-Version: 1.0"));
+    assert!(zip_entry(&data, "mail").is_err());
+    assert!(zip_entry(&data, "mail/missing.eml").is_err());
+  }
+
+  #[test]
+  fn tar_archive() {
+    let mut tar: Vec<u8> = vec![0; TAR_BLOCK];
+    tar[..9].copy_from_slice(b"notes.txt");
+    tar[124..135].copy_from_slice(b"00000000005");
+    tar[156] = b'0';
+    tar[257..262].copy_from_slice(b"ustar");
+    tar.extend(b"Hello");
+    tar.resize(TAR_BLOCK * 4, 0);
+    let archive = attachment("notes.tar", tar);
+    assert_eq!(kind(&archive), Some(ArchiveKind::Tar));
+    let opened = Archive::open(&archive).unwrap();
+    let listed = opened.entries();
+    assert_eq!(listed.len(), 1);
+    assert_eq!((listed[0].name.as_str(), listed[0].size), ("notes.txt", 5));
+    assert_eq!(opened.extract(&listed[0]).unwrap(), b"Hello");
+
+    assert_eq!(kind(&attachment("notes.txt", b"Hello".to_vec())), None);
+  }
+
+  #[test]
+  fn decompression_limit() {
+    let data = std::fs::read("tests/archive.zip").unwrap();
+    let opened = Archive::open(&attachment("deflated.zip", data.clone())).unwrap();
+    let lorem = &opened.entries()[1];
+    let name_length = u16_at(&data, lorem.offset + 26).unwrap() as usize;
+    let extra_length = u16_at(&data, lorem.offset + 28).unwrap() as usize;
+    let start = lorem.offset + 30 + name_length + extra_length;
+    let content = &data[start..start + lorem.compressed_size];
+    let format = gio::ZlibCompressorFormat::Raw;
+    assert_eq!(decompress(content, format, lorem.size).unwrap().len() as u64, lorem.size);
+    assert!(decompress(content, format, lorem.size - 1).is_err());
+  }
+}
@@ -17,10 +17,77 @@
  *
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
+use std::borrow::Cow;
+use std::cell::OnceCell;
 use std::error::Error;
+use std::io::{self, BufWriter, Write};
 use std::{fmt, fs};
 
+use gmime::glib;
+
 use super::message::TEMP_FOLDER;
+use super::sniff;
+use super::transfer;
+use super::vcard::VCard;
+
+/// Bytes of the content enough to sniff its type
+const SNIFF_LENGTH: usize = 512;
+
+/// Extensions of the programs and scripts the desktop may run when opened
+const EXECUTABLE_EXTENSIONS: [&str; 29] = [
+  "exe", "com", "scr", "pif", "bat", "cmd", "msi", "dll", "cpl", "lnk", "hta", "js", "jse",
+  "vbs", "vbe", "wsf", "ps1", "reg", "jar", "sh", "bash", "zsh", "py", "pl", "rb", "run",
+  "appimage", "desktop", "apk",
+];
+
+/// Declared types of programs and scripts
+const EXECUTABLE_TYPES: [&str; 8] = [
+  "application/x-msdownload",
+  "application/x-executable",
+  "application/x-sh",
+  "application/x-shellscript",
+  "text/x-shellscript",
+  "application/x-desktop",
+  "application/x-msi",
+  "application/java-archive",
+];
+
+/// Coarse kind of an attachment, used to filter the attachments list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentCategory {
+  Image,
+  Document,
+  Text,
+  Other,
+}
+
+impl AttachmentCategory {
+  pub const ALL: [AttachmentCategory; 4] = [
+    AttachmentCategory::Image,
+    AttachmentCategory::Document,
+    AttachmentCategory::Text,
+    AttachmentCategory::Other,
+  ];
+
+  pub fn from_mime(mime_type: &str) -> Self {
+    let mime_type = mime_type.to_lowercase();
+    if mime_type.starts_with("image/") {
+      AttachmentCategory::Image
+    } else if mime_type == "application/pdf"
+      || mime_type == "application/msword"
+      || mime_type == "application/rtf"
+      || mime_type.starts_with("application/vnd.openxmlformats-officedocument.")
+      || mime_type.starts_with("application/vnd.oasis.opendocument.")
+      || mime_type.starts_with("application/vnd.ms-")
+    {
+      AttachmentCategory::Document
+    } else if mime_type.starts_with("text/") {
+      AttachmentCategory::Text
+    } else {
+      AttachmentCategory::Other
+    }
+  }
+}
 
 #[derive(Debug, Clone)]
 pub struct Attachment {
@@ -28,6 +95,66 @@ pub struct Attachment {
   pub content_id: String,
   pub body: Vec<u8>,
   pub mime_type: Option<String>,
+  /// Content-Description header, if any
+  pub description: Option<String>,
+  /// base64 source of a large part, kept as is rather than decoded when
+  /// parsing. The body is then empty: bytes() decodes it once and saving
+  /// streams its decoding to the file.
+  pub encoded: Option<Encoded>,
+}
+
+/// base64 source of an attachment, decoded on the first bytes()
+#[derive(Debug, Clone)]
+pub struct Encoded {
+  source: Vec<u8>,
+  decoded: OnceCell<Vec<u8>>,
+}
+
+impl Encoded {
+  pub fn new(source: Vec<u8>) -> Self {
+    Self {
+      source,
+      decoded: OnceCell::new(),
+    }
+  }
+
+  fn decoded(&self) -> io::Result<&[u8]> {
+    if let Some(decoded) = self.decoded.get() {
+      return Ok(decoded);
+    }
+    let decoded = Vec::with_capacity(transfer::base64_decoded_len(&self.source));
+    let decoded = transfer::decode_base64_to(self.source.as_slice(), decoded)?;
+    Ok(self.decoded.get_or_init(|| decoded))
+  }
+
+  fn head(&self, length: usize) -> Cow<'_, [u8]> {
+    if let Some(decoded) = self.decoded.get() {
+      return Cow::Borrowed(&decoded[..decoded.len().min(length)]);
+    }
+    // 4 characters per 3 bytes, with line breaks every 76 at most
+    let source = &self.source[..self.source.len().min(length * 2 + 8)];
+    let mut decoder = transfer::Base64Writer::new(Vec::with_capacity(length + 3));
+    // writing to a Vec can't fail
+    let _ = decoder.write_all(source);
+    let mut head = decoder.into_inner();
+    head.truncate(length);
+    Cow::Owned(head)
+  }
+
+  fn decoded_len(&self) -> usize {
+    match self.decoded.get() {
+      Some(decoded) => decoded.len(),
+      None => transfer::base64_decoded_len(&self.source),
+    }
+  }
+
+  // streamed when it's not decoded yet, rather than kept
+  fn write_to<W: Write>(&self, mut writer: W) -> io::Result<W> {
+    match self.decoded.get() {
+      Some(decoded) => writer.write_all(decoded).map(|_| writer),
+      None => transfer::decode_base64_to(self.source.as_slice(), writer),
+    }
+  }
 }
 
 impl Attachment {
@@ -37,14 +164,114 @@ impl Attachment {
       log::debug!("create_dir({:?})", &tmp);
       fs::create_dir(&tmp)?;
     }
-    tmp.push(&self.filename);
+    tmp.push(self.safe_filename());
     log::debug!("write_to_tmp({:?})", &tmp);
     self.write_to_file(tmp.to_str().unwrap())?;
     Ok(tmp.to_string_lossy().to_string())
   }
 
-  pub fn write_to_file(&self, file: &str) -> std::io::Result<()> {
-    fs::write(&file, &self.body)
+  pub fn write_to_file(&self, file: &str) -> io::Result<()> {
+    match &self.encoded {
+      Some(encoded) => encoded.write_to(BufWriter::new(fs::File::create(file)?))?.flush(),
+      None => fs::write(file, &self.body),
+    }
+  }
+
+  /// The decoded content, the body or what `encoded` decodes to (once), an
+  /// error when it's corrupt. Use it rather than the body.
+  pub fn bytes(&self) -> io::Result<&[u8]> {
+    match &self.encoded {
+      Some(encoded) => encoded.decoded(),
+      None => Ok(&self.body),
+    }
+  }
+
+  /// The first (at most) `length` bytes of bytes(), without decoding the
+  /// rest: enough to tell the type of the content.
+  pub fn head(&self, length: usize) -> Cow<'_, [u8]> {
+    match &self.encoded {
+      Some(encoded) => encoded.head(length),
+      None => Cow::Borrowed(&self.body[..self.body.len().min(length)]),
+    }
+  }
+
+  /// Size of the decoded body, also when only its source is kept
+  pub fn size(&self) -> usize {
+    match &self.encoded {
+      Some(encoded) => encoded.decoded_len(),
+      None => self.body.len(),
+    }
+  }
+
+  /// The filename reduced to a harmless basename, it comes from untrusted mail.
+  pub fn safe_filename(&self) -> String {
+    sanitize_filename(&self.filename)
+  }
+
+  /// Content-ID without its angle brackets, None when the part declared none
+  pub fn content_id(&self) -> Option<&str> {
+    let id = self.content_id.trim().trim_start_matches('<').trim_end_matches('>');
+    match id {
+      "" | "none" => None,
+      id => Some(id),
+    }
+  }
+
+  /// Actual content type when the filename's extension lies about it
+  pub fn extension_mismatch(&self) -> Option<&'static str> {
+    sniff::extension_mismatch(&self.filename, &self.head(SNIFF_LENGTH))
+  }
+
+  /// Contact card, for text/vcard attachments
+  pub fn vcard(&self) -> Option<VCard> {
+    let mime_type = self.mime_type.as_deref().unwrap_or_default().to_lowercase();
+    let is_vcard = mime_type == "text/vcard"
+      || mime_type == "text/x-vcard"
+      || mime_type == "text/directory"
+      || self.filename.to_lowercase().ends_with(".vcf");
+    if !is_vcard {
+      return None;
+    }
+    match self.bytes() {
+      Ok(bytes) => VCard::parse(&String::from_utf8_lossy(bytes)),
+      Err(e) => {
+        log::warn!("vcard({}) => {}", self.filename, e);
+        None
+      }
+    }
+  }
+
+  /// A program or a script, by its content, its extension or its declared
+  /// type: opening it may run it.
+  pub fn is_dangerous_to_open(&self) -> bool {
+    let sniffed = sniff::sniff(&self.head(SNIFF_LENGTH)).is_some_and(sniff::is_executable);
+    let extension = std::path::Path::new(&self.filename)
+      .extension()
+      .map(|extension| extension.to_string_lossy().to_lowercase())
+      .is_some_and(|extension| EXECUTABLE_EXTENSIONS.contains(&extension.as_str()));
+    let declared = self
+      .mime_type
+      .as_deref()
+      .is_some_and(|m| EXECUTABLE_TYPES.contains(&m.to_lowercase().as_str()));
+    sniffed || extension || declared
+  }
+
+  pub fn is_pdf(&self) -> bool {
+    self
+      .mime_type
+      .as_deref()
+      .is_some_and(|m| m.eq_ignore_ascii_case("application/pdf"))
+      || self.filename.to_lowercase().ends_with(".pdf")
+  }
+
+  /// Lowercase hex SHA-256 of the body
+  pub fn sha256(&self) -> io::Result<String> {
+    let checksum = glib::compute_checksum_for_data(glib::ChecksumType::Sha256, self.bytes()?);
+    Ok(checksum.map(|checksum| checksum.to_string()).unwrap_or_default())
+  }
+
+  pub fn category(&self) -> AttachmentCategory {
+    AttachmentCategory::from_mime(self.mime_type.as_deref().unwrap_or_default())
   }
 }
 
@@ -59,3 +286,181 @@ impl fmt::Display for Attachment {
     )
   }
 }
+
+/// Strips directory components (both separators), replaces control
+/// characters and falls back to "attachment" for empty, "." or "..".
+pub fn sanitize_filename(name: &str) -> String {
+  let basename = name.rsplit(['/', '\\']).next().unwrap_or_default();
+  let basename: String = basename
+    .chars()
+    .map(|c| if c.is_control() { '_' } else { c })
+    .collect();
+  match basename.trim() {
+    "" | "." | ".." => "attachment".to_string(),
+    basename => basename.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sanitize() {
+    assert_eq!(sanitize_filename("../../evil.sh"), "evil.sh");
+    assert_eq!(sanitize_filename("..\\..\\evil.bat"), "evil.bat");
+    assert_eq!(sanitize_filename("/etc/passwd"), "passwd");
+    assert_eq!(sanitize_filename("re\x00port\n.pdf"), "re_port_.pdf");
+    assert_eq!(sanitize_filename("dir/.."), "attachment");
+    assert_eq!(sanitize_filename(""), "attachment");
+    assert_eq!(sanitize_filename("report.pdf"), "report.pdf");
+  }
+
+  #[test]
+  fn content_id() {
+    let mut attachment = Attachment {
+      filename: "logo.png".to_string(),
+      content_id: "<logo@example.com>".to_string(),
+      body: vec![],
+      mime_type: Some("image/png".to_string()),
+      description: None,
+      encoded: None,
+    };
+    assert_eq!(attachment.content_id(), Some("logo@example.com"));
+    attachment.content_id = "none".to_string();
+    assert_eq!(attachment.content_id(), None);
+  }
+
+  #[test]
+  fn write_encoded() {
+    let data: Vec<u8> = (0..3_000_000u32).map(|i| (i % 253) as u8 ^ (i >> 8) as u8).collect();
+    let attachment = Attachment {
+      filename: "large.bin".to_string(),
+      content_id: String::new(),
+      body: vec![],
+      mime_type: Some("application/octet-stream".to_string()),
+      description: None,
+      encoded: Some(Encoded::new(crate::message::eml::encode_base64(&data).into_bytes())),
+    };
+    assert_eq!(attachment.size(), data.len());
+
+    let path = std::env::temp_dir().join(format!("mailviewer-{}.bin", std::process::id()));
+    attachment.write_to_file(path.to_str().unwrap()).unwrap();
+    let written = fs::read(&path).unwrap();
+    assert!(written == data);
+    // streamed: only the source is held, the decoding isn't kept
+    let encoded = attachment.encoded.as_ref().unwrap();
+    assert!(encoded.decoded.get().is_none());
+    assert_eq!(attachment.head(4), &data[..4]);
+    assert!(encoded.decoded.get().is_none());
+
+    // decoded once, then reused for saving
+    let bytes = attachment.bytes().unwrap();
+    assert_eq!(bytes, data.as_slice());
+    assert_eq!(attachment.bytes().unwrap().as_ptr(), bytes.as_ptr());
+    attachment.write_to_file(path.to_str().unwrap()).unwrap();
+    assert!(fs::read(&path).unwrap() == data);
+
+    // cut in the middle of a quantum
+    let truncated = Attachment {
+      encoded: Some(Encoded::new(b"SGVsbG8gV".to_vec())),
+      ..attachment.clone()
+    };
+    assert!(truncated.bytes().is_err());
+    assert!(truncated.sha256().is_err());
+    assert!(truncated.write_to_file(path.to_str().unwrap()).is_err());
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn encoded_consumers() {
+    use crate::message::{eml, zip};
+
+    let data = b"Hello Lucas, this attachment was kept encoded.\n".repeat(40);
+    let plain = Attachment {
+      filename: "notes.txt".to_string(),
+      content_id: String::new(),
+      body: data.clone(),
+      mime_type: Some("text/plain".to_string()),
+      description: None,
+      encoded: None,
+    };
+    let streamed = Attachment {
+      body: vec![],
+      encoded: Some(Encoded::new(eml::encode_base64(&data).into_bytes())),
+      ..plain.clone()
+    };
+    assert_eq!(streamed.bytes().unwrap(), data.as_slice());
+    assert_eq!(streamed.head(5), &b"Hello"[..]);
+    assert_eq!(streamed.sha256().unwrap(), plain.sha256().unwrap());
+
+    // same crc and size as the plain body, then the data after the name
+    let expected = zip::write(Vec::new(), &[plain]).unwrap();
+    let archive = zip::write(Vec::new(), &[streamed.clone()]).unwrap();
+    assert_eq!(archive[14..22], expected[14..22]);
+    assert_eq!(&archive[39..39 + data.len()], data.as_slice());
+
+    let message = eml::build(&[], Some("Hello"), None, &[streamed]).unwrap();
+    let message = String::from_utf8(message).unwrap();
+    assert!(message.contains(&eml::encode_base64(&data)));
+  }
+
+  #[test]
+  fn dangerous_to_open() {
+    let attachment = |filename: &str, mime_type: &str, body: &[u8]| Attachment {
+      filename: filename.to_string(),
+      content_id: String::new(),
+      body: body.to_vec(),
+      mime_type: Some(mime_type.to_string()),
+      description: None,
+      encoded: None,
+    };
+    let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+    let script = b"#!/bin/sh\nrm -rf ~\n";
+    assert!(!attachment("photo.png", "image/png", png).is_dangerous_to_open());
+    assert!(attachment("install.sh", "application/x-sh", script).is_dangerous_to_open());
+    // whatever the name and the declared type
+    assert!(attachment("notes.txt", "text/plain", script).is_dangerous_to_open());
+    assert!(attachment("Invoice.PDF.exe", "application/pdf", b"").is_dangerous_to_open());
+    assert!(attachment("run", "application/x-msdownload", b"").is_dangerous_to_open());
+    assert!(!attachment("report.pdf", "application/pdf", b"%PDF-1.7").is_dangerous_to_open());
+
+    // kept encoded, sniffed by its decoded start
+    let elf = b"\x7fELF\x02\x01\x01\x00".repeat(100);
+    let streamed = Attachment {
+      body: vec![],
+      encoded: Some(Encoded::new(crate::message::eml::encode_base64(&elf).into_bytes())),
+      ..attachment("video.mp4", "video/mp4", b"")
+    };
+    assert!(streamed.is_dangerous_to_open());
+  }
+
+  #[test]
+  fn extension_mismatch() {
+    let attachment = Attachment {
+      filename: "doc.pdf".to_string(),
+      content_id: String::new(),
+      body: b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec(),
+      mime_type: Some("application/pdf".to_string()),
+      description: None,
+      encoded: None,
+    };
+    assert_eq!(attachment.extension_mismatch(), Some("image/png"));
+  }
+
+  #[test]
+  fn category() {
+    assert_eq!(AttachmentCategory::from_mime("image/png"), AttachmentCategory::Image);
+    assert_eq!(AttachmentCategory::from_mime("IMAGE/JPEG"), AttachmentCategory::Image);
+    assert_eq!(AttachmentCategory::from_mime("application/pdf"), AttachmentCategory::Document);
+    assert_eq!(
+      AttachmentCategory::from_mime(
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+      ),
+      AttachmentCategory::Document
+    );
+    assert_eq!(AttachmentCategory::from_mime("text/csv"), AttachmentCategory::Text);
+    assert_eq!(AttachmentCategory::from_mime("application/zip"), AttachmentCategory::Other);
+    assert_eq!(AttachmentCategory::from_mime(""), AttachmentCategory::Other);
+  }
+}
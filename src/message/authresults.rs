@@ -0,0 +1,266 @@
+/* authresults.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/// One "method=result" entry of an Authentication-Results header (RFC 8601),
+/// e.g. `dkim=pass header.d=example.com`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthResult {
+  pub method: String,
+  pub result: String,
+  pub properties: Vec<(String, String)>,
+}
+
+impl AuthResult {
+  pub fn property(&self, name: &str) -> Option<&str> {
+    self
+      .properties
+      .iter()
+      .find(|(key, _)| key.eq_ignore_ascii_case(name))
+      .map(|(_, value)| value.as_str())
+  }
+}
+
+/// A whole Authentication-Results header, as stamped by one server.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuthResults {
+  pub authserv_id: String,
+  pub results: Vec<AuthResult>,
+}
+
+impl AuthResults {
+  pub fn parse(value: &str) -> AuthResults {
+    let value = strip_comments(value);
+    let mut segments = value.split(';');
+    let mut auth = AuthResults::default();
+
+    if let Some(first) = segments.next() {
+      auth.authserv_id = first.split_whitespace().next().unwrap_or_default().to_string();
+    }
+    for segment in segments {
+      let mut tokens = tokenize(segment).into_iter();
+      let Some(first) = tokens.next() else {
+        continue;
+      };
+      let Some((method, result)) = first.split_once('=') else {
+        continue; // "none"
+      };
+      // "dkim/1=pass" carries an optional version
+      let method = method.split('/').next().unwrap_or_default();
+      auth.results.push(AuthResult {
+        method: method.trim().to_lowercase(),
+        result: result.trim().to_lowercase(),
+        properties: tokens
+          .filter_map(|token| {
+            token
+              .split_once('=')
+              .map(|(k, v)| (k.trim().to_lowercase(), v.trim_matches('"').to_string()))
+          })
+          .collect(),
+      });
+    }
+    auth
+  }
+
+  pub fn result(&self, method: &str) -> Option<&AuthResult> {
+    self.results.iter().find(|r| r.method == method)
+  }
+}
+
+/// Combines the outcome of `method` across all the headers: "pass" when every
+/// server says pass, otherwise the first result that isn't a pass.
+pub fn combined_result<'a>(all: &'a [AuthResults], method: &str) -> Option<&'a str> {
+  let mut results = all
+    .iter()
+    .flat_map(|a| a.results.iter())
+    .filter(|r| r.method == method)
+    .map(|r| r.result.as_str())
+    .peekable();
+
+  results.peek()?;
+  let mut combined = "pass";
+  for result in results {
+    if result != "pass" {
+      combined = result;
+      break;
+    }
+  }
+  Some(combined)
+}
+
+/// Whether the From domain matches a domain authenticated by SPF or DKIM,
+/// the crux of DMARC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+  Aligned,
+  /// SPF or DKIM passed for other domains only, or nothing passed
+  Misaligned,
+  /// no From domain, or no SPF nor DKIM identifier to compare it to
+  Unknown,
+}
+
+impl Alignment {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Alignment::Aligned => "aligned",
+      Alignment::Misaligned => "misaligned",
+      Alignment::Unknown => "unknown",
+    }
+  }
+}
+
+/// Relaxed alignment of `from_domain` with the domains of the passing DKIM
+/// signatures (header.d) and SPF checks (smtp.mailfrom, or smtp.helo).
+/// Without the public suffix list, domains align when one is the other or
+/// a subdomain of it.
+pub fn alignment(from_domain: &str, all: &[AuthResults]) -> Alignment {
+  let from_domain = from_domain.trim().trim_end_matches('.').to_lowercase();
+  let identifiers: Vec<(&str, String)> = all
+    .iter()
+    .flat_map(|a| a.results.iter())
+    .filter_map(|r| {
+      let identifier = match r.method.as_str() {
+        "dkim" => r.property("header.d").or(r.property("header.i")),
+        "spf" => r.property("smtp.mailfrom").or(r.property("smtp.helo")),
+        _ => None,
+      }?;
+      let domain = identifier.rsplit('@').next().unwrap_or_default();
+      Some((r.result.as_str(), domain.trim_end_matches('.').to_lowercase()))
+    })
+    .filter(|(_, domain)| !domain.is_empty())
+    .collect();
+  if from_domain.is_empty() || identifiers.is_empty() {
+    return Alignment::Unknown;
+  }
+  let aligned = identifiers
+    .iter()
+    .any(|(result, domain)| *result == "pass" && aligns(&from_domain, domain));
+  match aligned {
+    true => Alignment::Aligned,
+    false => Alignment::Misaligned,
+  }
+}
+
+fn aligns(a: &str, b: &str) -> bool {
+  a == b || a.ends_with(&format!(".{}", b)) || b.ends_with(&format!(".{}", a))
+}
+
+fn strip_comments(value: &str) -> String {
+  let mut depth = 0;
+  let mut quoted = false;
+  let mut out = String::with_capacity(value.len());
+  for c in value.chars() {
+    match c {
+      '"' if depth == 0 => {
+        quoted = !quoted;
+        out.push(c);
+      }
+      '(' if !quoted => depth += 1,
+      ')' if !quoted && depth > 0 => depth -= 1,
+      _ if depth == 0 => out.push(c),
+      _ => {}
+    }
+  }
+  out
+}
+
+fn tokenize(segment: &str) -> Vec<String> {
+  let mut tokens: Vec<String> = vec![];
+  let mut current = String::new();
+  let mut quoted = false;
+  for c in segment.chars() {
+    match c {
+      '"' => {
+        quoted = !quoted;
+        current.push(c);
+      }
+      c if c.is_whitespace() && !quoted => {
+        if !current.is_empty() {
+          tokens.push(std::mem::take(&mut current));
+        }
+      }
+      _ => current.push(c),
+    }
+  }
+  if !current.is_empty() {
+    tokens.push(current);
+  }
+  tokens
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    let auth = AuthResults::parse(
+      "mx.google.com;\r\n       dkim=pass header.i=@moon.space header.s=s1 header.b=abc;\r\n       spf=pass (google.com: domain of john@moon.space designates 1.2.3.4 as permitted sender) smtp.mailfrom=john@moon.space;\r\n       dmarc=fail (p=REJECT sp=REJECT dis=NONE) header.from=moon.space",
+    );
+    assert_eq!(auth.authserv_id, "mx.google.com");
+    assert_eq!(auth.results.len(), 3);
+    assert_eq!(auth.result("spf").unwrap().result, "pass");
+    assert_eq!(
+      auth.result("spf").unwrap().property("smtp.mailfrom"),
+      Some("john@moon.space")
+    );
+    assert_eq!(auth.result("dmarc").unwrap().result, "fail");
+    assert_eq!(
+      auth.result("dmarc").unwrap().property("header.from"),
+      Some("moon.space")
+    );
+    assert_eq!(auth.result("dkim").unwrap().property("header.s"), Some("s1"));
+  }
+
+  #[test]
+  fn parse_none() {
+    let auth = AuthResults::parse("example.org 1; none");
+    assert_eq!(auth.authserv_id, "example.org");
+    assert!(auth.results.is_empty());
+  }
+
+  #[test]
+  fn combined() {
+    let all = vec![
+      AuthResults::parse("mx1.example.org; spf=pass smtp.mailfrom=a@b.c; dkim=pass"),
+      AuthResults::parse("mx2.example.org; spf=softfail smtp.mailfrom=a@b.c"),
+    ];
+    assert_eq!(combined_result(&all, "spf"), Some("softfail"));
+    assert_eq!(combined_result(&all, "dkim"), Some("pass"));
+    assert_eq!(combined_result(&all, "dmarc"), None);
+  }
+
+  #[test]
+  fn domain_alignment() {
+    let dkim = vec![AuthResults::parse("mx.example.org; dkim=pass header.d=example.com")];
+    assert_eq!(alignment("example.com", &dkim), Alignment::Aligned);
+    assert_eq!(alignment("news.Example.com", &dkim), Alignment::Aligned);
+    assert_eq!(alignment("example.net", &dkim), Alignment::Misaligned);
+    assert_eq!(alignment("", &dkim), Alignment::Unknown);
+
+    let spf = vec![AuthResults::parse(
+      "mx.example.org; spf=pass smtp.mailfrom=bounce@mailer.net; dkim=fail header.d=example.com",
+    )];
+    assert_eq!(alignment("example.com", &spf), Alignment::Misaligned);
+    assert_eq!(alignment("mailer.net", &spf), Alignment::Aligned);
+    let none = vec![AuthResults::parse("mx.example.org; dmarc=pass header.from=example.com")];
+    assert_eq!(alignment("example.com", &none), Alignment::Unknown);
+    assert_eq!(alignment("example.com", &[]), Alignment::Unknown);
+  }
+}
@@ -0,0 +1,141 @@
+/* dsn.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/// Per-recipient fields of a message/delivery-status part (RFC 3464), the
+/// machine readable half of a bounce.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeliveryStatus {
+  pub recipient: String,
+  /// failed, delayed, delivered, relayed or expanded
+  pub action: String,
+  /// enhanced status code, e.g. "5.1.1"
+  pub status: String,
+  pub diagnostic: Option<String>,
+}
+
+impl DeliveryStatus {
+  /// One entry per recipient, the per-message fields (first block) are
+  /// skipped.
+  pub fn parse(text: &str) -> Vec<DeliveryStatus> {
+    let mut entries: Vec<DeliveryStatus> = vec![];
+    for block in blocks(text) {
+      let mut entry = DeliveryStatus::default();
+      let mut original_recipient: Option<String> = None;
+      for (name, value) in block {
+        match name.to_lowercase().as_str() {
+          "final-recipient" => entry.recipient = without_type(&value),
+          "original-recipient" => original_recipient = Some(without_type(&value)),
+          "action" => entry.action = value.to_lowercase(),
+          "status" => entry.status = value.split_whitespace().next().unwrap_or_default().to_string(),
+          "diagnostic-code" => entry.diagnostic = Some(without_type(&value)),
+          _ => {}
+        }
+      }
+      if entry.recipient.is_empty() {
+        entry.recipient = original_recipient.unwrap_or_default();
+      }
+      if !entry.recipient.is_empty() {
+        entries.push(entry);
+      }
+    }
+    entries
+  }
+
+  pub fn is_failure(&self) -> bool {
+    self.action == "failed" || self.status.starts_with('5')
+  }
+
+  /// The server's diagnostic ("550 No such user"), else the status code
+  pub fn reason(&self) -> Option<&str> {
+    match self.diagnostic.as_deref() {
+      Some(diagnostic) if !diagnostic.is_empty() => Some(diagnostic),
+      _ if !self.status.is_empty() => Some(self.status.as_str()),
+      _ => None,
+    }
+  }
+}
+
+// header-like blocks separated by blank lines, folded lines unfolded
+fn blocks(text: &str) -> Vec<Vec<(String, String)>> {
+  let mut blocks: Vec<Vec<(String, String)>> = vec![vec![]];
+  for line in text.lines() {
+    let block = blocks.last_mut().unwrap();
+    if line.trim().is_empty() {
+      if !block.is_empty() {
+        blocks.push(vec![]);
+      }
+    } else if line.starts_with([' ', '\t']) {
+      if let Some((_, value)) = block.last_mut() {
+        value.push(' ');
+        value.push_str(line.trim());
+      }
+    } else if let Some((name, value)) = line.split_once(':') {
+      block.push((name.trim().to_string(), value.trim().to_string()));
+    }
+  }
+  blocks.retain(|b| !b.is_empty());
+  blocks
+}
+
+// "rfc822; x@y.com" or "smtp; 550 ..."
+fn without_type(value: &str) -> String {
+  match value.split_once(';') {
+    Some((_, value)) => value.trim().to_string(),
+    None => value.trim().to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DSN: &str = "Reporting-MTA: dns; mx.moon.space\n\
+                     Arrival-Date: Wed, 23 Oct 2024 12:27:21 +0200\n\
+                     \n\
+                     Final-Recipient: rfc822; x@y.com\n\
+                     Action: failed\n\
+                     Status: 5.1.1\n\
+                     Diagnostic-Code: smtp; 550 No such\n \
+                     user\n\
+                     \n\
+                     Final-Recipient: rfc822; lucas@mercure.space\n\
+                     Action: delayed\n\
+                     Status: 4.4.1\n";
+
+  #[test]
+  fn parse() {
+    let entries = DeliveryStatus::parse(DSN);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].recipient, "x@y.com");
+    assert_eq!(entries[0].action, "failed");
+    assert_eq!(entries[0].status, "5.1.1");
+    assert_eq!(entries[0].diagnostic.as_deref(), Some("550 No such user"));
+    assert!(entries[0].is_failure());
+    assert!(!entries[1].is_failure());
+  }
+
+  #[test]
+  fn reason() {
+    let entries = DeliveryStatus::parse(DSN);
+    assert_eq!(entries[0].reason(), Some("550 No such user"));
+    assert_eq!(entries[1].reason(), Some("4.4.1"));
+    assert_eq!(DeliveryStatus::default().reason(), None);
+  }
+}
@@ -23,15 +23,24 @@ use base64::engine::general_purpose;
 use base64::Engine;
 use gmime::prelude::Cast;
 use gmime::traits::{
-  ContentTypeExt, DataWrapperExt, MessageExt, ObjectExt, ParserExt, PartExt, StreamExt, StreamMemExt
+  ContentDispositionExt, ContentTypeExt, DataWrapperExt, HeaderExt, HeaderListExt, MessageExt, MessagePartExt, MultipartExt, ObjectExt, ParserExt, PartExt, StreamExt, StreamMemExt
 };
 use gmime::{
-  glib, InternetAddressExt, InternetAddressList, InternetAddressListExt, Message, Parser, Part, Stream, StreamFs, StreamMem
+  glib, ContentEncoding, InternetAddressExt, InternetAddressList, InternetAddressListExt, Message, MessagePart, Multipart, Object, Parser, Part, Stream, StreamFs, StreamMem
 };
 use nipper::Document;
 
-use crate::message::attachment::Attachment;
-use crate::message::message::MessageParser;
+use crate::message::alternative::Alternative;
+use crate::message::applefile::AppleFile;
+use crate::message::attachment::{Attachment, Encoded};
+use crate::message::authresults::AuthResults;
+use crate::message::dsn::DeliveryStatus;
+use crate::message::embedded;
+use crate::message::message::{normalize_newlines, MessageParser};
+use crate::message::mimepart::MimePart;
+use crate::message::preview;
+use crate::message::rfc2231;
+use crate::message::sniff;
 
 #[allow(unused_variables, dead_code)]
 const O_RDONLY: i32 = 0;
@@ -41,32 +50,116 @@ pub const O_WRONLY: i32 = 1;
 pub const O_RDWR: i32 = 2;
 #[allow(unused_variables, dead_code)]
 pub const O_CREAT: i32 = 100;
+pub const DEFAULT_MAX_DEPTH: usize = 50;
+/// Encoded size above which a base64 attachment is kept as is rather than
+/// decoded, see Attachment::encoded
+const STREAMED_ATTACHMENT_SIZE: i64 = 64 * 1024 * 1024;
 #[allow(unused_variables, dead_code)]
 const INVALID_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
 
 #[derive(Debug, Default, Clone)]
 pub struct ElectronicMail {
   file: String,
+  data: Option<Vec<u8>>,
   pub from: String,
   pub to: String,
+  /// Sender header, when present
+  pub sender: Option<String>,
   pub date: String,
   pub subject: String,
   pub body_html: Option<String>,
   pub body_text: Option<String>,
+  /// declared charsets of the body parts, before transcoding
+  pub html_charset: Option<String>,
+  pub text_charset: Option<String>,
+  /// Some(delsp) when the text body is format=flowed
+  pub text_flowed: Option<bool>,
   pub attachments: Vec<Attachment>,
+  pub alternatives: Vec<Alternative>,
+  pub mime_tree: MimePart,
+  /// the leaf parts, in mime_tree() order, decoded or written out only when
+  /// asked for
+  leaves: Vec<Object>,
+  pub headers: Vec<(String, String)>,
+  pub delivery_status: Vec<DeliveryStatus>,
+  /// maximum multipart nesting walked, deeper parts are ignored
+  pub max_depth: usize,
+  pub truncated: bool,
+  /// type and bytes of the first unnamed part of an unknown type, previewed
+  /// when the message has no text or HTML body
+  unknown_part: Option<(String, Vec<u8>)>,
+  /// related stylesheets by "cid:" URL and Content-Location
+  pub stylesheets: Vec<(String, String)>,
+  /// recoverable anomalies met while parsing
+  pub warnings: Vec<String>,
 }
 
 impl ElectronicMail {
   pub fn new(file: &str) -> ElectronicMail {
     ElectronicMail {
       file: file.to_string(),
+      data: None,
       from: String::new(),
       to: String::new(),
+      sender: None,
       subject: String::new(),
       body_html: None,
       body_text: None,
+      html_charset: None,
+      text_charset: None,
+      text_flowed: None,
       date: String::new(),
       attachments: vec![],
+      alternatives: vec![],
+      leaves: vec![],
+      mime_tree: MimePart::default(),
+      headers: vec![],
+      delivery_status: vec![],
+      max_depth: DEFAULT_MAX_DEPTH,
+      truncated: false,
+      unknown_part: None,
+      stylesheets: vec![],
+      warnings: vec![],
+    }
+  }
+
+  pub fn with_bytes(data: &[u8]) -> ElectronicMail {
+    ElectronicMail {
+      file: String::new(),
+      data: Some(data.to_vec()),
+      from: String::new(),
+      to: String::new(),
+      sender: None,
+      subject: String::new(),
+      body_html: None,
+      body_text: None,
+      html_charset: None,
+      text_charset: None,
+      text_flowed: None,
+      date: String::new(),
+      attachments: vec![],
+      alternatives: vec![],
+      leaves: vec![],
+      mime_tree: MimePart::default(),
+      headers: vec![],
+      delivery_status: vec![],
+      max_depth: DEFAULT_MAX_DEPTH,
+      truncated: false,
+      unknown_part: None,
+      stylesheets: vec![],
+      warnings: vec![],
+    }
+  }
+
+  fn parse_headers(&mut self, message: &Message) {
+    if let Some(list) = message.header_list() {
+      for i in 0..list.count() {
+        if let Some(header) = list.header_at(i) {
+          let name = header.name().map(|n| n.to_string()).unwrap_or_default();
+          let value = header.value().map(|v| v.to_string()).unwrap_or_default();
+          self.headers.push((name, value));
+        }
+      }
     }
   }
 
@@ -108,30 +201,198 @@ impl ElectronicMail {
 
   fn parse_body(&mut self, message: &Message) {
     let mut html: Option<String> = None;
-    message.foreach(|_, current| {
-      log::debug!("part() => {:?}", current.content_id());
-      if let Some(part) = current.dynamic_cast_ref::<Part>() {
-        if part.is_attachment() {
-          self.add_attachment(part);
-        } else {
-          // Note is_attachment() is false for inline (cid)
-          if let Some(content_type) = part.content_type() {
-            if content_type.is_type("text", "html") {
-              html = Some(self.get_content(part));
-            } else if content_type.is_type("text", "plain") {
-              self.body_text = Some(self.get_content(part));
-            } else {
-              self.add_attachment(part);
-            }
-          }
-        }
+    if let Some(root) = message.mime_part() {
+      self.parse_parts(&root, 0, &mut html);
+    }
+    for i in 0..self.alternatives.len() {
+      if !self.alternatives[i].is_text() {
+        self.alternatives[i].body = self.integrate_cid(&self.alternatives[i].body);
       }
-    });
+    }
     if let Some(html) = html {
       self.body_html = Some(self.integrate_cid(&html));
       // for debugging parsed html
       // self.write_debug_html();
+    } else if self.body_text.is_none() {
+      if let Some((mime_type, bytes)) = self.unknown_part.take() {
+        log::debug!("parse_body() => preview of {}", mime_type);
+        self.body_text = Some(preview::describe(&mime_type, &bytes));
+      }
+    }
+  }
+
+  // Explicit walk (instead of Message::foreach) to bound the nesting depth
+  fn parse_parts(&mut self, object: &Object, depth: usize, html: &mut Option<String>) {
+    if self.exceeds_max_depth(depth) {
+      return;
+    }
+    if let Some(multipart) = object.dynamic_cast_ref::<Multipart>() {
+      let content_type = object.content_type();
+      if content_type.as_ref().and_then(|c| c.parameter("boundary")).is_none() {
+        let mime_type = content_type
+          .and_then(|c| c.mime_type())
+          .map(|m| m.to_lowercase())
+          .unwrap_or_else(|| "multipart".to_string());
+        self.warn(format!("{} without a boundary, its parts may be missing", mime_type));
+      }
+      for i in 0..multipart.count() {
+        if let Some(child) = multipart.part(i) {
+          self.parse_parts(&child, depth + 1, html);
+        }
+      }
+    } else if let Some(part) = object.dynamic_cast_ref::<Part>() {
+      log::debug!("part() => {:?}", part.content_id());
+      if part.is_attachment() || self.is_named_text(part) {
+        self.add_attachment(part);
+      } else {
+        // Note is_attachment() is false for inline (cid)
+        let (mime_type, charset) = Self::declared_type(part);
+        match mime_type.as_str() {
+          "text/html" => {
+            let content = self.get_content(part);
+            self.alternatives.push(Alternative::new("text/html", &content));
+            self.html_charset = charset;
+            *html = Some(content);
+          }
+          "text/plain" => {
+            let content = self.get_text_content(part);
+            self.alternatives.push(Alternative::new("text/plain", &content));
+            self.text_charset = charset;
+            self.text_flowed = Self::flowed(part);
+            self.body_text = Some(content);
+          }
+          "text/x-amp-html" => {
+            let content = self.get_content(part);
+            self.alternatives.push(Alternative::new("text/x-amp-html", &content));
+          }
+          "text/css" if !Self::references(part).is_empty() => {
+            let css = self.get_content(part);
+            for reference in Self::references(part) {
+              self.stylesheets.push((reference, css.clone()));
+            }
+          }
+          "message/delivery-status" | "message/global-delivery-status" => {
+            let content = self.get_content(part);
+            self.delivery_status.extend(DeliveryStatus::parse(&content));
+          }
+          // without a name it can't be listed as an attachment
+          _ if !mime_type.starts_with("image/") && Self::filename(part).is_none() => {
+            if self.unknown_part.is_none() {
+              self.unknown_part = Some((mime_type, self.get_bytes(part)));
+            }
+          }
+          _ => self.add_attachment(part),
+        }
+      }
+    }
+  }
+
+  /// Text part after the body of its type, named only by the Content-Type
+  /// "name" parameter, as older mailers attach files without a
+  /// Content-Disposition.
+  fn is_named_text(&self, part: &Part) -> bool {
+    if part.header("Content-Disposition").is_some() {
+      return false;
     }
+    let named = rfc2231::parameter(&part.header("Content-Type").unwrap_or_default(), "name")
+      .is_some_and(|name| !name.trim().is_empty());
+    match Self::declared_type(part).0.as_str() {
+      "text/plain" => named && self.body_text.is_some(),
+      "text/html" => named && self.alternatives.iter().any(|a| a.content_type == "text/html"),
+      _ => false,
+    }
+  }
+
+  /// Some(delsp) for a format=flowed part (RFC 3676)
+  fn flowed(part: &Part) -> Option<bool> {
+    let content_type = part.content_type()?;
+    let format = content_type.parameter("format")?;
+    if !format.trim().eq_ignore_ascii_case("flowed") {
+      return None;
+    }
+    let delsp = content_type.parameter("delsp");
+    Some(delsp.is_some_and(|delsp| delsp.trim().eq_ignore_ascii_case("yes")))
+  }
+
+  /// Lowercase mime type and charset of a part, text/plain; charset=us-ascii
+  /// when it has no Content-Type header (RFC 2045, 5.2).
+  fn declared_type(part: &Part) -> (String, Option<String>) {
+    let content_type = match part.header("Content-Type") {
+      Some(_) => part.content_type(),
+      None => None,
+    };
+    match content_type {
+      Some(content_type) => (
+        content_type
+          .mime_type()
+          .map(|m| m.to_lowercase())
+          .unwrap_or_default(),
+        content_type.parameter("charset").map(|c| c.to_string()),
+      ),
+      None => ("text/plain".to_string(), Some("us-ascii".to_string())),
+    }
+  }
+
+  fn warn(&mut self, warning: String) {
+    log::warn!("{}", warning);
+    if !self.warnings.contains(&warning) {
+      self.warnings.push(warning);
+    }
+  }
+
+  fn exceeds_max_depth(&mut self, depth: usize) -> bool {
+    if depth <= self.max_depth {
+      return false;
+    }
+    if !self.truncated {
+      self.warn(format!("MIME nesting deeper than {}, message truncated", self.max_depth));
+      self.truncated = true;
+    }
+    true
+  }
+
+  fn parse_mime_tree(&mut self, object: &Object, depth: usize) -> MimePart {
+    let mut node = MimePart::default();
+    if let Some(content_type) = object.content_type() {
+      if let Some(mime_type) = content_type.mime_type() {
+        node.content_type = mime_type.to_string();
+      }
+    }
+    if let Some(disposition) = object.content_disposition() {
+      node.disposition = disposition.disposition().map(|d| d.to_string());
+    }
+    node.encoding = object
+      .header("Content-Transfer-Encoding")
+      .map(|e| e.trim().to_lowercase());
+
+    if let Some(multipart) = object.dynamic_cast_ref::<Multipart>() {
+      if self.exceeds_max_depth(depth + 1) {
+        return node;
+      }
+      for i in 0..multipart.count() {
+        if let Some(child) = multipart.part(i) {
+          let child = self.parse_mime_tree(&child, depth + 1);
+          node.children.push(child);
+        }
+      }
+    } else if let Some(message_part) = object.dynamic_cast_ref::<MessagePart>() {
+      if self.exceeds_max_depth(depth + 1) {
+        return node;
+      }
+      if let Some(child) = message_part.message().and_then(|m| m.mime_part()) {
+        let child = self.parse_mime_tree(&child, depth + 1);
+        node.children.push(child);
+      }
+    } else if let Some(part) = object.dynamic_cast_ref::<Part>() {
+      node.content_id = part.content_id().map(|id| id.to_string());
+      node.size = self.get_bytes(part).len();
+      node.index = Some(self.leaves.len());
+      self.leaves.push(object.clone());
+    }
+    if !node.children.is_empty() {
+      node.size = node.children.iter().map(|c| c.size).sum();
+    }
+    node
   }
 
   #[allow(dead_code)]
@@ -150,23 +411,23 @@ impl ElectronicMail {
     if let Some(id) = part.content_id() {
       content_id = id.to_string();
     }
-    if let Some(file) = part.filename() {
-      let filename = file.to_string();
+    if let Some(filename) = Self::filename(part) {
       if let Some(content_type) = part.content_type() {
         if let Some(parameter) = content_type.mime_type() {
           mime_type = Some(parameter.to_string());
         }
-        if let Some(content) = part.content() {
-          let stream = StreamMem::new();
-          content.write_to_stream(&stream);
-          let body = stream.byte_array().unwrap().to_vec();
-          stream.close();
-
+        if part.content().is_some() {
+          let encoded = Self::large_base64(part);
           return Some(Attachment {
             content_id,
             filename,
             mime_type,
-            body,
+            body: match encoded {
+              Some(_) => vec![],
+              None => self.get_bytes(part),
+            },
+            description: part.content_description().map(|d| d.trim().to_string()),
+            encoded,
           });
         }
       }
@@ -174,7 +435,49 @@ impl ElectronicMail {
     None
   }
 
+  /// URLs the HTML part can reference a related part by
+  fn references(part: &Part) -> Vec<String> {
+    let content_id = part
+      .content_id()
+      .map(|id| format!("cid:{}", id.trim().trim_matches(['<', '>'])));
+    let location = part.header("Content-Location").map(|l| l.trim().to_string());
+    [content_id, location]
+      .into_iter()
+      .flatten()
+      .filter(|reference| reference != "cid:" && !reference.is_empty())
+      .collect()
+  }
+
+  /// Content-Disposition filename or Content-Type name, decoding the RFC 2231
+  /// continuations and charsets ourselves so long names don't come out
+  /// truncated or percent-encoded.
+  fn filename(part: &Part) -> Option<String> {
+    let decoded = [("Content-Disposition", "filename"), ("Content-Type", "name")]
+      .iter()
+      .find_map(|(header, name)| rfc2231::parameter(&part.header(header)?, name))
+      .filter(|filename| !filename.trim().is_empty());
+    decoded.or_else(|| part.filename().map(|f| f.to_string()))
+  }
+
+  fn first_header(&self, name: &str) -> Option<String> {
+    self
+      .headers
+      .iter()
+      .find(|(n, _)| n.eq_ignore_ascii_case(name))
+      .map(|(_, value)| value.trim().to_string())
+  }
+
   // It seems that gmime-rs has a memory free bug with g_mime_message_get_date()
+  fn decode_date(value: &str) -> Option<String> {
+    let value = std::ffi::CString::new(value).ok()?;
+    let date: Option<glib::DateTime> = unsafe {
+      glib::translate::from_glib_full(gmime::ffi::g_mime_utils_header_decode_date(
+        value.as_ptr(),
+      ))
+    };
+    date.and_then(|date| date.format("%Y-%m-%d %H:%M:%S").ok().map(|f| f.into()))
+  }
+
   fn my_mime_message_get_date(e: &Message) -> Option<String> {
     let date: Option<glib::DateTime> = unsafe {
       glib::translate::from_glib_none(gmime::ffi::g_mime_message_get_date(
@@ -205,6 +508,14 @@ impl ElectronicMail {
     false
   }
 
+  // ASCII is a subset of UTF-8
+  fn is_utf8(charset: &str) -> bool {
+    matches!(
+      charset.to_lowercase().as_str(),
+      "utf-8" | "utf8" | "us-ascii" | "ascii"
+    )
+  }
+
   fn integrate_cid(&self, body: &str) -> String {
     let document = Document::from(body);
     document.select("img").iter().for_each(|mut node| {
@@ -215,9 +526,14 @@ impl ElectronicMail {
           if let Some(attachment) = self.attachments.iter().find(|a| a.content_id == cid) {
             log::debug!("Found CID Attachment => {}", attachment.filename);
             if let Some(mime_type) = attachment.mime_type.as_deref() {
-              let b64 = general_purpose::STANDARD.encode(&attachment.body);
-              log::debug!("Found CID with mime type => {}", mime_type);
-              node.set_attr("src", &format!("data:{};base64,{}", mime_type, &b64));
+              match attachment.bytes() {
+                Ok(bytes) => {
+                  let b64 = general_purpose::STANDARD.encode(bytes);
+                  log::debug!("Found CID with mime type => {}", mime_type);
+                  node.set_attr("src", &format!("data:{};base64,{}", mime_type, &b64));
+                }
+                Err(e) => log::warn!("integrate_cid({}) => {}", attachment.filename, e),
+              }
             }
           }
         }
@@ -226,9 +542,73 @@ impl ElectronicMail {
     document.html().to_string()
   }
 
-  fn get_content(&self, part: &Part) -> String {
+  fn get_content(&mut self, part: &Part) -> String {
     let mut charset: Option<glib::GString> = None;
+    if let Some(content_type) = part.content_type() {
+      charset = content_type.parameter("charset");
+    }
+    let bytes = self.get_bytes(part);
+    self.to_text(bytes, charset)
+  }
+
+  // Same as get_content(), moving uuencoded/yEnc blocks to attachments
+  fn get_text_content(&mut self, part: &Part) -> String {
+    let mut charset: Option<glib::GString> = None;
+    if let Some(content_type) = part.content_type() {
+      charset = content_type.parameter("charset");
+    }
+    let (content, attachments) = embedded::extract(&self.get_bytes(part));
+    self.attachments.extend(attachments);
+    self.to_text(content, charset)
+  }
+
+  fn to_text(&mut self, array: Vec<u8>, charset: Option<glib::GString>) -> String {
+    if ElectronicMail::is_latin1(charset.clone()) {
+      log::debug!("get_content() ISO-8859-1");
+      return normalize_newlines(&ElectronicMail::latin1_to_string(&array));
+    }
+    let charset = charset.map(|c| c.to_string());
+    if let Some(charset) = charset.as_deref().filter(|c| !ElectronicMail::is_utf8(c)) {
+      match glib::convert(&array, "UTF-8", charset) {
+        Ok((converted, _)) => {
+          log::debug!("get_content() {}", charset);
+          return normalize_newlines(&String::from_utf8_lossy(&converted));
+        }
+        Err(e) => {
+          log::debug!("get_content() {} => {}", charset, e);
+          self.warn(format!("Unsupported charset \"{}\", read as UTF-8", charset));
+        }
+      }
+    }
+    match String::from_utf8(array) {
+      Ok(body) => {
+        log::debug!("get_content() UTF8");
+        normalize_newlines(&body)
+      }
+      Err(e) => {
+        log::debug!("get_content() FAILED => to convert to string");
+        self.warn(format!(
+          "Text of charset \"{}\" is not valid UTF-8, undecodable bytes are replaced",
+          charset.as_deref().unwrap_or("us-ascii")
+        ));
+        // the whole text, only the undecodable bytes are lost
+        normalize_newlines(&String::from_utf8_lossy(e.as_bytes()))
+      }
+    }
+  }
 
+  // the part as gmime writes it back: its headers, and its body as still
+  // encoded in the parsed stream
+  fn raw_bytes(object: &Object) -> Vec<u8> {
+    let stream = StreamMem::new();
+    if object.write_to_stream(None, &stream) < 0 {
+      log::error!("raw_bytes() => write_to_stream() failed");
+      return vec![];
+    }
+    stream.byte_array().map(|bytes| bytes.to_vec()).unwrap_or_default()
+  }
+
+  fn get_bytes(&self, part: &Part) -> Vec<u8> {
     log::debug!(
       "get_content() => part.content_type() {:?}",
       part.content_type()
@@ -242,37 +622,86 @@ impl ElectronicMail {
       part.content_disposition()
     );
 
-    if let Some(content_type) = part.content_type() {
-      charset = content_type.parameter("charset");
-    }
-
     if let Some(content) = part.content() {
       let stream = StreamMem::new();
-      let size = content.write_to_stream(&stream) as u32;
+      let size = match content.encoding() {
+        // raw bytes, copied as is: decoding them would corrupt any "=XX" or
+        // base64-looking text
+        ContentEncoding::_7bit | ContentEncoding::_8bit | ContentEncoding::Binary => {
+          match content.stream() {
+            Some(raw) => {
+              raw.reset();
+              raw.write_to_stream(&stream) as u32
+            }
+            None => 0,
+          }
+        }
+        ContentEncoding::Base64 | ContentEncoding::Quotedprintable | ContentEncoding::Uuencode => {
+          content.write_to_stream(&stream) as u32
+        }
+        // no (or an unknown) Content-Transfer-Encoding, gmime leaves it as is
+        _ => content.write_to_stream(&stream) as u32,
+      };
 
       if size > 0 {
-        let array: Vec<u8> = stream.byte_array().unwrap().to_vec();
-
-        if ElectronicMail::is_latin1(charset) {
-          log::debug!("get_content() ISO-8859-1");
-          return ElectronicMail::latin1_to_string(&array);
-        } else if let Some(body) = String::from_utf8(array).ok() {
-          log::debug!("get_content() UTF8");
-          return body;
-        } else {
-          log::debug!("get_content() FAILED => to convert to string");
-        }
+        return stream.byte_array().unwrap().to_vec();
       } else {
         log::debug!("get_content() FAILED => size");
       }
     } else {
       log::debug!("get_content() FAILED => part.content()");
     }
-    String::new()
+    vec![]
+  }
+
+  // base64 source of a large part, see Attachment::encoded
+  fn large_base64(part: &Part) -> Option<Encoded> {
+    let content = part.content()?;
+    if !matches!(content.encoding(), ContentEncoding::Base64) {
+      return None;
+    }
+    let raw = content.stream()?;
+    if raw.length() < STREAMED_ATTACHMENT_SIZE {
+      return None;
+    }
+    log::debug!("large_base64() => {} bytes kept encoded", raw.length());
+    raw.reset();
+    let stream = StreamMem::new();
+    if raw.write_to_stream(&stream) < 0 {
+      return None;
+    }
+    stream.byte_array().map(|bytes| Encoded::new(bytes.to_vec()))
   }
 
   fn add_attachment(&mut self, part: &Part) {
-    if let Some(attachment) = self.get_attachment(part) {
+    if let Some(mut attachment) = self.get_attachment(part) {
+      let applefile = attachment
+        .mime_type
+        .as_deref()
+        .is_some_and(|m| m.eq_ignore_ascii_case("application/applefile"));
+      if applefile {
+        // the AppleDouble header only has the resource fork, the data fork
+        // is the next part of the multipart/appledouble
+        let data = match attachment.bytes() {
+          Ok(data) => data.to_vec(),
+          Err(e) => {
+            log::warn!("add_attachment({}) => {}", attachment.filename, e);
+            return;
+          }
+        };
+        let Some(file) = AppleFile::parse(&data).filter(|f| f.data_fork.is_some()) else {
+          log::debug!("add_attachment() => skipped resource fork {}", attachment.filename);
+          return;
+        };
+        attachment.body = file.data_fork.unwrap_or_default();
+        attachment.encoded = None;
+        attachment.filename = file.real_name.unwrap_or(attachment.filename);
+        attachment.mime_type = Some(
+          sniff::sniff(&attachment.body)
+            .unwrap_or("application/octet-stream")
+            .to_string(),
+        );
+      }
       log::debug!(
         "add_attachment() => added attachment => {}",
         attachment.filename
@@ -299,6 +728,7 @@ mod tests {
   use std::error::Error;
   use std::path::Path;
 
+  use crate::message::alternative;
   use crate::message::electronicmail::ElectronicMail;
   use crate::message::message::Message;
 
@@ -314,6 +744,7 @@ mod tests {
     let attachment = &parser.attachments[0];
     assert_eq!(attachment.filename, "Deus_Gnome.png");
     assert_eq!(attachment.content_id, "ii_m2lqbrhv0");
+    assert_eq!(attachment.content_id(), Some("ii_m2lqbrhv0"));
     assert_eq!(attachment.mime_type.as_ref().unwrap(), "image/png");
     let _name = attachment.write_to_tmp()?;
     let _file = Path::new(&_name);
@@ -369,6 +800,400 @@ mod tests {
     Ok(())
   }
 
+  #[test]
+  fn test_sample_uuencode() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/uuencode.eml");
+    parser.parse()?;
+    assert_eq!(parser.subject, "Old school");
+    assert_eq!(parser.attachments.len(), 1);
+    assert_eq!(parser.attachments[0].filename, "hello.txt");
+    assert_eq!(parser.attachments[0].body, b"Hello, World!\n");
+    let text = parser.body_text.unwrap();
+    assert!(text.contains("Here is the file"));
+    assert!(!text.contains("begin 644"));
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_auth_results() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/auth.eml");
+    parser.parse()?;
+    let results = parser.auth_results();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].authserv_id, "mx.mercure.space");
+    assert_eq!(results[0].result("spf").unwrap().result, "pass");
+    assert_eq!(results[0].result("dmarc").unwrap().result, "fail");
+    assert_eq!(results[1].authserv_id, "relay.mercure.space");
+    assert_eq!(results[1].result("spf").unwrap().result, "pass");
+
+    Ok(())
+  }
+
+  fn nested_multipart(depth: usize) -> Vec<u8> {
+    let mut eml = String::from("MIME-Version: 1.0\r\nSubject: Nested\r\nFrom: john@moon.space\r\n");
+    for i in 0..depth {
+      eml.push_str(&format!(
+        "Content-Type: multipart/mixed; boundary=\"b{}\"\r\n\r\n--b{}\r\n",
+        i, i
+      ));
+    }
+    eml.push_str("Content-Type: text/plain\r\n\r\nHello\r\n");
+    for i in (0..depth).rev() {
+      eml.push_str(&format!("--b{}--\r\n", i));
+    }
+    eml.into_bytes()
+  }
+
+  #[test]
+  fn test_sample_latin1() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/latin1.eml");
+    parser.parse()?;
+    assert_eq!(parser.charset().as_deref(), Some("ISO-8859-1"));
+    assert_eq!(parser.body_text.as_deref().map(|t| t.trim()), Some("Un café crème, s'il vous plaît."));
+
+    let mut parser = ElectronicMail::with_bytes(&nested_multipart(1));
+    parser.parse()?;
+    assert_eq!(parser.charset(), None);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_8bit() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/8bit.eml");
+    parser.parse()?;
+    assert_eq!(
+      parser.body_text.as_deref().map(|t| t.trim()),
+      Some("Crème brûlée à 5€ =C3=A9 (not quoted-printable)")
+    );
+    assert_eq!(parser.attachments.len(), 1);
+    // the trailing newline belongs to the boundary
+    assert_eq!(parser.attachments[0].body, "SGVsbG8= =41 é\n".as_bytes());
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_flowed() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/flowed.eml");
+    parser.parse()?;
+    assert_eq!(parser.text_flowed, Some(false));
+    let reflowed = parser.body_text_reflowed().unwrap();
+    assert!(reflowed.contains(
+      "This paragraph was wrapped by the client at 72 columns, the reader joins the lines back.\n"
+    ));
+    assert!(reflowed.contains("\nThe second line is a hard break.\n"));
+    // the literal body keeps the wrapping
+    assert!(parser.body_text.unwrap().contains("at 72 \ncolumns"));
+
+    let mut parser = ElectronicMail::new("tests/text.eml");
+    parser.parse()?;
+    assert_eq!(parser.text_flowed, None);
+    assert_eq!(parser.body_text_reflowed(), parser.body_text);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_rfc2231() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/rfc2231.eml");
+    parser.parse()?;
+    assert_eq!(parser.attachments.len(), 1);
+    assert_eq!(
+      parser.attachments[0].filename,
+      "Rapport annuel de l’observatoire lunaire — été 2024 (version finale).txt"
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_organization() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/organization.eml");
+    parser.parse()?;
+    assert_eq!(parser.organization().as_deref(), Some("Example Corp"));
+
+    let mut parser = ElectronicMail::with_bytes(
+      b"From: john@moon.space\r\nOrganization: =?UTF-8?Q?Soci=C3=A9t=C3=A9_lunaire?=\r\n\r\nHi\r\n",
+    );
+    parser.parse()?;
+    assert_eq!(parser.organization().as_deref(), Some("Soci\u{e9}t\u{e9} lunaire"));
+
+    let mut parser = ElectronicMail::new("sample.eml");
+    parser.parse()?;
+    assert_eq!(parser.organization(), None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_sender() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::with_bytes(
+      b"From: John Doe <john@moon.space>\r\nSender: =?UTF-8?Q?Liste_de_la_Lune?= <list-bounces@lists.moon.space>\r\n\r\nHi\r\n",
+    );
+    parser.parse()?;
+    assert_eq!(parser.from, "John Doe <john@moon.space>");
+    assert_eq!(
+      parser.sender().as_deref(),
+      Some("Liste de la Lune <list-bounces@lists.moon.space>")
+    );
+    assert_eq!(parser.sent_via(), parser.sender());
+
+    let mut parser = ElectronicMail::with_bytes(
+      b"From: John Doe <john@moon.space>\r\nSender: JOHN@moon.space\r\n\r\nHi\r\n",
+    );
+    parser.parse()?;
+    assert!(parser.sender().is_some());
+    assert_eq!(parser.sent_via(), None);
+
+    let mut parser = ElectronicMail::new("sample.eml");
+    parser.parse()?;
+    assert_eq!(parser.sender(), None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_name_only() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/name-only.eml");
+    parser.parse()?;
+    let filenames: Vec<&str> = parser.attachments.iter().map(|a| a.filename.as_str()).collect();
+    assert_eq!(filenames, vec!["minutes.pdf", "notes.txt"]);
+    assert_eq!(parser.attachments[0].mime_type.as_deref(), Some("application/pdf"));
+    assert!(parser.attachments[1].body.starts_with(b"Bring the telescope."));
+    assert_eq!(parser.body_text.as_deref().map(str::trim), Some("Minutes and notes attached."));
+    Ok(())
+  }
+
+  #[test]
+  fn test_related_stylesheet() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::with_bytes(
+      b"From: john@moon.space\r\n\
+        Content-Type: multipart/related; boundary=\"rel\"\r\n\r\n\
+        --rel\r\nContent-Type: text/html\r\n\r\n\
+        <html><head><link rel=\"stylesheet\" href=\"cid:style@moon.space\"></head>\
+        <body><p class=\"title\">Hello</p></body></html>\r\n\
+        --rel\r\nContent-Type: text/css\r\nContent-ID: <style@moon.space>\r\n\
+        Content-Location: style.css\r\n\r\n\
+        .title { color: navy; }\r\n\
+        --rel--\r\n",
+    );
+    parser.parse()?;
+    assert!(parser.attachments.is_empty());
+    let references: Vec<&str> = parser.stylesheets.iter().map(|(r, _)| r.as_str()).collect();
+    assert_eq!(references, vec!["cid:style@moon.space", "style.css"]);
+    let html = crate::html::Html::new(&parser.body_html.unwrap(), false)
+      .with_stylesheets(&parser.stylesheets)
+      .safe();
+    assert!(html.contains(".title { color: navy; }"));
+    assert!(!html.contains("<link"));
+    Ok(())
+  }
+
+  #[test]
+  fn test_warnings() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::with_bytes(
+      b"From: john@moon.space\r\nContent-Type: text/plain; charset=x-moon\r\n\r\n\xf0\xd2\xc9\xd7\xc5\xd4\r\n",
+    );
+    parser.parse()?;
+    assert_eq!(
+      parser.warnings(),
+      vec![
+        "Unsupported charset \"x-moon\", read as UTF-8",
+        "Text of charset \"x-moon\" is not valid UTF-8, undecodable bytes are replaced",
+      ]
+    );
+
+    let mut parser = ElectronicMail::with_bytes(&nested_multipart(1));
+    parser.parse()?;
+    assert!(parser.warnings().is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_charset_body() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::with_bytes(
+      b"From: john@moon.space\r\nContent-Type: text/plain; charset=koi8-r\r\n\r\n\xf0\xd2\xc9\xd7\xc5\xd4\r\n",
+    );
+    parser.parse()?;
+    assert_eq!(parser.body_text.as_deref().map(|text| text.trim()), Some("Привет"));
+    assert!(parser.warnings().is_empty());
+
+    // converted whole, not as a bounded preview
+    let long = "A".repeat(8000);
+    let mut eml = format!(
+      "From: john@moon.space\r\nContent-Type: text/html; charset=windows-1252\r\n\r\n\
+       <p>{}</p><p>It",
+      long
+    )
+    .into_bytes();
+    eml.extend_from_slice(b"\x92s here</p>\r\n");
+    let mut parser = ElectronicMail::with_bytes(&eml);
+    parser.parse()?;
+    let html = parser.body_html.unwrap();
+    assert!(html.contains(&long));
+    assert!(html.contains("<p>It\u{2019}s here</p>"));
+    Ok(())
+  }
+
+  #[test]
+  fn test_unknown_body() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::with_bytes(
+      b"From: john@moon.space\r\nContent-Type: application/x-moon\r\n\r\nphase\x00\x01\r\n",
+    );
+    parser.parse()?;
+    let text = parser.body_text.unwrap();
+    assert!(text.starts_with("[Unknown content type application/x-moon, "));
+    assert!(text.contains("|phase.."));
+    assert!(parser.attachments.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_cr() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/cr.eml");
+    parser.parse()?;
+    let text = parser.body_text.unwrap();
+    assert!(!text.contains('\r'));
+    assert_eq!(
+      text.lines().collect::<Vec<&str>>(),
+      vec!["Hello Lucas,", "The first line.", "The second line.", "", "John"]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_description() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/description.eml");
+    parser.parse()?;
+    assert_eq!(parser.attachments.len(), 2);
+    assert_eq!(parser.attachments[0].filename, "q3.pdf");
+    assert_eq!(
+      parser.attachments[0].description.as_deref(),
+      Some("Quarterly report, third quarter")
+    );
+    assert_eq!(parser.attachments[1].description, None);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_duplicate_headers() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/duplicate.eml");
+    parser.parse()?;
+    assert_eq!(parser.subject(), "Original subject");
+    assert_eq!(parser.date(), "2024-10-23 12:27:21");
+    let subjects: Vec<String> = parser
+      .headers()
+      .into_iter()
+      .filter(|(name, _)| name == "Subject")
+      .map(|(_, value)| value)
+      .collect();
+    assert_eq!(subjects, vec!["Original subject", "[list] Injected subject"]);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_language() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::with_bytes(
+      b"From: john@moon.space\r\nSubject: Bonjour\r\nContent-Language: fr-FR\r\nContent-Type: text/plain\r\n\r\nBonjour\r\n",
+    );
+    parser.parse()?;
+    assert_eq!(parser.language().as_deref(), Some("fr-FR"));
+
+    let mut parser = ElectronicMail::new("tests/text.eml");
+    parser.parse()?;
+    assert_eq!(parser.language(), None);
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_no_content_type() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/no-content-type.eml");
+    parser.parse()?;
+    assert_eq!(
+      parser.body_text.as_deref().map(|t| t.trim()),
+      Some("Hello Lucas,\n\nThis part declares no Content-Type.")
+    );
+    assert_eq!(parser.charset().as_deref(), Some("us-ascii"));
+    assert_eq!(parser.body_html, None);
+    assert_eq!(parser.attachments.len(), 1);
+    assert_eq!(parser.attachments[0].filename, "report.pdf");
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_appledouble() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/appledouble.eml");
+    parser.parse()?;
+    assert_eq!(parser.attachments.len(), 1);
+    assert_eq!(parser.attachments[0].filename, "notes.txt");
+    assert_eq!(parser.attachments[0].mime_type.as_deref(), Some("text/plain"));
+    assert_eq!(
+      String::from_utf8_lossy(&parser.attachments[0].body).trim(),
+      "Data fork content."
+    );
+    assert_eq!(parser.body_text.as_deref().map(|t| t.trim()), Some("Notes attached."));
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_bounce() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/bounce.eml");
+    parser.parse()?;
+    let status = parser.delivery_status();
+    assert_eq!(status.len(), 1);
+    assert_eq!(status[0].recipient, "x@y.com");
+    assert!(status[0].is_failure());
+    assert_eq!(status[0].reason(), Some("550 5.1.1 No such user"));
+    assert!(parser.attachments.is_empty());
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_sample_amp() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::new("tests/amp.eml");
+    parser.parse()?;
+    let alternatives = parser.alternatives();
+    let types: Vec<&str> = alternatives.iter().map(|a| a.content_type.as_str()).collect();
+    assert_eq!(types, vec!["text/plain", "text/x-amp-html", "text/html"]);
+    let preferred = alternative::preferred(&alternatives).unwrap();
+    assert_eq!(alternatives[preferred].content_type, "text/html");
+    assert!(alternatives[preferred].body.contains("Regular HTML"));
+    assert!(parser.attachments.is_empty());
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_nested_multipart() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::with_bytes(&nested_multipart(10));
+    parser.parse()?;
+    assert!(!parser.is_truncated());
+    assert_eq!(parser.body_text.as_deref().map(|t| t.trim()), Some("Hello"));
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_too_deeply_nested_multipart() -> Result<(), Box<dyn Error>> {
+    let mut parser = ElectronicMail::with_bytes(&nested_multipart(200));
+    parser.parse()?;
+    assert_eq!(parser.subject, "Nested");
+    assert!(parser.is_truncated());
+    assert_eq!(parser.body_text, None);
+
+    let mut parser = ElectronicMail::with_bytes(&nested_multipart(10));
+    parser.max_depth = 5;
+    parser.parse()?;
+    assert!(parser.is_truncated());
+
+    Ok(())
+  }
+
   #[test]
   fn test_sample_php() -> Result<(), Box<dyn Error>> {
     let mut parser = ElectronicMail::new("tests/test-php.eml");
@@ -416,26 +1241,47 @@ mod tests {
 
 impl super::message::Message for ElectronicMail {
   fn parse(&mut self) -> Result<(), Box<dyn Error>> {
-    let stream: Stream = StreamFs::open(&self.file, O_RDONLY, 0644)?;
+    let stream: Stream = match &self.data {
+      Some(data) => StreamMem::with_buffer(data).upcast(),
+      None => StreamFs::open(&self.file, O_RDONLY, 0644)?,
+    };
     let parser = Parser::with_stream(&stream);
     let message = parser.construct_message(None);
     let mut isok = false;
 
     if let Some(eml) = &message {
       isok = true;
+      self.parse_headers(&eml);
       if let Some(from) = &eml.from() {
         self.from = self.internet_list(from);
       }
+      self.sender = eml
+        .sender()
+        .map(|sender| self.internet_list(&sender))
+        .filter(|sender| !sender.is_empty());
       self.to = self.internet_list(&self.merge_to(&eml));
-      if let Some(subject) = &eml.subject() {
+      // gmime keeps the last Subject/Date when there are duplicates, the
+      // first one (the original, not one injected by a list) is shown
+      if let Some(subject) = self.first_header("Subject") {
+        self.subject = subject;
+      } else if let Some(subject) = &eml.subject() {
         self.subject = subject.to_string();
       }
-      if let Some(date) = ElectronicMail::my_mime_message_get_date(&eml) {
+      if let Some(date) = self
+        .first_header("Date")
+        .and_then(|d| ElectronicMail::decode_date(&d))
+      {
+        self.date = date;
+      } else if let Some(date) = ElectronicMail::my_mime_message_get_date(&eml) {
         self.date = date;
       }
       self.parse_body(&eml);
+      if let Some(root) = eml.mime_part() {
+        self.mime_tree = self.parse_mime_tree(&root, 0);
+      }
     }
-    stream.close();
+    // left open: the leaves read their content from it on demand, it's
+    // closed when the last of them is dropped
 
     if !isok {
       log::error!("parse() => no message");
@@ -464,6 +1310,10 @@ impl super::message::Message for ElectronicMail {
     self.attachments.clone()
   }
 
+  fn attachments_ref(&self) -> &[Attachment] {
+    &self.attachments
+  }
+
   fn body_html(&self) -> Option<String> {
     self.body_html.clone()
   }
@@ -471,4 +1321,80 @@ impl super::message::Message for ElectronicMail {
   fn body_text(&self) -> Option<String> {
     self.body_text.clone()
   }
+
+  fn charset(&self) -> Option<String> {
+    match self.body_html {
+      Some(_) => self.html_charset.clone(),
+      None => self.text_charset.clone(),
+    }
+  }
+
+  fn language(&self) -> Option<String> {
+    // "en, fr" lists several languages, the first one is the main one
+    self
+      .first_header("Content-Language")
+      .and_then(|value| value.split(',').next().map(|l| l.trim().to_string()))
+      .filter(|language| !language.is_empty())
+  }
+
+  fn sender(&self) -> Option<String> {
+    self.sender.clone()
+  }
+
+  fn organization(&self) -> Option<String> {
+    // gmime hands out the header values RFC 2047 decoded
+    self
+      .first_header("Organization")
+      .filter(|organization| !organization.is_empty())
+  }
+
+  fn mime_tree(&self) -> MimePart {
+    self.mime_tree.clone()
+  }
+
+  fn is_truncated(&self) -> bool {
+    self.truncated
+  }
+
+  fn warnings(&self) -> Vec<String> {
+    self.warnings.clone()
+  }
+
+  fn stylesheets(&self) -> Vec<(String, String)> {
+    self.stylesheets.clone()
+  }
+
+  fn alternatives(&self) -> Vec<Alternative> {
+    self.alternatives.clone()
+  }
+
+  fn headers(&self) -> Vec<(String, String)> {
+    self.headers.clone()
+  }
+
+  fn part_bytes(&self, index: usize) -> Option<Vec<u8>> {
+    let part = self.leaves.get(index)?.dynamic_cast_ref::<Part>()?;
+    Some(self.get_bytes(part))
+  }
+
+  fn raw_part(&self, index: usize) -> Option<Vec<u8>> {
+    self.leaves.get(index).map(Self::raw_bytes)
+  }
+
+  fn text_flowed(&self) -> Option<bool> {
+    self.text_flowed
+  }
+
+  fn delivery_status(&self) -> Vec<DeliveryStatus> {
+    self.delivery_status.clone()
+  }
+
+  fn auth_results(&self) -> Vec<AuthResults> {
+    self
+      .headers
+      .iter()
+      .filter(|(name, _)| name.eq_ignore_ascii_case("Authentication-Results"))
+      .map(|(_, value)| AuthResults::parse(value))
+      .collect()
+  }
 }
@@ -0,0 +1,193 @@
+/* embedded.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use crate::message::attachment::Attachment;
+
+enum Block {
+  Uuencode(String),
+  Yenc(String),
+}
+
+/// Extracts uuencoded ("begin 644 file") and yEnc ("=ybegin ... name=file")
+/// blocks embedded in a text body. Returns the body without those blocks
+/// and the decoded attachments. Unterminated blocks are left in the body.
+pub fn extract(data: &[u8]) -> (Vec<u8>, Vec<Attachment>) {
+  let mut text: Vec<u8> = Vec::with_capacity(data.len());
+  let mut attachments: Vec<Attachment> = vec![];
+  let mut block: Option<Block> = None;
+  let mut raw: Vec<u8> = vec![];
+  let mut body: Vec<u8> = vec![];
+
+  for line in data.split_inclusive(|&c| c == b'\n') {
+    let trimmed = trim_eol(line);
+    match &block {
+      None => {
+        if let Some(filename) = uuencode_begin(trimmed) {
+          block = Some(Block::Uuencode(filename));
+        } else if let Some(filename) = yenc_begin(trimmed) {
+          block = Some(Block::Yenc(filename));
+        } else {
+          text.extend_from_slice(line);
+          continue;
+        }
+        raw.extend_from_slice(line);
+      }
+      Some(current) => {
+        raw.extend_from_slice(line);
+        let filename = match current {
+          Block::Uuencode(filename) if trimmed == b"end" => filename,
+          Block::Yenc(filename) if trimmed.starts_with(b"=yend") => filename,
+          Block::Uuencode(_) => {
+            uudecode_line(trimmed, &mut body);
+            continue;
+          }
+          Block::Yenc(_) => {
+            if !trimmed.starts_with(b"=ypart ") {
+              ydecode_line(trimmed, &mut body);
+            }
+            continue;
+          }
+        };
+        log::debug!("extract() => embedded attachment {}", filename);
+        attachments.push(Attachment {
+          filename: filename.clone(),
+          content_id: "none".to_string(),
+          body: std::mem::take(&mut body),
+          mime_type: Some("application/octet-stream".to_string()),
+          description: None,
+          encoded: None,
+        });
+        raw.clear();
+        block = None;
+      }
+    }
+  }
+  if block.is_some() {
+    log::warn!("extract() => unterminated embedded block, kept as text");
+    text.extend_from_slice(&raw);
+  }
+  (text, attachments)
+}
+
+fn trim_eol(line: &[u8]) -> &[u8] {
+  let mut end = line.len();
+  while end > 0 && (line[end - 1] == b'\n' || line[end - 1] == b'\r') {
+    end -= 1;
+  }
+  &line[..end]
+}
+
+fn uuencode_begin(line: &[u8]) -> Option<String> {
+  let line = std::str::from_utf8(line).ok()?;
+  let (mode, filename) = line.strip_prefix("begin ")?.split_once(' ')?;
+  let filename = filename.trim();
+  if mode.len() < 3 || mode.len() > 4 || !mode.chars().all(|c| ('0'..='7').contains(&c)) {
+    return None;
+  }
+  if filename.is_empty() {
+    return None;
+  }
+  Some(filename.to_string())
+}
+
+fn yenc_begin(line: &[u8]) -> Option<String> {
+  let line = String::from_utf8_lossy(line);
+  if !line.starts_with("=ybegin ") {
+    return None;
+  }
+  // name is always the last keyword and may contain spaces
+  let (_, filename) = line.split_once(" name=")?;
+  let filename = filename.trim();
+  if filename.is_empty() {
+    return None;
+  }
+  Some(filename.to_string())
+}
+
+fn uudecode_line(line: &[u8], out: &mut Vec<u8>) {
+  if line.is_empty() {
+    return;
+  }
+  let len = (line[0].wrapping_sub(32) & 63) as usize;
+  let mut chars: Vec<u8> = line[1..].iter().map(|c| c.wrapping_sub(32) & 63).collect();
+  // some encoders strip trailing spaces (zero values)
+  let needed = len.div_ceil(3) * 4;
+  if chars.len() < needed {
+    chars.resize(needed, 0);
+  }
+  let mut decoded: Vec<u8> = Vec::with_capacity(needed / 4 * 3);
+  for chunk in chars.chunks(4) {
+    let c = |i: usize| chunk.get(i).copied().unwrap_or(0);
+    decoded.push((c(0) << 2) | (c(1) >> 4));
+    decoded.push((c(1) << 4) | (c(2) >> 2));
+    decoded.push((c(2) << 6) | c(3));
+  }
+  decoded.truncate(len);
+  out.extend(decoded);
+}
+
+fn ydecode_line(line: &[u8], out: &mut Vec<u8>) {
+  let mut escape = false;
+  for &c in line {
+    if escape {
+      out.push(c.wrapping_sub(64).wrapping_sub(42));
+      escape = false;
+    } else if c == b'=' {
+      escape = true;
+    } else {
+      out.push(c.wrapping_sub(42));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn uuencode() {
+    let data = b"Hi,\r\n\r\nbegin 644 hello.txt\r\n.2&5L;&\\L(%=O<FQD(0H`\r\n`\r\nend\r\nBye\r\n";
+    let (text, attachments) = extract(data);
+    assert_eq!(text, b"Hi,\r\n\r\nBye\r\n");
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(attachments[0].filename, "hello.txt");
+    assert_eq!(attachments[0].body, b"Hello, World!\n");
+  }
+
+  #[test]
+  fn yenc() {
+    let encoded: Vec<u8> = b"Hello, yEnc!".iter().map(|c| c.wrapping_add(42)).collect();
+    let mut data = b"=ybegin line=128 size=12 name=my file.bin\n".to_vec();
+    data.extend(&encoded);
+    data.extend(b"\n=yend size=12\nThe end\n");
+    let (text, attachments) = extract(&data);
+    assert_eq!(text, b"The end\n");
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(attachments[0].filename, "my file.bin");
+    assert_eq!(attachments[0].body, b"Hello, yEnc!");
+  }
+
+  #[test]
+  fn unterminated() {
+    let data = b"begin 644 hello.txt\n.2&5L;&\\L(%=O<FQD(0H`\n";
+    let (text, attachments) = extract(data);
+    assert_eq!(text, data);
+    assert!(attachments.is_empty());
+  }
+}
@@ -0,0 +1,227 @@
+/* eml.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::io;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use uuid::Uuid;
+
+use super::attachment::{sanitize_filename, Attachment};
+
+// rebuilt from the parsed bodies and attachments
+const MIME_HEADERS: &[&str] = &[
+  "mime-version",
+  "content-type",
+  "content-transfer-encoding",
+  "content-disposition",
+  "content-id",
+  "content-description",
+  "content-length",
+];
+
+/// Serializes a parsed message back to RFC 5322 bytes: the original
+/// headers, then a fresh MIME structure (text and HTML alternatives, then the
+/// attachments), every part base64 encoded.
+pub fn build(
+  headers: &[(String, String)],
+  text: Option<&str>,
+  html: Option<&str>,
+  attachments: &[Attachment],
+) -> io::Result<Vec<u8>> {
+  let mut eml = String::new();
+  for (name, value) in headers {
+    if MIME_HEADERS.contains(&name.to_lowercase().as_str()) {
+      continue;
+    }
+    eml.push_str(&format!("{}: {}\r\n", name, encode_header(value)));
+  }
+  eml.push_str("MIME-Version: 1.0\r\n");
+
+  let body = alternative(text, html);
+  match attachments.is_empty() {
+    true => eml.push_str(&body),
+    false => {
+      let boundary = boundary();
+      eml.push_str(&format!(
+        "Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n",
+        boundary
+      ));
+      eml.push_str(&format!("--{}\r\n{}\r\n", boundary, body));
+      for attachment in attachments {
+        eml.push_str(&format!("--{}\r\n{}\r\n", boundary, attachment_part(attachment)?));
+      }
+      eml.push_str(&format!("--{}--\r\n", boundary));
+    }
+  }
+  Ok(eml.into_bytes())
+}
+
+/// A new unsent message with `original` (RFC 5322 bytes) attached as
+/// message/rfc822, to forward it unchanged from a mail client.
+pub fn forward(subject: &str, original: &[u8]) -> Vec<u8> {
+  let subject = subject.trim();
+  let name = match subject.is_empty() {
+    true => "message".to_string(),
+    false => subject.chars().take(100).collect(),
+  };
+  let filename = quoted(&encode_header(&sanitize_filename(&format!("{}.eml", name))));
+  // message/rfc822 parts can't be base64 encoded (RFC 2046 5.2.1)
+  let encoding = match original.is_ascii() {
+    true => "7bit",
+    false => "8bit",
+  };
+  let boundary = boundary();
+  let mut eml = Vec::new();
+  eml.extend_from_slice(
+    format!(
+      "Subject: {}\r\nX-Unsent: 1\r\nMIME-Version: 1.0\r\n\
+       Content-Type: multipart/mixed; boundary=\"{b}\"\r\n\r\n\
+       --{b}\r\n{}\r\n\
+       --{b}\r\nContent-Type: message/rfc822; name={f}\r\n\
+       Content-Disposition: attachment; filename={f}\r\n\
+       Content-Transfer-Encoding: {}\r\n\r\n",
+      encode_header(&format!("Fwd: {}", subject)),
+      text_part("text/plain", ""),
+      encoding,
+      b = boundary,
+      f = filename
+    )
+    .as_bytes(),
+  );
+  eml.extend_from_slice(original);
+  if !original.ends_with(b"\n") {
+    eml.extend_from_slice(b"\r\n");
+  }
+  eml.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+  eml
+}
+
+// headers and body of the text/html part(s)
+fn alternative(text: Option<&str>, html: Option<&str>) -> String {
+  match (text, html) {
+    (Some(text), Some(html)) => {
+      let boundary = boundary();
+      format!(
+        "Content-Type: multipart/alternative; boundary=\"{b}\"\r\n\r\n\
+         --{b}\r\n{}\r\n--{b}\r\n{}\r\n--{b}--\r\n",
+        text_part("text/plain", text),
+        text_part("text/html", html),
+        b = boundary
+      )
+    }
+    (None, Some(html)) => text_part("text/html", html),
+    (Some(text), None) => text_part("text/plain", text),
+    (None, None) => text_part("text/plain", ""),
+  }
+}
+
+fn text_part(mime_type: &str, content: &str) -> String {
+  format!(
+    "Content-Type: {}; charset=\"UTF-8\"\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+    mime_type,
+    encode_base64(content.as_bytes())
+  )
+}
+
+fn attachment_part(attachment: &Attachment) -> io::Result<String> {
+  let filename = quoted(&encode_header(&attachment.safe_filename()));
+  let mut part = format!(
+    "Content-Type: {}; name={}\r\n",
+    attachment.mime_type.as_deref().unwrap_or("application/octet-stream"),
+    filename
+  );
+  part.push_str(&format!("Content-Disposition: attachment; filename={}\r\n", filename));
+  if let Some(content_id) = attachment.content_id() {
+    part.push_str(&format!("Content-ID: <{}>\r\n", content_id));
+  }
+  if let Some(description) = &attachment.description {
+    part.push_str(&format!("Content-Description: {}\r\n", encode_header(description)));
+  }
+  part.push_str("Content-Transfer-Encoding: base64\r\n\r\n");
+  part.push_str(&encode_base64(attachment.bytes()?));
+  Ok(part)
+}
+
+fn boundary() -> String {
+  format!("=_mailviewer_{}", Uuid::new_v4().simple())
+}
+
+fn quoted(value: &str) -> String {
+  format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// 76 columns lines
+pub fn encode_base64(data: &[u8]) -> String {
+  let encoded = general_purpose::STANDARD.encode(data);
+  let mut lines = String::with_capacity(encoded.len() + encoded.len() / 38);
+  for line in encoded.as_bytes().chunks(76) {
+    lines.push_str(std::str::from_utf8(line).unwrap_or_default());
+    lines.push_str("\r\n");
+  }
+  lines
+}
+
+/// RFC 2047 encoded-words for the non-ASCII words of a header value, line
+/// breaks (unfolded or not) become spaces.
+fn encode_header(value: &str) -> String {
+  value
+    .split_whitespace()
+    .map(|word| match word.is_ascii() {
+      true => word.to_string(),
+      false => format!("=?UTF-8?B?{}?=", general_purpose::STANDARD.encode(word)),
+    })
+    .collect::<Vec<String>>()
+    .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn header_encoding() {
+    assert_eq!(encode_header("Lorem  ipsum\r\n dolor"), "Lorem ipsum dolor");
+    assert_eq!(encode_header("Café crème"), "=?UTF-8?B?Q2Fmw6k=?= =?UTF-8?B?Y3LDqG1l?=");
+  }
+
+  #[test]
+  fn structure() {
+    let headers = vec![
+      ("Subject".to_string(), "Hello".to_string()),
+      ("Content-Type".to_string(), "text/plain".to_string()),
+    ];
+    let eml = String::from_utf8(build(&headers, Some("Hi"), None, &[]).unwrap()).unwrap();
+    assert!(eml.starts_with("Subject: Hello\r\nMIME-Version: 1.0\r\nContent-Type: text/plain;"));
+    assert!(eml.ends_with("\r\n\r\nSGk=\r\n"));
+    assert_eq!(eml.matches("Content-Type").count(), 1);
+  }
+
+  #[test]
+  fn forward_as_attachment() {
+    let headers = vec![("Subject".to_string(), "Lorem ipsum".to_string())];
+    let original = build(&headers, Some("Hi"), None, &[]).unwrap();
+    let eml = String::from_utf8(forward("Lorem ipsum", &original)).unwrap();
+    assert!(eml.starts_with("Subject: Fwd: Lorem ipsum\r\nX-Unsent: 1\r\nMIME-Version: 1.0\r\n"));
+    let inner = &eml[eml.find("Content-Type: message/rfc822").unwrap()..];
+    assert!(inner.contains("filename=\"Lorem ipsum.eml\"\r\n"));
+    assert!(inner.contains("Content-Transfer-Encoding: 7bit\r\n\r\nSubject: Lorem ipsum\r\n"));
+    assert!(inner.trim_end().ends_with("--"));
+  }
+}
@@ -0,0 +1,83 @@
+/* emlx.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/// Apple Mail .emlx file: a line with the byte count of the message, the
+/// RFC822 message itself, then an XML property list with the mailbox flags.
+#[derive(Debug)]
+pub struct Emlx<'a> {
+  pub message: &'a [u8],
+  /// `flags` key of the plist, bit 0 is read, bit 4 flagged
+  pub flags: Option<u64>,
+}
+
+impl<'a> Emlx<'a> {
+  /// None unless `data` is wrapped as an .emlx
+  pub fn parse(data: &'a [u8]) -> Option<Self> {
+    let newline = data.iter().take(21).position(|&b| b == b'\n')?;
+    let count: usize = std::str::from_utf8(&data[..newline]).ok()?.trim().parse().ok()?;
+    let message = data.get(newline + 1..newline + 1 + count)?;
+    let plist = String::from_utf8_lossy(&data[newline + 1 + count..]);
+    let plist = plist.trim_start();
+    if !plist.starts_with("<?xml") && !plist.starts_with("<plist") {
+      return None;
+    }
+    Some(Self {
+      message,
+      flags: Self::integer(plist, "flags"),
+    })
+  }
+
+  #[allow(dead_code)]
+  pub fn is_read(&self) -> bool {
+    self.flags.is_some_and(|flags| flags & 1 != 0)
+  }
+
+  #[allow(dead_code)]
+  pub fn is_flagged(&self) -> bool {
+    self.flags.is_some_and(|flags| flags & (1 << 4) != 0)
+  }
+
+  // <key>name</key><integer>value</integer>
+  fn integer(plist: &str, name: &str) -> Option<u64> {
+    let key = format!("<key>{}</key>", name);
+    let rest = plist[plist.find(&key)? + key.len()..].trim_start();
+    let rest = rest.strip_prefix("<integer>")?;
+    rest[..rest.find("</integer>")?].trim().parse().ok()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    let data = std::fs::read("tests/sample.emlx").unwrap();
+    let emlx = Emlx::parse(&data).unwrap();
+    assert!(emlx.message.starts_with(b"From: John Doe"));
+    assert!(emlx.message.ends_with(b"Regards,\nJohn\n"));
+    assert_eq!(emlx.flags, Some(8590195729));
+    assert!(emlx.is_read());
+    assert!(emlx.is_flagged());
+
+    assert!(Emlx::parse(b"From: John Doe <john@moon.space>\n\nHello\n").is_none());
+    assert!(Emlx::parse(b"12\nshort").is_none());
+  }
+}
@@ -0,0 +1,85 @@
+/* flowed.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/// Joins the soft line breaks of a format=flowed body (RFC 3676): a line
+/// ending with a space continues on the next one, unless the quote depth
+/// changes or it's the "-- " signature separator. `delsp` drops that space.
+pub fn reflow(text: &str, delsp: bool) -> String {
+  let mut output = String::new();
+  // quote depth and content of the paragraph being joined
+  let mut paragraph: Option<(usize, String)> = None;
+  for line in text.lines() {
+    let depth = line.chars().take_while(|&c| c == '>').count();
+    let content = &line[depth..];
+    // space stuffing
+    let content = content.strip_prefix(' ').unwrap_or(content);
+    let is_signature = content == "-- ";
+    let (depth, mut joined) = match paragraph.take() {
+      Some((current, joined)) if current == depth && !is_signature => (depth, joined + content),
+      // a quote depth change ends the flowed paragraph anyway
+      Some((current, joined)) => {
+        push_line(&mut output, current, &joined);
+        (depth, content.to_string())
+      }
+      None => (depth, content.to_string()),
+    };
+    if content.ends_with(' ') && !is_signature {
+      if delsp {
+        joined.pop();
+      }
+      paragraph = Some((depth, joined));
+    } else {
+      push_line(&mut output, depth, &joined);
+    }
+  }
+  if let Some((depth, joined)) = paragraph {
+    push_line(&mut output, depth, &joined);
+  }
+  output
+}
+
+fn push_line(output: &mut String, depth: usize, content: &str) {
+  if depth > 0 {
+    output.push_str(&">".repeat(depth));
+    output.push(' ');
+  }
+  output.push_str(content);
+  output.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn soft_breaks() {
+    let text = "This paragraph was \r\nsoft wrapped by the \r\nclient.\r\nThis line is hard.\r\n\r\n-- \r\nJohn\r\n";
+    assert_eq!(
+      reflow(text, false),
+      "This paragraph was soft wrapped by the client.\nThis line is hard.\n\n-- \nJohn\n"
+    );
+  }
+
+  #[test]
+  fn quotes_and_delsp() {
+    let text = ">> Deeply quo \n>> ted.\n> Quoted \nNew text.\n  stuffed\n";
+    assert_eq!(reflow(text, true), ">> Deeply quoted.\n> Quoted\nNew text.\n stuffed\n");
+  }
+}
@@ -0,0 +1,141 @@
+/* folder.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use super::archive::gunzip;
+use super::mbox::Mailbox;
+use super::message::MessageParser;
+
+/// Message files of a folder, compressed (".gz") or not
+const EXTENSIONS: [&str; 3] = ["eml", "emlx", "msg"];
+
+/// A directory of message files browsed like a mailbox: only their paths
+/// are kept, each file is read and parsed when it's selected.
+#[derive(Debug)]
+pub struct Folder {
+  files: Vec<PathBuf>,
+}
+
+impl Folder {
+  /// Lists the message files of `path` (not its subdirectories) by name,
+  /// unreadable ones are skipped with a warning.
+  pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(path)?
+      .filter_map(|entry| match entry {
+        Ok(entry) => Some(entry.path()),
+        Err(e) => {
+          log::warn!("Folder::open({:?}) => skipped an entry : {}", path, e);
+          None
+        }
+      })
+      .filter(|file| Self::is_message_file(file))
+      .filter(|file| match File::open(file) {
+        Ok(opened) => opened.metadata().is_ok_and(|metadata| metadata.is_file()),
+        Err(e) => {
+          log::warn!("Folder::open() => skipped {:?} : {}", file, e);
+          false
+        }
+      })
+      .collect();
+    files.sort();
+    log::debug!("Folder::open({:?}) => {} messages", path, files.len());
+    Ok(Self { files })
+  }
+
+  /// "message.eml", "Message.MSG" or "message.eml.gz"
+  pub fn is_message_file(path: &Path) -> bool {
+    let name = path
+      .file_name()
+      .map(|name| name.to_string_lossy().to_lowercase())
+      .unwrap_or_default();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    match name.rsplit_once('.') {
+      Some((stem, extension)) => !stem.is_empty() && EXTENSIONS.contains(&extension),
+      None => false,
+    }
+  }
+
+  pub fn count(&self) -> usize {
+    self.files.len()
+  }
+
+  pub fn path(&self, index: usize) -> Option<&Path> {
+    self.files.get(index).map(|file| file.as_path())
+  }
+
+  /// Content of the file at `index`, decompressed
+  pub fn message_bytes(&self, index: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let file = self
+      .files
+      .get(index)
+      .ok_or(format!("No message {} in folder", index))?;
+    let data = fs::read(file)?;
+    let compressed = file
+      .extension()
+      .is_some_and(|extension| extension.eq_ignore_ascii_case("gz"));
+    match compressed {
+      true => gunzip(&data),
+      false => Ok(data),
+    }
+  }
+}
+
+impl Mailbox for Folder {
+  fn count(&self) -> usize {
+    Folder::count(self)
+  }
+
+  fn parse(&self, index: usize) -> Result<MessageParser, Box<dyn Error>> {
+    Ok(MessageParser::from_bytes(&self.message_bytes(index)?)?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::message::message::Message;
+
+  #[test]
+  fn message_files() {
+    assert!(Folder::is_message_file(Path::new("/tmp/message.eml")));
+    assert!(Folder::is_message_file(Path::new("Message.MSG")));
+    assert!(Folder::is_message_file(Path::new("message.eml.gz")));
+    assert!(Folder::is_message_file(Path::new("message.emlx")));
+    assert!(!Folder::is_message_file(Path::new("notes.txt")));
+    assert!(!Folder::is_message_file(Path::new("notes.txt.gz")));
+    assert!(!Folder::is_message_file(Path::new(".eml")));
+    assert!(!Folder::is_message_file(Path::new("eml")));
+  }
+
+  #[test]
+  fn folder() {
+    // the broken link is skipped, so are notes.txt and the subfolder
+    let folder = Folder::open(Path::new("tests/folder")).unwrap();
+    assert_eq!(folder.count(), 3);
+    assert_eq!(folder.path(0), Some(Path::new("tests/folder/organization.eml")));
+    assert_eq!(folder.parse(0).unwrap().subject(), "Quarterly figures");
+    assert_eq!(folder.path(1), Some(Path::new("tests/folder/outlook.msg")));
+    assert_eq!(folder.parse(1).unwrap().from(), "John Doe <john@moon.space>");
+    assert_eq!(folder.parse(2).unwrap().subject(), "Lorem ipsum");
+    assert!(folder.parse(3).is_err());
+  }
+}
@@ -0,0 +1,208 @@
+/* idn.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+// RFC 3492 parameters
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+/// Cyrillic and Greek letters drawn like a Latin one
+const CONFUSABLES: [(char, char); 25] = [
+  ('а', 'a'),
+  ('с', 'c'),
+  ('ԁ', 'd'),
+  ('е', 'e'),
+  ('һ', 'h'),
+  ('і', 'i'),
+  ('ј', 'j'),
+  ('ӏ', 'l'),
+  ('о', 'o'),
+  ('р', 'p'),
+  ('ԛ', 'q'),
+  ('ѕ', 's'),
+  ('у', 'y'),
+  ('ԝ', 'w'),
+  ('х', 'x'),
+  ('ү', 'y'),
+  ('α', 'a'),
+  ('ε', 'e'),
+  ('ι', 'i'),
+  ('κ', 'k'),
+  ('ν', 'v'),
+  ('ο', 'o'),
+  ('ρ', 'p'),
+  ('τ', 't'),
+  ('υ', 'u'),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+  Latin,
+  Greek,
+  Cyrillic,
+  Other,
+}
+
+/// Unicode form of a domain, the "xn--" labels being decoded. Labels that
+/// fail to decode are kept as they are.
+pub fn to_unicode(domain: &str) -> String {
+  domain
+    .split('.')
+    .map(|label| {
+      match label.get(..4).is_some_and(|prefix| prefix.eq_ignore_ascii_case("xn--")) {
+        true => decode_punycode(&label[4..]).unwrap_or_else(|| label.to_string()),
+        false => label.to_string(),
+      }
+    })
+    .collect::<Vec<String>>()
+    .join(".")
+}
+
+/// The Latin domain imitated by `domain` (punycode or Unicode), when one of
+/// its labels mixes Latin letters with Cyrillic or Greek ones, or is only
+/// made of letters drawn like Latin ones: "xn--80ak6aa92e.com" => "apple.com".
+pub fn lookalike(domain: &str) -> Option<String> {
+  let decoded = to_unicode(&domain.to_lowercase());
+  if decoded.is_ascii() {
+    return None;
+  }
+  let suspicious = decoded.split('.').any(|label| {
+    let scripts: Vec<Script> = label.chars().filter(|c| c.is_alphabetic()).map(script).collect();
+    let latin = scripts.contains(&Script::Latin);
+    let confusable = scripts.contains(&Script::Cyrillic) || scripts.contains(&Script::Greek);
+    (latin && confusable) || (!label.is_ascii() && skeleton(label).is_ascii())
+  });
+  match suspicious {
+    true => Some(skeleton(&decoded)),
+    false => None,
+  }
+}
+
+/// "xn--" label (without the prefix) to Unicode, None when malformed
+pub fn decode_punycode(input: &str) -> Option<String> {
+  let (basic, extended) = match input.rfind('-') {
+    Some(index) => (&input[..index], &input[index + 1..]),
+    None => ("", input),
+  };
+  if !basic.is_ascii() {
+    return None;
+  }
+  let mut output: Vec<char> = basic.chars().collect();
+  let mut n = INITIAL_N;
+  let mut i: u32 = 0;
+  let mut bias = INITIAL_BIAS;
+  let mut digits = extended.bytes().peekable();
+  while digits.peek().is_some() {
+    let old_i = i;
+    let mut weight: u32 = 1;
+    let mut k = BASE;
+    loop {
+      let digit = match digits.next()? {
+        c @ b'a'..=b'z' => c - b'a',
+        c @ b'A'..=b'Z' => c - b'A',
+        c @ b'0'..=b'9' => c - b'0' + 26,
+        _ => return None,
+      } as u32;
+      i = i.checked_add(digit.checked_mul(weight)?)?;
+      let t = match k {
+        k if k <= bias => T_MIN,
+        k if k >= bias + T_MAX => T_MAX,
+        k => k - bias,
+      };
+      if digit < t {
+        break;
+      }
+      weight = weight.checked_mul(BASE - t)?;
+      k += BASE;
+    }
+    let length = output.len() as u32 + 1;
+    bias = adapt(i - old_i, length, old_i == 0);
+    n = n.checked_add(i / length)?;
+    i %= length;
+    output.insert(i as usize, char::from_u32(n)?);
+    i += 1;
+  }
+  Some(output.into_iter().collect())
+}
+
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+  let mut delta = match first {
+    true => delta / DAMP,
+    false => delta / 2,
+  };
+  delta += delta / points;
+  let mut k = 0;
+  while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+    delta /= BASE - T_MIN;
+    k += BASE;
+  }
+  k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+fn script(c: char) -> Script {
+  match c as u32 {
+    0x41..=0x5a | 0x61..=0x7a | 0xc0..=0x24f | 0x1e00..=0x1eff => Script::Latin,
+    0x370..=0x3ff | 0x1f00..=0x1fff => Script::Greek,
+    0x400..=0x52f => Script::Cyrillic,
+    _ => Script::Other,
+  }
+}
+
+// the lookalike letters replaced by the Latin ones
+fn skeleton(text: &str) -> String {
+  text
+    .chars()
+    .map(|c| match CONFUSABLES.iter().find(|(confusable, _)| *confusable == c) {
+      Some((_, latin)) => *latin,
+      None => c,
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn punycode() {
+    assert_eq!(decode_punycode("mnchen-3ya").as_deref(), Some("münchen"));
+    assert_eq!(decode_punycode("80ak6aa92e").as_deref(), Some("аррӏе"));
+    assert_eq!(decode_punycode("pypal-4ve").as_deref(), Some("pаypal"));
+    assert_eq!(decode_punycode("99!"), None);
+    assert_eq!(to_unicode("www.XN--mnchen-3ya.de"), "www.münchen.de");
+    assert_eq!(to_unicode("moon.space"), "moon.space");
+  }
+
+  #[test]
+  fn lookalikes() {
+    // Cyrillic only
+    assert_eq!(lookalike("xn--80ak6aa92e.com").as_deref(), Some("apple.com"));
+    // Cyrillic "а" in a Latin name
+    assert_eq!(lookalike("login.xn--pypal-4ve.com").as_deref(), Some("login.paypal.com"));
+    assert_eq!(lookalike("pаypal.com").as_deref(), Some("paypal.com"));
+    assert_eq!(lookalike("xn--mnchen-3ya.de"), None);
+    assert_eq!(lookalike("xn--e1afmkfd.xn--p1ai"), None);
+    assert_eq!(lookalike("moon.space"), None);
+  }
+}
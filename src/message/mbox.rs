@@ -0,0 +1,177 @@
+/* mbox.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use super::message::MessageParser;
+
+const SEPARATOR: &[u8] = b"From ";
+
+/// Bytes scanned between two progress reports of open_with_progress()
+const PROGRESS_STEP: u64 = 1024 * 1024;
+
+/// Several messages browsed one at a time, parsed when selected
+pub trait Mailbox: std::fmt::Debug {
+  fn count(&self) -> usize;
+  fn parse(&self, index: usize) -> Result<MessageParser, Box<dyn Error>>;
+}
+
+/// An mbox archive, only the message offsets are kept in memory: each
+/// message is read and parsed when it's selected.
+#[derive(Debug)]
+pub struct Mbox {
+  path: PathBuf,
+  messages: Vec<Range<u64>>,
+}
+
+impl Mbox {
+  pub fn looks_like_mbox(data: &[u8]) -> bool {
+    data.starts_with(SEPARATOR)
+  }
+
+  /// Scans the file for "From " separator lines.
+  pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+    Self::open_with_progress(path, |_, _| ())
+  }
+
+  /// open(), calling `progress(scanned, size)` every megabyte and at the end
+  pub fn open_with_progress<F: FnMut(u64, u64)>(
+    path: &Path,
+    mut progress: F,
+  ) -> Result<Self, Box<dyn Error>> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut next_report = PROGRESS_STEP;
+    let mut messages: Vec<Range<u64>> = vec![];
+    let mut line: Vec<u8> = vec![];
+    let mut offset: u64 = 0;
+    let mut previous_blank = true;
+    loop {
+      line.clear();
+      let read = reader.read_until(b'\n', &mut line)? as u64;
+      if read == 0 {
+        break;
+      }
+      if previous_blank && line.starts_with(SEPARATOR) {
+        if let Some(last) = messages.last_mut() {
+          last.end = offset;
+        }
+        messages.push(offset + read..offset + read);
+      }
+      offset += read;
+      previous_blank = line == b"\n" || line == b"\r\n";
+      if offset >= next_report {
+        progress(offset, size.max(offset));
+        next_report = offset + PROGRESS_STEP;
+      }
+    }
+    if let Some(last) = messages.last_mut() {
+      last.end = offset;
+    }
+    progress(offset, size.max(offset));
+    log::debug!("Mbox::open({:?}) => {} messages", path, messages.len());
+    Ok(Self {
+      path: path.to_path_buf(),
+      messages,
+    })
+  }
+
+  pub fn count(&self) -> usize {
+    self.messages.len()
+  }
+
+  /// Raw message, without its separator line and with ">From " unquoted.
+  pub fn message_bytes(&self, index: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let range = self
+      .messages
+      .get(index)
+      .ok_or(format!("No message {} in mailbox", index))?;
+    let mut file = File::open(&self.path)?;
+    file.seek(SeekFrom::Start(range.start))?;
+    let mut data: Vec<u8> = vec![];
+    file.take(range.end - range.start).read_to_end(&mut data)?;
+
+    let mut message: Vec<u8> = Vec::with_capacity(data.len());
+    for line in data.split_inclusive(|b| *b == b'\n') {
+      let quotes = line.iter().take_while(|b| **b == b'>').count();
+      match quotes > 0 && line[quotes..].starts_with(SEPARATOR) {
+        true => message.extend_from_slice(&line[1..]),
+        false => message.extend_from_slice(line),
+      }
+    }
+    Ok(message)
+  }
+
+  pub fn parse(&self, index: usize) -> Result<MessageParser, Box<dyn Error>> {
+    Ok(MessageParser::from_bytes(&self.message_bytes(index)?)?)
+  }
+}
+
+impl Mailbox for Mbox {
+  fn count(&self) -> usize {
+    Mbox::count(self)
+  }
+
+  fn parse(&self, index: usize) -> Result<MessageParser, Box<dyn Error>> {
+    Mbox::parse(self, index)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::message::message::Message;
+
+  #[test]
+  fn index() {
+    let mbox = Mbox::open(Path::new("tests/sample.mbox")).unwrap();
+
+    assert_eq!(mbox.count(), 3);
+    let first = String::from_utf8(mbox.message_bytes(0).unwrap()).unwrap();
+    assert!(first.starts_with("From: John Doe <john@moon.space>"));
+    assert!(first.contains("\nFrom the moon, with love.\n"));
+    assert!(mbox.message_bytes(3).is_err());
+  }
+
+  #[test]
+  fn progress() {
+    let mut reports: Vec<(u64, u64)> = vec![];
+    let mbox = Mbox::open_with_progress(Path::new("tests/sample.mbox"), |read, size| {
+      reports.push((read, size))
+    })
+    .unwrap();
+
+    assert_eq!(mbox.count(), 3);
+    let size = std::fs::metadata("tests/sample.mbox").unwrap().len();
+    assert_eq!(reports, vec![(size, size)]);
+  }
+
+  #[test]
+  fn parse() {
+    let mbox = Mbox::open(Path::new("tests/sample.mbox")).unwrap();
+
+    assert_eq!(mbox.parse(1).unwrap().subject(), "Second message");
+    assert_eq!(mbox.parse(2).unwrap().subject(), "Third message");
+  }
+}
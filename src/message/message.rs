@@ -18,14 +18,28 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 use std::error::Error;
-use std::fs;
+use std::io::Read;
+use std::{fmt, fs};
 use std::path::PathBuf;
 
+use base64::engine::general_purpose;
+use base64::Engine;
 use lazy_static::lazy_static;
 use uuid::Uuid;
 
+use super::alternative::Alternative;
 use super::attachment::Attachment;
+use super::authresults::{self, Alignment, AuthResults};
+use super::dsn::DeliveryStatus;
+use super::eml;
+use super::emlx::Emlx;
+use super::flowed;
+use super::mimepart::MimePart;
+use super::search::{self, SearchMatch};
+use super::security::{self, Finding, Signals};
+use super::value::Value;
 use crate::config::APP_NAME;
+use crate::html::Html;
 use crate::message::electronicmail::ElectronicMail;
 use crate::message::outlook::OutlookMessage;
 
@@ -44,17 +58,290 @@ lazy_static! {
   };
 }
 
+const KNOWN_HEADERS: &[&str] = &[
+  "From",
+  "To",
+  "Subject",
+  "Date",
+  "Message-ID",
+  "MIME-Version",
+  "Received",
+  "Return-Path",
+];
+
+// reply and forward prefixes, lowercase, English and localized
+const SUBJECT_PREFIXES: &[&str] = &[
+  "re", "fw", "fwd", "aw", "wg", "sv", "vs", "vb", "antw", "doorst", "rif", "r", "i", "tr",
+  "ref", "réf", "res", "enc", "odp", "pd", "yv", "vl", "回复", "回覆", "答复", "转发", "轉寄",
+];
+
+/// Subject without its reply and forward prefixes ("Re: Fwd: Hello" =>
+/// "Hello"), including counters ("Re[2]:") and localized ones ("Aw:", "Sv:").
+pub fn thread_subject(subject: &str) -> String {
+  let mut rest = subject.trim();
+  while let Some(colon) = rest.find([':', '\u{ff1a}']) {
+    let prefix = rest[..colon]
+      .trim()
+      .trim_end_matches(|c: char| c.is_ascii_digit() || "[]()".contains(c))
+      .to_lowercase();
+    if !SUBJECT_PREFIXES.contains(&prefix.as_str()) {
+      break;
+    }
+    let separator = rest[colon..].chars().next().map(char::len_utf8).unwrap_or(1);
+    rest = rest[colon + separator..].trim_start();
+  }
+  rest.to_string()
+}
+
+/// Text with CRLF and lone CR (classic Mac) line endings turned into LF
+pub fn normalize_newlines(text: &str) -> String {
+  text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
 pub trait Message {
   fn parse(&mut self) -> Result<(), Box<dyn Error>>;
   fn from(&self) -> String;
   fn to(&self) -> String;
+  /// first Subject header when there are several, see headers() for the others
   fn subject(&self) -> String;
+  /// subject() without the Re:/Fwd: prefixes, the same for a whole thread
+  fn thread_subject(&self) -> String {
+    thread_subject(&self.subject())
+  }
+  /// first Date header when there are several
   fn date(&self) -> String;
+  /// All the headers, in message order, duplicates included
+  fn headers(&self) -> Vec<(String, String)>;
   fn attachments(&self) -> Vec<Attachment>;
+  /// Borrowing counterpart of attachments()
+  fn attachments_ref(&self) -> &[Attachment];
   fn body_html(&self) -> Option<String>;
   fn body_text(&self) -> Option<String>;
+  /// charset declared by the displayed body part, None when unspecified
+  fn charset(&self) -> Option<String>;
+  /// main language of the Content-Language header (e.g. "fr-FR")
+  fn language(&self) -> Option<String>;
+  /// Sender header (mailing lists, delegated sending), RFC 2047 decoded
+  fn sender(&self) -> Option<String>;
+  /// sender() when it isn't the address of the From header
+  fn sent_via(&self) -> Option<String> {
+    self
+      .sender()
+      .filter(|sender| !security::same_address(sender, &self.from()))
+  }
+  /// Organization header, RFC 2047 decoded
+  fn organization(&self) -> Option<String>;
+  fn mime_tree(&self) -> MimePart;
+  /// Transfer-decoded bytes of the `index`th leaf part of mime_tree()
+  /// (depth first), whatever its charset.
+  fn part_bytes(&self, index: usize) -> Option<Vec<u8>>;
+  /// Source of the `index`th leaf part, headers and encoded body as in the
+  /// message, None when the format has no MIME source.
+  fn raw_part(&self, index: usize) -> Option<Vec<u8>>;
+  /// true when parts were skipped because the MIME nesting was too deep
+  fn is_truncated(&self) -> bool;
+  /// Recoverable anomalies met while parsing (unsupported charset, missing
+  /// boundary, ...), to explain why the message may look off.
+  fn warnings(&self) -> Vec<String>;
+  /// CSS of the related parts, by the "cid:" URL or Content-Location the
+  /// HTML part links them with
+  fn stylesheets(&self) -> Vec<(String, String)>;
+  fn auth_results(&self) -> Vec<AuthResults>;
+  /// Recipients reported by the message/delivery-status parts of a bounce
+  fn delivery_status(&self) -> Vec<DeliveryStatus>;
+  /// Some(delsp) when the text body is format=flowed (RFC 3676)
+  fn text_flowed(&self) -> Option<bool>;
+  /// body_text() with the soft line breaks of a format=flowed body joined,
+  /// the literal body otherwise.
+  fn body_text_reflowed(&self) -> Option<String> {
+    let text = self.body_text()?;
+    match self.text_flowed() {
+      Some(delsp) => Some(flowed::reflow(&text, delsp)),
+      None => Some(text),
+    }
+  }
+  /// Every security signal of the message (authentication, spoofing, links,
+  /// tracking, attachments), most severe first.
+  fn security_findings(&self) -> Vec<Finding> {
+    let html = Html::new(&self.body_html().unwrap_or_default(), false);
+    let headers = self.headers();
+    let reply_to = headers
+      .iter()
+      .find(|(name, _)| name.eq_ignore_ascii_case("Reply-To"))
+      .map(|(_, value)| value.as_str());
+    let auth_results = self.auth_results();
+    let from = self.from();
+    security::findings(&Signals {
+      auth_results: &auth_results,
+      from: &from,
+      reply_to,
+      deceptive_links: html.deceptive_links(),
+      link_hosts: html.link_hosts(),
+      tracking_pixels: html.tracking_pixels(),
+      attachments: self.attachments_ref(),
+    })
+  }
+  /// Newsletters and mailing lists: Precedence: bulk (or list, junk), or a
+  /// List-Id or List-Unsubscribe header.
+  fn is_bulk(&self) -> bool {
+    self.headers().iter().any(|(name, value)| {
+      match name.to_lowercase().as_str() {
+        "list-id" | "list-unsubscribe" => true,
+        "precedence" => matches!(value.trim().to_lowercase().as_str(), "bulk" | "list" | "junk"),
+        _ => false,
+      }
+    })
+  }
+  /// Whether the From domain aligns with a domain SPF or DKIM authenticated
+  fn is_domain_aligned(&self) -> Alignment {
+    let from_domain = security::from_domain(&self.from()).unwrap_or_default();
+    authresults::alignment(&from_domain, &self.auth_results())
+  }
+  /// Everything found in the message (headers, MIME parts, bodies,
+  /// attachments without their content, authentication, security findings)
+  /// for external tools. The keys are stable, `to_string()` gives JSON.
+  fn to_value(&self) -> Value {
+    let headers: Vec<Value> = self
+      .headers()
+      .into_iter()
+      .map(|(name, value)| Value::object(vec![("name", name.into()), ("value", value.into())]))
+      .collect();
+    Value::object(vec![
+      ("from", self.from().into()),
+      ("to", self.to().into()),
+      ("subject", self.subject().into()),
+      ("thread_subject", self.thread_subject().into()),
+      ("date", self.date().into()),
+      ("headers", Value::Array(headers)),
+      ("charset", self.charset().into()),
+      ("language", self.language().into()),
+      ("sender", self.sender().into()),
+      ("organization", self.organization().into()),
+      ("body_text", self.body_text().into()),
+      ("body_html", self.body_html().into()),
+      ("text_flowed", self.text_flowed().is_some().into()),
+      ("parts", Value::from(&self.mime_tree())),
+      ("truncated", self.is_truncated().into()),
+      ("warnings", self.warnings().into()),
+      (
+        "attachments",
+        Value::Array(self.attachments_ref().iter().map(Value::from).collect()),
+      ),
+      (
+        "authentication",
+        Value::Array(self.auth_results().iter().map(Value::from).collect()),
+      ),
+      ("domain_alignment", self.is_domain_aligned().as_str().into()),
+      ("bulk", self.is_bulk().into()),
+      (
+        "delivery_status",
+        Value::Array(self.delivery_status().iter().map(Value::from).collect()),
+      ),
+      (
+        "findings",
+        Value::Array(self.security_findings().iter().map(Value::from).collect()),
+      ),
+    ])
+  }
+  /// The message as .eml bytes, an equivalent message rather than the
+  /// original one: headers are kept, the MIME structure is rebuilt.
+  fn to_eml(&self) -> std::io::Result<Vec<u8>> {
+    let mut headers = self.headers();
+    if headers.is_empty() {
+      // .msg files have no parsed headers (and date() isn't RFC 5322)
+      headers = vec![
+        ("From".to_string(), self.from()),
+        ("To".to_string(), self.to()),
+        ("Subject".to_string(), self.subject()),
+      ];
+    }
+    eml::build(
+      &headers,
+      self.body_text().as_deref(),
+      self.body_html().as_deref(),
+      self.attachments_ref(),
+    )
+  }
+  /// Every displayable representation of the body, in message order.
+  fn alternatives(&self) -> Vec<Alternative> {
+    let mut alternatives = vec![];
+    if let Some(text) = self.body_text() {
+      alternatives.push(Alternative::new("text/plain", &text));
+    }
+    if let Some(html) = self.body_html() {
+      alternatives.push(Alternative::new("text/html", &html));
+    }
+    alternatives
+  }
+
+  /// Case insensitive search in the headers and bodies, and in the text-like
+  /// attachments when `include_attachments` is set (binary ones are skipped).
+  fn search(&self, query: &str, include_attachments: bool) -> Vec<SearchMatch> {
+    let mut matches = vec![];
+    if query.is_empty() {
+      return matches;
+    }
+    if search::contains(&self.subject(), query) {
+      matches.push(SearchMatch::Subject);
+    }
+    if search::contains(&self.from(), query) {
+      matches.push(SearchMatch::From);
+    }
+    if search::contains(&self.to(), query) {
+      matches.push(SearchMatch::To);
+    }
+    if self.body_text().is_some_and(|text| search::contains(&text, query)) {
+      matches.push(SearchMatch::TextBody);
+    }
+    if self
+      .body_html()
+      .is_some_and(|html| search::contains(&search::html_text(&html), query))
+    {
+      matches.push(SearchMatch::HtmlBody);
+    }
+    if include_attachments {
+      for attachment in self.attachments_ref() {
+        if search::attachment_text(attachment).is_some_and(|text| search::contains(&text, query)) {
+          matches.push(SearchMatch::Attachment(attachment.filename.clone()));
+        }
+      }
+    }
+    matches
+  }
+
+  fn is_html_only(&self) -> bool {
+    self.body_html().is_some() && self.body_text().is_none()
+  }
+
+  fn is_text_only(&self) -> bool {
+    self.body_text().is_some() && self.body_html().is_none()
+  }
+
+  fn has_both(&self) -> bool {
+    self.body_html().is_some() && self.body_text().is_some()
+  }
+}
+
+/// OLE2 compound file signature, the container of Outlook .msg files
+const OLE_MAGIC: &[u8] = &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+
+#[derive(Debug)]
+pub enum ParseError {
+  Empty,
+  Invalid(String),
 }
 
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ParseError::Empty => write!(f, "Empty message"),
+      ParseError::Invalid(e) => write!(f, "Invalid message: {}", e),
+    }
+  }
+}
+
+impl Error for ParseError {}
+
 #[derive(PartialEq, Debug)]
 #[repr(u8)]
 pub enum MessageType {
@@ -69,13 +356,25 @@ pub struct MessageParser {
 }
 
 impl MessageParser {
+  // file based, the application goes through from_bytes()
+  #[allow(dead_code)]
   pub fn new(file: &str) -> Self {
     // assert!(file.ends_with(".eml") || file.ends_with(".msg"));
+    if file.to_lowercase().ends_with(".emlx") {
+      let data = fs::read(file).unwrap_or_default();
+      return Self::with_bytes(Self::unwrap_emlx(&data));
+    }
     let message_type = if file.to_lowercase().ends_with(".msg") {
       MessageType::Msg
     } else {
       MessageType::Eml
     };
+    if message_type == MessageType::Eml && Self::starts_like_base64(file) {
+      let data = fs::read(file).unwrap_or_default();
+      if let Some(message) = Self::unwrap_base64(&data) {
+        return Self::with_bytes(&message);
+      }
+    }
     Self {
       parser: if message_type == MessageType::Msg {
         Box::new(OutlookMessage::new(file))
@@ -86,6 +385,119 @@ impl MessageParser {
     }
   }
 
+  pub fn with_bytes(data: &[u8]) -> Self {
+    Self {
+      parser: Box::new(ElectronicMail::with_bytes(data)),
+      message_type: MessageType::Eml,
+    }
+  }
+
+  /// Parses an in-memory .eml or .msg (told apart by the OLE signature),
+  /// without going through the filesystem.
+  pub fn from_bytes(data: &[u8]) -> Result<Self, ParseError> {
+    if data.is_empty() {
+      return Err(ParseError::Empty);
+    }
+    let decoded = Self::unwrap_base64(data);
+    let data = Self::unwrap_emlx(decoded.as_deref().unwrap_or(data));
+    let mut parser = if data.starts_with(OLE_MAGIC) {
+      Self {
+        parser: Box::new(OutlookMessage::with_bytes(data)),
+        message_type: MessageType::Msg,
+      }
+    } else {
+      Self::with_bytes(data)
+    };
+    parser
+      .parse()
+      .map_err(|e| ParseError::Invalid(e.to_string()))?;
+    Ok(parser)
+  }
+
+  /// The message inside an Apple Mail .emlx, `data` itself otherwise
+  fn unwrap_emlx(data: &[u8]) -> &[u8] {
+    match Emlx::parse(data) {
+      Some(emlx) => {
+        log::debug!("unwrap_emlx() flags {:?}", emlx.flags);
+        emlx.message
+      }
+      None => data,
+    }
+  }
+
+  /// The message of gateways that base64 the whole of it, headers included.
+  /// None unless `data` isn't a message itself and decodes to one.
+  fn unwrap_base64(data: &[u8]) -> Option<Vec<u8>> {
+    if !Self::is_base64_text(data) || Self::looks_like_message(data) {
+      return None;
+    }
+    let compact: Vec<u8> = data
+      .iter()
+      .filter(|b| !b.is_ascii_whitespace())
+      .copied()
+      .collect();
+    let decoded = general_purpose::STANDARD.decode(compact).ok()?;
+    match Self::looks_like_message(&decoded) {
+      true => {
+        log::debug!("unwrap_base64() => base64 encoded message");
+        Some(decoded)
+      }
+      false => None,
+    }
+  }
+
+  fn is_base64_text(data: &[u8]) -> bool {
+    data.iter().any(|b| !b.is_ascii_whitespace())
+      && data
+        .iter()
+        .all(|b| b.is_ascii_alphanumeric() || b"+/=".contains(b) || b.is_ascii_whitespace())
+  }
+
+  // checks the first KiB only, to avoid reading every file twice
+  fn starts_like_base64(file: &str) -> bool {
+    let mut head = [0u8; 1024];
+    let read = fs::File::open(file).and_then(|mut f| f.read(&mut head));
+    match read {
+      Ok(size) => Self::is_base64_text(&head[..size]),
+      Err(_) => false,
+    }
+  }
+
+  /// Cheap check that `data` starts with an RFC822 header block containing
+  /// at least one well known header (From, Subject, Date...).
+  pub fn looks_like_message(data: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(data);
+    let mut fields = 0;
+    let mut known = false;
+
+    for (i, line) in text.trim_start().lines().enumerate() {
+      if i == 0 && line.starts_with("From ") {
+        continue; // mbox separator
+      }
+      if line.trim().is_empty() {
+        break;
+      }
+      if line.starts_with(' ') || line.starts_with('\t') {
+        if fields == 0 {
+          return false;
+        }
+        continue; // folded header
+      }
+      match line.split_once(':') {
+        Some((name, value))
+          if !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_graphic())
+            && !value.starts_with("//") =>
+        {
+          fields += 1;
+          known |= KNOWN_HEADERS.iter().any(|h| name.eq_ignore_ascii_case(h));
+        }
+        _ => return false,
+      }
+    }
+    fields > 0 && known
+  }
+
   pub fn cleanup() {
     log::debug!("MessageParser::cleanup()");
     if TEMP_FOLDER.exists() {
@@ -125,10 +537,18 @@ impl Message for MessageParser {
     self.parser.date()
   }
 
+  fn headers(&self) -> Vec<(String, String)> {
+    self.parser.headers()
+  }
+
   fn attachments(&self) -> Vec<Attachment> {
     self.parser.attachments()
   }
 
+  fn attachments_ref(&self) -> &[Attachment] {
+    self.parser.attachments_ref()
+  }
+
   fn body_html(&self) -> Option<String> {
     self.parser.body_html()
   }
@@ -136,6 +556,66 @@ impl Message for MessageParser {
   fn body_text(&self) -> Option<String> {
     self.parser.body_text()
   }
+
+  fn charset(&self) -> Option<String> {
+    self.parser.charset()
+  }
+
+  fn language(&self) -> Option<String> {
+    self.parser.language()
+  }
+
+  fn sender(&self) -> Option<String> {
+    self.parser.sender()
+  }
+
+  fn organization(&self) -> Option<String> {
+    self.parser.organization()
+  }
+
+  fn mime_tree(&self) -> MimePart {
+    self.parser.mime_tree()
+  }
+
+  fn part_bytes(&self, index: usize) -> Option<Vec<u8>> {
+    self.parser.part_bytes(index)
+  }
+
+  fn raw_part(&self, index: usize) -> Option<Vec<u8>> {
+    self.parser.raw_part(index)
+  }
+
+  fn is_truncated(&self) -> bool {
+    self.parser.is_truncated()
+  }
+
+  fn warnings(&self) -> Vec<String> {
+    self.parser.warnings()
+  }
+
+  fn stylesheets(&self) -> Vec<(String, String)> {
+    self.parser.stylesheets()
+  }
+
+  fn auth_results(&self) -> Vec<AuthResults> {
+    self.parser.auth_results()
+  }
+
+  fn delivery_status(&self) -> Vec<DeliveryStatus> {
+    self.parser.delivery_status()
+  }
+
+  fn to_eml(&self) -> std::io::Result<Vec<u8>> {
+    self.parser.to_eml()
+  }
+
+  fn text_flowed(&self) -> Option<bool> {
+    self.parser.text_flowed()
+  }
+
+  fn alternatives(&self) -> Vec<Alternative> {
+    self.parser.alternatives()
+  }
 }
 
 #[cfg(test)]
@@ -172,6 +652,254 @@ mod tests {
     assert_eq!(attachment.mime_type.as_ref().unwrap(), "image/png");
   }
 
+  #[test]
+  fn test_html_only() {
+    let mut message = MessageParser::new("tests/html.eml");
+    message.parse().unwrap();
+    assert!(message.is_html_only());
+    assert!(!message.is_text_only());
+    assert!(!message.has_both());
+  }
+
+  #[test]
+  fn test_text_only() {
+    let mut message = MessageParser::new("tests/text.eml");
+    message.parse().unwrap();
+    assert!(!message.is_html_only());
+    assert!(message.is_text_only());
+    assert!(!message.has_both());
+  }
+
+  #[test]
+  fn test_has_both() {
+    let mut message = MessageParser::new("sample.eml");
+    message.parse().unwrap();
+    assert!(!message.is_html_only());
+    assert!(!message.is_text_only());
+    assert!(message.has_both());
+  }
+
+  #[test]
+  fn test_looks_like_message() {
+    assert!(MessageParser::looks_like_message(
+      &std::fs::read("sample.eml").unwrap()
+    ));
+    assert!(MessageParser::looks_like_message(
+      b"From: John Doe <john@moon.space>\r\nSubject: Hi\r\n\r\nHello"
+    ));
+    assert!(MessageParser::looks_like_message(
+      b"\nReceived: from mx\n  by mx2\nTo: lucas@mercure.space\n\nbody"
+    ));
+    assert!(!MessageParser::looks_like_message(b""));
+    assert!(!MessageParser::looks_like_message(b"Hello Lucas, how are you ?"));
+    assert!(!MessageParser::looks_like_message(b"Note: call me back\n\nJohn"));
+    assert!(!MessageParser::looks_like_message(
+      b"https://example.com/path\nFrom: nobody"
+    ));
+  }
+
+  #[test]
+  fn test_with_bytes() {
+    let mut message = MessageParser::with_bytes(&std::fs::read("sample.eml").unwrap());
+    message.parse().unwrap();
+    assert_eq!(message.message_type, MessageType::Eml);
+    assert_eq!(message.subject(), "Lorem ipsum");
+    assert_eq!(message.attachments().len(), 1);
+  }
+
+  #[test]
+  fn test_from_bytes() {
+    let message = MessageParser::from_bytes(include_bytes!("../../sample.eml")).unwrap();
+    assert_eq!(message.message_type, MessageType::Eml);
+    assert_eq!(message.subject(), "Lorem ipsum");
+    assert_eq!(message.attachments().len(), 1);
+
+    let message = MessageParser::from_bytes(&fs::read("sample.msg").unwrap()).unwrap();
+    assert_eq!(message.message_type, MessageType::Msg);
+
+    assert!(matches!(MessageParser::from_bytes(b""), Err(ParseError::Empty)));
+  }
+
+  #[test]
+  fn test_search() {
+    let mut message = MessageParser::new("tests/test-php.eml");
+    message.parse().unwrap();
+    // the content of attachment.txt
+    let needle = "PLAIN text attachment";
+
+    assert!(message.search(needle, false).is_empty());
+    assert_eq!(
+      message.search(needle, true),
+      vec![SearchMatch::Attachment("attachment.txt".to_string())]
+    );
+    // images are never scanned
+    assert!(!message
+      .search("GIF89a", true)
+      .iter()
+      .any(|m| matches!(m, SearchMatch::Attachment(_))));
+    assert!(message.search("", true).is_empty());
+  }
+
+  #[test]
+  fn test_mime_tree() {
+    let mut message = MessageParser::new("sample.eml");
+    message.parse().unwrap();
+    let tree = message.mime_tree();
+    assert_eq!(tree.content_type, "multipart/related");
+    assert_eq!(tree.children.len(), 2);
+    assert_eq!(tree.children[0].content_type, "multipart/alternative");
+    assert_eq!(tree.children[0].children.len(), 2);
+    assert_eq!(tree.children[0].children[0].content_type, "text/plain");
+    assert_eq!(tree.children[0].children[1].encoding.as_deref(), Some("quoted-printable"));
+    assert_eq!(tree.children[1].content_type, "image/png");
+    assert_eq!(tree.children[1].disposition.as_deref(), Some("attachment"));
+    assert_eq!(tree.children[1].content_id.as_deref(), Some("ii_m2lqbrhv0"));
+    assert_eq!(tree.children[0].children[0].content_id, None);
+  }
+
+  #[test]
+  fn test_emlx() {
+    let mut message = MessageParser::new("tests/sample.emlx");
+    message.parse().unwrap();
+    assert_eq!(message.subject(), "Apple Mail message");
+    assert_eq!(message.from(), "John Doe <john@moon.space>");
+
+    let message = MessageParser::from_bytes(&fs::read("tests/sample.emlx").unwrap()).unwrap();
+    assert_eq!(message.subject(), "Apple Mail message");
+    assert!(message.body_text().unwrap().ends_with("Regards,\nJohn\n"));
+  }
+
+  #[test]
+  fn test_to_eml() {
+    let mut message = MessageParser::new("sample.eml");
+    message.parse().unwrap();
+    let eml = message.to_eml().unwrap();
+    let copy = MessageParser::from_bytes(&eml).unwrap();
+    assert_eq!(copy.subject(), message.subject());
+    assert_eq!(copy.from(), message.from());
+    assert_eq!(copy.to(), message.to());
+    assert_eq!(copy.date(), message.date());
+    assert_eq!(copy.body_text(), message.body_text());
+    assert_eq!(copy.attachments().len(), 1);
+    assert_eq!(copy.attachments()[0].filename, "Deus_Gnome.png");
+    assert_eq!(copy.attachments()[0].body, message.attachments()[0].body);
+  }
+
+  #[test]
+  fn test_to_value() {
+    let mut message = MessageParser::new("sample.eml");
+    message.parse().unwrap();
+    let value = message.to_value();
+    let headers = value.get("headers").and_then(Value::as_array).unwrap();
+    assert!(headers.iter().any(|header| {
+      header.get("name").and_then(Value::as_str) == Some("Subject")
+        && header.get("value").and_then(Value::as_str) == Some("Lorem ipsum")
+    }));
+    let attachments = value.get("attachments").and_then(Value::as_array).unwrap();
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(
+      attachments[0].get("filename").and_then(Value::as_str),
+      Some("Deus_Gnome.png")
+    );
+    let text = value.get("body_text").and_then(Value::as_str).unwrap();
+    assert_eq!(Some(text.to_string()), message.body_text());
+    assert!(value.get("parts").and_then(|p| p.get("children")).is_some());
+    assert!(value.get("findings").and_then(Value::as_array).is_some());
+    assert!(value.to_string().starts_with("{\"from\":\"John Doe <john@moon.space>\""));
+  }
+
+  #[test]
+  fn test_domain_alignment() {
+    let mut message = MessageParser::with_bytes(
+      b"From: John <john@example.com>\r\n\
+        Authentication-Results: mx.example.org; dkim=pass header.d=example.com\r\n\
+        \r\n\
+        Hello\r\n",
+    );
+    message.parse().unwrap();
+    assert_eq!(message.is_domain_aligned(), Alignment::Aligned);
+    let mut message = MessageParser::new("sample.eml");
+    message.parse().unwrap();
+    assert_eq!(message.is_domain_aligned(), Alignment::Unknown);
+  }
+
+  #[test]
+  fn test_is_bulk() {
+    let bulk = |headers: &str| {
+      let eml = format!("{}\r\n\r\nHello\r\n", headers);
+      let mut message = MessageParser::with_bytes(eml.as_bytes());
+      message.parse().unwrap();
+      message.is_bulk()
+    };
+    assert!(bulk("From: news@example.com\r\nList-Id: Moon news <news.example.com>"));
+    assert!(bulk("From: news@example.com\r\nList-Unsubscribe: <mailto:leave@example.com>"));
+    assert!(bulk("From: news@example.com\r\nPrecedence: Bulk"));
+    assert!(!bulk("From: john@example.com\r\nPrecedence: first-class"));
+    let mut message = MessageParser::new("sample.eml");
+    message.parse().unwrap();
+    assert!(!message.is_bulk());
+  }
+
+  #[test]
+  fn test_thread_subject() {
+    assert_eq!(thread_subject("Re: Re: Fwd: Hello"), "Hello");
+    assert_eq!(thread_subject("AW: Sv: RE[2]: Hello"), "Hello");
+    assert_eq!(thread_subject("回复：Hello"), "Hello");
+    assert_eq!(thread_subject("Meeting: Monday"), "Meeting: Monday");
+    assert_eq!(thread_subject("Re:"), "");
+    assert_eq!(thread_subject("  Hello  "), "Hello");
+    let mut message = MessageParser::new("sample.eml");
+    message.parse().unwrap();
+    assert_eq!(message.thread_subject(), "Lorem ipsum");
+  }
+
+  #[test]
+  fn test_normalize_newlines() {
+    assert_eq!(normalize_newlines("one\rtwo\r\nthree\nfour\r"), "one\ntwo\nthree\nfour\n");
+    assert_eq!(normalize_newlines("\r\r"), "\n\n");
+  }
+
+  #[test]
+  fn test_base64_message() {
+    let mut message = MessageParser::new("tests/base64.eml");
+    message.parse().unwrap();
+    assert_eq!(message.subject(), "Lorem ipsum");
+    assert_eq!(message.attachments().len(), 1);
+
+    let data = fs::read("tests/base64.eml").unwrap();
+    let message = MessageParser::from_bytes(&data).unwrap();
+    assert_eq!(message.from(), "John Doe <john@moon.space>");
+
+    // base64, but not of a message
+    assert!(MessageParser::unwrap_base64(b"SGVsbG8gTHVjYXMsIGhvdyBhcmUgeW91ID8=\n").is_none());
+    // a message whose body happens to be base64
+    assert!(MessageParser::unwrap_base64(&fs::read("sample.eml").unwrap()).is_none());
+  }
+
+  #[test]
+  fn test_part_bytes() {
+    let mut message = MessageParser::new("sample.eml");
+    message.parse().unwrap();
+    assert_eq!(message.part_bytes(0).map(|b| b.len()), Some(499));
+    assert_eq!(message.part_bytes(2).map(|b| b.len()), Some(11080));
+    assert_eq!(message.part_bytes(3), None);
+  }
+
+  #[test]
+  fn test_raw_part() {
+    let mut message = MessageParser::new("sample.eml");
+    message.parse().unwrap();
+    let raw = String::from_utf8(message.raw_part(0).unwrap()).unwrap();
+    assert!(raw
+      .lines()
+      .any(|line| line.to_lowercase().starts_with("content-type: text/plain")));
+    assert_eq!(message.mime_tree().children[0].children[0].index, Some(0));
+    // written out from the parsed stream after parsing, still base64
+    let image = message.raw_part(2).unwrap();
+    assert!(image.len() > message.part_bytes(2).unwrap().len() * 4 / 3);
+    assert_eq!(message.raw_part(3), None);
+  }
+
   #[test]
   fn test_uppercase_msg() {
     let message = MessageParser::new("sample.MSG");
@@ -0,0 +1,49 @@
+/* mimepart.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/// One node of the MIME structure of a message. Multipart (and
+/// message/rfc822) nodes have children, leaf parts don't.
+#[derive(Debug, Default, Clone)]
+pub struct MimePart {
+  pub content_type: String,
+  pub disposition: Option<String>,
+  pub encoding: Option<String>,
+  /// Content-ID of leaf parts, what cid: URLs refer to
+  pub content_id: Option<String>,
+  /// decoded size in bytes (sum of the children for containers)
+  pub size: usize,
+  /// position of a leaf part for Message::part_bytes() and raw_part()
+  pub index: Option<usize>,
+  pub children: Vec<MimePart>,
+}
+
+impl MimePart {
+  pub fn leaf(content_type: &str, disposition: Option<&str>, size: usize) -> Self {
+    Self {
+      content_type: content_type.to_string(),
+      disposition: disposition.map(|d| d.to_string()),
+      encoding: None,
+      content_id: None,
+      size,
+      index: None,
+      children: vec![],
+    }
+  }
+}
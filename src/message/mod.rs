@@ -1,4 +1,27 @@
+pub(crate) mod alternative;
+mod applefile;
+pub(crate) mod archive;
 pub(crate) mod attachment;
+pub(crate) mod authresults;
+pub(crate) mod dsn;
 mod electronicmail;
+mod embedded;
+pub(crate) mod eml;
+mod emlx;
+mod flowed;
+pub(crate) mod folder;
+pub(crate) mod idn;
+pub(crate) mod mbox;
 pub(crate) mod message;
+pub(crate) mod mimepart;
 mod outlook;
+pub(crate) mod partial;
+mod preview;
+mod rfc2231;
+pub(crate) mod search;
+pub(crate) mod security;
+pub(crate) mod sniff;
+pub(crate) mod transfer;
+pub(crate) mod value;
+pub(crate) mod vcard;
+pub(crate) mod zip;
@@ -22,12 +22,16 @@ use std::error::Error;
 use msg_parser::Outlook;
 
 use super::attachment::Attachment;
-use super::message::Message;
+use super::authresults::AuthResults;
+use super::dsn::DeliveryStatus;
+use super::message::{normalize_newlines, Message};
+use super::mimepart::MimePart;
 use crate::message::message::MessageParser;
 
 #[derive(Debug, Default, Clone)]
 pub struct OutlookMessage {
   file: String,
+  data: Option<Vec<u8>>,
   pub from: String,
   pub to: String,
   pub date: String,
@@ -40,6 +44,20 @@ impl OutlookMessage {
   pub fn new(file: &str) -> Self {
     Self {
       file: file.to_string(),
+      data: None,
+      from: String::new(),
+      to: String::new(),
+      date: String::new(),
+      subject: String::new(),
+      body: None,
+      attachments: vec![],
+    }
+  }
+
+  pub fn with_bytes(data: &[u8]) -> Self {
+    Self {
+      file: String::new(),
+      data: Some(data.to_vec()),
       from: String::new(),
       to: String::new(),
       date: String::new(),
@@ -64,12 +82,15 @@ impl OutlookMessage {
 
 impl Message for OutlookMessage {
   fn parse(&mut self) -> Result<(), Box<dyn Error>> {
-    let outlook = Outlook::from_path(&self.file)?;
+    let outlook = match &self.data {
+      Some(data) => Outlook::from_slice(data)?,
+      None => Outlook::from_path(&self.file)?,
+    };
     self.from = OutlookMessage::person_to_string(&outlook.sender);
     self.to = OutlookMessage::person_list_to_string(&outlook.to);
     self.subject = outlook.subject;
     self.date = outlook.headers.date;
-    self.body = Some(outlook.body.clone());
+    self.body = Some(normalize_newlines(&outlook.body));
 
     for i in 0..outlook.attachments.capacity() {
       let att = &outlook.attachments[i];
@@ -78,6 +99,8 @@ impl Message for OutlookMessage {
         content_id: att.file_name.clone(), // Uuid::new_v4().simple().to_string(),
         body: hex::decode(&att.payload)?,
         mime_type: Some(att.mime_tag.clone()),
+        description: None,
+        encoded: None,
       });
     }
 
@@ -100,10 +123,19 @@ impl Message for OutlookMessage {
     self.date.clone()
   }
 
+  fn headers(&self) -> Vec<(String, String)> {
+    // msg_parser doesn't expose the raw transport headers
+    vec![]
+  }
+
   fn attachments(&self) -> Vec<Attachment> {
     self.attachments.clone()
   }
 
+  fn attachments_ref(&self) -> &[Attachment] {
+    &self.attachments
+  }
+
   fn body_html(&self) -> Option<String> {
     None
   }
@@ -111,6 +143,84 @@ impl Message for OutlookMessage {
   fn body_text(&self) -> Option<String> {
     self.body.clone()
   }
+
+  fn charset(&self) -> Option<String> {
+    // msg_parser hands out decoded strings
+    None
+  }
+
+  fn language(&self) -> Option<String> {
+    None
+  }
+
+  fn sender(&self) -> Option<String> {
+    None
+  }
+
+  fn organization(&self) -> Option<String> {
+    None
+  }
+
+  // .msg files are not MIME, this only mirrors what was extracted
+  fn mime_tree(&self) -> MimePart {
+    let mut root = MimePart::leaf("application/vnd.ms-outlook", None, 0);
+    if let Some(body) = &self.body {
+      root.children.push(MimePart::leaf("text/plain", Some("inline"), body.len()));
+    }
+    for attachment in &self.attachments {
+      root.children.push(MimePart::leaf(
+        attachment.mime_type.as_deref().unwrap_or("application/octet-stream"),
+        Some("attachment"),
+        attachment.size(),
+      ));
+    }
+    for (index, child) in root.children.iter_mut().enumerate() {
+      child.index = Some(index);
+    }
+    root.size = root.children.iter().map(|c| c.size).sum();
+    root
+  }
+
+  fn part_bytes(&self, index: usize) -> Option<Vec<u8>> {
+    // same order as mime_tree(): body first, then the attachments
+    self
+      .body
+      .iter()
+      .map(|body| Some(body.as_bytes().to_vec()))
+      .chain(self.attachments.iter().map(|a| a.bytes().ok().map(|bytes| bytes.to_vec())))
+      .nth(index)
+      .flatten()
+  }
+
+  fn raw_part(&self, _index: usize) -> Option<Vec<u8>> {
+    // .msg files have no MIME source
+    None
+  }
+
+  fn is_truncated(&self) -> bool {
+    false
+  }
+
+  fn warnings(&self) -> Vec<String> {
+    vec![]
+  }
+
+  fn stylesheets(&self) -> Vec<(String, String)> {
+    vec![]
+  }
+
+  // transport headers of .msg files are not parsed
+  fn auth_results(&self) -> Vec<AuthResults> {
+    vec![]
+  }
+
+  fn delivery_status(&self) -> Vec<DeliveryStatus> {
+    vec![]
+  }
+
+  fn text_flowed(&self) -> Option<bool> {
+    None
+  }
 }
 
 impl Drop for OutlookMessage {
@@ -0,0 +1,236 @@
+/* partial.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+// header fields of the first fragment replaced by the encapsulated ones
+const ENCAPSULATED: &[&str] = &["subject", "message-id", "encrypted", "mime-version"];
+// fragments are only looked for in files starting with a small header block
+const HEAD_SIZE: u64 = 16 * 1024;
+
+/// One message/partial fragment (RFC 2046, 5.2.2) of a split message
+#[derive(Debug, Clone)]
+pub struct Fragment {
+  pub id: String,
+  pub number: usize,
+  /// only required in the last fragment
+  pub total: Option<usize>,
+  fields: Vec<String>,
+  body: Vec<u8>,
+}
+
+impl Fragment {
+  /// None unless `data` is a message/partial fragment
+  pub fn parse(data: &[u8]) -> Option<Self> {
+    let (fields, body) = split_message(data);
+    let content_type = fields
+      .iter()
+      .find(|field| field_name(field).eq_ignore_ascii_case("content-type"))?;
+    let value = unfold(&content_type[content_type.find(':')? + 1..]);
+    let mime_type = value.split(';').next()?.trim();
+    if !mime_type.eq_ignore_ascii_case("message/partial") {
+      return None;
+    }
+    Some(Self {
+      id: parameter(&value, "id")?,
+      number: parameter(&value, "number")?.parse().ok()?,
+      total: parameter(&value, "total").and_then(|total| total.parse().ok()),
+      fields,
+      body: body.to_vec(),
+    })
+  }
+}
+
+/// Rebuilds the message split in `fragments` (any order, same id).
+pub fn reassemble(fragments: &[Fragment]) -> Result<Vec<u8>, Box<dyn Error>> {
+  let mut fragments: Vec<&Fragment> = fragments.iter().collect();
+  fragments.sort_by_key(|fragment| fragment.number);
+  fragments.dedup_by_key(|fragment| fragment.number);
+  let total = fragments
+    .iter()
+    .find_map(|fragment| fragment.total)
+    .ok_or("Incomplete split message: the last fragment is missing")?;
+  if fragments.len() != total || fragments.iter().enumerate().any(|(i, f)| f.number != i + 1) {
+    let error = format!("Incomplete split message: {} of {} fragments", fragments.len(), total);
+    return Err(error.into());
+  }
+
+  let mut encapsulated: Vec<u8> = vec![];
+  for fragment in &fragments {
+    encapsulated.extend_from_slice(&fragment.body);
+  }
+  let (inner_fields, body) = split_message(&encapsulated);
+
+  let mut message = String::new();
+  // outer headers of the first fragment, minus Content-* and the replaced ones
+  for field in &fragments[0].fields {
+    if !is_encapsulated(field_name(field)) {
+      message.push_str(&format!("{}\r\n", field));
+    }
+  }
+  // then only those from the encapsulated message
+  for field in &inner_fields {
+    if is_encapsulated(field_name(field)) {
+      message.push_str(&format!("{}\r\n", field));
+    }
+  }
+  message.push_str("\r\n");
+  let mut message = message.into_bytes();
+  message.extend_from_slice(body);
+  Ok(message)
+}
+
+/// The message split in `fragment` (read from `path`), from the fragments
+/// found next to it.
+pub fn reassemble_from_dir(path: &Path, fragment: &Fragment) -> Result<Vec<u8>, Box<dyn Error>> {
+  let mut fragments = vec![fragment.clone()];
+  let dir = match path.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => dir,
+    _ => Path::new("."),
+  };
+  for entry in fs::read_dir(dir)? {
+    let sibling = entry?.path();
+    if !sibling.is_file() || sibling == path || !may_be_fragment(&sibling) {
+      continue;
+    }
+    if let Some(other) = Fragment::parse(&fs::read(&sibling)?) {
+      if other.id == fragment.id {
+        log::debug!("reassemble_from_dir() fragment {} in {:?}", other.number, sibling);
+        fragments.push(other);
+      }
+    }
+  }
+  reassemble(&fragments)
+}
+
+// cheap check of the headers, before reading a whole file
+fn may_be_fragment(path: &Path) -> bool {
+  let mut head = vec![];
+  match File::open(path).and_then(|file| file.take(HEAD_SIZE).read_to_end(&mut head)) {
+    Ok(_) => String::from_utf8_lossy(&head).to_lowercase().contains("message/partial"),
+    Err(_) => false,
+  }
+}
+
+fn is_encapsulated(name: &str) -> bool {
+  let name = name.to_lowercase();
+  name.starts_with("content-") || ENCAPSULATED.contains(&name.as_str())
+}
+
+fn field_name(field: &str) -> &str {
+  field.split(':').next().unwrap_or_default().trim()
+}
+
+// header fields (folded lines kept) and body
+fn split_message(data: &[u8]) -> (Vec<String>, &[u8]) {
+  let mut fields: Vec<String> = vec![];
+  let mut offset = 0;
+  while offset < data.len() {
+    let end = data[offset..]
+      .iter()
+      .position(|&b| b == b'\n')
+      .map_or(data.len(), |i| offset + i + 1);
+    let line = String::from_utf8_lossy(&data[offset..end]);
+    let line = line.trim_end_matches(['\r', '\n']);
+    offset = end;
+    if line.is_empty() {
+      break;
+    }
+    match fields.last_mut() {
+      Some(field) if line.starts_with([' ', '\t']) => {
+        field.push_str("\r\n");
+        field.push_str(line);
+      }
+      _ => fields.push(line.to_string()),
+    }
+  }
+  (fields, &data[offset.min(data.len())..])
+}
+
+fn unfold(value: &str) -> String {
+  value.replace("\r\n", "").replace('\n', "")
+}
+
+// parameter of a structured header value, unquoted
+fn parameter(value: &str, name: &str) -> Option<String> {
+  value.split(';').skip(1).find_map(|param| {
+    let (key, value) = param.split_once('=')?;
+    match key.trim().eq_ignore_ascii_case(name) {
+      true => Some(value.trim().trim_matches('"').to_string()),
+      false => None,
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const PART1: &[u8] = b"From: John Doe <john@moon.space>\r\n\
+To: Lucas <lucas@mercure.space>\r\n\
+Subject: Split message (part 1/2)\r\n\
+Message-ID: <part1@moon.space>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: message/partial; id=\"split@moon.space\";\r\n number=1; total=2\r\n\
+\r\n\
+Subject: Holiday pictures\r\n\
+Message-ID: <split@moon.space>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+\r\n\
+The first half of the body, ";
+
+  const PART2: &[u8] = b"From: John Doe <john@moon.space>\r\n\
+Subject: Split message (part 2/2)\r\n\
+Content-Type: message/partial; id=\"split@moon.space\"; number=2; total=2\r\n\
+\r\n\
+then the second half.\r\n";
+
+  #[test]
+  fn fragment() {
+    let fragment = Fragment::parse(PART1).unwrap();
+    assert_eq!(fragment.id, "split@moon.space");
+    assert_eq!(fragment.number, 1);
+    assert_eq!(fragment.total, Some(2));
+    assert!(Fragment::parse(b"Subject: Hello\r\n\r\nHello\r\n").is_none());
+  }
+
+  #[test]
+  fn reassembly() {
+    let fragments = [Fragment::parse(PART2).unwrap(), Fragment::parse(PART1).unwrap()];
+    let message = String::from_utf8(reassemble(&fragments).unwrap()).unwrap();
+    assert_eq!(
+      message,
+      "From: John Doe <john@moon.space>\r\n\
+To: Lucas <lucas@mercure.space>\r\n\
+Subject: Holiday pictures\r\n\
+Message-ID: <split@moon.space>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+\r\n\
+The first half of the body, then the second half.\r\n"
+    );
+
+    let error = reassemble(&fragments[1..]).unwrap_err();
+    assert_eq!(error.to_string(), "Incomplete split message: 1 of 2 fragments");
+  }
+}
@@ -0,0 +1,141 @@
+/* preview.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::fmt::Write;
+
+/// Bytes of a part shown at most, the rest is only counted
+pub const PREVIEW_LIMIT: usize = 4096;
+
+/// Text shown in place of a body part of an unknown type: a note, then an
+/// escaped or hex dumped preview of its first bytes.
+pub fn describe(content_type: &str, data: &[u8]) -> String {
+  format!(
+    "[Unknown content type {}, {} bytes]\n\n{}",
+    match content_type {
+      "" => "(none)",
+      content_type => content_type,
+    },
+    data.len(),
+    preview(data)
+  )
+}
+
+/// Escaped text when `data` looks like text, a hex dump otherwise, bounded
+/// to PREVIEW_LIMIT bytes.
+pub fn preview(data: &[u8]) -> String {
+  let shown = &data[..data.len().min(PREVIEW_LIMIT)];
+  let mut preview = match is_binary(shown) {
+    true => hexdump(shown),
+    false => escape(shown),
+  };
+  if data.len() > shown.len() {
+    let _ = write!(preview, "\n[{} more bytes]", data.len() - shown.len());
+  }
+  preview
+}
+
+/// NUL bytes, or more than a tenth of invalid UTF-8 and control characters
+fn is_binary(data: &[u8]) -> bool {
+  if data.contains(&0) {
+    return true;
+  }
+  let mut odd = 0;
+  for chunk in data.utf8_chunks() {
+    odd += chunk.invalid().len();
+    odd += chunk.valid().chars().filter(|c| is_escaped(*c)).count();
+  }
+  odd * 10 > data.len()
+}
+
+fn is_escaped(c: char) -> bool {
+  c.is_control() && !matches!(c, '\n' | '\t' | '\r')
+}
+
+/// The valid UTF-8 of `data` as is, control characters and invalid bytes as
+/// \xNN (or \u{NNNN}) escapes.
+pub fn escape(data: &[u8]) -> String {
+  let mut text = String::new();
+  for chunk in data.utf8_chunks() {
+    for c in chunk.valid().chars() {
+      match is_escaped(c) {
+        true if (c as u32) < 0x100 => {
+          let _ = write!(text, "\\x{:02x}", c as u32);
+        }
+        true => {
+          let _ = write!(text, "\\u{{{:04x}}}", c as u32);
+        }
+        false => text.push(c),
+      }
+    }
+    for byte in chunk.invalid() {
+      let _ = write!(text, "\\x{:02x}", byte);
+    }
+  }
+  text
+}
+
+/// Offset, 16 bytes in hex and their printable ASCII per line
+pub fn hexdump(data: &[u8]) -> String {
+  let mut dump = String::new();
+  for (i, line) in data.chunks(16).enumerate() {
+    let _ = write!(dump, "{:08x} ", i * 16);
+    for column in 0..16 {
+      if column == 8 {
+        dump.push(' ');
+      }
+      match line.get(column) {
+        Some(byte) => {
+          let _ = write!(dump, " {:02x}", byte);
+        }
+        None => dump.push_str("   "),
+      }
+    }
+    dump.push_str("  |");
+    dump.extend(line.iter().map(|&byte| match byte.is_ascii_graphic() || byte == b' ' {
+      true => byte as char,
+      false => '.',
+    }));
+    dump.push_str("|\n");
+  }
+  dump
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn hexdump_and_escape() {
+    let data = b"Hello,\x00\xffworld!\n\x1b[0m";
+    assert_eq!(
+      hexdump(data),
+      "00000000  48 65 6c 6c 6f 2c 00 ff  77 6f 72 6c 64 21 0a 1b  |Hello,..world!..|\n\
+       00000010  5b 30 6d                                          |[0m|\n"
+    );
+    assert_eq!(escape(data), "Hello,\\x00\\xffworld!\n\\x1b[0m");
+    assert_eq!(escape("été\u{7f}".as_bytes()), "été\\x7f");
+
+    assert_eq!(preview(data), hexdump(data));
+    assert_eq!(preview(b"plain text\r\n"), "plain text\r\n");
+    let large = vec![b'a'; PREVIEW_LIMIT + 10];
+    assert!(preview(&large).ends_with("\n[10 more bytes]"));
+    assert!(describe("application/x-unknown", b"abc")
+      .starts_with("[Unknown content type application/x-unknown, 3 bytes]\n\nabc"));
+  }
+}
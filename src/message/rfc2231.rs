@@ -0,0 +1,180 @@
+/* rfc2231.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::collections::BTreeMap;
+
+use gmime::glib;
+
+/// Value of the parameter `name` in a structured header (Content-Type,
+/// Content-Disposition), with the RFC 2231 continuations (`filename*0*=`,
+/// `filename*1*=`) joined and the charset (`filename*=UTF-8''...`) decoded.
+/// That form wins over the plain one, usually an ASCII fallback.
+pub fn parameter(header: &str, name: &str) -> Option<String> {
+  let name = name.to_lowercase();
+  let mut plain: Option<String> = None;
+  // section => (value, extended)
+  let mut sections: BTreeMap<usize, (String, bool)> = BTreeMap::new();
+  for (key, value) in parameters(header) {
+    let Some(rest) = key.strip_prefix(&name) else {
+      continue;
+    };
+    let Some(rest) = rest.strip_prefix('*') else {
+      if rest.is_empty() {
+        plain = Some(value);
+      }
+      continue;
+    };
+    if rest.is_empty() {
+      sections.insert(0, (value, true));
+      continue;
+    }
+    let (index, extended) = match rest.strip_suffix('*') {
+      Some(index) => (index, true),
+      None => (rest, false),
+    };
+    if let Ok(index) = index.parse::<usize>() {
+      sections.insert(index, (value, extended));
+    }
+  }
+  if sections.is_empty() {
+    return plain;
+  }
+
+  let mut charset: Option<String> = None;
+  let mut bytes: Vec<u8> = vec![];
+  for (expected, (index, (value, extended))) in sections.iter().enumerate() {
+    // a missing section ends the value
+    if *index != expected {
+      break;
+    }
+    match (*extended, *index) {
+      (true, 0) => {
+        let mut fields = value.splitn(3, '\'');
+        match (fields.next(), fields.next(), fields.next()) {
+          (Some(set), Some(_language), Some(encoded)) => {
+            charset = Some(set.to_string()).filter(|set| !set.is_empty());
+            bytes.extend(percent_decode(encoded));
+          }
+          _ => bytes.extend(percent_decode(value)),
+        }
+      }
+      (true, _) => bytes.extend(percent_decode(value)),
+      (false, _) => bytes.extend(value.as_bytes()),
+    }
+  }
+  Some(decode(&bytes, charset.as_deref()))
+}
+
+/// (lowercase key, unquoted value) of the parameters following the value
+fn parameters(header: &str) -> Vec<(String, String)> {
+  let mut parameters: Vec<(String, String)> = vec![];
+  let mut chars = header.chars().peekable();
+  // skip the value itself, "attachment" or "text/plain"
+  while chars.next_if(|c| *c != ';').is_some() {}
+  while chars.next().is_some() {
+    let key: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=' && *c != ';')).collect();
+    let mut value = String::new();
+    if chars.next_if_eq(&'=').is_some() {
+      while chars.next_if(|c| c.is_whitespace()).is_some() {}
+      if chars.next_if_eq(&'"').is_some() {
+        while let Some(c) = chars.next() {
+          match c {
+            '"' => break,
+            '\\' => value.extend(chars.next()),
+            _ => value.push(c),
+          }
+        }
+        while chars.next_if(|c| *c != ';').is_some() {}
+      } else {
+        value = std::iter::from_fn(|| chars.next_if(|c| *c != ';')).collect();
+      }
+    }
+    let key = key.trim().to_lowercase();
+    if !key.is_empty() {
+      parameters.push((key, value.trim().to_string()));
+    }
+  }
+  parameters
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+  let bytes = value.as_bytes();
+  let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let hex = bytes
+      .get(i + 1..i + 3)
+      .and_then(|hex| std::str::from_utf8(hex).ok())
+      .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    match (bytes[i], hex) {
+      (b'%', Some(byte)) => {
+        decoded.push(byte);
+        i += 3;
+      }
+      (byte, _) => {
+        decoded.push(byte);
+        i += 1;
+      }
+    }
+  }
+  decoded
+}
+
+fn decode(bytes: &[u8], charset: Option<&str>) -> String {
+  let charset = charset.unwrap_or("us-ascii").to_lowercase();
+  match charset.as_str() {
+    "utf-8" | "utf8" | "us-ascii" => String::from_utf8_lossy(bytes).to_string(),
+    "iso-8859-1" | "latin1" => bytes.iter().map(|&c| c as char).collect(),
+    _ => match glib::convert(bytes, "UTF-8", charset.as_str()) {
+      Ok((converted, _)) => String::from_utf8_lossy(&converted).to_string(),
+      Err(err) => {
+        log::warn!("rfc2231::decode({}) => {}", charset, err);
+        String::from_utf8_lossy(bytes).to_string()
+      }
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn continuations() {
+    let header = "attachment;\n filename*0*=UTF-8''Rapport%20annuel%20;\n \
+                  filename*1*=%C3%A9t%C3%A9%202024;\n filename*2=\".pdf\"";
+    assert_eq!(
+      parameter(header, "filename").as_deref(),
+      Some("Rapport annuel été 2024.pdf")
+    );
+    // a missing section ends the value
+    let header = "attachment; filename*0=\"abc\"; filename*2=\"ghi\"";
+    assert_eq!(parameter(header, "filename").as_deref(), Some("abc"));
+  }
+
+  #[test]
+  fn charsets() {
+    let header = "attachment; filename=\"fallback.txt\"; filename*=iso-8859-1'fr'caf%E9.txt";
+    assert_eq!(parameter(header, "filename").as_deref(), Some("café.txt"));
+    let header = "text/plain; charset=\"utf-8\"; name=\"a \\\"quoted\\\" name.txt\"";
+    assert_eq!(parameter(header, "name").as_deref(), Some("a \"quoted\" name.txt"));
+    assert_eq!(parameter(header, "charset").as_deref(), Some("utf-8"));
+    assert_eq!(parameter(header, "filename"), None);
+  }
+}
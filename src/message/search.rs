@@ -0,0 +1,83 @@
+/* search.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use nipper::Document;
+
+use super::attachment::Attachment;
+
+/// Where a search query was found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchMatch {
+  Subject,
+  From,
+  To,
+  TextBody,
+  HtmlBody,
+  Attachment(String),
+}
+
+const TEXT_EXTENSIONS: &[&str] = &[
+  "txt", "csv", "tsv", "eml", "log", "json", "xml", "md", "ics", "vcf", "html", "htm",
+];
+
+pub fn contains(haystack: &str, query: &str) -> bool {
+  haystack.to_lowercase().contains(&query.to_lowercase())
+}
+
+/// Visible text of an HTML body, so tags and attributes don't match.
+pub fn html_text(html: &str) -> String {
+  let document = Document::from(html);
+  document.select("script,style").iter().for_each(|mut node| {
+    node.remove();
+  });
+  document.select("body").text().to_string()
+}
+
+/// Text content of a text-like attachment (by mime type, or by extension
+/// for application/octet-stream), None for binary ones.
+pub fn attachment_text(attachment: &Attachment) -> Option<String> {
+  let mime_type = attachment
+    .mime_type
+    .as_deref()
+    .unwrap_or_default()
+    .to_lowercase();
+  let extension = attachment
+    .filename
+    .rsplit_once('.')
+    .map(|(_, e)| e.to_lowercase())
+    .unwrap_or_default();
+  let text_like = mime_type.starts_with("text/")
+    || mime_type == "message/rfc822"
+    || mime_type == "application/json"
+    || mime_type.ends_with("+xml")
+    || mime_type == "application/xml"
+    || TEXT_EXTENSIONS.contains(&extension.as_str());
+  if !text_like {
+    return None;
+  }
+  let bytes = attachment
+    .bytes()
+    .map_err(|e| log::warn!("attachment_text({}) => {}", attachment.filename, e))
+    .ok()?;
+  // a NUL byte is a good hint of a binary file whatever the name says
+  if bytes.contains(&0) {
+    return None;
+  }
+  Some(String::from_utf8_lossy(bytes).to_string())
+}
@@ -0,0 +1,309 @@
+/* security.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use super::attachment::Attachment;
+use super::authresults::{combined_result, AuthResults};
+use super::idn;
+use super::sniff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  Info,
+  Warning,
+  Danger,
+}
+
+/// One reason to be wary of a message, the window words it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+  /// SPF, DKIM or DMARC didn't pass
+  AuthFailed { method: String, result: String },
+  /// no Authentication-Results at all
+  NoAuthentication,
+  /// the From display name shows another address, "paypal.com <x@evil.ru>"
+  SpoofedFrom { shown: String, address: String },
+  /// replies go to another domain than the sender's
+  ReplyToMismatch { from: String, reply_to: String },
+  /// the link text is an URL to another host than the target
+  DeceptiveLink { text: String, href: String },
+  /// an internationalized sender or link domain imitating a Latin one,
+  /// "xn--80ak6aa92e.com" reads "аррӏе.com"
+  HomographDomain { domain: String, decoded: String, lookalike: String },
+  TrackingPixels(usize),
+  /// the content type doesn't match the file name
+  AttachmentMismatch { filename: String, actual: String },
+}
+
+impl Finding {
+  pub fn severity(&self) -> Severity {
+    match self {
+      Finding::AuthFailed { result, .. } => match result.as_str() {
+        "fail" | "permerror" => Severity::Danger,
+        _ => Severity::Warning,
+      },
+      Finding::NoAuthentication => Severity::Info,
+      Finding::SpoofedFrom { .. } => Severity::Danger,
+      Finding::ReplyToMismatch { .. } => Severity::Warning,
+      Finding::DeceptiveLink { .. } => Severity::Danger,
+      Finding::HomographDomain { .. } => Severity::Danger,
+      Finding::TrackingPixels(_) => Severity::Info,
+      Finding::AttachmentMismatch { actual, .. } => match sniff::is_executable(actual) {
+        true => Severity::Danger,
+        false => Severity::Warning,
+      },
+    }
+  }
+}
+
+/// Everything the findings are derived from, gathered by
+/// Message::security_findings().
+#[derive(Debug, Default)]
+pub struct Signals<'a> {
+  pub auth_results: &'a [AuthResults],
+  pub from: &'a str,
+  pub reply_to: Option<&'a str>,
+  /// (text, href) of the links whose text looks like an URL
+  pub deceptive_links: Vec<(String, String)>,
+  /// hosts of all the links
+  pub link_hosts: Vec<String>,
+  pub tracking_pixels: usize,
+  pub attachments: &'a [Attachment],
+}
+
+/// All the findings, most severe first.
+pub fn findings(signals: &Signals) -> Vec<Finding> {
+  let mut findings = vec![];
+  if signals.auth_results.is_empty() {
+    findings.push(Finding::NoAuthentication);
+  }
+  for method in ["spf", "dkim", "dmarc"] {
+    match combined_result(signals.auth_results, method) {
+      None | Some("pass") | Some("none") => (),
+      Some(result) => findings.push(Finding::AuthFailed {
+        method: method.to_string(),
+        result: result.to_string(),
+      }),
+    }
+  }
+  let from = address(signals.from);
+  if let Some(shown) = shown_address(signals.from) {
+    if from.as_deref().map(domain) != Some(domain(&shown)) {
+      findings.push(Finding::SpoofedFrom {
+        shown,
+        address: from.clone().unwrap_or_default(),
+      });
+    }
+  }
+  if let (Some(from), Some(reply_to)) = (&from, signals.reply_to.and_then(address)) {
+    if domain(from) != domain(&reply_to) {
+      findings.push(Finding::ReplyToMismatch {
+        from: from.clone(),
+        reply_to,
+      });
+    }
+  }
+  for (text, href) in &signals.deceptive_links {
+    findings.push(Finding::DeceptiveLink {
+      text: text.clone(),
+      href: href.clone(),
+    });
+  }
+  let mut domains: Vec<&str> = from.as_deref().map(domain).into_iter().collect();
+  domains.extend(signals.link_hosts.iter().map(|host| host.as_str()));
+  let mut seen: Vec<String> = vec![];
+  for domain in domains {
+    let domain = domain.to_lowercase();
+    if seen.contains(&domain) {
+      continue;
+    }
+    if let Some(lookalike) = idn::lookalike(&domain) {
+      findings.push(Finding::HomographDomain {
+        decoded: idn::to_unicode(&domain),
+        domain: domain.clone(),
+        lookalike,
+      });
+    }
+    seen.push(domain);
+  }
+  if signals.tracking_pixels > 0 {
+    findings.push(Finding::TrackingPixels(signals.tracking_pixels));
+  }
+  for attachment in signals.attachments {
+    if let Some(actual) = attachment.extension_mismatch() {
+      findings.push(Finding::AttachmentMismatch {
+        filename: attachment.filename.clone(),
+        actual: actual.to_string(),
+      });
+    }
+  }
+  // stable, keeps the order above within a severity
+  findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity()));
+  findings
+}
+
+/// Lowercase domain of the address of a From header
+pub fn from_domain(from: &str) -> Option<String> {
+  address(from).map(|address| domain(&address).to_string())
+}
+
+/// Whether two headers name the same address, whatever the display names
+pub fn same_address(a: &str, b: &str) -> bool {
+  match (address(a), address(b)) {
+    (Some(a), Some(b)) => a == b,
+    _ => a.trim().eq_ignore_ascii_case(b.trim()),
+  }
+}
+
+// "John <john@moon.space>" => "john@moon.space"
+fn address(value: &str) -> Option<String> {
+  let address = match (value.rfind('<'), value.rfind('>')) {
+    (Some(start), Some(end)) if start < end => &value[start + 1..end],
+    _ => value,
+  };
+  let address = address.trim();
+  match address.contains('@') {
+    true => Some(address.to_lowercase()),
+    false => None,
+  }
+}
+
+// an address (or a domain) in the display name
+fn shown_address(from: &str) -> Option<String> {
+  let name = &from[..from.rfind('<')?];
+  name
+    .split(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '(' || c == ')')
+    .find(|word| {
+      let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+      word.contains('@') || (word.contains('.') && word.rsplit('.').next().unwrap().len() >= 2)
+    })
+    .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase())
+}
+
+fn domain(address: &str) -> &str {
+  address.rsplit('@').next().unwrap_or(address)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn aggregation() {
+    let auth_results = vec![AuthResults::parse(
+      "mx.moon.space; spf=pass smtp.mailfrom=bank.example; dkim=fail header.d=bank.example; dmarc=fail",
+    )];
+    let attachments = vec![Attachment {
+      filename: "invoice.pdf".to_string(),
+      content_id: String::new(),
+      body: b"MZ\x90\x00".to_vec(),
+      mime_type: Some("application/pdf".to_string()),
+      description: None,
+      encoded: None,
+    }];
+    let signals = Signals {
+      auth_results: &auth_results,
+      from: "\"support@bank.example\" <support@evil.example>",
+      reply_to: Some("Support <help@other.example>"),
+      deceptive_links: vec![(
+        "https://bank.example/login".to_string(),
+        "https://evil.example/login".to_string(),
+      )],
+      link_hosts: vec![],
+      tracking_pixels: 2,
+      attachments: &attachments,
+    };
+    let findings = findings(&signals);
+    let severities: Vec<Severity> = findings.iter().map(|f| f.severity()).collect();
+    assert_eq!(
+      severities,
+      [
+        Severity::Danger,
+        Severity::Danger,
+        Severity::Danger,
+        Severity::Danger,
+        Severity::Danger,
+        Severity::Warning,
+        Severity::Info
+      ]
+    );
+    assert_eq!(
+      findings[0],
+      Finding::AuthFailed {
+        method: "dkim".to_string(),
+        result: "fail".to_string()
+      }
+    );
+    assert_eq!(
+      findings[2],
+      Finding::SpoofedFrom {
+        shown: "support@bank.example".to_string(),
+        address: "support@evil.example".to_string()
+      }
+    );
+    assert!(findings.contains(&Finding::AttachmentMismatch {
+      filename: "invoice.pdf".to_string(),
+      actual: "application/x-msdownload".to_string()
+    }));
+    assert_eq!(findings[6], Finding::TrackingPixels(2));
+  }
+
+  #[test]
+  fn homograph_domains() {
+    let signals = Signals {
+      from: "Apple <id@xn--80ak6aa92e.com>",
+      link_hosts: vec![
+        "xn--80ak6aa92e.com".to_string(),
+        "xn--mnchen-3ya.de".to_string(),
+        "moon.space".to_string(),
+      ],
+      ..Default::default()
+    };
+    let findings = findings(&signals);
+    assert_eq!(
+      findings[0],
+      Finding::HomographDomain {
+        domain: "xn--80ak6aa92e.com".to_string(),
+        decoded: "аррӏе.com".to_string(),
+        lookalike: "apple.com".to_string()
+      }
+    );
+    assert_eq!(findings[0].severity(), Severity::Danger);
+    assert_eq!(findings, vec![findings[0].clone(), Finding::NoAuthentication]);
+  }
+
+  #[test]
+  fn clean_message() {
+    let auth_results = vec![AuthResults::parse(
+      "mx.moon.space; spf=pass; dkim=pass header.d=moon.space; dmarc=pass",
+    )];
+    let signals = Signals {
+      auth_results: &auth_results,
+      from: "John Doe <john@moon.space>",
+      reply_to: Some("john@moon.space"),
+      ..Default::default()
+    };
+    assert_eq!(findings(&signals), vec![]);
+
+    let signals = Signals {
+      from: "moon.space <john@moon.space>",
+      ..Default::default()
+    };
+    assert_eq!(findings(&signals), vec![Finding::NoAuthentication]);
+  }
+}
@@ -0,0 +1,147 @@
+/* sniff.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+/// Mime type matching the magic bytes of `data`, None when unknown.
+/// Declared types and filenames come from the sender and can't be trusted.
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+  const MAGICS: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", "application/x-ole-storage"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"Rar!\x1a\x07", "application/vnd.rar"),
+    (b"MZ", "application/x-msdownload"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"#!", "text/x-shellscript"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"ID3", "audio/mpeg"),
+    (b"\xff\xfb", "audio/mpeg"),
+    (b"\x1a\x45\xdf\xa3", "video/webm"),
+  ];
+  // containers tagged after a size or chunk header
+  const TAGGED: &[(usize, &[u8], &str)] = &[
+    (4, b"ftyp", "video/mp4"),
+    (8, b"WAVE", "audio/wav"),
+    (8, b"AVI ", "video/x-msvideo"),
+  ];
+  MAGICS
+    .iter()
+    .find(|(magic, _)| data.starts_with(magic))
+    .map(|(_, mime)| *mime)
+    .or_else(|| {
+      TAGGED
+        .iter()
+        .find(|(offset, tag, _)| data.get(*offset..offset + tag.len()) == Some(*tag))
+        .map(|(_, _, mime)| *mime)
+    })
+}
+
+/// Whether the sniffed type is audio or video.
+pub fn is_media(mime_type: &str) -> bool {
+  mime_type.starts_with("audio/") || mime_type.starts_with("video/")
+}
+
+/// Whether the sniffed type is something that runs when opened.
+pub fn is_executable(mime_type: &str) -> bool {
+  matches!(
+    mime_type,
+    "application/x-msdownload" | "application/x-executable" | "text/x-shellscript"
+  )
+}
+
+// sniffed type a file with this extension is expected to have
+fn expected_type(extension: &str) -> Option<&'static str> {
+  let expected = match extension.to_lowercase().as_str() {
+    "png" => "image/png",
+    "jpg" | "jpeg" => "image/jpeg",
+    "gif" => "image/gif",
+    "pdf" => "application/pdf",
+    "zip" | "docx" | "xlsx" | "pptx" | "odt" | "ods" | "odp" | "jar" | "epub" => "application/zip",
+    "doc" | "xls" | "ppt" | "msg" => "application/x-ole-storage",
+    "gz" | "tgz" => "application/gzip",
+    "7z" => "application/x-7z-compressed",
+    "rar" => "application/vnd.rar",
+    "exe" | "dll" | "scr" | "com" => "application/x-msdownload",
+    "sh" => "text/x-shellscript",
+    _ => return None,
+  };
+  Some(expected)
+}
+
+/// The sniffed type when it contradicts the filename's extension: a known
+/// extension with other content, or an executable behind any other name.
+pub fn extension_mismatch(filename: &str, data: &[u8]) -> Option<&'static str> {
+  let sniffed = sniff(data)?;
+  let extension = std::path::Path::new(filename)
+    .extension()
+    .map(|e| e.to_string_lossy().to_string())
+    .unwrap_or_default();
+  match expected_type(&extension) {
+    Some(expected) if expected == sniffed => None,
+    Some(_) => Some(sniffed),
+    None if is_executable(sniffed) => Some(sniffed),
+    None => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+
+  #[test]
+  fn sniff_types() {
+    assert_eq!(sniff(PNG), Some("image/png"));
+    assert_eq!(sniff(b"%PDF-1.7\n"), Some("application/pdf"));
+    assert_eq!(sniff(b"MZ\x90\x00"), Some("application/x-msdownload"));
+    assert_eq!(sniff(b"\x7fELF\x02\x01"), Some("application/x-executable"));
+    assert_eq!(sniff(b"OggS\x00\x02"), Some("audio/ogg"));
+    assert_eq!(sniff(b"\x00\x00\x00\x20ftypisom"), Some("video/mp4"));
+    assert_eq!(sniff(b"RIFF\x24\x08\x00\x00WAVEfmt "), Some("audio/wav"));
+    assert_eq!(sniff(b"RIFF"), None);
+    assert_eq!(sniff(b"Hello"), None);
+    assert_eq!(sniff(b""), None);
+  }
+
+  #[test]
+  fn mismatch() {
+    assert_eq!(extension_mismatch("doc.pdf", PNG), Some("image/png"));
+    assert_eq!(
+      extension_mismatch("invoice.PDF", b"MZ\x90\x00"),
+      Some("application/x-msdownload")
+    );
+    assert_eq!(
+      extension_mismatch("notes.txt", b"#!/bin/sh\nrm -rf ~"),
+      Some("text/x-shellscript")
+    );
+    assert_eq!(extension_mismatch("image.png", PNG), None);
+    assert_eq!(extension_mismatch("report.docx", b"PK\x03\x04"), None);
+    assert_eq!(extension_mismatch("setup.exe", b"MZ\x90\x00"), None);
+    // nothing known to compare with
+    assert_eq!(extension_mismatch("notes.txt", b"Hello"), None);
+    assert_eq!(extension_mismatch("data.bin", PNG), None);
+  }
+}
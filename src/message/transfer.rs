@@ -0,0 +1,155 @@
+/* transfer.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::io::{self, Read, Write};
+
+/// Decodes the base64 written to it into `inner` as it comes, whatever the
+/// line breaks and however the input is cut: only an incomplete quantum is
+/// kept between two writes. Characters out of the alphabet are skipped, as
+/// gmime does, and the input ends at the first padding.
+pub struct Base64Writer<W: Write> {
+  inner: W,
+  quantum: [u8; 4],
+  length: usize,
+  padded: bool,
+}
+
+impl<W: Write> Base64Writer<W> {
+  pub fn new(inner: W) -> Self {
+    Self {
+      inner,
+      quantum: [0; 4],
+      length: 0,
+      padded: false,
+    }
+  }
+
+  /// Writes the last, unpadded, quantum and returns the inner writer. A lone
+  /// sextet left means the input was cut.
+  pub fn finish(mut self) -> io::Result<W> {
+    if self.length == 1 {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "Truncated base64"));
+    }
+    let tail = decode_quantum(&self.quantum, self.length);
+    self.inner.write_all(&tail)?;
+    Ok(self.inner)
+  }
+
+  /// The inner writer, without the incomplete quantum: a prefix of the input
+  /// decodes to a prefix of the data.
+  pub fn into_inner(self) -> W {
+    self.inner
+  }
+}
+
+impl<W: Write> Write for Base64Writer<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let mut decoded: Vec<u8> = Vec::with_capacity(buf.len() / 4 * 3 + 3);
+    for byte in buf {
+      if self.padded {
+        break;
+      }
+      let value = match *byte {
+        c @ b'A'..=b'Z' => c - b'A',
+        c @ b'a'..=b'z' => c - b'a' + 26,
+        c @ b'0'..=b'9' => c - b'0' + 52,
+        b'+' => 62,
+        b'/' => 63,
+        b'=' => {
+          self.padded = true;
+          continue;
+        }
+        _ => continue,
+      };
+      self.quantum[self.length] = value;
+      self.length += 1;
+      if self.length == 4 {
+        decoded.extend_from_slice(&decode_quantum(&self.quantum, 4));
+        self.length = 0;
+      }
+    }
+    self.inner.write_all(&decoded)?;
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+/// Decodes the base64 of `reader` to `writer` a buffer at a time
+pub fn decode_base64_to<R: Read, W: Write>(mut reader: R, writer: W) -> io::Result<W> {
+  let mut decoder = Base64Writer::new(writer);
+  io::copy(&mut reader, &mut decoder)?;
+  decoder.finish()
+}
+
+/// Size of the data `encoded` decodes to, without decoding it
+pub fn base64_decoded_len(encoded: &[u8]) -> usize {
+  let digits = encoded
+    .iter()
+    .take_while(|c| **c != b'=')
+    .filter(|c| c.is_ascii_alphanumeric() || **c == b'+' || **c == b'/')
+    .count();
+  digits / 4 * 3
+    + match digits % 4 {
+      2 => 1,
+      3 => 2,
+      _ => 0,
+    }
+}
+
+// `length` sextets to bytes, a lone sextet has no whole byte
+fn decode_quantum(quantum: &[u8; 4], length: usize) -> Vec<u8> {
+  let bytes = [
+    (quantum[0] << 2) | (quantum[1] >> 4),
+    (quantum[1] << 4) | (quantum[2] >> 2),
+    (quantum[2] << 6) | quantum[3],
+  ];
+  bytes[..length.saturating_sub(1)].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::message::eml::encode_base64;
+
+  #[test]
+  fn streaming_base64() {
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 + i / 251) as u8).collect();
+    for length in [data.len(), data.len() - 1, data.len() - 2] {
+      let encoded = encode_base64(&data[..length]);
+      assert_eq!(base64_decoded_len(encoded.as_bytes()), length);
+
+      // cut anywhere, lines and quanta included
+      let mut decoder = Base64Writer::new(vec![]);
+      for chunk in encoded.as_bytes().chunks(1000 + length % 7) {
+        decoder.write_all(chunk).unwrap();
+      }
+      assert_eq!(decoder.finish().unwrap(), &data[..length]);
+    }
+    let decoded = decode_base64_to("SGVs\r\nbG8=\r\nignored".as_bytes(), vec![]).unwrap();
+    assert_eq!(decoded, b"Hello");
+    assert_eq!(decode_base64_to("SGVsbG8".as_bytes(), vec![]).unwrap(), b"Hello");
+    assert!(decode_base64_to("SGVsbG8gV".as_bytes(), vec![]).is_err());
+    let mut decoder = Base64Writer::new(vec![]);
+    decoder.write_all(b"SGVsbG8gV").unwrap();
+    assert_eq!(decoder.into_inner(), b"Hello ");
+  }
+}
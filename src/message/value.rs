@@ -0,0 +1,464 @@
+/* value.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use super::attachment::Attachment;
+use super::authresults::AuthResults;
+use super::dsn::DeliveryStatus;
+use super::mimepart::MimePart;
+use super::security::{Finding, Severity};
+
+/// JSON-like tree of everything parsed from a message, see
+/// Message::to_value(). Objects keep their keys in insertion order and
+/// `to_string()` serializes it as JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Null,
+  Bool(bool),
+  Number(u64),
+  String(String),
+  Array(Vec<Value>),
+  Object(Vec<(String, Value)>),
+}
+
+impl Value {
+  pub fn object(entries: Vec<(&str, Value)>) -> Value {
+    Value::Object(
+      entries
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect(),
+    )
+  }
+
+  /// Member `key` of an object
+  #[allow(dead_code)]
+  pub fn get(&self, key: &str) -> Option<&Value> {
+    match self {
+      Value::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Value::String(s) => Some(s),
+      _ => None,
+    }
+  }
+
+  #[allow(dead_code)]
+  pub fn as_array(&self) -> Option<&[Value]> {
+    match self {
+      Value::Array(values) => Some(values),
+      _ => None,
+    }
+  }
+
+  pub fn as_object(&self) -> Option<&[(String, Value)]> {
+    match self {
+      Value::Object(entries) => Some(entries),
+      _ => None,
+    }
+  }
+
+  /// Reads a JSON document. Numbers are limited to what Value holds,
+  /// unsigned integers.
+  pub fn parse(text: &str) -> Result<Value, Box<dyn Error>> {
+    let mut chars = text.chars().peekable();
+    let value = parse_value(&mut chars)?;
+    skip_whitespace(&mut chars);
+    match chars.next() {
+      None => Ok(value),
+      Some(c) => Err(format!("Unexpected '{}' after the JSON value", c).into()),
+    }
+  }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+  while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), Box<dyn Error>> {
+  skip_whitespace(chars);
+  match chars.next() {
+    Some(c) if c == expected => Ok(()),
+    Some(c) => Err(format!("Expected '{}', found '{}'", expected, c).into()),
+    None => Err(format!("Expected '{}', found the end", expected).into()),
+  }
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<Value, Box<dyn Error>> {
+  skip_whitespace(chars);
+  match chars.peek().copied() {
+    Some('{') => {
+      chars.next();
+      let mut entries = vec![];
+      skip_whitespace(chars);
+      if chars.next_if_eq(&'}').is_some() {
+        return Ok(Value::Object(entries));
+      }
+      loop {
+        expect(chars, '"')?;
+        let key = parse_string(chars)?;
+        expect(chars, ':')?;
+        entries.push((key, parse_value(chars)?));
+        skip_whitespace(chars);
+        match chars.next() {
+          Some(',') => continue,
+          Some('}') => return Ok(Value::Object(entries)),
+          _ => return Err("Unterminated JSON object".into()),
+        }
+      }
+    }
+    Some('[') => {
+      chars.next();
+      let mut values = vec![];
+      skip_whitespace(chars);
+      if chars.next_if_eq(&']').is_some() {
+        return Ok(Value::Array(values));
+      }
+      loop {
+        values.push(parse_value(chars)?);
+        skip_whitespace(chars);
+        match chars.next() {
+          Some(',') => continue,
+          Some(']') => return Ok(Value::Array(values)),
+          _ => return Err("Unterminated JSON array".into()),
+        }
+      }
+    }
+    Some('"') => {
+      chars.next();
+      Ok(Value::String(parse_string(chars)?))
+    }
+    Some(c) if c.is_ascii_digit() => {
+      let mut number = String::new();
+      while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+        number.push(digit);
+      }
+      Ok(Value::Number(number.parse()?))
+    }
+    Some(c) if c.is_ascii_alphabetic() => {
+      let mut word = String::new();
+      while let Some(letter) = chars.next_if(|c| c.is_ascii_alphabetic()) {
+        word.push(letter);
+      }
+      match word.as_str() {
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        "null" => Ok(Value::Null),
+        _ => Err(format!("Unexpected \"{}\" in JSON", word).into()),
+      }
+    }
+    Some(c) => Err(format!("Unexpected '{}' in JSON", c).into()),
+    None => Err("Unexpected end of JSON".into()),
+  }
+}
+
+/// Rest of a string, after its opening quote
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, Box<dyn Error>> {
+  let mut string = String::new();
+  loop {
+    match chars.next().ok_or("Unterminated JSON string")? {
+      '"' => return Ok(string),
+      '\\' => match chars.next().ok_or("Unterminated JSON string")? {
+        'n' => string.push('\n'),
+        'r' => string.push('\r'),
+        't' => string.push('\t'),
+        'b' => string.push('\u{8}'),
+        'f' => string.push('\u{c}'),
+        'u' => {
+          let mut code = parse_hex4(chars)?;
+          // a surrogate pair encodes one character beyond the BMP
+          if (0xd800..0xdc00).contains(&code) {
+            if chars.next() != Some('\\') || chars.next() != Some('u') {
+              return Err("Unpaired JSON surrogate".into());
+            }
+            let low = parse_hex4(chars)?;
+            if !(0xdc00..0xe000).contains(&low) {
+              return Err("Unpaired JSON surrogate".into());
+            }
+            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+          }
+          string.push(char::from_u32(code).ok_or("Invalid JSON \\u escape")?);
+        }
+        c => string.push(c),
+      },
+      c => string.push(c),
+    }
+  }
+}
+
+fn parse_hex4(chars: &mut Peekable<Chars>) -> Result<u32, Box<dyn Error>> {
+  let hex: String = chars.by_ref().take(4).collect();
+  Ok(u32::from_str_radix(&hex, 16)?)
+}
+
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::Null => write!(f, "null"),
+      Value::Bool(b) => write!(f, "{}", b),
+      Value::Number(n) => write!(f, "{}", n),
+      Value::String(s) => write_string(f, s),
+      Value::Array(values) => {
+        write!(f, "[")?;
+        for (i, value) in values.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write!(f, "{}", value)?;
+        }
+        write!(f, "]")
+      }
+      Value::Object(entries) => {
+        write!(f, "{{")?;
+        for (i, (key, value)) in entries.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write_string(f, key)?;
+          write!(f, ":{}", value)?;
+        }
+        write!(f, "}}")
+      }
+    }
+  }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+  write!(f, "\"")?;
+  for c in s.chars() {
+    match c {
+      '"' => write!(f, "\\\"")?,
+      '\\' => write!(f, "\\\\")?,
+      '\n' => write!(f, "\\n")?,
+      '\r' => write!(f, "\\r")?,
+      '\t' => write!(f, "\\t")?,
+      c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+      c => write!(f, "{}", c)?,
+    }
+  }
+  write!(f, "\"")
+}
+
+impl From<&str> for Value {
+  fn from(s: &str) -> Self {
+    Value::String(s.to_string())
+  }
+}
+
+impl From<String> for Value {
+  fn from(s: String) -> Self {
+    Value::String(s)
+  }
+}
+
+impl From<bool> for Value {
+  fn from(b: bool) -> Self {
+    Value::Bool(b)
+  }
+}
+
+impl From<usize> for Value {
+  fn from(n: usize) -> Self {
+    Value::Number(n as u64)
+  }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+  fn from(value: Option<T>) -> Self {
+    value.map(Into::into).unwrap_or(Value::Null)
+  }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+  fn from(values: Vec<T>) -> Self {
+    Value::Array(values.into_iter().map(Into::into).collect())
+  }
+}
+
+impl From<&MimePart> for Value {
+  fn from(part: &MimePart) -> Self {
+    Value::object(vec![
+      ("content_type", part.content_type.as_str().into()),
+      ("disposition", part.disposition.clone().into()),
+      ("encoding", part.encoding.clone().into()),
+      ("content_id", part.content_id.clone().into()),
+      ("size", part.size.into()),
+      (
+        "children",
+        Value::Array(part.children.iter().map(Value::from).collect()),
+      ),
+    ])
+  }
+}
+
+impl From<&Attachment> for Value {
+  fn from(attachment: &Attachment) -> Self {
+    Value::object(vec![
+      ("filename", attachment.filename.as_str().into()),
+      ("mime_type", attachment.mime_type.clone().into()),
+      ("content_id", attachment.content_id().into()),
+      ("description", attachment.description.clone().into()),
+      ("size", attachment.size().into()),
+    ])
+  }
+}
+
+impl From<&AuthResults> for Value {
+  fn from(auth: &AuthResults) -> Self {
+    let results: Vec<Value> = auth
+      .results
+      .iter()
+      .map(|result| {
+        Value::object(vec![
+          ("method", result.method.as_str().into()),
+          ("result", result.result.as_str().into()),
+          (
+            "properties",
+            Value::Object(
+              result
+                .properties
+                .iter()
+                .map(|(key, value)| (key.clone(), value.as_str().into()))
+                .collect(),
+            ),
+          ),
+        ])
+      })
+      .collect();
+    Value::object(vec![
+      ("authserv_id", auth.authserv_id.as_str().into()),
+      ("results", results.into()),
+    ])
+  }
+}
+
+impl From<&DeliveryStatus> for Value {
+  fn from(status: &DeliveryStatus) -> Self {
+    Value::object(vec![
+      ("recipient", status.recipient.as_str().into()),
+      ("action", status.action.as_str().into()),
+      ("status", status.status.as_str().into()),
+      ("diagnostic", status.diagnostic.clone().into()),
+    ])
+  }
+}
+
+impl From<&Finding> for Value {
+  fn from(finding: &Finding) -> Self {
+    let severity = match finding.severity() {
+      Severity::Info => "info",
+      Severity::Warning => "warning",
+      Severity::Danger => "danger",
+    };
+    let mut entries: Vec<(&str, Value)> = match finding {
+      Finding::AuthFailed { method, result } => vec![
+        ("kind", "auth-failed".into()),
+        ("method", method.as_str().into()),
+        ("result", result.as_str().into()),
+      ],
+      Finding::NoAuthentication => vec![("kind", "no-authentication".into())],
+      Finding::SpoofedFrom { shown, address } => vec![
+        ("kind", "spoofed-from".into()),
+        ("shown", shown.as_str().into()),
+        ("address", address.as_str().into()),
+      ],
+      Finding::ReplyToMismatch { from, reply_to } => vec![
+        ("kind", "reply-to-mismatch".into()),
+        ("from", from.as_str().into()),
+        ("reply_to", reply_to.as_str().into()),
+      ],
+      Finding::DeceptiveLink { text, href } => vec![
+        ("kind", "deceptive-link".into()),
+        ("text", text.as_str().into()),
+        ("href", href.as_str().into()),
+      ],
+      Finding::HomographDomain { domain, decoded, lookalike } => vec![
+        ("kind", "homograph-domain".into()),
+        ("domain", domain.as_str().into()),
+        ("decoded", decoded.as_str().into()),
+        ("lookalike", lookalike.as_str().into()),
+      ],
+      Finding::TrackingPixels(count) => vec![
+        ("kind", "tracking-pixels".into()),
+        ("count", (*count).into()),
+      ],
+      Finding::AttachmentMismatch { filename, actual } => vec![
+        ("kind", "attachment-mismatch".into()),
+        ("filename", filename.as_str().into()),
+        ("actual", actual.as_str().into()),
+      ],
+    };
+    entries.insert(1, ("severity", severity.into()));
+    Value::object(entries)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn json() {
+    let value = Value::object(vec![
+      ("subject", "Say \"hi\"\n\tback\u{1}".into()),
+      ("size", 42usize.into()),
+      ("charset", None::<String>.into()),
+      ("flags", vec![true, false].into()),
+    ]);
+    assert_eq!(
+      value.to_string(),
+      r#"{"subject":"Say \"hi\"\n\tback\u0001","size":42,"charset":null,"flags":[true,false]}"#
+    );
+    assert_eq!(
+      value.get("subject").and_then(Value::as_str),
+      Some("Say \"hi\"\n\tback\u{1}")
+    );
+    assert_eq!(value.get("missing"), None);
+  }
+
+  #[test]
+  fn parse() {
+    let text = r#"{"subject":"Say \"hi\"\n\tback\u0001","size":42,"charset":null,"flags":[true,false]}"#;
+    let value = Value::parse(text).unwrap();
+    assert_eq!(value.to_string(), text);
+    assert_eq!(
+      Value::parse(" { \"a\" : [ ] , \"b\" : { } , \"c\" : \"\\u00e9\\ud83d\\ude00\" } ").unwrap(),
+      Value::object(vec![
+        ("a", Value::Array(vec![])),
+        ("b", Value::Object(vec![])),
+        ("c", "\u{e9}\u{1f600}".into()),
+      ])
+    );
+    assert!(Value::parse("{\"a\": 1,}").is_err());
+    assert!(Value::parse("[1, 2").is_err());
+    assert!(Value::parse("\"open").is_err());
+    assert!(Value::parse("nope").is_err());
+    assert!(Value::parse("1 2").is_err());
+    assert!(Value::parse("\"\\ud800\\u0041\"").is_err());
+    assert!(Value::parse("\"\\ud800\\ud800\"").is_err());
+  }
+}
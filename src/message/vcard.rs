@@ -0,0 +1,171 @@
+/* vcard.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/// The common fields of a vCard (RFC 6350, older 2.1/3.0 cards parse too).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VCard {
+  pub full_name: String,
+  pub organization: Option<String>,
+  pub phones: Vec<String>,
+  pub emails: Vec<String>,
+}
+
+impl VCard {
+  /// First card of `text`, None when there's no BEGIN:VCARD.
+  pub fn parse(text: &str) -> Option<Self> {
+    let mut card: Option<VCard> = None;
+    let mut name: Option<String> = None;
+    for line in unfold(text) {
+      let Some((property, value)) = line.split_once(':') else {
+        continue;
+      };
+      // TEL;TYPE=work, item1.EMAIL...
+      let property = property.split(';').next().unwrap_or_default();
+      let property = property.rsplit('.').next().unwrap_or_default().to_uppercase();
+      let value = value.trim();
+      match (property.as_str(), card.as_mut()) {
+        ("BEGIN", None) if value.eq_ignore_ascii_case("VCARD") => card = Some(VCard::default()),
+        ("END", Some(_)) if value.eq_ignore_ascii_case("VCARD") => break,
+        ("FN", Some(card)) => card.full_name = unescape(value),
+        ("N", Some(_)) => {
+          // family;given;additional;prefixes;suffixes
+          let parts: Vec<String> = split_unescaped(value).iter().map(|p| unescape(p)).collect();
+          let order = [3, 1, 2, 0, 4];
+          let words: Vec<&str> = order
+            .iter()
+            .filter_map(|i| parts.get(*i))
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty())
+            .collect();
+          name = Some(words.join(" "));
+        }
+        ("ORG", Some(card)) => {
+          let units: Vec<String> = split_unescaped(value)
+            .iter()
+            .map(|u| unescape(u))
+            .filter(|u| !u.trim().is_empty())
+            .collect();
+          if !units.is_empty() {
+            card.organization = Some(units.join(", "));
+          }
+        }
+        ("TEL", Some(card)) if !value.is_empty() => {
+          card.phones.push(unescape(value.trim_start_matches("tel:")))
+        }
+        ("EMAIL", Some(card)) if !value.is_empty() => card.emails.push(unescape(value)),
+        _ => {}
+      }
+    }
+    let mut card = card?;
+    if card.full_name.trim().is_empty() {
+      card.full_name = name.unwrap_or_default();
+    }
+    Some(card)
+  }
+}
+
+// continuation lines start with a space or a tab
+fn unfold(text: &str) -> Vec<String> {
+  let mut lines: Vec<String> = vec![];
+  for line in text.lines() {
+    match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+      (Some(continuation), Some(last)) => last.push_str(continuation),
+      _ => lines.push(line.to_string()),
+    }
+  }
+  lines
+}
+
+// structured values are separated by unescaped ';'
+fn split_unescaped(value: &str) -> Vec<String> {
+  let mut parts: Vec<String> = vec![String::new()];
+  let mut chars = value.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => {
+        let last = parts.last_mut().unwrap();
+        last.push(c);
+        if let Some(next) = chars.next() {
+          last.push(next);
+        }
+      }
+      ';' => parts.push(String::new()),
+      c => parts.last_mut().unwrap().push(c),
+    }
+  }
+  parts
+}
+
+fn unescape(value: &str) -> String {
+  let mut unescaped = String::with_capacity(value.len());
+  let mut chars = value.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      unescaped.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('n') | Some('N') => unescaped.push('\n'),
+      Some(next) => unescaped.push(next),
+      None => {}
+    }
+  }
+  unescaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn minimal() {
+    let card = VCard::parse(
+      "BEGIN:VCARD\r\nVERSION:4.0\r\nFN:John Doe\r\nEMAIL:john@moon.space\r\nEND:VCARD\r\n",
+    )
+    .unwrap();
+    assert_eq!(card.full_name, "John Doe");
+    assert_eq!(card.emails, vec!["john@moon.space"]);
+    assert!(card.phones.is_empty());
+    assert_eq!(card.organization, None);
+  }
+
+  #[test]
+  fn fields() {
+    let card = VCard::parse(
+      "BEGIN:VCARD\n\
+       VERSION:3.0\n\
+       N:Doe;Jane;;Dr.;\n\
+       ORG:Mars\\, Inc.;Research\n\
+       TEL;TYPE=work,voice:+33 1 23 45 67 89\n\
+       TEL;VALUE=uri:tel:+33-6-00-00-00-00\n\
+       item1.EMAIL;TYPE=INTERNET:jane@mars.spa\n ce\n\
+       END:VCARD\n\
+       BEGIN:VCARD\n\
+       FN:Someone else\n\
+       END:VCARD\n",
+    )
+    .unwrap();
+    assert_eq!(card.full_name, "Dr. Jane Doe");
+    assert_eq!(card.organization.as_deref(), Some("Mars, Inc., Research"));
+    assert_eq!(card.phones, vec!["+33 1 23 45 67 89", "+33-6-00-00-00-00"]);
+    assert_eq!(card.emails, vec!["jane@mars.space"]);
+    assert_eq!(VCard::parse("Hello"), None);
+  }
+}
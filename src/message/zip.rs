@@ -0,0 +1,209 @@
+/* zip.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+use std::io::{self, Write};
+
+use gmime::glib;
+
+use super::attachment::Attachment;
+
+/// Sanitized attachment filenames, " (2)", " (3)"... inserted before the
+/// extension of duplicates.
+pub fn unique_filenames(attachments: &[Attachment]) -> Vec<String> {
+  let mut names: Vec<String> = vec![];
+  for attachment in attachments {
+    let filename = attachment.safe_filename();
+    let (stem, extension) = match filename.rfind('.') {
+      Some(dot) if dot > 0 => filename.split_at(dot),
+      _ => (filename.as_str(), ""),
+    };
+    let mut name = filename.clone();
+    let mut n = 2;
+    while names.iter().any(|other| other.eq_ignore_ascii_case(&name)) {
+      name = format!("{} ({}){}", stem, n, extension);
+      n += 1;
+    }
+    names.push(name);
+  }
+  names
+}
+
+/// Writes the attachments to `writer` as a zip archive, entry by entry.
+/// They're stored, not compressed: most are compressed formats already.
+pub fn write<W: Write>(writer: W, attachments: &[Attachment]) -> io::Result<W> {
+  let (time, date) = dos_datetime(&glib::DateTime::now_local().ok());
+  let mut writer = CountingWriter { inner: writer, count: 0 };
+  let mut central: Vec<u8> = vec![];
+  let names = unique_filenames(attachments);
+  for (attachment, name) in attachments.iter().zip(&names) {
+    let offset = u32_or_error(writer.count)?;
+    let body = attachment.bytes()?;
+    let size = u32_or_error(body.len() as u64)?;
+    let crc = crc32(body);
+    let entry = Entry { name, crc, size, time, date };
+
+    writer.write_all(&0x04034b50u32.to_le_bytes())?;
+    writer.write_all(&entry.header())?;
+    writer.write_all(name.as_bytes())?;
+    writer.write_all(body)?;
+
+    central.extend(0x02014b50u32.to_le_bytes());
+    central.extend(20u16.to_le_bytes()); // made by
+    central.extend(entry.header());
+    central.extend(0u16.to_le_bytes()); // comment length
+    central.extend(0u16.to_le_bytes()); // disk number
+    central.extend(0u16.to_le_bytes()); // internal attributes
+    central.extend(0u32.to_le_bytes()); // external attributes
+    central.extend(offset.to_le_bytes());
+    central.extend(name.as_bytes());
+  }
+  let central_offset = u32_or_error(writer.count)?;
+  let count = u16::try_from(names.len())
+    .map_err(|_| io::Error::new(io::ErrorKind::Other, "Too many attachments for a zip"))?;
+  writer.write_all(&central)?;
+  writer.write_all(&0x06054b50u32.to_le_bytes())?;
+  writer.write_all(&0u16.to_le_bytes())?; // disk
+  writer.write_all(&0u16.to_le_bytes())?; // disk of the central directory
+  writer.write_all(&count.to_le_bytes())?;
+  writer.write_all(&count.to_le_bytes())?;
+  writer.write_all(&(central.len() as u32).to_le_bytes())?;
+  writer.write_all(&central_offset.to_le_bytes())?;
+  writer.write_all(&0u16.to_le_bytes())?; // comment length
+  writer.flush()?;
+  Ok(writer.inner)
+}
+
+// fields shared by the local header and the central directory entry
+struct Entry<'a> {
+  name: &'a str,
+  crc: u32,
+  size: u32,
+  time: u16,
+  date: u16,
+}
+
+impl Entry<'_> {
+  fn header(&self) -> Vec<u8> {
+    let mut header: Vec<u8> = vec![];
+    header.extend(20u16.to_le_bytes()); // version needed, 2.0
+    header.extend(0x0800u16.to_le_bytes()); // UTF-8 names
+    header.extend(0u16.to_le_bytes()); // stored
+    header.extend(self.time.to_le_bytes());
+    header.extend(self.date.to_le_bytes());
+    header.extend(self.crc.to_le_bytes());
+    header.extend(self.size.to_le_bytes()); // compressed
+    header.extend(self.size.to_le_bytes());
+    header.extend((self.name.len() as u16).to_le_bytes());
+    header.extend(0u16.to_le_bytes()); // extra field length
+    header
+  }
+}
+
+struct CountingWriter<W: Write> {
+  inner: W,
+  count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let written = self.inner.write(buf)?;
+    self.count += written as u64;
+    Ok(written)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+// no Zip64, archives are limited to 4 GiB
+fn u32_or_error(value: u64) -> io::Result<u32> {
+  u32::try_from(value)
+    .map_err(|_| io::Error::new(io::ErrorKind::Other, "Attachments too large for a zip"))
+}
+
+/// (time, date) in MS-DOS format, 1980-01-01 when unknown
+fn dos_datetime(now: &Option<glib::DateTime>) -> (u16, u16) {
+  match now {
+    Some(now) if now.year() >= 1980 => (
+      ((now.hour() << 11) | (now.minute() << 5) | (now.second() / 2)) as u16,
+      (((now.year() - 1980) << 9) | (now.month() << 5) | now.day_of_month()) as u16,
+    ),
+    _ => (0, (1 << 5) | 1),
+  }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+  let mut crc = 0xffffffffu32;
+  for byte in data {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = match crc & 1 {
+        1 => (crc >> 1) ^ 0xedb88320,
+        _ => crc >> 1,
+      };
+    }
+  }
+  !crc
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn attachment(filename: &str, body: &[u8]) -> Attachment {
+    Attachment {
+      filename: filename.to_string(),
+      content_id: "none".to_string(),
+      body: body.to_vec(),
+      mime_type: None,
+      description: None,
+      encoded: None,
+    }
+  }
+
+  #[test]
+  fn filenames() {
+    let attachments = [
+      attachment("report.pdf", b""),
+      attachment("../report.pdf", b""),
+      attachment("Report.PDF", b""),
+      attachment("notes", b""),
+      attachment("notes", b""),
+    ];
+    assert_eq!(
+      unique_filenames(&attachments),
+      vec!["report.pdf", "report (2).pdf", "Report (3).PDF", "notes", "notes (2)"]
+    );
+  }
+
+  #[test]
+  fn archive() {
+    assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    let zip = write(Vec::new(), &[attachment("hello.txt", b"Hello Lucas")]).unwrap();
+    assert_eq!(&zip[..4], b"PK\x03\x04");
+    assert_eq!(&zip[30..39], b"hello.txt");
+    assert_eq!(&zip[39..50], b"Hello Lucas");
+    let end = zip.len() - 22;
+    assert_eq!(&zip[end..end + 4], b"PK\x05\x06");
+    // one entry, central directory right after the data
+    assert_eq!(&zip[end + 10..end + 12], &1u16.to_le_bytes());
+    assert_eq!(&zip[end + 16..end + 20], &50u32.to_le_bytes());
+  }
+}
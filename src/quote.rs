@@ -0,0 +1,97 @@
+/* quote.rs
+ *
+ * Copyright 2024 Alexandre Del Bigio
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/// Byte offset where the quoted history of a reply starts, or None when
+/// there's no trailing history (or nothing but history). Quotes interleaved
+/// with new content are left alone.
+pub fn quote_start(text: &str) -> Option<usize> {
+  let mut start: Option<usize> = None;
+  // last non blank line, to catch attributions wrapped over two lines
+  let mut previous: Option<(usize, String)> = None;
+  let mut offset = 0;
+  for line in text.split_inclusive('\n') {
+    let trimmed = line.trim();
+    if is_original_message(trimmed) {
+      start = start.or(Some(offset));
+      break;
+    }
+    if !trimmed.is_empty() {
+      if trimmed.starts_with('>') {
+        start = start.or(Some(offset));
+      } else if is_attribution(trimmed) {
+        start = start.or(match &previous {
+          Some((previous, line)) if line.to_lowercase().starts_with("on ") => Some(*previous),
+          _ => Some(offset),
+        });
+      } else {
+        start = None;
+      }
+      previous = Some((offset, trimmed.to_string()));
+    }
+    offset += line.len();
+  }
+  start.filter(|&start| !text[..start].trim().is_empty())
+}
+
+// "On Mon, Oct 21, 2024 at 10:00 AM John <john@moon.space> wrote:", possibly
+// wrapped by the client over two lines
+fn is_attribution(line: &str) -> bool {
+  let line = line.to_lowercase();
+  (line.starts_with("on ") && line.contains(" wrote"))
+    || line.ends_with("wrote:")
+    || line.ends_with("a écrit :")
+    || line.ends_with("a écrit:")
+}
+
+// Outlook doesn't prefix the history with ">"
+fn is_original_message(line: &str) -> bool {
+  let line = line.to_lowercase();
+  line.starts_with("-----original message-----")
+    || line.starts_with("-------- original message --------")
+    || line.starts_with("-------- message d'origine --------")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reply() {
+    let text = "Sounds good, see you then.\n\nJohn\n\nOn Mon, Oct 21, 2024 at 10:00 AM Lucas <lucas@mercure.space>\nwrote:\n> Lunch on Friday?\n>\n> Lucas\n";
+    let start = quote_start(text).unwrap();
+    assert_eq!(&text[..start], "Sounds good, see you then.\n\nJohn\n\n");
+    assert!(text[start..].starts_with("On Mon, Oct 21"));
+  }
+
+  #[test]
+  fn outlook_reply() {
+    let text = "Done.\r\n\r\n-----Original Message-----\r\nFrom: Lucas\r\nCan you fix it?\r\n";
+    let start = quote_start(text).unwrap();
+    assert_eq!(&text[..start], "Done.\r\n\r\n");
+  }
+
+  #[test]
+  fn interleaved() {
+    let text = "> Lunch on Friday?\nYes!\n> Where?\nAt the usual place.\n";
+    assert_eq!(quote_start(text), None);
+    assert_eq!(quote_start("> only a quote\n"), None);
+    assert_eq!(quote_start("No quote at all\n"), None);
+  }
+}
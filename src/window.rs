@@ -17,25 +17,86 @@
  *
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::option::Option;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
 
 use adw::glib::clone;
 use adw::prelude::{AlertDialogExt, *};
 use adw::subclass::prelude::*;
+use base64::engine::general_purpose;
+use base64::Engine;
 use gettextrs::{gettext, ngettext};
 use gtk4::prelude::FileChooserExt;
 use gtk4::{gio, glib, template_callbacks, ResponseType};
 use webkit6::prelude::{PolicyDecisionExt, WebViewExt};
-use webkit6::{NavigationPolicyDecision, PolicyDecision, PolicyDecisionType, WebView};
+use webkit6::{
+  ContextMenuAction, ContextMenuItem, LoadEvent, NavigationPolicyDecision, NavigationType, PolicyDecision, PolicyDecisionType, WebProcessTerminationReason, WebView
+};
 
-use crate::html::Html;
-use crate::mailservice::MailService;
-use crate::message::attachment::Attachment;
+use crate::avatar;
+use crate::html::{Html, Sanitization, VisitedLinks, IMAGE_SCHEME};
+use crate::linkify::find_links;
+use crate::mailservice::{self, MailService, MessageFile};
+use crate::message::alternative::{self, Alternative};
+use crate::message::archive::{self, Archive, ArchiveEntry};
+use crate::message::attachment::{sanitize_filename, Attachment, AttachmentCategory};
+use crate::message::authresults::combined_result;
+use crate::message::dsn::DeliveryStatus;
+use crate::message::message::{normalize_newlines, MessageParser};
+use crate::message::mimepart::MimePart;
+use crate::message::search::SearchMatch;
+use crate::message::security::{self, Finding, Severity};
+use crate::message::sniff;
+use crate::message::vcard::VCard;
+use crate::quote::quote_start;
 
 const SETTINGS_SHOW_FILE_NAME: &str = "show-file-name";
+const SETTINGS_SEARCH_URL: &str = "search-url";
+const SETTINGS_COLLAPSE_QUOTES: &str = "collapse-quotes";
+const SETTINGS_STRIP_COMMENTS: &str = "strip-comments";
+const SETTINGS_LINE_ENDING: &str = "line-ending";
+const SETTINGS_IMAGE_PROXY: &str = "image-proxy";
+const SETTINGS_CUSTOM_CSS: &str = "custom-css";
+const SETTINGS_TINT_TEXT_VIEW: &str = "tint-text-view";
+const SETTINGS_IDLE_TIMEOUT: &str = "idle-timeout";
+const SETTINGS_COMPACT_HEADER: &str = "compact-header";
+const SETTINGS_LOAD_AVATARS: &str = "load-avatars";
+const SETTINGS_CLOSE_ON_ERROR: &str = "close-on-error";
+const SETTINGS_REFLOW_TEXT: &str = "reflow-text";
+const SETTINGS_IMAGE_PLACEHOLDERS: &str = "image-placeholders";
+const SETTINGS_PDF_AS_BODY: &str = "pdf-as-body";
+const SETTINGS_TEXT_WHEN_SANITIZED: &str = "text-when-sanitized";
+const SETTINGS_LINK_ACTION: &str = "link-action";
+const SETTINGS_SPLIT_VIEW: &str = "split-view";
+const SETTINGS_SPLIT_POSITION: &str = "split-position";
+const SETTINGS_ZOOM: &str = "zoom";
+const SETTINGS_ZOOM_PER_SENDER: &str = "zoom-per-sender";
+const SETTINGS_SENDER_ZOOMS: &str = "sender-zooms";
+const SETTINGS_HTML_TYPES: &str = "html-types";
+const SETTINGS_MAX_WIDTH: &str = "max-width";
+const SETTINGS_MEDIA_PREVIEW: &str = "media-preview";
+const SETTINGS_TYPE_ICONS: &str = "type-icons";
+const SETTINGS_SCROLL_TO_TOP: &str = "scroll-to-top";
+const SETTINGS_TEXT_FOR_BULK: &str = "text-for-bulk";
+const SETTINGS_SUMMARY_BAR: &str = "summary-bar";
+/// files offered by the open dialog and accepted by drag and drop
+const MAIL_FILE_EXTENSIONS: [&str; 4] = ["eml", "emlx", "msg", "mbox"];
+/// set to disable the HTML view (e.g. no GPU/EGL on the system)
+const ENV_DISABLE_WEBKIT: &str = "MAILVIEWER_DISABLE_WEBKIT";
+/// names of the text tags of the links, followed by their URI
+const LINK_TAG_PREFIX: &str = "link:";
+
+// the fallback notice is shown once, not in every window
+static WEBKIT_NOTICE_SHOWN: AtomicBool = AtomicBool::new(false);
+
+// the tint stylesheet is global to the display, each window gets its own class
+static TINT_CLASS_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 mod imp {
-  use std::cell::OnceCell;
+  use std::cell::{Cell, OnceCell, RefCell};
 
   use adw::subclass::prelude::CompositeTemplateClass;
   use gtk4::ScrolledWindow;
@@ -54,58 +115,146 @@ mod imp {
     #[template_child]
     pub date: TemplateChild<gtk4::Entry>,
     #[template_child]
+    pub auth_badges: TemplateChild<gtk4::Box>,
+    #[template_child]
+    pub summary_bar: TemplateChild<gtk4::Label>,
+    #[template_child]
+    pub avatar: TemplateChild<adw::Avatar>,
+    #[template_child]
+    pub sender_via: TemplateChild<gtk4::Label>,
+    #[template_child]
+    pub bounce_banner: TemplateChild<adw::Banner>,
+    #[template_child]
+    pub open_progress: TemplateChild<gtk4::ProgressBar>,
+    #[template_child]
     pub placeholder: TemplateChild<gtk4::ScrolledWindow>,
     #[template_child]
+    pub pdf_placeholder: TemplateChild<gtk4::ScrolledWindow>,
+    #[template_child]
     pub force_css: TemplateChild<gtk4::ToggleButton>,
     #[template_child]
+    pub reader_view: TemplateChild<gtk4::ToggleButton>,
+    #[template_child]
     pub zoom_minus: TemplateChild<gtk4::Button>,
     #[template_child]
     pub zoom_plus: TemplateChild<gtk4::Button>,
     #[template_child]
+    pub view_menu: TemplateChild<gtk4::MenuButton>,
+    #[template_child]
+    pub text_scroll: TemplateChild<gtk4::ScrolledWindow>,
+    #[template_child]
     pub body_text: TemplateChild<gtk4::TextView>,
     #[template_child]
+    pub quoted_expander: TemplateChild<gtk4::Expander>,
+    #[template_child]
+    pub quoted_text: TemplateChild<gtk4::TextView>,
+    #[template_child]
     pub show_images: TemplateChild<gtk4::ToggleButton>,
     #[template_child]
     pub show_text: TemplateChild<gtk4::ToggleButton>,
     #[template_child]
+    pub alternatives: TemplateChild<gtk4::DropDown>,
+    #[template_child]
+    pub message_index: TemplateChild<gtk4::SpinButton>,
+    #[template_child]
     pub stack: TemplateChild<adw::ViewStack>,
     #[template_child]
+    pub split: TemplateChild<gtk4::Paned>,
+    #[template_child]
+    pub headers_pane: TemplateChild<gtk4::ScrolledWindow>,
+    #[template_child]
+    pub headers_text: TemplateChild<gtk4::TextView>,
+    #[template_child]
+    pub empty_state: TemplateChild<adw::StatusPage>,
+    #[template_child]
     pub pull_label: TemplateChild<gtk4::Label>,
     #[template_child]
+    pub toast_overlay: TemplateChild<adw::ToastOverlay>,
+    #[template_child]
     pub sheet: TemplateChild<adw::BottomSheet>,
     #[template_child]
+    pub toolbar_view: TemplateChild<adw::ToolbarView>,
+    #[template_child]
+    pub vertical_box: TemplateChild<gtk4::Box>,
+    #[template_child]
     pub attachments_clamp: TemplateChild<adw::Clamp>,
     //
     pub scrolled_window: ScrolledWindow,
-    pub webview: webkit6::WebView,
-    pub websettings: webkit6::Settings,
+    pub webview: OnceCell<webkit6::WebView>,
+    pub websettings: OnceCell<webkit6::Settings>,
+    /// WebKit's PDF viewer, created for the first PDF shown as the body
+    pub pdf_view: OnceCell<webkit6::WebView>,
+    pub webkit_failed: Cell<bool>,
     pub settings: OnceCell<gio::Settings>,
     pub service: MailService,
+    pub visited: RefCell<VisitedLinks>,
+    /// remote images loaded one by one from their placeholder
+    pub loaded_images: RefCell<Vec<String>>,
+    pub attachment_rows: RefCell<Vec<(AttachmentCategory, adw::ActionRow)>>,
+    pub idle_source: RefCell<Option<glib::SourceId>>,
+    pub distraction_free: Cell<bool>,
+    pub scroll_reset: ScrollReset,
+    pub tint_css: gtk4::CssProvider,
+    pub tint_class: String,
   }
 
   impl Default for MailViewerWindow {
     fn default() -> Self {
       let window = MailViewerWindow {
-        webview: WebView::new(),
-        websettings: webkit6::Settings::new(),
+        webview: OnceCell::new(),
+        websettings: OnceCell::new(),
+        pdf_view: OnceCell::new(),
+        webkit_failed: Cell::new(false),
         scrolled_window: ScrolledWindow::new(),
         from: TemplateChild::default(),
         to: TemplateChild::default(),
         subject: TemplateChild::default(),
         date: TemplateChild::default(),
+        auth_badges: TemplateChild::default(),
+        summary_bar: TemplateChild::default(),
+        avatar: TemplateChild::default(),
+        sender_via: TemplateChild::default(),
+        bounce_banner: TemplateChild::default(),
+        open_progress: TemplateChild::default(),
         placeholder: TemplateChild::default(),
+        pdf_placeholder: TemplateChild::default(),
         show_images: TemplateChild::default(),
         force_css: TemplateChild::default(),
+        reader_view: TemplateChild::default(),
         zoom_minus: TemplateChild::default(),
         zoom_plus: TemplateChild::default(),
+        view_menu: TemplateChild::default(),
         show_text: TemplateChild::default(),
+        alternatives: TemplateChild::default(),
+        message_index: TemplateChild::default(),
+        text_scroll: TemplateChild::default(),
         body_text: TemplateChild::default(),
+        quoted_expander: TemplateChild::default(),
+        quoted_text: TemplateChild::default(),
         stack: TemplateChild::default(),
+        split: TemplateChild::default(),
+        headers_pane: TemplateChild::default(),
+        headers_text: TemplateChild::default(),
+        empty_state: TemplateChild::default(),
         pull_label: TemplateChild::default(),
         attachments_clamp: TemplateChild::default(),
+        toast_overlay: TemplateChild::default(),
         sheet: TemplateChild::default(),
+        toolbar_view: TemplateChild::default(),
+        vertical_box: TemplateChild::default(),
         settings: OnceCell::new(),
         service: MailService::new(),
+        visited: RefCell::new(VisitedLinks::default()),
+        loaded_images: RefCell::new(vec![]),
+        attachment_rows: RefCell::new(vec![]),
+        idle_source: RefCell::new(None),
+        distraction_free: Cell::new(false),
+        scroll_reset: ScrollReset::default(),
+        tint_css: gtk4::CssProvider::new(),
+        tint_class: format!(
+          "mail-tinted-{}",
+          TINT_CLASS_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ),
       };
       window
     }
@@ -149,12 +298,70 @@ mod imp {
           }
         },
       );
+      klass.install_action_async("win.paste-message", None, |window, _, _| async move {
+        window.paste_message().await;
+      });
       klass.install_action("win.preferences", None, move |win, _, _| {
         win.show_preferences();
       });
       klass.install_action("win.reset-zoom", None, move |win, _, _| {
         win.reset_zoom();
       });
+      klass.install_action("win.zoom-in", None, move |win, _, _| {
+        win.on_zoom_plus_clicked();
+      });
+      klass.install_action("win.zoom-out", None, move |win, _, _| {
+        win.on_zoom_minus_clicked();
+      });
+      klass.install_action("win.toggle-force-css", None, move |win, _, _| {
+        let force_css = &win.imp().force_css;
+        force_css.set_active(!force_css.is_active());
+        win.on_force_css_clicked();
+      });
+      klass.install_action("win.toggle-reader-view", None, move |win, _, _| {
+        let reader_view = &win.imp().reader_view;
+        reader_view.set_active(!reader_view.is_active());
+        win.on_reader_view_clicked();
+      });
+      klass.install_action("win.reload", None, move |win, _, _| {
+        win.reload();
+      });
+      klass.install_action_async("win.save-body", None, |window, _, _| async move {
+        window.save_body().await;
+      });
+      klass.install_action("win.open-body", None, move |win, _, _| {
+        win.open_body_in_editor();
+      });
+      klass.install_action_async("win.save-message", None, |window, _, _| async move {
+        window.save_message().await;
+      });
+      klass.install_action_async("win.forward-attachment", None, |window, _, _| async move {
+        window.save_forward().await;
+      });
+      klass.install_action_async("win.save-attachments-zip", None, |window, _, _| async move {
+        window.save_attachments_zip().await;
+      });
+      klass.install_action("win.distraction-free", None, move |win, _, _| {
+        win.toggle_distraction_free();
+      });
+      klass.install_action("win.copy-markdown", None, move |win, _, _| {
+        win.copy_markdown();
+      });
+      klass.install_action("win.find-all", None, move |win, _, _| {
+        win.show_find_all();
+      });
+      klass.install_action("win.message-info", None, move |win, _, _| {
+        win.show_message_info();
+      });
+      klass.install_action("win.security-report", None, move |win, _, _| {
+        win.show_security_report();
+      });
+      klass.install_action("win.mime-tree", None, move |win, _, _| {
+        win.show_mime_tree();
+      });
+      klass.install_action("win.search-selection", None, move |win, _, _| {
+        win.search_text_selection();
+      });
     }
 
     fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
@@ -162,9 +369,21 @@ mod imp {
     }
   }
 
-  impl ObjectImpl for MailViewerWindow {}
+  impl ObjectImpl for MailViewerWindow {
+    // also when the application quits without closing the window
+    fn dispose(&self) {
+      if let Some(display) = gtk4::gdk::Display::default() {
+        gtk4::style_context_remove_provider_for_display(&display, &self.tint_css);
+      }
+    }
+  }
   impl WidgetImpl for MailViewerWindow {}
-  impl WindowImpl for MailViewerWindow {}
+  impl WindowImpl for MailViewerWindow {
+    fn close_request(&self) -> glib::Propagation {
+      self.obj().save_geometry();
+      self.parent_close_request()
+    }
+  }
   impl ApplicationWindowImpl for MailViewerWindow {}
   impl AdwApplicationWindowImpl for MailViewerWindow {}
 }
@@ -198,12 +417,45 @@ impl MailViewerWindow {
     window
   }
 
+  #[template_callback]
+  pub fn on_alternative_selected(&self) {
+    log::debug!("on_alternative_selected({})", self.imp().alternatives.selected());
+    self.load_html(self.imp().force_css.is_active());
+  }
+
+  #[template_callback]
+  pub fn on_message_index_changed(&self) {
+    let imp = self.imp();
+    let index = (imp.message_index.value() as usize).saturating_sub(1);
+    if index == imp.service.selected_message() {
+      return;
+    }
+    log::debug!("on_message_index_changed({})", index);
+    match imp.service.select_message(index) {
+      Ok(_) => self.display_message(),
+      Err(e) => {
+        log::error!("select_message({}) : {}", index, e);
+        self.alert_error(
+          &gettext("Mailbox Error"),
+          &format!("{}:\n{}", &gettext("Failed to parse message"), e),
+          false,
+        );
+      }
+    }
+  }
+
   #[template_callback]
   pub fn on_force_css_clicked(&self) {
     log::debug!("on_force_css_clicked()");
     self.load_html(self.imp().force_css.is_active());
   }
 
+  #[template_callback]
+  pub fn on_reader_view_clicked(&self) {
+    log::debug!("on_reader_view_clicked({})", self.imp().reader_view.is_active());
+    self.load_html(self.imp().force_css.is_active());
+  }
+
   #[template_callback]
   pub fn on_show_text_clicked(&self) {
     let show = self.imp().show_text.is_active();
@@ -215,19 +467,27 @@ impl MailViewerWindow {
   pub fn on_show_images_clicked(&self) {
     let show = self.imp().show_images.is_active();
     log::debug!("on_show_images_clicked({})", show);
-    self.imp().websettings.set_auto_load_images(show);
+    self.update_auto_load_images();
+    // image URLs are rewritten when rendering
+    if self.get_image_proxy().is_some() || self.get_image_placeholders() {
+      self.load_html(self.imp().force_css.is_active());
+    }
   }
 
   #[template_callback]
   pub fn on_zoom_minus_clicked(&self) {
     log::debug!("on_zoom_minus_clicked()");
-    self.set_zoom_level(self.imp().webview.zoom_level() - 0.1);
+    if let Some(webview) = self.webview() {
+      self.set_zoom_level(webview.zoom_level() - 0.1);
+    }
   }
 
   #[template_callback]
   pub fn on_zoom_plus_clicked(&self) {
     log::debug!("on_zoom_plus_clicked()");
-    self.set_zoom_level(self.imp().webview.zoom_level() + 0.1);
+    if let Some(webview) = self.webview() {
+      self.set_zoom_level(webview.zoom_level() + 0.1);
+    }
   }
 
   fn initialize(&self) {
@@ -236,61 +496,292 @@ impl MailViewerWindow {
 
     self.initialize_settings();
     self.initialize_actions();
+    self.initialize_idle_timeout();
+    self.initialize_max_width();
+    self.initialize_text_links();
+    self.initialize_distraction_free();
+    self.initialize_focus_chain();
+    if webkit_available() {
+      self.initialize_webview();
+    }
+    if let Some(display) = gtk4::gdk::Display::default() {
+      gtk4::style_context_add_provider_for_display(
+        &display,
+        &imp.tint_css,
+        gtk4::STYLE_PROVIDER_PRIORITY_APPLICATION,
+      );
+    }
 
-    imp.websettings.set_allow_file_access_from_file_urls(false);
-    imp
-      .websettings
-      .set_enable_back_forward_navigation_gestures(false);
-    imp.websettings.set_enable_developer_extras(false);
-    imp.websettings.set_enable_dns_prefetching(false);
-    imp.websettings.set_allow_modal_dialogs(false);
-    imp
-      .websettings
-      .set_allow_universal_access_from_file_urls(false);
-    imp.websettings.set_enable_javascript(false);
-    imp.websettings.set_auto_load_images(false);
-    imp.webview.set_settings(&imp.websettings);
-    imp.webview.set_editable(false);
-    imp.webview.connect_context_menu(move |_, _, _| {
-      log::debug!("WebView() => context_menu() cancelled");
-      true
-    });
-    imp.webview.set_receives_default(false);
-    imp.placeholder.set_child(Some(&imp.webview));
+    let menu = gio::Menu::new();
+    menu.append(
+      Some(&gettext("Search the Web")),
+      Some("win.search-selection"),
+    );
+    imp.body_text.set_extra_menu(Some(&menu));
+    for view in [imp.body_text.get(), imp.quoted_text.get()] {
+      view.connect_copy_clipboard(clone!(
+        #[weak(rename_to = win)]
+        self,
+        move |view| {
+          view.stop_signal_emission_by_name("copy-clipboard");
+          win.copy_text_selection(view);
+        }
+      ));
+    }
   }
 
-  fn initialize_actions(&self) {
-    let win = self;
+  fn initialize_webview(&self) {
     let imp = self.imp();
+    let webview = WebView::new();
+    let websettings = webkit6::Settings::new();
 
-    let drop_target = gtk4::DropTarget::new(gio::File::static_type(), gtk4::gdk::DragAction::COPY);
-    imp.body_text.add_controller(drop_target.clone());
-    drop_target.connect_drop(clone!(
-      #[strong]
-      win,
-      move |_, data, _, _| {
-        if let Ok(file) = data.get::<gio::File>() {
-          if let Some(filepath) = file.path() {
-            if let Some(filepath) = filepath.to_str() {
-              let lowercase = filepath.to_lowercase();
-              if lowercase.ends_with(".eml") || lowercase.ends_with(".msg") {
-                win.open_file(filepath);
-                return true;
-              }
+    websettings.set_allow_file_access_from_file_urls(false);
+    websettings.set_enable_back_forward_navigation_gestures(false);
+    websettings.set_enable_developer_extras(false);
+    websettings.set_enable_dns_prefetching(false);
+    websettings.set_allow_modal_dialogs(false);
+    websettings.set_allow_universal_access_from_file_urls(false);
+    // Scripts from the mail never run, ours are evaluated in the isolated
+    // "mailviewer" world
+    websettings.set_enable_javascript(false);
+    websettings.set_enable_javascript_markup(false);
+    websettings.set_auto_load_images(self.get_image_placeholders());
+    webview.set_settings(&websettings);
+    webview.set_editable(false);
+    webview.connect_context_menu(clone!(
+      #[weak(rename_to = win)]
+      self,
+      #[upgrade_or]
+      true,
+      move |_, menu, hit_test| {
+        menu.remove_all();
+        let image_uri = hit_test.image_uri().filter(|_| hit_test.context_is_image());
+        if let Some(uri) = image_uri {
+          match image_copy(&uri, win.imp().show_images.is_active()) {
+            ImageCopy::Data(bytes) => {
+              let action = gio::SimpleAction::new("copy-image", None);
+              action.connect_activate(clone!(
+                #[weak]
+                win,
+                move |_, _| {
+                  win.copy_image(&bytes);
+                }
+              ));
+              menu.append(&ContextMenuItem::from_gaction(
+                &action,
+                &gettext("Copy Image"),
+                None,
+              ));
             }
+            // already loaded, WebKit copies it from its cache
+            ImageCopy::Loaded => menu.append(&ContextMenuItem::from_stock_action_with_label(
+              ContextMenuAction::CopyImageToClipboard,
+              &gettext("Copy Image"),
+            )),
+            ImageCopy::Unavailable => (),
           }
         }
+        if hit_test.context_is_selection() {
+          let action = gio::SimpleAction::new("search-selection", None);
+          action.connect_activate(clone!(
+            #[weak]
+            win,
+            move |_, _| {
+              glib::spawn_future_local(async move {
+                win.search_webview_selection().await;
+              });
+            }
+          ));
+          menu.append(&ContextMenuItem::from_gaction(
+            &action,
+            &gettext("Search the Web"),
+            None,
+          ));
+        }
+        if menu.n_items() == 0 {
+          log::debug!("WebView() => context_menu() cancelled");
+          return true;
+        }
         false
       }
     ));
 
-    imp.webview.connect_decide_policy(clone!(
-      #[strong]
-      win,
+    webview.connect_decide_policy(clone!(
+      #[weak(rename_to = win)]
+      self,
+      #[upgrade_or]
+      false,
       move |webview: &WebView, policy: &PolicyDecision, decision_type: PolicyDecisionType| {
         return win.on_decide_policy(webview, policy, decision_type);
       }
     ));
+    webview.connect_load_changed(clone!(
+      #[weak(rename_to = win)]
+      self,
+      move |_, event| {
+        if event == LoadEvent::Finished && win.imp().scroll_reset.finished() {
+          win.scroll_webview_to_top();
+        }
+      }
+    ));
+    webview.connect_web_process_terminated(clone!(
+      #[weak(rename_to = win)]
+      self,
+      move |_, reason| {
+        log::error!("WebView web_process_terminated({:?})", reason);
+        if reason != WebProcessTerminationReason::TerminatedByApi {
+          win.imp().webkit_failed.set(true);
+          win.fallback_to_text();
+        }
+      }
+    ));
+    adw::StyleManager::default().connect_dark_notify(clone!(
+      #[weak(rename_to = win)]
+      self,
+      move |_| {
+        win.on_dark_changed();
+      }
+    ));
+    if let Some(settings) = imp.settings.get() {
+      webview.set_zoom_level(settings.get::<f64>(SETTINGS_ZOOM));
+    }
+    webview.set_receives_default(false);
+    imp.placeholder.set_child(Some(&webview));
+    let _ = imp.websettings.set(websettings);
+    let _ = imp.webview.set(webview);
+  }
+
+  /// The HTML view, None when WebKit is unavailable or its process died.
+  fn webview(&self) -> Option<&WebView> {
+    if self.imp().webkit_failed.get() {
+      return None;
+    }
+    self.imp().webview.get()
+  }
+
+  fn fallback_to_text(&self) {
+    log::warn!("fallback_to_text() => HTML view unavailable");
+    let imp = self.imp();
+    if imp.service.body_text().is_none() {
+      if let Some(html) = self.current_html() {
+        self.set_body_text(&Html::new(&html, false).to_text());
+      }
+    }
+    imp.show_text.set_visible(false);
+    imp.alternatives.set_visible(false);
+    self.on_show_text(true);
+    if !WEBKIT_NOTICE_SHOWN.swap(true, Ordering::Relaxed) {
+      self.alert_error(
+        &gettext("HTML View Unavailable"),
+        &gettext("WebKit could not be started, messages are shown as plain text."),
+        false,
+      );
+    }
+  }
+
+  fn initialize_actions(&self) {
+    let win = self;
+    let imp = self.imp();
+
+    let show_html = gio::ActionEntry::builder("show-html-view")
+      .activate(|win: &Self, _, _| win.switch_view(false))
+      .build();
+    let show_text = gio::ActionEntry::builder("show-text-view")
+      .activate(|win: &Self, _, _| win.switch_view(true))
+      .build();
+    self.add_action_entries([show_html, show_text]);
+    if let Some(settings) = imp.settings.get() {
+      // stateful, persisted as is
+      self.add_action(&settings.create_action(SETTINGS_COMPACT_HEADER));
+      settings.connect_changed(
+        Some(SETTINGS_COMPACT_HEADER),
+        clone!(
+          #[weak]
+          win,
+          move |_, _| {
+            let html = win.imp().stack.visible_child_name().as_deref() == Some("html");
+            win.update_view_controls(html);
+          }
+        ),
+      );
+      self.update_view_controls(true);
+      self.add_action(&settings.create_action(SETTINGS_SPLIT_VIEW));
+      settings.connect_changed(
+        Some(SETTINGS_SPLIT_VIEW),
+        clone!(
+          #[weak]
+          win,
+          move |_, _| {
+            win.update_split_view();
+          }
+        ),
+      );
+      imp.split.set_position(restored_split_position(
+        settings.get::<i32>(SETTINGS_SPLIT_POSITION),
+      ));
+      imp.split.connect_position_notify(clone!(
+        #[weak]
+        win,
+        move |split| {
+          win.on_split_position_changed(split.position());
+        }
+      ));
+      self.update_split_view();
+      self.add_action(&settings.create_action(SETTINGS_SUMMARY_BAR));
+      settings.connect_changed(
+        Some(SETTINGS_SUMMARY_BAR),
+        clone!(
+          #[weak]
+          win,
+          move |_, _| {
+            win.update_summary_bar();
+          }
+        ),
+      );
+      self.add_action(&settings.create_action(SETTINGS_REFLOW_TEXT));
+      settings.connect_changed(
+        Some(SETTINGS_REFLOW_TEXT),
+        clone!(
+          #[weak]
+          win,
+          move |_, _| {
+            if let Some(text) = win.body_text() {
+              win.set_body_text(&text);
+            }
+          }
+        ),
+      );
+    }
+    // until a message with a body is displayed
+    self.action_set_enabled("win.save-body", false);
+    self.action_set_enabled("win.open-body", false);
+    self.action_set_enabled("win.copy-markdown", false);
+    self.action_set_enabled("win.save-message", false);
+    self.action_set_enabled("win.forward-attachment", false);
+    self.action_set_enabled("win.save-attachments-zip", false);
+
+    for widget in [imp.body_text.upcast_ref::<gtk4::Widget>(), imp.empty_state.upcast_ref()] {
+      let drop_target = gtk4::DropTarget::new(gio::File::static_type(), gtk4::gdk::DragAction::COPY);
+      widget.add_controller(drop_target.clone());
+      drop_target.connect_drop(clone!(
+        #[strong]
+        win,
+        move |_, data, _, _| {
+          if let Ok(file) = data.get::<gio::File>() {
+            if let Some(filepath) = file.path() {
+              if let Some(filepath) = filepath.to_str() {
+                // a folder is browsed like a mailbox
+                if is_mail_file(filepath) || std::path::Path::new(filepath).is_dir() {
+                  win.open_file(filepath);
+                  return true;
+                }
+              }
+            }
+          }
+          false
+        }
+      ));
+    }
   }
 
   fn initialize_settings(&self) {
@@ -298,24 +789,12 @@ impl MailViewerWindow {
     let imp = self.imp();
 
     imp.settings.set(settings.clone()).unwrap();
-    imp.webview.set_zoom_level(settings.get::<f64>("zoom"));
 
-    settings
-      .bind("width", self, "default-width")
-      .flags(gio::SettingsBindFlags::DEFAULT)
-      .build();
-    settings
-      .bind("height", self, "default-height")
-      .flags(gio::SettingsBindFlags::DEFAULT)
-      .build();
-    settings
-      .bind("is-maximized", self, "maximized")
-      .flags(gio::SettingsBindFlags::DEFAULT)
-      .build();
-    settings
-      .bind("is-fullscreen", self, "fullscreened")
-      .flags(gio::SettingsBindFlags::DEFAULT)
-      .build();
+    // Geometry is only read here and written back by save_geometry(), so
+    // that several windows don't overwrite each other's size.
+    self.set_default_size(settings.get::<i32>("width"), settings.get::<i32>("height"));
+    self.set_maximized(settings.get::<bool>("is-maximized"));
+    self.set_fullscreened(settings.get::<bool>("is-fullscreen"));
 
     imp.service.connect_title_changed(clone!(
       #[weak(rename_to = window)]
@@ -324,371 +803,3419 @@ impl MailViewerWindow {
         window.set_title(Some(title));
       }
     ));
+    imp.service.connect_open_progress(clone!(
+      #[weak(rename_to = window)]
+      self,
+      move |_, read, total| {
+        window.on_open_progress(read, total);
+      }
+    ));
     imp.service.set_show_file_name(self.get_show_file_name());
   }
 
-  fn reset_zoom(&self) {
-    log::debug!("reset_zoom()");
-    self.set_zoom_level(1.0);
+  fn on_open_progress(&self, read: u64, total: u64) {
+    let open_progress = &self.imp().open_progress;
+    if !show_open_progress(read, total) {
+      open_progress.set_visible(false);
+      return;
+    }
+    open_progress.set_fraction(read as f64 / total as f64);
+    open_progress.set_visible(true);
   }
 
-  fn add_attachment(&self, attachment: &Attachment, preferences_group: &adw::PreferencesGroup) {
-    let window = self;
-    let mime = &attachment
-      .clone()
-      .mime_type
-      .clone()
-      .unwrap_or("Unknown".to_string());
-    let icon = if mime.starts_with("image") {
-      "image-x-generic-symbolic"
-    } else {
-      "document-open"
-    };
+  /// The text view follows the width of its scrolled window, the HTML view
+  /// is reloaded with the new column width.
+  fn initialize_max_width(&self) {
+    let imp = self.imp();
+    imp.text_scroll.hadjustment().connect_page_size_notify(clone!(
+      #[weak(rename_to = win)]
+      self,
+      move |_| win.update_text_width()
+    ));
+    if let Some(settings) = imp.settings.get() {
+      settings.connect_changed(
+        Some(SETTINGS_MAX_WIDTH),
+        clone!(
+          #[weak(rename_to = win)]
+          self,
+          move |_, _| {
+            win.update_text_width();
+            if win.current_html().is_some() {
+              win.load_html(win.imp().force_css.is_active());
+            }
+          }
+        ),
+      );
+    }
+  }
 
-    let save = gtk4::Button::new();
-    save.set_valign(gtk4::Align::Center);
-    save.set_icon_name("document-save-as-symbolic");
-    save.set_tooltip_text(Some(&gettext("Save as...")));
-    save.connect_clicked(clone!(
-      #[strong]
-      window,
-      #[strong]
-      attachment,
-      move |_| {
-        let window = window.clone();
-        let attachment = attachment.clone();
-        glib::MainContext::default().spawn_local(async move {
-          window.on_attachment_save(&attachment).await;
-        });
+  /// Centers the text in a column of the maximum width with side margins
+  fn update_text_width(&self) {
+    let imp = self.imp();
+    let available = imp.text_scroll.hadjustment().page_size() as i32;
+    let char_width = imp.body_text.create_pango_layout(Some("0")).pixel_size().0;
+    let margin = text_margin(available, self.get_max_width(), char_width);
+    for view in [imp.body_text.get(), imp.quoted_text.get()] {
+      view.set_left_margin(margin);
+      view.set_right_margin(margin);
+    }
+  }
+
+  /// Any user interaction restarts the idle timeout
+  fn initialize_idle_timeout(&self) {
+    let key = gtk4::EventControllerKey::new();
+    key.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    key.connect_key_pressed(clone!(
+      #[weak(rename_to = win)]
+      self,
+      #[upgrade_or]
+      glib::Propagation::Proceed,
+      move |_, _, _, _| {
+        win.reset_idle_timeout();
+        glib::Propagation::Proceed
       }
     ));
-    let btn = adw::ActionRow::builder()
-      .title(attachment.filename.to_string())
-      .subtitle(mime)
-      .activatable(true)
-      .build();
-    btn.add_prefix(&gtk4::Image::from_icon_name(icon));
-    btn.add_suffix(&save);
-
-    btn.connect_activated(clone!(
-      #[strong]
-      window,
-      #[strong]
-      attachment,
-      move |_| {
-        window.on_attachment_open(&attachment);
+    let click = gtk4::GestureClick::new();
+    click.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    click.connect_pressed(clone!(
+      #[weak(rename_to = win)]
+      self,
+      move |_, _, _, _| win.reset_idle_timeout()
+    ));
+    let scroll = gtk4::EventControllerScroll::new(gtk4::EventControllerScrollFlags::BOTH_AXES);
+    scroll.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    scroll.connect_scroll(clone!(
+      #[weak(rename_to = win)]
+      self,
+      #[upgrade_or]
+      glib::Propagation::Proceed,
+      move |_, _, _| {
+        win.reset_idle_timeout();
+        glib::Propagation::Proceed
       }
     ));
-    preferences_group.add(&btn);
-  }
-
-  async fn on_attachment_save(&self, attachment: &Attachment) {
-    log::debug!("on_attachment_save({})", attachment.filename);
-
-    let current_file = gio::File::for_path(self.imp().service.get_fullpath().unwrap());
-    let initial_file = current_file
-      .parent()
-      .unwrap()
-      .child(attachment.filename.as_str());
+    let motion = gtk4::EventControllerMotion::new();
+    motion.connect_motion(clone!(
+      #[weak(rename_to = win)]
+      self,
+      move |_, _, _| win.reset_idle_timeout()
+    ));
+    self.add_controller(key);
+    self.add_controller(click);
+    self.add_controller(scroll);
+    self.add_controller(motion);
 
-    let save_dialog = gtk4::FileDialog::builder()
-      .title(&gettext("Save attachment..."))
-      .modal(true)
-      .initial_file(&initial_file)
-      .build();
+    if let Some(settings) = self.imp().settings.get() {
+      settings.connect_changed(
+        Some(SETTINGS_IDLE_TIMEOUT),
+        clone!(
+          #[weak(rename_to = win)]
+          self,
+          move |_, _| win.reset_idle_timeout()
+        ),
+      );
+    }
+    self.reset_idle_timeout();
+  }
 
-    match save_dialog.save_future(Some(self)).await {
-      Ok(file) => {
-        if let Some(path) = file.peek_path() {
-          log::debug!("Saving attachment to {:?}", path);
-          match attachment.write_to_file(path.to_str().unwrap()) {
-            Ok(_) => log::debug!("write_to_file({:?})", &path),
-            Err(e) => {
-              log::error!("write_to_file({})", e);
-              self.alert_error(&gettext("File Error"), &e.to_string(), false);
-            }
-          };
+  /// Phone numbers and addresses of the plain text views open on click
+  fn initialize_text_links(&self) {
+    let imp = self.imp();
+    for view in [imp.body_text.get(), imp.quoted_text.get()] {
+      let click = gtk4::GestureClick::new();
+      click.connect_released(clone!(
+        #[weak(rename_to = win)]
+        self,
+        #[weak]
+        view,
+        move |_, _, x, y| {
+          // not at the end of a selection
+          if view.buffer().has_selection() {
+            return;
+          }
+          if let Some(uri) = Self::link_at(&view, x, y) {
+            win.activate_link(&uri);
+          }
         }
-      }
-      Err(e) => match e.kind() {
-        Some(gtk4::DialogError::Dismissed) | Some(gtk4::DialogError::Cancelled) => return,
-        _ => log::error!("save_dialog({})", e),
-      },
+      ));
+      let motion = gtk4::EventControllerMotion::new();
+      motion.connect_motion(clone!(
+        #[weak]
+        view,
+        move |_, x, y| {
+          let cursor = Self::link_at(&view, x, y).map(|_| "pointer");
+          view.set_cursor_from_name(cursor.or(Some("text")));
+        }
+      ));
+      view.add_controller(click);
+      view.add_controller(motion);
     }
   }
 
-  fn on_attachment_open(&self, attachment: &Attachment) {
-    log::debug!("on_button_clicked({})", attachment.filename);
-    match attachment.write_to_tmp() {
-      Ok(file) => {
-        log::debug!("write_to_tmp({}) success", &file);
-        if let Err(e) = open::that(&file) {
-          log::error!("{} ({}): {}", &gettext("Failed to open file"), &file, e);
+  /// URI of the link at a position, from the tags of set_text_links()
+  fn link_at(view: &gtk4::TextView, x: f64, y: f64) -> Option<String> {
+    let (x, y) = view.window_to_buffer_coords(gtk4::TextWindowType::Widget, x as i32, y as i32);
+    let iter = view.iter_at_location(x, y)?;
+    iter
+      .tags()
+      .iter()
+      .find_map(|tag| Some(tag.name()?.strip_prefix(LINK_TAG_PREFIX)?.to_string()))
+  }
+
+  /// Escape also leaves the distraction-free mode
+  fn initialize_distraction_free(&self) {
+    let key = gtk4::EventControllerKey::new();
+    key.connect_key_pressed(clone!(
+      #[weak(rename_to = win)]
+      self,
+      #[upgrade_or]
+      glib::Propagation::Proceed,
+      move |_, key, _, _| {
+        if key == gtk4::gdk::Key::Escape && win.imp().distraction_free.get() {
+          win.toggle_distraction_free();
+          return glib::Propagation::Stop;
         }
+        glib::Propagation::Proceed
       }
-      Err(e) => log::error!("write_to_tmp({})", e),
-    };
+    ));
+    self.add_controller(key);
   }
 
-  fn set_zoom_level(&self, zoom: f64) {
-    log::debug!("set_zoom({})", zoom);
-    self.imp().webview.set_zoom_level(zoom);
-    if let Some(settings) = self.imp().settings.get() {
-      let _ = settings.set("zoom", zoom);
-    }
+  /// Tab follows the reading order instead of the widget tree, where the
+  /// header bar comes first: message headers, view toggles, then the body.
+  /// From there GTK already moves on to the attachments and their buttons.
+  fn initialize_focus_chain(&self) {
+    let key = gtk4::EventControllerKey::new();
+    key.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    key.connect_key_pressed(clone!(
+      #[weak(rename_to = win)]
+      self,
+      #[upgrade_or]
+      glib::Propagation::Proceed,
+      move |_, key, _, modifiers| {
+        let backward = match key {
+          gtk4::gdk::Key::Tab | gtk4::gdk::Key::KP_Tab => {
+            modifiers.contains(gtk4::gdk::ModifierType::SHIFT_MASK)
+          }
+          gtk4::gdk::Key::ISO_Left_Tab => true,
+          _ => return glib::Propagation::Proceed,
+        };
+        let shortcut = gtk4::gdk::ModifierType::CONTROL_MASK | gtk4::gdk::ModifierType::ALT_MASK;
+        if modifiers.intersects(shortcut) {
+          return glib::Propagation::Proceed;
+        }
+        match win.move_focus(backward) {
+          true => glib::Propagation::Stop,
+          false => glib::Propagation::Proceed,
+        }
+      }
+    ));
+    self.add_controller(key);
   }
 
-  fn load_html(&self, force_css: bool) {
-    log::debug!("load_html({})", force_css);
-    let html = self.imp().service.body_html().unwrap_or(String::new());
-    self
-      .imp()
-      .webview
-      .load_html(&*Html::new(&html, force_css).safe(), None);
+  fn focus_chain(&self) -> Vec<gtk4::Widget> {
+    let imp = self.imp();
+    vec![
+      imp.from.get().upcast(),
+      imp.date.get().upcast(),
+      imp.to.get().upcast(),
+      imp.subject.get().upcast(),
+      imp.show_text.get().upcast(),
+      imp.show_images.get().upcast(),
+      imp.force_css.get().upcast(),
+      imp.reader_view.get().upcast(),
+      imp.zoom_minus.get().upcast(),
+      imp.zoom_plus.get().upcast(),
+      imp.stack.get().upcast(),
+      imp.attachments_clamp.get().upcast(),
+    ]
   }
 
-  fn decide_policy(&self, policy: &PolicyDecision) -> Result<bool, Box<dyn std::error::Error>> {
-    match policy.clone().downcast::<NavigationPolicyDecision>() {
-      Ok(policy) => {
-        let navigation_action = policy.navigation_action();
-        if let Some(navigation_action) = navigation_action {
-          if let Some(request) = navigation_action.clone().request() {
-            if let Some(uri) = request.uri() {
-              if uri.starts_with("about:") {
-                return Ok(false);
-              }
-              log::debug!("WebView on_decide_policy(open) => {}", uri);
-              open::that(uri.to_string())?;
-            }
-            policy.ignore();
-            return Ok(true);
-          }
-        }
-      }
-      Err(e) => {
-        log::error!("WebView policy.clone().downcast({:?})", e);
-        return Err(format!("on_decide_policy() policy downcast failed ({:?})", e).into());
-      }
+  /// Moves the focus from a header field or a view toggle to the next
+  /// visible widget of the chain. The body and the attachments keep their
+  /// own Tab handling (links in the HTML, save buttons).
+  fn move_focus(&self, backward: bool) -> bool {
+    let chain = self.focus_chain();
+    let Some(focus) = gtk4::prelude::GtkWindowExt::focus(self) else {
+      return false;
+    };
+    let Some(current) = chain
+      .iter()
+      .position(|widget| focus == *widget || focus.is_ancestor(widget))
+    else {
+      return false;
+    };
+    // from the body on, the widgets are containers
+    let stack: gtk4::Widget = self.imp().stack.get().upcast();
+    if chain.iter().position(|widget| *widget == stack).is_some_and(|body| current >= body) {
+      return false;
     }
-    Ok(false)
+    let direction = match backward {
+      true => gtk4::DirectionType::TabBackward,
+      false => gtk4::DirectionType::TabForward,
+    };
+    focus_order(chain.len(), current, backward)
+      .any(|index| chain[index].is_drawable() && chain[index].child_focus(direction))
   }
 
-  fn on_decide_policy(
-    &self,
-    _: &WebView,
-    policy: &PolicyDecision,
-    _decision_type: PolicyDecisionType,
-  ) -> bool {
-    match self.decide_policy(policy) {
-      Ok(res) => res,
-      Err(e) => {
-        log::error!("WebView on_decide_policy({:?})", e);
-        false
+  /// Shows only the body, full window: hides the header bar, the message
+  /// headers and the attachments bar. Per session, nothing is persisted.
+  fn toggle_distraction_free(&self) {
+    let imp = self.imp();
+    let active = !imp.distraction_free.get();
+    log::debug!("toggle_distraction_free({})", active);
+    imp.distraction_free.set(active);
+    imp.toolbar_view.set_reveal_top_bars(!active);
+    imp.sheet.set_reveal_bottom_bar(!active);
+    if active {
+      imp.sheet.set_open(false);
+    }
+    match active {
+      true => {
+        // the From/To/Subject rows and the headers pane, the body stays
+        let mut child = imp.vertical_box.first_child();
+        while let Some(widget) = child {
+          widget.set_visible(widget == *imp.split.upcast_ref::<gtk4::Widget>());
+          child = widget.next_sibling();
+        }
+        imp.headers_pane.set_visible(false);
+        self.show_toast(&gettext("Press Escape or Shift+F11 to leave distraction-free reading"));
+      }
+      false => {
+        self.show_empty_state(imp.service.message_count() == 0);
+        self.update_split_view();
       }
     }
   }
 
-  fn on_show_text(&self, show: bool) {
-    log::debug!("on_show_text({})", show);
+  fn reset_idle_timeout(&self) {
     let imp = self.imp();
+    if let Some(source) = imp.idle_source.take() {
+      source.remove();
+    }
+    let minutes = self.get_idle_timeout();
+    if minutes == 0 {
+      return;
+    }
+    let source = glib::timeout_add_seconds_local_once(
+      minutes.saturating_mul(60),
+      clone!(
+        #[weak(rename_to = win)]
+        self,
+        move || {
+          // the source is gone once fired, reset_idle_timeout() must not remove it
+          win.imp().idle_source.take();
+          log::debug!("idle timeout");
+          win.clear_message();
+        }
+      ),
+    );
+    imp.idle_source.replace(Some(source));
+  }
 
-    imp
-      .stack
-      .get()
-      .set_visible_child_name(if show { "text" } else { "html" });
+  /// On close, and for every window when the application quits
+  pub fn save_geometry(&self) {
+    let Some(settings) = self.imp().settings.get() else {
+      return;
+    };
+    let window = self.upcast_ref::<gtk4::Window>();
+    let save = match self.application() {
+      Some(app) => should_save_geometry(window, app.active_window().as_ref(), app.windows().len()),
+      None => true,
+    };
+    if !save {
+      log::debug!("save_geometry() => skipped, not the last focused window");
+      return;
+    }
+    let (width, height) = self.default_size();
+    log::debug!("save_geometry({}x{})", width, height);
+    let _ = settings.set("width", width);
+    let _ = settings.set("height", height);
+    let _ = settings.set("is-maximized", self.is_maximized());
+    let _ = settings.set("is-fullscreen", self.is_fullscreen());
+  }
 
-    if imp.show_text.is_active() != show {
-      imp.show_text.set_active(show);
+  async fn search_webview_selection(&self) {
+    let Some(webview) = self.webview() else {
+      return;
+    };
+    let selection = webview
+      .evaluate_javascript_future(
+        "window.getSelection().toString()",
+        Some("mailviewer"),
+        None,
+      )
+      .await;
+    match selection {
+      Ok(value) => self.search_web(&value.to_str()),
+      Err(e) => log::error!("search_webview_selection({})", e),
     }
-    imp.show_images.set_visible(!show);
-    imp.force_css.set_visible(!show);
-    imp.zoom_minus.set_visible(!show);
-    imp.zoom_plus.set_visible(!show);
   }
 
-  fn build_mail_file_dialog(&self, title: &String) -> gtk4::FileDialog {
-    let filter = gtk4::FileFilter::new();
-    filter.set_name(Some(&gettext("Mail Files")));
-    filter.add_pattern("*.eml");
-    filter.add_pattern("*.msg");
+  fn search_text_selection(&self) {
+    let buffer = self.imp().body_text.buffer();
+    if let Some((start, end)) = buffer.selection_bounds() {
+      self.search_web(&buffer.text(&start, &end, false));
+    }
+  }
 
-    let filters = gio::ListStore::new::<gtk4::FileFilter>();
-    filters.append(&filter);
-    return gtk4::FileDialog::builder()
-      .title(title)
-      .modal(true)
-      .filters(&filters)
-      .build();
+  fn search_web(&self, selection: &str) {
+    if selection.trim().is_empty() {
+      return;
+    }
+    let template = match self.imp().settings.get() {
+      Some(settings) => settings.get::<String>(SETTINGS_SEARCH_URL),
+      None => return,
+    };
+    let url = search_url(&template, selection);
+    log::debug!("search_web({})", url);
+    if let Err(e) = open::that(&url) {
+      log::error!("{} ({}): {}", &gettext("Failed to open URL"), &url, e);
+    }
   }
 
-  pub async fn open_file_dialog(&self, close_on_cancel: bool) -> bool {
-    log::debug!("open_file_dialog()");
+  fn reset_zoom(&self) {
+    log::debug!("reset_zoom()");
+    self.set_zoom_level(1.0);
+  }
 
-    let load_dialog = self.build_mail_file_dialog(&gettext("Open Mail File"));
-    match load_dialog.open_future(Some(self)).await {
-      Ok(file) => {
-        if let Some(path) = file.path() {
-          self.open_file(path.to_str().unwrap());
-          return true;
-        }
+  fn reload(&self) {
+    log::debug!("reload()");
+    let imp = self.imp();
+    match imp.service.reload() {
+      Ok(_) => {
+        imp.force_css.set_active(false);
+        imp.reader_view.set_active(false);
+        self.display_message();
+      }
+      Err(e) => {
+        log::error!("reload({})", e);
+        self.alert_error(
+          &gettext("File Error"),
+          &format!("{}:\n{}", &gettext("Failed to reload file"), e),
+          false,
+        );
       }
-      Err(e) => match e.kind() {
-        Some(gtk4::DialogError::Dismissed) | Some(gtk4::DialogError::Cancelled) => {
-          if close_on_cancel {
-            self.close();
-          }
-        }
-        _ => log::error!("open_file_dialog({})", e),
-      },
     }
-
-    false
   }
 
-  pub fn open_file(&self, file: &str) {
-    log::debug!("open_file({})", file);
-    glib::idle_add_local_once(glib::clone!(
-      #[weak(rename_to = window)]
+  fn show_find_all(&self) {
+    log::debug!("show_find_all()");
+    let entry = gtk4::SearchEntry::builder().hexpand(true).build();
+    let include_attachments = gtk4::CheckButton::with_label(&gettext("Include attachments"));
+    include_attachments.set_active(true);
+    let results = gtk4::ListBox::new();
+    results.add_css_class("boxed-list");
+    results.set_selection_mode(gtk4::SelectionMode::None);
+    results.set_visible(false);
+
+    let search = clone!(
+      #[weak(rename_to = win)]
       self,
-      #[strong(rename_to = filename)]
-      file.to_string(),
+      #[weak]
+      entry,
+      #[weak]
+      include_attachments,
+      #[weak]
+      results,
       move || {
-        match window.imp().service.open_message(&filename) {
-          Ok(_) => {
-            window.display_message();
-          }
-          Err(e) => {
-            log::error!("service(ERR) : {}", e);
-            window.alert_error(
-              &gettext("File Error"),
-              &format!("{}:\n{}", &gettext("Failed to open file"), e),
-              true,
-            );
-          }
+        while let Some(child) = results.first_child() {
+          results.remove(&child);
+        }
+        let matches = win
+          .imp()
+          .service
+          .search(&entry.text(), include_attachments.is_active());
+        for found in &matches {
+          let title = match found {
+            SearchMatch::Subject => gettext("Subject"),
+            SearchMatch::From => gettext("From"),
+            SearchMatch::To => gettext("To"),
+            SearchMatch::TextBody => gettext("Text body"),
+            SearchMatch::HtmlBody => gettext("HTML body"),
+            SearchMatch::Attachment(filename) => {
+              gettext("Attachment {filename}").replace("{filename}", filename)
+            }
+          };
+          results.append(&adw::ActionRow::builder().title(title).build());
         }
+        results.set_visible(!matches.is_empty());
       }
-    ));
-  }
-
-  pub fn display_message(&self) {
-    log::debug!("display_eml()");
-    let imp = self.imp();
+    );
+    let on_change = search.clone();
+    entry.connect_search_changed(move |_| on_change());
+    include_attachments.connect_toggled(move |_| search());
 
-    imp.from.set_text(imp.service.from().as_str());
-    imp.date.set_text(imp.service.date().as_str());
-    imp.to.set_text(imp.service.to().as_str());
-    imp.subject.set_text(imp.service.subject().as_str());
+    let content = gtk4::Box::new(gtk4::Orientation::Vertical, 12);
+    content.set_margin_top(12);
+    content.set_margin_bottom(12);
+    content.set_margin_start(12);
+    content.set_margin_end(12);
+    content.append(&entry);
+    content.append(&include_attachments);
+    content.append(&results);
 
-    let mut has_text: bool = false;
-    let mut has_html: bool = false;
-
-    if let Some(text) = imp.service.body_text() {
-      imp.body_text.buffer().set_text(&text);
-      has_text = true;
-    }
+    let toolbar = adw::ToolbarView::new();
+    toolbar.add_top_bar(&adw::HeaderBar::new());
+    toolbar.set_content(Some(&content));
+    let dialog = adw::Dialog::builder()
+      .title(gettext("Find in All Parts"))
+      .content_width(450)
+      .child(&toolbar)
+      .build();
+    dialog.present(Some(self));
+    entry.grab_focus();
+  }
 
-    if let Some(html) = imp.service.body_html() {
-      imp.webview.load_html(&Html::new(&html, false).safe(), None);
-      has_html = true;
+  fn show_message_info(&self) {
+    log::debug!("show_message_info()");
+    let imp = self.imp();
+    let group = adw::PreferencesGroup::new();
+    let row = |title: &str, value: &str| {
+      let row = adw::ActionRow::builder()
+        .title(title)
+        .subtitle(value)
+        .subtitle_selectable(true)
+        .build();
+      row.add_css_class("property");
+      group.add(&row);
+    };
+    row(
+      &gettext("File"),
+      &imp
+        .service
+        .get_fullpath()
+        .unwrap_or_else(|| gettext("Pasted message")),
+    );
+    row(
+      &gettext("Charset"),
+      &imp
+        .service
+        .charset()
+        .unwrap_or_else(|| gettext("Unspecified")),
+    );
+    row(
+      &gettext("Language"),
+      &imp
+        .service
+        .language()
+        .unwrap_or_else(|| gettext("Unspecified")),
+    );
+    if let Some(organization) = imp.service.organization() {
+      row(&gettext("Organization"), &organization);
     }
-
-    imp.show_text.set_visible(has_text && has_html);
-    self.on_show_text(!has_html);
-
-    let preferences_group: adw::PreferencesGroup = adw::PreferencesGroup::new();
-    self
-      .imp()
-      .attachments_clamp
-      .set_child(Some(&preferences_group));
-
-    let attachments = imp.service.attachments();
-    let total = attachments.len();
-    if total > 0 {
-      for attachment in &attachments {
-        self.add_attachment(&attachment, &preferences_group);
+    let hidden = Html::new(&self.current_html().unwrap_or_default(), false).hidden_text();
+    if !hidden.is_empty() {
+      let expander = adw::ExpanderRow::builder()
+        .title(gettext("Hidden text"))
+        .subtitle(gettext("Invisible in the message, often preheaders or spam keywords"))
+        .build();
+      for text in &hidden {
+        let label = gtk4::Label::builder()
+          .label(text)
+          .wrap(true)
+          .xalign(0.0)
+          .selectable(true)
+          .margin_top(6)
+          .margin_bottom(6)
+          .margin_start(12)
+          .margin_end(12)
+          .build();
+        expander.add_row(&label);
       }
-      let fmt: String = ngettext(
-        "{total} attachment",
-        "{total} attachments",
-        total.try_into().unwrap(),
-      )
-      .replace("{total}", &total.to_string());
-      log::debug!("display_message() => {}", fmt);
-      preferences_group.set_title(&fmt);
-      imp.pull_label.set_text(&fmt);
-    } else {
-      // never shown
-      imp.pull_label.set_text(&gettext("No attachments"));
+      group.add(&expander);
     }
-
-    if let Some(widget) = imp.sheet.bottom_bar() {
-      if total > 0 {
-        widget.set_visible(true)
-      } else {
-        widget.set_visible(false)
+    let warnings = imp.service.warnings();
+    if !warnings.is_empty() {
+      let expander = adw::ExpanderRow::builder()
+        .title(gettext("Parsing notes"))
+        .subtitle(gettext("Anomalies worked around while reading the message"))
+        .build();
+      for warning in &warnings {
+        let label = gtk4::Label::builder()
+          .label(warning)
+          .wrap(true)
+          .xalign(0.0)
+          .selectable(true)
+          .margin_top(6)
+          .margin_bottom(6)
+          .margin_start(12)
+          .margin_end(12)
+          .build();
+        expander.add_row(&label);
       }
+      group.add(&expander);
     }
+    let page = adw::PreferencesPage::new();
+    page.add(&group);
+
+    let toolbar = adw::ToolbarView::new();
+    toolbar.add_top_bar(&adw::HeaderBar::new());
+    toolbar.set_content(Some(&page));
+    let dialog = adw::Dialog::builder()
+      .title(gettext("Message Information"))
+      .content_width(500)
+      .child(&toolbar)
+      .build();
+    dialog.present(Some(self));
   }
 
-  pub fn alert_error(&self, title: &str, message: &str, close_window: bool) -> adw::AlertDialog {
-    let alert = adw::AlertDialog::new(Some(title), Some(message));
-    alert.add_response("close", &gettext("Close"));
-    alert.set_response_appearance("close", adw::ResponseAppearance::Destructive);
-    alert.present(Some(self));
-    if close_window {
-      alert.connect_response(
-        Some("close"),
-        clone!(
-          #[strong(rename_to = window)]
-          self,
-          move |_, _| {
-            window.close();
-          }
+  fn show_security_report(&self) {
+    log::debug!("show_security_report()");
+    let findings = self.imp().service.security_findings();
+    let group = adw::PreferencesGroup::builder()
+      .description(gettext(
+        "Signals found in the headers and the content, a hint rather than a verdict",
+      ))
+      .build();
+    for finding in &findings {
+      let (title, subtitle) = match finding {
+        Finding::AuthFailed { method, result } => (
+          gettext("Authentication failed"),
+          format!("{}: {}", method.to_uppercase(), result),
         ),
-      );
+        Finding::NoAuthentication => (
+          gettext("No authentication"),
+          gettext("The receiving server did not record SPF, DKIM or DMARC results"),
+        ),
+        Finding::SpoofedFrom { shown, address } => (
+          gettext("Misleading sender name"),
+          gettext("Shows {shown} but was sent by {address}")
+            .replace("{shown}", shown)
+            .replace("{address}", address),
+        ),
+        Finding::ReplyToMismatch { from, reply_to } => (
+          gettext("Replies go elsewhere"),
+          gettext("Sent by {from} but replies go to {reply_to}")
+            .replace("{from}", from)
+            .replace("{reply_to}", reply_to),
+        ),
+        Finding::DeceptiveLink { text, href } => (
+          gettext("Deceptive link"),
+          gettext("Shows {text} but leads to {href}")
+            .replace("{text}", text)
+            .replace("{href}", href),
+        ),
+        Finding::HomographDomain { domain, decoded, lookalike } => (
+          gettext("Lookalike domain"),
+          gettext("{domain} reads {decoded}, imitating {lookalike}")
+            .replace("{domain}", domain)
+            .replace("{decoded}", decoded)
+            .replace("{lookalike}", lookalike),
+        ),
+        Finding::TrackingPixels(count) => (
+          gettext("Tracking pixels"),
+          ngettext(
+            "{count} invisible remote image reports when the message is read",
+            "{count} invisible remote images report when the message is read",
+            *count as u32,
+          )
+          .replace("{count}", &count.to_string()),
+        ),
+        Finding::AttachmentMismatch { filename, actual } => (
+          gettext("Disguised attachment"),
+          gettext("{filename} is actually {actual}")
+            .replace("{filename}", filename)
+            .replace("{actual}", actual),
+        ),
+      };
+      let icon = match finding.severity() {
+        Severity::Danger => "dialog-error-symbolic",
+        Severity::Warning => "dialog-warning-symbolic",
+        Severity::Info => "dialog-information-symbolic",
+      };
+      let row = adw::ActionRow::builder()
+        .title(glib::markup_escape_text(&title).as_str())
+        .subtitle(glib::markup_escape_text(&subtitle).as_str())
+        .subtitle_selectable(true)
+        .build();
+      row.add_prefix(&gtk4::Image::from_icon_name(icon));
+      group.add(&row);
     }
-    alert
+    if findings.is_empty() {
+      let row = adw::ActionRow::builder()
+        .title(gettext("No issue found"))
+        .build();
+      row.add_prefix(&gtk4::Image::from_icon_name("emblem-ok-symbolic"));
+      group.add(&row);
+    }
+    let page = adw::PreferencesPage::new();
+    page.add(&group);
+
+    let toolbar = adw::ToolbarView::new();
+    toolbar.add_top_bar(&adw::HeaderBar::new());
+    toolbar.set_content(Some(&page));
+    let dialog = adw::Dialog::builder()
+      .title(gettext("Why Is This Message Suspicious?"))
+      .content_width(500)
+      .child(&toolbar)
+      .build();
+    dialog.present(Some(self));
   }
 
-  fn get_show_file_name(&self) -> bool {
-    if let Some(settings) = self.imp().settings.get() {
-      settings.get::<bool>(SETTINGS_SHOW_FILE_NAME)
-    } else {
-      false
-    }
+  fn show_mime_tree(&self) {
+    log::debug!("show_mime_tree()");
+    let group = adw::PreferencesGroup::new();
+    group.add(&self.mime_tree_row(&self.imp().service.mime_tree()));
+    let page = adw::PreferencesPage::new();
+    page.add(&group);
+
+    let toolbar = adw::ToolbarView::new();
+    toolbar.add_top_bar(&adw::HeaderBar::new());
+    toolbar.set_content(Some(&page));
+    let dialog = adw::Dialog::builder()
+      .title(gettext("MIME Structure"))
+      .content_width(500)
+      .content_height(600)
+      .child(&toolbar)
+      .build();
+    dialog.present(Some(self));
   }
 
-  fn show_preferences(&self) {
-    log::debug!("show_preferences()");
-    match self.imp().settings.get() {
-      Some(settings) => {
-        let builder = gtk4::Builder::from_resource("/io/github/alescdb/mailviewer/preferences.ui");
-        let show_file_name: adw::SwitchRow = builder.object("show_file_name").unwrap();
-        settings
-          .bind(SETTINGS_SHOW_FILE_NAME, &show_file_name, "active")
-          .build();
+  fn mime_tree_row(&self, part: &MimePart) -> gtk4::Widget {
+    let mut details: Vec<String> = vec![];
+    if let Some(disposition) = &part.disposition {
+      details.push(disposition.clone());
+    }
+    if let Some(encoding) = &part.encoding {
+      details.push(encoding.clone());
+    }
+    if let Some(content_id) = &part.content_id {
+      details.push(format!("cid:{}", content_id));
+    }
+    details.push(glib::format_size(part.size as u64).to_string());
+    let subtitle = details.join(" · ");
 
-        let prefs: adw::PreferencesDialog = builder.object("preferences").unwrap();
-        prefs.present(Some(self));
-        prefs.connect_closed(clone!(
+    if part.children.is_empty() {
+      let row = adw::ActionRow::builder()
+        .title(&part.content_type)
+        .subtitle(subtitle)
+        .build();
+      if let Some(index) = part.index {
+        let raw = gtk4::Button::builder()
+          .icon_name("text-x-generic-symbolic")
+          .tooltip_text(gettext("View Raw"))
+          .valign(gtk4::Align::Center)
+          .build();
+        raw.add_css_class("flat");
+        let title = part.content_type.clone();
+        raw.connect_clicked(clone!(
           #[weak(rename_to = win)]
           self,
           move |_| {
-            log::debug!("show_preferences() => done");
-            win
-              .imp()
-              .service
-              .set_show_file_name(win.get_show_file_name());
+            win.show_raw_part(index, &title);
           }
         ));
+        row.add_suffix(&raw);
       }
-      None => {
-        self.alert_error(
-          &gettext("Settings"),
-          &gettext("Failed to get settings"),
-          false,
+      return row.upcast();
+    }
+    let row = adw::ExpanderRow::builder()
+      .title(&part.content_type)
+      .subtitle(subtitle)
+      .expanded(true)
+      .build();
+    for child in &part.children {
+      row.add_row(&self.mime_tree_row(child));
+    }
+    row.upcast()
+  }
+
+  /// Source of one part of the MIME structure, headers included
+  fn show_raw_part(&self, index: usize, title: &str) {
+    log::debug!("show_raw_part({})", index);
+    let Some(raw) = self.imp().service.raw_part(index) else {
+      self.show_toast(&gettext("The source of this part is not available"));
+      return;
+    };
+    let mut text = String::from_utf8_lossy(&raw[..raw.len().min(RAW_PART_LIMIT)]).to_string();
+    if raw.len() > RAW_PART_LIMIT {
+      text.push_str(&format!("\n[{} more bytes]", raw.len() - RAW_PART_LIMIT));
+    }
+    let view = gtk4::TextView::builder()
+      .editable(false)
+      .monospace(true)
+      .wrap_mode(gtk4::WrapMode::WordChar)
+      .top_margin(6)
+      .bottom_margin(6)
+      .left_margin(12)
+      .right_margin(12)
+      .build();
+    view.buffer().set_text(&text);
+    let scrolled = gtk4::ScrolledWindow::builder()
+      .child(&view)
+      .hscrollbar_policy(gtk4::PolicyType::Never)
+      .vexpand(true)
+      .build();
+
+    let toolbar = adw::ToolbarView::new();
+    toolbar.add_top_bar(&adw::HeaderBar::new());
+    toolbar.set_content(Some(&scrolled));
+    let dialog = adw::Dialog::builder()
+      .title(title)
+      .content_width(700)
+      .content_height(600)
+      .child(&toolbar)
+      .build();
+    dialog.present(Some(self));
+  }
+
+  /// Plays an audio or video attachment in a dialog, in the default
+  /// application when GStreamer can't decode it.
+  fn show_media_preview(&self, attachment: &Attachment) {
+    log::debug!("show_media_preview({})", attachment.filename);
+    let file = match attachment.write_to_tmp() {
+      Ok(file) => file,
+      Err(e) => {
+        log::error!("write_to_tmp({})", e);
+        return;
+      }
+    };
+    let media = gtk4::MediaFile::for_filename(&file);
+    let video = gtk4::Video::builder()
+      .media_stream(&media)
+      .autoplay(true)
+      .vexpand(true)
+      .build();
+
+    let toolbar = adw::ToolbarView::new();
+    toolbar.add_top_bar(&adw::HeaderBar::new());
+    toolbar.set_content(Some(&video));
+    let dialog = adw::Dialog::builder()
+      .title(&attachment.filename)
+      .content_width(640)
+      .content_height(420)
+      .child(&toolbar)
+      .build();
+    let attachment = attachment.clone();
+    media.connect_error_notify(clone!(
+      #[weak(rename_to = window)]
+      self,
+      #[weak]
+      dialog,
+      #[strong]
+      attachment,
+      move |media| {
+        if let Some(error) = media.error() {
+          log::warn!("show_media_preview({}) => external player", error);
+          dialog.close();
+          window.on_attachment_open(&attachment);
+        }
+      }
+    ));
+    dialog.connect_closed(move |_| media.set_playing(false));
+    dialog.present(Some(self));
+  }
+
+  fn add_attachment(&self, attachment: &Attachment, preferences_group: &adw::PreferencesGroup) {
+    let window = self;
+    let mime = &attachment
+      .clone()
+      .mime_type
+      .clone()
+      .unwrap_or("Unknown".to_string());
+    let icon = match self.get_type_icons() {
+      true => attachment_icon(mime),
+      false if mime.starts_with("image") => "image-x-generic-symbolic",
+      false => "document-open",
+    };
+
+    let save = gtk4::Button::new();
+    save.set_valign(gtk4::Align::Center);
+    save.set_icon_name("document-save-as-symbolic");
+    save.set_tooltip_text(Some(&gettext("Save as...")));
+    save.update_property(&[gtk4::accessible::Property::Label(
+      &gettext("Save {filename}").replace("{filename}", &attachment.filename),
+    )]);
+    save.connect_clicked(clone!(
+      #[strong]
+      window,
+      #[strong]
+      attachment,
+      move |_| {
+        let window = window.clone();
+        let attachment = attachment.clone();
+        glib::MainContext::default().spawn_local(async move {
+          window.on_attachment_save(&attachment).await;
+        });
+      }
+    ));
+    let btn = match attachment.vcard() {
+      Some(card) => self.contact_row(attachment, &card),
+      None => attachment_row(attachment, mime, icon),
+    };
+    if let Some(actual) = attachment.extension_mismatch() {
+      let warning = gtk4::Image::from_icon_name("dialog-warning-symbolic");
+      warning.add_css_class("warning");
+      warning.set_tooltip_text(Some(
+        &gettext("The file name doesn't match its content ({mime_type})")
+          .replace("{mime_type}", actual),
+      ));
+      btn.add_suffix(&warning);
+    }
+    if archive::kind(attachment).is_some() {
+      let contents = gtk4::MenuButton::builder()
+        .icon_name("view-list-symbolic")
+        .tooltip_text(gettext("Show contents"))
+        .valign(gtk4::Align::Center)
+        .build();
+      // listed on demand, not for every archive displayed
+      contents.set_create_popup_func(clone!(
+        #[weak(rename_to = win)]
+        self,
+        #[strong]
+        attachment,
+        move |button| {
+          button.set_popover(Some(&win.archive_popover(&attachment)));
+        }
+      ));
+      btn.add_suffix(&contents);
+    }
+    if self.get_media_preview() && is_media_previewable(attachment) {
+      let play = gtk4::Button::builder()
+        .icon_name("media-playback-start-symbolic")
+        .tooltip_text(gettext("Play"))
+        .valign(gtk4::Align::Center)
+        .build();
+      play.connect_clicked(clone!(
+        #[weak(rename_to = win)]
+        self,
+        #[strong]
+        attachment,
+        move |_| win.show_media_preview(&attachment)
+      ));
+      btn.add_suffix(&play);
+    }
+    btn.add_suffix(&save);
+
+    btn.connect_activated(clone!(
+      #[strong]
+      window,
+      #[strong]
+      attachment,
+      move |_| {
+        window.on_attachment_open(&attachment);
+      }
+    ));
+    preferences_group.add(&btn);
+    self
+      .imp()
+      .attachment_rows
+      .borrow_mut()
+      .push((attachment.category(), btn));
+  }
+
+  /// Entries of an archive attachment, each with an extract button. The
+  /// archive is read on another thread, a spinner is shown meanwhile.
+  fn archive_popover(&self, attachment: &Attachment) -> gtk4::Popover {
+    let list = gtk4::ListBox::new();
+    list.set_selection_mode(gtk4::SelectionMode::None);
+    let spinner = adw::Spinner::builder().margin_top(12).margin_bottom(12).build();
+    list.append(&spinner);
+    glib::spawn_future_local(clone!(
+      #[weak(rename_to = win)]
+      self,
+      #[weak]
+      list,
+      #[strong]
+      attachment,
+      async move {
+        let filename = attachment.filename.clone();
+        let opened = gio::spawn_blocking(move || {
+          Archive::open(&attachment).map_err(|e| e.to_string())
+        })
+        .await;
+        list.remove(&spinner);
+        match opened {
+          Ok(Ok(archive)) => win.fill_archive_list(&list, Arc::new(archive)),
+          Ok(Err(e)) => {
+            log::error!("archive_popover({}) => {}", filename, e);
+            list.append(&gtk4::Label::new(Some(&gettext("Unable to read the archive"))));
+          }
+          Err(_) => log::error!("archive_popover({}) => panicked", filename),
+        }
+      }
+    ));
+    let scrolled = gtk4::ScrolledWindow::builder()
+      .child(&list)
+      .hscrollbar_policy(gtk4::PolicyType::Never)
+      .propagate_natural_height(true)
+      .max_content_height(400)
+      .min_content_width(320)
+      .build();
+    gtk4::Popover::builder().child(&scrolled).build()
+  }
+
+  fn fill_archive_list(&self, list: &gtk4::ListBox, archive: Arc<Archive>) {
+    for entry in archive.entries().iter().filter(|entry| !entry.is_dir) {
+      let row = adw::ActionRow::builder()
+        .title(&entry.name)
+        .title_lines(ATTACHMENT_TITLE_LINES)
+        .subtitle(glib::format_size(entry.size).as_str())
+        .use_markup(false)
+        .tooltip_text(&entry.name)
+        .build();
+      let extract = gtk4::Button::builder()
+        .icon_name("document-save-as-symbolic")
+        .tooltip_text(gettext("Extract..."))
+        .valign(gtk4::Align::Center)
+        .build();
+      extract.add_css_class("flat");
+      extract.connect_clicked(clone!(
+        #[weak(rename_to = win)]
+        self,
+        #[strong]
+        archive,
+        #[strong]
+        entry,
+        move |_| {
+          let archive = archive.clone();
+          let entry = entry.clone();
+          glib::spawn_future_local(async move {
+            win.on_archive_extract(archive, &entry).await;
+          });
+        }
+      ));
+      row.add_suffix(&extract);
+      list.append(&row);
+    }
+  }
+
+  /// Saves one file of an archive attachment, like an attachment of its own
+  async fn on_archive_extract(&self, archive: Arc<Archive>, entry: &ArchiveEntry) {
+    log::debug!("on_archive_extract({})", entry.name);
+    let extracted = {
+      let entry = entry.clone();
+      gio::spawn_blocking(move || archive.extract(&entry).map_err(|e| e.to_string())).await
+    };
+    match extracted {
+      Ok(Ok(body)) => {
+        let file = Attachment {
+          filename: entry.basename().to_string(),
+          content_id: "none".to_string(),
+          body,
+          mime_type: None,
+          description: None,
+          encoded: None,
+        };
+        self.on_attachment_save(&file).await;
+      }
+      Ok(Err(e)) => {
+        log::error!("on_archive_extract({})", e);
+        self.alert_error(&gettext("Archive Error"), &e, false);
+      }
+      Err(_) => log::error!("on_archive_extract({}) => panicked", entry.name),
+    }
+  }
+
+  /// Contact panel of a vCard attachment, opening the .vcf hands it to the
+  /// contacts application.
+  fn contact_row(&self, attachment: &Attachment, card: &VCard) -> adw::ActionRow {
+    let mut details: Vec<String> = vec![];
+    details.extend(card.organization.clone());
+    details.extend(card.phones.iter().cloned());
+    details.extend(card.emails.iter().cloned());
+    let title = match card.full_name.is_empty() {
+      true => attachment.filename.to_string(),
+      false => card.full_name.clone(),
+    };
+    let row = adw::ActionRow::builder()
+      .title(title)
+      .subtitle(details.join("\n"))
+      .use_markup(false)
+      .tooltip_text(&attachment.filename)
+      .build();
+    row.add_prefix(&gtk4::Image::from_icon_name("avatar-default-symbolic"));
+
+    let add = gtk4::Button::new();
+    add.set_valign(gtk4::Align::Center);
+    add.set_icon_name("contact-new-symbolic");
+    add.set_tooltip_text(Some(&gettext("Add to contacts")));
+    add.connect_clicked(clone!(
+      #[weak(rename_to = win)]
+      self,
+      #[strong]
+      attachment,
+      move |_| {
+        win.on_attachment_open(&attachment);
+      }
+    ));
+    row.add_suffix(&add);
+    row
+  }
+
+  /// Toggle chips filtering the attachments by category, None when there's
+  /// nothing to filter.
+  fn attachment_filters(&self) -> Option<gtk4::Box> {
+    let categories: Vec<AttachmentCategory> = AttachmentCategory::ALL
+      .into_iter()
+      .filter(|c| self.imp().attachment_rows.borrow().iter().any(|(r, _)| r == c))
+      .collect();
+    if categories.len() < 2 {
+      return None;
+    }
+    let chips = gtk4::Box::new(gtk4::Orientation::Horizontal, 0);
+    chips.add_css_class("linked");
+    chips.set_valign(gtk4::Align::Center);
+
+    let all = gtk4::ToggleButton::with_label(&gettext("All"));
+    all.set_active(true);
+    all.connect_toggled(clone!(
+      #[weak(rename_to = win)]
+      self,
+      move |button| {
+        if button.is_active() {
+          win.filter_attachments(None);
+        }
+      }
+    ));
+    chips.append(&all);
+    for category in categories {
+      let label = match category {
+        AttachmentCategory::Image => gettext("Images"),
+        AttachmentCategory::Document => gettext("Documents"),
+        AttachmentCategory::Text => gettext("Text"),
+        AttachmentCategory::Other => gettext("Other"),
+      };
+      let chip = gtk4::ToggleButton::with_label(&label);
+      chip.set_group(Some(&all));
+      chip.connect_toggled(clone!(
+        #[weak(rename_to = win)]
+        self,
+        move |button| {
+          if button.is_active() {
+            win.filter_attachments(Some(category));
+          }
+        }
+      ));
+      chips.append(&chip);
+    }
+    Some(chips)
+  }
+
+  fn filter_attachments(&self, category: Option<AttachmentCategory>) {
+    log::debug!("filter_attachments({:?})", category);
+    for (row_category, row) in self.imp().attachment_rows.borrow().iter() {
+      row.set_visible(category.map_or(true, |c| c == *row_category));
+    }
+  }
+
+  async fn on_attachment_save(&self, attachment: &Attachment) {
+    log::debug!("on_attachment_save({})", attachment.filename);
+
+    let save_dialog = gtk4::FileDialog::builder()
+      .title(&gettext("Save attachment..."))
+      .modal(true)
+      .initial_name(attachment.safe_filename().as_str())
+      .build();
+    // pasted messages have no path
+    if let Some(fullpath) = self.imp().service.get_fullpath() {
+      if let Some(parent) = gio::File::for_path(fullpath).parent() {
+        save_dialog.set_initial_folder(Some(&parent));
+      }
+    }
+
+    match save_dialog.save_future(Some(self)).await {
+      Ok(file) => {
+        if let Some(path) = file.peek_path() {
+          log::debug!("Saving attachment to {:?}", path);
+          match attachment.write_to_file(path.to_str().unwrap()) {
+            Ok(_) => {
+              log::debug!("write_to_file({:?})", &path);
+              self.show_toast(&gettext("Attachment saved"));
+            }
+            Err(e) => {
+              log::error!("write_to_file({})", e);
+              self.alert_error(&gettext("File Error"), &e.to_string(), false);
+            }
+          };
+        }
+      }
+      Err(e) => match e.kind() {
+        Some(gtk4::DialogError::Dismissed) | Some(gtk4::DialogError::Cancelled) => return,
+        _ => log::error!("save_dialog({})", e),
+      },
+    }
+  }
+
+  /// Opens the plain text body with the default text editor
+  fn open_body_in_editor(&self) {
+    match self.imp().service.write_body_to_tmp() {
+      Ok(file) => {
+        log::debug!("open_body_in_editor({})", &file);
+        if let Err(e) = open::that(&file) {
+          log::error!("{} ({}): {}", &gettext("Failed to open file"), &file, e);
+        }
+      }
+      Err(e) => log::error!("open_body_in_editor({})", e),
+    }
+  }
+
+  fn on_attachment_open(&self, attachment: &Attachment) {
+    log::debug!("on_button_clicked({})", attachment.filename);
+    match attachment.is_dangerous_to_open() {
+      true => self.ask_attachment_open(attachment),
+      false => self.open_attachment(attachment),
+    }
+  }
+
+  /// Programs and scripts only open once confirmed, the desktop may run them
+  fn ask_attachment_open(&self, attachment: &Attachment) {
+    let alert = adw::AlertDialog::new(
+      Some(&gettext("Open Executable File?")),
+      Some(
+        &gettext("{filename} may run a program. Only open it if you trust the sender.")
+          .replace("{filename}", &attachment.filename),
+      ),
+    );
+    alert.add_response("cancel", &gettext("_Cancel"));
+    alert.add_response("open", &gettext("_Open Anyway"));
+    alert.set_response_appearance("open", adw::ResponseAppearance::Destructive);
+    alert.set_default_response(Some("cancel"));
+    alert.set_close_response("cancel");
+    alert.connect_response(
+      Some("open"),
+      clone!(
+        #[weak(rename_to = win)]
+        self,
+        #[strong]
+        attachment,
+        move |_, _| win.open_attachment(&attachment)
+      ),
+    );
+    alert.present(Some(self));
+  }
+
+  fn open_attachment(&self, attachment: &Attachment) {
+    match attachment.write_to_tmp() {
+      Ok(file) => {
+        log::debug!("write_to_tmp({}) success", &file);
+        if let Err(e) = open::that(&file) {
+          log::error!("{} ({}): {}", &gettext("Failed to open file"), &file, e);
+        }
+      }
+      Err(e) => log::error!("write_to_tmp({})", e),
+    };
+  }
+
+  fn set_zoom_level(&self, zoom: f64) {
+    log::debug!("set_zoom({})", zoom);
+    if let Some(webview) = self.webview() {
+      webview.set_zoom_level(zoom);
+    }
+    let Some(settings) = self.imp().settings.get() else {
+      return;
+    };
+    match self.sender_zoom_domain() {
+      Some(domain) => {
+        let mut zooms = settings.get::<HashMap<String, f64>>(SETTINGS_SENDER_ZOOMS);
+        let default = settings.get::<f64>(SETTINGS_ZOOM);
+        record_sender_zoom(&mut zooms, &domain, zoom, default);
+        let _ = settings.set(SETTINGS_SENDER_ZOOMS, zooms.to_variant());
+      }
+      None => {
+        let _ = settings.set(SETTINGS_ZOOM, zoom);
+      }
+    }
+  }
+
+  /// Sender domain the zoom is remembered for, when that's enabled
+  fn sender_zoom_domain(&self) -> Option<String> {
+    let settings = self.imp().settings.get()?;
+    match settings.get::<bool>(SETTINGS_ZOOM_PER_SENDER) {
+      true => security::from_domain(&self.imp().service.from()),
+      false => None,
+    }
+  }
+
+  /// Zoom remembered for the sender of the displayed message, or the
+  /// global one.
+  fn apply_sender_zoom(&self) {
+    let (Some(settings), Some(webview)) = (self.imp().settings.get(), self.webview()) else {
+      return;
+    };
+    if !settings.get::<bool>(SETTINGS_ZOOM_PER_SENDER) {
+      return;
+    }
+    let zooms = settings.get::<HashMap<String, f64>>(SETTINGS_SENDER_ZOOMS);
+    let zoom = self
+      .sender_zoom_domain()
+      .and_then(|domain| sender_zoom(&zooms, &domain))
+      .unwrap_or_else(|| settings.get::<f64>(SETTINGS_ZOOM));
+    webview.set_zoom_level(zoom);
+  }
+
+  fn load_html(&self, force_css: bool) {
+    log::debug!("load_html({})", force_css);
+    let html = self.current_html().unwrap_or(String::new());
+    if let Some(webview) = self.webview() {
+      self.imp().scroll_reset.render_again();
+      webview.load_html(&self.safe_html(&html, force_css), None);
+    }
+  }
+
+  /// The forced stylesheet follows the system style, the message is rendered
+  /// again when it changes. The zoom and the displayed view are kept.
+  fn on_dark_changed(&self) {
+    let imp = self.imp();
+    let dark = adw::StyleManager::default().is_dark();
+    log::debug!("on_dark_changed({})", dark);
+    let restyle = restyle_on_dark_change(
+      imp.force_css.is_active(),
+      self.get_custom_css().is_some(),
+      self.current_html().is_some(),
+    );
+    if restyle {
+      self.load_html(true);
+    }
+  }
+
+  /// Remote images are kept out by the placeholders and their
+  /// Content-Security-Policy when enabled, so the loaded ones and the inline
+  /// ones can be displayed.
+  fn update_auto_load_images(&self) {
+    let imp = self.imp();
+    if let Some(websettings) = imp.websettings.get() {
+      websettings.set_auto_load_images(imp.show_images.is_active() || self.get_image_placeholders());
+    }
+  }
+
+  fn safe_html(&self, html: &str, force_css: bool) -> String {
+    self.html(html, force_css).safe()
+  }
+
+  fn html(&self, html: &str, force_css: bool) -> Html {
+    // only rewrite when images are loaded at all
+    let image_proxy = match self.imp().show_images.is_active() {
+      true => self.get_image_proxy(),
+      false => None,
+    };
+    let placeholder = match !self.imp().show_images.is_active() && self.get_image_placeholders() {
+      true => Some(gettext("Click to load image")),
+      false => None,
+    };
+    self
+      .imp()
+      .service
+      .html(html, force_css)
+      .with_strip_comments(self.get_strip_comments())
+      .with_image_proxy(image_proxy.as_deref())
+      .with_visited(&self.imp().visited.borrow())
+      .with_custom_css(self.get_custom_css().as_deref())
+      .with_dark(adw::StyleManager::default().is_dark())
+      .with_max_width(self.get_max_width())
+      .with_reader(self.imp().reader_view.is_active())
+      .with_image_placeholders(placeholder.as_deref(), &self.imp().loaded_images.borrow())
+  }
+
+  /// HTML-ish representations offered in the alternatives dropdown
+  fn html_alternatives(&self) -> Vec<Alternative> {
+    let allowlist = self.get_html_types();
+    let mut alternatives = alternative::allowed(self.imp().service.alternatives(), &allowlist);
+    alternatives.retain(|a| !a.is_text());
+    alternatives
+  }
+
+  fn current_html(&self) -> Option<String> {
+    let selected = self.imp().alternatives.selected() as usize;
+    match self.html_alternatives().into_iter().nth(selected) {
+      Some(alternative) => Some(alternative.body),
+      None => self
+        .imp()
+        .service
+        .body_html()
+        .filter(|_| alternative::is_allowed("text/html", &self.get_html_types())),
+    }
+  }
+
+  /// Content types the WebView may render, the others fall back to text
+  fn get_html_types(&self) -> Vec<String> {
+    match self.imp().settings.get() {
+      Some(settings) => alternative::allowlist(&settings.get::<String>(SETTINGS_HTML_TYPES)),
+      None => vec!["text/html".to_string(), "text/x-amp-html".to_string()],
+    }
+  }
+
+  fn update_alternatives(&self) {
+    let imp = self.imp();
+    let alternatives = self.html_alternatives();
+    let types: Vec<&str> = alternatives.iter().map(|a| a.content_type.as_str()).collect();
+    imp.alternatives.set_model(Some(&gtk4::StringList::new(&types)));
+    imp.alternatives.set_selected(
+      alternative::preferred(&alternatives)
+        .map(|i| i as u32)
+        .unwrap_or(gtk4::INVALID_LIST_POSITION),
+    );
+    imp.alternatives.set_visible(alternatives.len() > 1);
+  }
+
+  fn decide_policy(&self, policy: &PolicyDecision) -> Result<bool, Box<dyn std::error::Error>> {
+    match policy.clone().downcast::<NavigationPolicyDecision>() {
+      Ok(policy) => {
+        let navigation_action = policy.navigation_action();
+        if let Some(navigation_action) = navigation_action {
+          if let Some(request) = navigation_action.clone().request() {
+            if let Some(uri) = request.uri() {
+              if uri.starts_with("about:") {
+                return Ok(false);
+              }
+              if let Some(image) = uri.strip_prefix(IMAGE_SCHEME) {
+                policy.ignore();
+                self.load_image(&glib::Uri::unescape_string(image, None::<&str>).unwrap_or_default());
+                return Ok(true);
+              }
+              log::debug!("WebView on_decide_policy(link) => {}", uri);
+              self.activate_link(&uri);
+            }
+            policy.ignore();
+            return Ok(true);
+          }
+        }
+      }
+      Err(e) => {
+        log::error!("WebView policy.clone().downcast({:?})", e);
+        return Err(format!("on_decide_policy() policy downcast failed ({:?})", e).into());
+      }
+    }
+    Ok(false)
+  }
+
+  /// Click on a link, in the HTML or the text view: opens it, copies it or
+  /// asks which of the two, depending on the preferences.
+  fn activate_link(&self, uri: &str) {
+    match self.get_link_action() {
+      LinkAction::Open => self.open_link(uri),
+      LinkAction::Copy => self.copy_link(uri),
+      LinkAction::Ask => self.ask_link(uri),
+    }
+  }
+
+  fn open_link(&self, uri: &str) {
+    log::debug!("open_link({})", uri);
+    match open::that(uri) {
+      Ok(_) => self.mark_visited(uri),
+      Err(e) => log::error!("{} ({}): {}", &gettext("Failed to open URL"), uri, e),
+    }
+  }
+
+  fn copy_link(&self, uri: &str) {
+    log::debug!("copy_link({})", uri);
+    self.clipboard().set_text(uri);
+    self.show_toast(&gettext("Link copied to clipboard"));
+  }
+
+  fn ask_link(&self, uri: &str) {
+    let alert = adw::AlertDialog::new(Some(&gettext("Open Link?")), Some(uri));
+    alert.add_response("cancel", &gettext("_Cancel"));
+    alert.add_response("copy", &gettext("_Copy Link"));
+    alert.add_response("open", &gettext("_Open"));
+    alert.set_response_appearance("open", adw::ResponseAppearance::Suggested);
+    alert.set_default_response(Some("open"));
+    alert.set_close_response("cancel");
+    let uri = uri.to_string();
+    alert.connect_response(
+      None,
+      clone!(
+        #[weak(rename_to = win)]
+        self,
+        #[strong]
+        uri,
+        move |_, response| match response {
+          "open" => win.open_link(&uri),
+          "copy" => win.copy_link(&uri),
+          _ => {}
+        }
+      ),
+    );
+    alert.present(Some(self));
+  }
+
+  /// Click on an image placeholder: renders again with that image loaded
+  fn load_image(&self, url: &str) {
+    log::debug!("load_image({})", url);
+    if url.is_empty() {
+      return;
+    }
+    self.imp().loaded_images.borrow_mut().push(url.to_string());
+    self.load_html(self.imp().force_css.is_active());
+  }
+
+  // WebKit may restore the position of the previous document
+  fn scroll_webview_to_top(&self) {
+    let Some(webview) = self.webview() else {
+      return;
+    };
+    webview.evaluate_javascript(
+      "window.scrollTo(0, 0)",
+      Some("mailviewer"),
+      None,
+      None::<&gio::Cancellable>,
+      |result| {
+        if let Err(e) = result {
+          log::error!("scroll_webview_to_top({})", e);
+        }
+      },
+    );
+  }
+
+  fn mark_visited(&self, uri: &str) {
+    if !self.imp().visited.borrow_mut().visit(uri) {
+      return;
+    }
+    let Some(webview) = self.webview() else {
+      return;
+    };
+    webview.evaluate_javascript(
+      &VisitedLinks::mark_script(uri),
+      Some("mailviewer"),
+      None,
+      None::<&gio::Cancellable>,
+      |result| {
+        if let Err(e) = result {
+          log::error!("mark_visited({})", e);
+        }
+      },
+    );
+  }
+
+  fn on_decide_policy(
+    &self,
+    _: &WebView,
+    policy: &PolicyDecision,
+    _decision_type: PolicyDecisionType,
+  ) -> bool {
+    match self.decide_policy(policy) {
+      Ok(res) => res,
+      Err(e) => {
+        log::error!("WebView on_decide_policy({:?})", e);
+        false
+      }
+    }
+  }
+
+  /// Keyboard counterpart of the show_text toggle, does nothing when the
+  /// message has only one of the views.
+  fn switch_view(&self, text: bool) {
+    let imp = self.imp();
+    let available = imp.service.has_both() && (text || self.webview().is_some());
+    log::debug!("switch_view(text: {}) => available: {}", text, available);
+    if available {
+      self.on_show_text(text);
+    }
+  }
+
+  fn on_show_text(&self, show: bool) {
+    log::debug!("on_show_text({})", show);
+    let imp = self.imp();
+    let show = show_text_view(self.webview().is_some(), show);
+
+    imp
+      .stack
+      .get()
+      .set_visible_child_name(if show { "text" } else { "html" });
+
+    if imp.show_text.is_active() != show {
+      imp.show_text.set_active(show);
+    }
+    self.update_view_controls(!show);
+  }
+
+  /// Some text in the plain text or the HTML body
+  fn has_visible_body(&self) -> bool {
+    let service = &self.imp().service;
+    service
+      .body_text()
+      .is_some_and(|text| !text.trim().is_empty())
+      || service
+        .body_html()
+        .is_some_and(|html| !Html::new(&html, false).to_text().trim().is_empty())
+  }
+
+  /// Renders the only attachment, a PDF, in place of an empty body when the
+  /// option is on. WebKit displays it with its built-in PDF viewer.
+  fn show_pdf_body(&self) {
+    let imp = self.imp();
+    let has_body = self.has_visible_body();
+    let attachments = imp.service.attachments();
+    let enabled = self.get_pdf_as_body() && self.webview().is_some();
+    let Some(pdf) = pdf_as_body(enabled, has_body, &attachments) else {
+      return;
+    };
+    log::debug!("show_pdf_body({})", pdf.filename);
+    let view = imp.pdf_view.get_or_init(|| {
+      let view = WebView::new();
+      view.connect_decide_policy(clone!(
+        #[weak(rename_to = win)]
+        self,
+        #[upgrade_or]
+        false,
+        move |webview: &WebView, policy: &PolicyDecision, decision_type: PolicyDecisionType| {
+          // the viewer navigates to its own pages, only clicked links leave
+          let clicked = policy
+            .clone()
+            .downcast::<NavigationPolicyDecision>()
+            .ok()
+            .and_then(|policy| policy.navigation_action())
+            .is_some_and(|mut action| action.navigation_type() == NavigationType::LinkClicked);
+          clicked && win.on_decide_policy(webview, policy, decision_type)
+        }
+      ));
+      imp.pdf_placeholder.set_child(Some(&view));
+      view
+    });
+    let bytes = match pdf.bytes() {
+      Ok(bytes) => bytes,
+      Err(e) => {
+        log::error!("show_pdf_body({}) => {}", pdf.filename, e);
+        return;
+      }
+    };
+    view.load_bytes(&glib::Bytes::from(bytes), Some("application/pdf"), None, None);
+    imp.stack.set_visible_child_name("pdf");
+    imp.show_text.set_visible(false);
+    self.update_view_controls(false);
+  }
+
+  /// HTML view controls, folded into the overflow menu in compact mode
+  fn update_view_controls(&self, html: bool) {
+    let imp = self.imp();
+    let compact = self.get_compact_header();
+    imp.show_images.set_visible(html);
+    imp.force_css.set_visible(html && !compact);
+    imp.reader_view.set_visible(html && !compact);
+    imp.zoom_minus.set_visible(html && !compact);
+    imp.zoom_plus.set_visible(html && !compact);
+    imp.view_menu.set_visible(html && compact);
+  }
+
+  /// Headers pinned in a pane at the left of the body, when enabled
+  fn update_split_view(&self) {
+    let imp = self.imp();
+    let enabled = self.get_split_view() && !imp.distraction_free.get();
+    log::debug!("update_split_view({})", enabled);
+    imp.headers_pane.set_visible(enabled);
+  }
+
+  fn on_split_position_changed(&self, position: i32) {
+    let Some(settings) = self.imp().settings.get() else {
+      return;
+    };
+    let saved = settings.get::<i32>(SETTINGS_SPLIT_POSITION);
+    if let Some(position) =
+      split_position_to_save(self.imp().headers_pane.is_visible(), position, saved)
+    {
+      let _ = settings.set(SETTINGS_SPLIT_POSITION, position);
+    }
+  }
+
+  fn set_headers_text(&self) {
+    let text = self
+      .imp()
+      .service
+      .headers()
+      .iter()
+      .map(|(name, value)| format!("{}: {}", name, value))
+      .collect::<Vec<String>>()
+      .join("\n");
+    self.imp().headers_text.buffer().set_text(&text);
+  }
+
+  fn build_mail_file_dialog(&self, title: &String) -> gtk4::FileDialog {
+    let filter = gtk4::FileFilter::new();
+    filter.set_name(Some(&gettext("Mail Files")));
+    for pattern in mail_file_patterns() {
+      filter.add_pattern(&pattern);
+    }
+
+    let filters = gio::ListStore::new::<gtk4::FileFilter>();
+    filters.append(&filter);
+    return gtk4::FileDialog::builder()
+      .title(title)
+      .modal(true)
+      .filters(&filters)
+      .build();
+  }
+
+  pub async fn open_file_dialog(&self, close_on_cancel: bool) -> bool {
+    log::debug!("open_file_dialog()");
+
+    let load_dialog = self.build_mail_file_dialog(&gettext("Open Mail File"));
+    match load_dialog.open_future(Some(self)).await {
+      Ok(file) => {
+        if let Some(path) = file.path() {
+          match path.to_str() {
+            Some(path) => self.open_file(path),
+            None => log::error!("open_file_dialog() => not an UTF-8 file name: {:?}", path),
+          }
+          return true;
+        }
+      }
+      Err(e) => match e.kind() {
+        Some(gtk4::DialogError::Dismissed) | Some(gtk4::DialogError::Cancelled) => {
+          if close_on_cancel {
+            self.close_or_empty();
+          }
+        }
+        _ => log::error!("open_file_dialog({})", e),
+      },
+    }
+
+    false
+  }
+
+  pub fn open_file(&self, file: &str) {
+    log::debug!("open_file({})", file);
+    glib::spawn_future_local(glib::clone!(
+      #[weak(rename_to = window)]
+      self,
+      #[strong(rename_to = filename)]
+      file.to_string(),
+      async move {
+        let opened = match window.read_message_file(&filename).await {
+          Ok(file) => window.imp().service.open_read(&filename, file),
+          Err(e) => Err(e),
+        };
+        window.imp().open_progress.set_visible(false);
+        match opened {
+          Ok(_) => {
+            window.display_message();
+          }
+          Err(e) => {
+            log::error!("service(ERR) : {}", e);
+            window.alert_error(
+              &gettext("File Error"),
+              &format!("{}:\n{}", &gettext("Failed to open file"), e),
+              true,
+            );
+          }
+        }
+      }
+    ));
+  }
+
+  /// Reads the file on another thread, large ones are reported on the
+  /// progress bar meanwhile. The parsing is left to the main thread.
+  async fn read_message_file(
+    &self,
+    filename: &str,
+  ) -> Result<MessageFile, Box<dyn std::error::Error>> {
+    let (sender, receiver) = mpsc::channel::<(u64, u64)>();
+    glib::timeout_add_local(
+      OPEN_PROGRESS_INTERVAL,
+      clone!(
+        #[weak(rename_to = window)]
+        self,
+        #[upgrade_or]
+        glib::ControlFlow::Break,
+        move || {
+          if let Some((read, total)) = receiver.try_iter().last() {
+            window.on_open_progress(read, total);
+          }
+          match receiver.try_recv() {
+            Err(mpsc::TryRecvError::Disconnected) => glib::ControlFlow::Break,
+            _ => glib::ControlFlow::Continue,
+          }
+        }
+      ),
+    );
+    let filename = filename.to_string();
+    let read = gio::spawn_blocking(move || {
+      mailservice::read_message_file(&filename, |read, total| {
+        let _ = sender.send((read, total));
+      })
+      .map_err(|e| e.to_string())
+    })
+    .await;
+    match read {
+      Ok(read) => Ok(read?),
+      Err(_) => Err("Failed to read the file".into()),
+    }
+  }
+
+  async fn paste_message(&self) {
+    log::debug!("paste_message()");
+    match self.clipboard().read_text_future().await {
+      Ok(Some(text)) if MessageParser::looks_like_message(text.as_bytes()) => {
+        match self.imp().service.open_from_reader(text.as_bytes()) {
+          Ok(_) => self.display_message(),
+          Err(e) => {
+            log::error!("open_from_reader({})", e);
+            self.alert_error(
+              &gettext("Paste Error"),
+              &format!("{}:\n{}", &gettext("Failed to parse message"), e),
+              false,
+            );
+          }
+        }
+      }
+      Ok(_) => {
+        self.alert_error(
+          &gettext("Paste Error"),
+          &gettext("The clipboard does not contain a mail message"),
+          false,
+        );
+      }
+      Err(e) => {
+        log::error!("read_text_future({})", e);
+        self.alert_error(&gettext("Paste Error"), &e.to_string(), false);
+      }
+    }
+  }
+
+  pub fn display_message(&self) {
+    log::debug!("display_eml()");
+    let imp = self.imp();
+
+    imp.from.set_text(imp.service.from().as_str());
+    self.update_sender_via();
+    imp.date.set_text(imp.service.date().as_str());
+    imp.to.set_text(imp.service.to().as_str());
+    imp.subject.set_text(imp.service.subject().as_str());
+    self.show_empty_state(imp.service.message_count() == 0);
+    self.update_auth_badges();
+    self.update_avatar();
+    self.update_bounce_banner();
+    self.update_message_index();
+
+    let html = imp.service.body_html();
+    self.set_body_text(&text_view_body(self.body_text(), html.as_deref()));
+    self.set_headers_text();
+    self.apply_sender_zoom();
+    let scroll_reset = self.get_scroll_to_top();
+    if scroll_reset {
+      imp.text_scroll.vadjustment().set_value(0.0);
+    }
+
+    imp.visited.borrow_mut().clear();
+    imp.loaded_images.borrow_mut().clear();
+    self.update_alternatives();
+    let mut sanitization = Sanitization::default();
+    if let Some(html) = self.current_html() {
+      if let Some(webview) = self.webview() {
+        let (safe, report) = self.html(&html, false).safe_report();
+        log::debug!("display_message() => {:?}", report);
+        imp.scroll_reset.new_message(scroll_reset);
+        webview.load_html(&safe, None);
+        sanitization = report;
+      }
+    }
+
+    // pasted messages can't be reloaded
+    self.action_set_enabled("win.reload", imp.service.get_fullpath().is_some());
+    let has_body = imp.service.body_html().is_some() || imp.service.body_text().is_some();
+    self.action_set_enabled("win.save-body", has_body);
+    self.action_set_enabled("win.open-body", has_body);
+    self.action_set_enabled("win.copy-markdown", imp.service.body_html().is_some());
+    self.action_set_enabled("win.save-message", imp.service.message_count() > 0);
+    self.action_set_enabled("win.forward-attachment", imp.service.message_count() > 0);
+    if self.webview().is_some() {
+      let html_allowed = self.current_html().is_some();
+      imp.show_text.set_visible(imp.service.has_both() && html_allowed);
+      let bulk_as_text =
+        self.get_text_for_bulk() && imp.service.is_bulk() && imp.service.body_text().is_some();
+      self.on_show_text(prefer_text_view(
+        imp.service.is_text_only() || !html_allowed || bulk_as_text,
+        self.get_text_when_sanitized() && imp.service.body_text().is_some(),
+        &sanitization,
+      ));
+    } else {
+      self.fallback_to_text();
+    }
+    self.show_pdf_body();
+
+    let preferences_group: adw::PreferencesGroup = adw::PreferencesGroup::new();
+    self
+      .imp()
+      .attachments_clamp
+      .set_child(Some(&preferences_group));
+
+    imp.attachment_rows.borrow_mut().clear();
+    let mut total: usize = 0;
+    imp.service.for_each_attachment(|attachment| {
+      self.add_attachment(attachment, &preferences_group);
+      total += 1;
+    });
+    preferences_group.set_header_suffix(self.attachment_filters().as_ref());
+    self.action_set_enabled("win.save-attachments-zip", total > 0);
+    if total > 0 {
+      let fmt: String = ngettext(
+        "{total} attachment",
+        "{total} attachments",
+        total.try_into().unwrap(),
+      )
+      .replace("{total}", &total.to_string());
+      log::debug!("display_message() => {}", fmt);
+      preferences_group.set_title(&fmt);
+      imp.pull_label.set_text(&fmt);
+    } else {
+      // never shown
+      imp.pull_label.set_text(&gettext("No attachments"));
+    }
+
+    if let Some(widget) = imp.sheet.bottom_bar() {
+      if total > 0 {
+        widget.set_visible(true)
+      } else {
+        widget.set_visible(false)
+      }
+    }
+
+    let pdf_shown = imp.stack.visible_child_name().as_deref() == Some("pdf");
+    let summary = attachments_only_summary(
+      &imp.service.subject(),
+      pdf_shown || self.has_visible_body(),
+      total,
+    );
+    imp.subject.set_placeholder_text(summary.as_deref());
+    if let Some(summary) = summary {
+      // nothing else to read, the attachments are the message
+      log::debug!("display_message() => {}", summary);
+      imp.sheet.set_open(true);
+      imp.attachments_clamp.child_focus(gtk4::DirectionType::TabForward);
+    }
+  }
+
+  /// Blanks the views and fields so nothing of the message is left on
+  /// screen.
+  pub fn clear_message(&self) {
+    log::debug!("clear_message()");
+    let imp = self.imp();
+    imp.service.clear();
+    self.display_message();
+    if let Some(webview) = self.webview() {
+      webview.load_html("", None);
+    }
+    imp.sheet.set_open(false);
+  }
+
+  /// Plain text body, reflowed unless disabled
+  fn body_text(&self) -> Option<String> {
+    let service = &self.imp().service;
+    match self.imp().settings.get() {
+      Some(settings) if !settings.get::<bool>(SETTINGS_REFLOW_TEXT) => service.body_text(),
+      _ => service.body_text_reflowed(),
+    }
+  }
+
+  /// Fills the text view, the quoted history of a reply goes to the
+  /// collapsed expander below it.
+  fn set_body_text(&self, text: &str) {
+    let imp = self.imp();
+    let language = imp.service.language();
+    let start = match self.get_collapse_quotes() {
+      true => quote_start(text),
+      false => None,
+    };
+    match start {
+      Some(start) => {
+        let (text, quoted) = text.split_at(start);
+        imp.body_text.buffer().set_text(text.trim_end());
+        imp.quoted_text.buffer().set_text(quoted);
+        imp.quoted_expander.set_expanded(false);
+        imp.quoted_expander.set_visible(true);
+      }
+      None => {
+        imp.body_text.buffer().set_text(text);
+        imp.quoted_text.buffer().set_text("");
+        imp.quoted_expander.set_visible(false);
+      }
+    }
+    Self::set_text_language(&imp.body_text, language.as_deref());
+    Self::set_text_language(&imp.quoted_text, language.as_deref());
+    Self::set_text_links(&imp.body_text);
+    Self::set_text_links(&imp.quoted_text);
+    self.update_text_tint();
+  }
+
+  /// "via ..." next to From when a mailing list or a delegate sent the message
+  fn update_sender_via(&self) {
+    let imp = self.imp();
+    match imp.service.sent_via() {
+      Some(sender) => {
+        imp.sender_via.set_text(&gettext("via {sender}").replace("{sender}", &sender));
+        imp.sender_via.set_visible(true);
+      }
+      None => imp.sender_via.set_visible(false),
+    }
+  }
+
+  /// Sender initials, replaced by their Libravatar image when enabled
+  fn update_avatar(&self) {
+    let imp = self.imp();
+    let from = imp.service.from();
+    imp.avatar.set_text(Some(&avatar::display_name(&from)));
+    imp.avatar.set_custom_image(None::<&gtk4::gdk::Paintable>);
+    imp.avatar.set_visible(!from.trim().is_empty());
+    let Some(email) = avatar::email_address(&from) else {
+      return;
+    };
+    if !self.get_load_avatars() {
+      return;
+    }
+    let url = avatar::url(&email, (imp.avatar.size() * self.scale_factor()) as u32);
+    log::debug!("update_avatar({})", url);
+    glib::spawn_future_local(clone!(
+      #[weak(rename_to = win)]
+      self,
+      async move {
+        match gio::File::for_uri(&url).load_bytes_future().await {
+          // another message may be displayed by now
+          Ok(_) if win.imp().service.from() != from => (),
+          Ok((bytes, _)) => match gtk4::gdk::Texture::from_bytes(&bytes) {
+            Ok(texture) => win.imp().avatar.set_custom_image(Some(&texture)),
+            Err(e) => log::debug!("update_avatar({}): {}", url, e),
+          },
+          // no avatar (404) or offline, the initials stay
+          Err(e) => log::debug!("update_avatar({}): {}", url, e),
+        }
+      }
+    ));
+  }
+
+  fn update_bounce_banner(&self) {
+    let imp = self.imp();
+    match bounce_text(&imp.service.delivery_status()) {
+      Some(text) => {
+        imp.bounce_banner.set_title(&text);
+        imp.bounce_banner.set_revealed(true);
+      }
+      None => imp.bounce_banner.set_revealed(false),
+    }
+  }
+
+  /// Message selector, only shown for mailboxes
+  fn update_message_index(&self) {
+    let imp = self.imp();
+    let count = imp.service.message_count();
+    // all at once, value-changed is only emitted for the final value
+    imp.message_index.adjustment().configure(
+      (imp.service.selected_message() + 1) as f64,
+      1.0,
+      count.max(1) as f64,
+      1.0,
+      10.0,
+      0.0,
+    );
+    imp.message_index.set_visible(count > 1);
+  }
+
+  /// Gives the text views the background color of the HTML body
+  fn update_text_tint(&self) {
+    let imp = self.imp();
+    let background = match self.get_tint_text_view() {
+      true => self
+        .current_html()
+        .and_then(|html| Html::new(&html, false).background_color()),
+      false => None,
+    };
+    let class = imp.tint_class.as_str();
+    match background {
+      Some(background) => {
+        let foreground = match Html::foreground_for(&background) {
+          Some(foreground) => format!("color: {foreground};"),
+          None => String::new(),
+        };
+        imp.tint_css.load_from_data(&format!(
+          "textview.{class}, textview.{class} text {{ background-color: {background}; {foreground} }}"
+        ));
+        imp.body_text.add_css_class(class);
+        imp.quoted_text.add_css_class(class);
+      }
+      None => {
+        imp.body_text.remove_css_class(class);
+        imp.quoted_text.remove_css_class(class);
+      }
+    }
+  }
+
+  /// Language hint (hyphenation...) from Content-Language
+  fn set_text_language(view: &gtk4::TextView, language: Option<&str>) {
+    let buffer = view.buffer();
+    // set_text() already dropped the previous tags
+    let Some(language) = language else {
+      return;
+    };
+    let tag = match buffer.tag_table().lookup("language") {
+      Some(tag) => tag,
+      None => {
+        let tag = gtk4::TextTag::new(Some("language"));
+        buffer.tag_table().add(&tag);
+        tag
+      }
+    };
+    tag.set_language(Some(language));
+    let (start, end) = buffer.bounds();
+    buffer.apply_tag(&tag, &start, &end);
+  }
+
+  /// Underlines the detected phone numbers and addresses
+  /// Underlines the links of the text just set, once: each is tagged with
+  /// its URI so that link_at() doesn't scan the text again.
+  fn set_text_links(view: &gtk4::TextView) {
+    let buffer = view.buffer();
+    let table = buffer.tag_table();
+    // those of the previous text
+    let mut previous: Vec<gtk4::TextTag> = vec![];
+    table.foreach(|tag| {
+      if tag.name().is_some_and(|name| name.starts_with(LINK_TAG_PREFIX)) {
+        previous.push(tag.clone());
+      }
+    });
+    for tag in previous {
+      table.remove(&tag);
+    }
+    let (start, end) = buffer.bounds();
+    let text = buffer.text(&start, &end, false);
+    for link in find_links(&text) {
+      let name = format!("{}{}", LINK_TAG_PREFIX, link.uri());
+      let tag = match table.lookup(&name) {
+        Some(tag) => tag,
+        None => {
+          let tag = gtk4::TextTag::new(Some(&name));
+          tag.set_underline(gtk4::pango::Underline::Single);
+          table.add(&tag);
+          tag
+        }
+      };
+      let start = buffer.iter_at_offset(text[..link.range.start].chars().count() as i32);
+      let end = buffer.iter_at_offset(text[..link.range.end].chars().count() as i32);
+      buffer.apply_tag(&tag, &start, &end);
+    }
+  }
+
+  fn get_link_action(&self) -> LinkAction {
+    match self.imp().settings.get() {
+      Some(settings) => LinkAction::from_setting(&settings.get::<String>(SETTINGS_LINK_ACTION)),
+      None => LinkAction::Open,
+    }
+  }
+
+  fn get_line_ending(&self) -> LineEnding {
+    match self.imp().settings.get() {
+      Some(settings) => LineEnding::from_setting(&settings.get::<String>(SETTINGS_LINE_ENDING)),
+      None => LineEnding::Lf,
+    }
+  }
+
+  fn copy_text_selection(&self, view: &gtk4::TextView) {
+    let buffer = view.buffer();
+    if let Some((start, end)) = buffer.selection_bounds() {
+      let text = buffer.text(&start, &end, false);
+      view
+        .clipboard()
+        .set_text(&normalize_line_endings(&text, self.get_line_ending()));
+      self.show_toast(&gettext("Copied to clipboard"));
+    }
+  }
+
+  fn copy_image(&self, bytes: &[u8]) {
+    match gtk4::gdk::Texture::from_bytes(&glib::Bytes::from(bytes)) {
+      Ok(texture) => {
+        self.clipboard().set_texture(&texture);
+        self.show_toast(&gettext("Image copied to clipboard"));
+      }
+      Err(e) => {
+        log::error!("copy_image({})", e);
+        self.show_toast(&gettext("Unable to copy the image"));
+      }
+    }
+  }
+
+  /// Saves the displayed message as .eml, e.g. one message of a mailbox or a
+  /// pasted one.
+  async fn save_message(&self) {
+    log::debug!("save_message()");
+    let imp = self.imp();
+    let Some(eml) = imp.service.to_eml() else {
+      return;
+    };
+    let initial_name = format!("{}.eml", sanitize_filename(&imp.service.subject()));
+    self
+      .save_eml(eml, &initial_name, &gettext("Save Message As..."))
+      .await;
+  }
+
+  /// Saves a new message with the displayed one attached, unchanged, to be
+  /// opened and sent from the mail client.
+  async fn save_forward(&self) {
+    log::debug!("save_forward()");
+    let imp = self.imp();
+    let Some(eml) = imp.service.forward_eml() else {
+      return;
+    };
+    let initial_name = format!("Fwd {}.eml", sanitize_filename(&imp.service.subject()));
+    self
+      .save_eml(eml, &initial_name, &gettext("Forward as Attachment..."))
+      .await;
+  }
+
+  async fn save_eml(&self, eml: std::io::Result<Vec<u8>>, initial_name: &str, title: &str) {
+    let eml = match eml {
+      Ok(eml) => eml,
+      Err(e) => {
+        log::error!("save_eml({})", e);
+        self.alert_error(&gettext("File Error"), &e.to_string(), false);
+        return;
+      }
+    };
+    let save_dialog = gtk4::FileDialog::builder()
+      .title(title)
+      .modal(true)
+      .initial_name(initial_name)
+      .build();
+    match save_dialog.save_future(Some(self)).await {
+      Ok(file) => {
+        if let Some(path) = file.peek_path() {
+          log::debug!("Saving message to {:?}", path);
+          match std::fs::write(&path, eml) {
+            Ok(_) => self.show_toast(&gettext("Message saved")),
+            Err(e) => {
+              log::error!("save_message({})", e);
+              self.alert_error(&gettext("File Error"), &e.to_string(), false);
+            }
+          }
+        }
+      }
+      Err(e) => match e.kind() {
+        Some(gtk4::DialogError::Dismissed) | Some(gtk4::DialogError::Cancelled) => (),
+        _ => log::error!("save_dialog({})", e),
+      },
+    }
+  }
+
+  async fn save_attachments_zip(&self) {
+    log::debug!("save_attachments_zip()");
+    let imp = self.imp();
+    let initial_name = format!("{}.zip", sanitize_filename(&imp.service.subject()));
+    let save_dialog = gtk4::FileDialog::builder()
+      .title(&gettext("Save Attachments as Zip..."))
+      .modal(true)
+      .initial_name(initial_name.as_str())
+      .build();
+    match save_dialog.save_future(Some(self)).await {
+      Ok(file) => {
+        if let Some(path) = file.peek_path() {
+          log::debug!("Saving attachments to {:?}", path);
+          let written = std::fs::File::create(&path).and_then(|file| {
+            imp
+              .service
+              .write_attachments_zip(std::io::BufWriter::new(file))
+          });
+          match written {
+            Ok(_) => self.show_toast(&gettext("Attachments saved")),
+            Err(e) => {
+              log::error!("save_attachments_zip({})", e);
+              self.alert_error(&gettext("File Error"), &e.to_string(), false);
+            }
+          }
+        }
+      }
+      Err(e) => match e.kind() {
+        Some(gtk4::DialogError::Dismissed) | Some(gtk4::DialogError::Cancelled) => (),
+        _ => log::error!("save_dialog({})", e),
+      },
+    }
+  }
+
+  fn copy_markdown(&self) {
+    log::debug!("copy_markdown()");
+    if let Some(html) = self.current_html() {
+      let markdown = Html::new(&html, false).to_markdown();
+      self
+        .clipboard()
+        .set_text(&normalize_line_endings(&markdown, self.get_line_ending()));
+      self.show_toast(&gettext("Copied as Markdown"));
+    }
+  }
+
+  async fn save_body(&self) {
+    log::debug!("save_body()");
+    let imp = self.imp();
+    let html = self.current_html();
+    let text = imp.service.body_text();
+    let extension = if html.is_some() { "html" } else { "txt" };
+    let initial_name = imp
+      .service
+      .get_fullpath()
+      .and_then(|path| {
+        std::path::Path::new(&path)
+          .file_stem()
+          .map(|stem| format!("{}.{}", stem.to_string_lossy(), extension))
+      })
+      .unwrap_or_else(|| format!("message.{}", extension));
+
+    let html_filter = gtk4::FileFilter::new();
+    html_filter.set_name(Some(&gettext("HTML Files")));
+    html_filter.add_pattern("*.html");
+    html_filter.add_pattern("*.htm");
+    let text_filter = gtk4::FileFilter::new();
+    text_filter.set_name(Some(&gettext("Text Files")));
+    text_filter.add_pattern("*.txt");
+    let filters = gio::ListStore::new::<gtk4::FileFilter>();
+    filters.append(&html_filter);
+    filters.append(&text_filter);
+
+    let save_dialog = gtk4::FileDialog::builder()
+      .title(&gettext("Save Body As..."))
+      .modal(true)
+      .filters(&filters)
+      .initial_name(initial_name.as_str())
+      .build();
+    match save_dialog.save_future(Some(self)).await {
+      Ok(file) => {
+        if let Some(path) = file.peek_path() {
+          log::debug!("Saving body to {:?}", path);
+          let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_string())
+            .unwrap_or_default();
+          let content = body_content(&extension, html.as_deref(), text.as_deref())
+            .unwrap_or_default();
+          let content = normalize_line_endings(&content, self.get_line_ending());
+          match std::fs::write(&path, content) {
+            Ok(_) => self.show_toast(&gettext("Body saved")),
+            Err(e) => {
+              log::error!("save_body({})", e);
+              self.alert_error(&gettext("File Error"), &e.to_string(), false);
+            }
+          }
+        }
+      }
+      Err(e) => match e.kind() {
+        Some(gtk4::DialogError::Dismissed) | Some(gtk4::DialogError::Cancelled) => return,
+        _ => log::error!("save_dialog({})", e),
+      },
+    }
+  }
+
+  fn update_auth_badges(&self) {
+    let imp = self.imp();
+    while let Some(child) = imp.auth_badges.first_child() {
+      imp.auth_badges.remove(&child);
+    }
+
+    let results = imp.service.auth_results();
+    let servers = results
+      .iter()
+      .map(|r| r.authserv_id.as_str())
+      .collect::<Vec<&str>>()
+      .join(", ");
+    for method in ["spf", "dkim", "dmarc"] {
+      if let Some(result) = combined_result(&results, method) {
+        let badge = gtk4::Label::new(Some(&format!("{} {}", method.to_uppercase(), result)));
+        badge.add_css_class("auth-badge");
+        badge.add_css_class(match result {
+          "pass" => "success",
+          "none" | "neutral" => "dim-label",
+          _ => "error",
+        });
+        badge.set_tooltip_text(Some(&servers));
+        imp.auth_badges.append(&badge);
+      }
+    }
+    imp.auth_badges.set_visible(imp.auth_badges.first_child().is_some());
+  }
+
+  /// One line gist of the message above the headers, when enabled
+  fn update_summary_bar(&self) {
+    let imp = self.imp();
+    let enabled = match imp.settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_SUMMARY_BAR),
+      None => false,
+    };
+    if !enabled || imp.service.message_count() == 0 {
+      imp.summary_bar.set_visible(false);
+      return;
+    }
+    let results = imp.service.auth_results();
+    let badges: Vec<String> = ["spf", "dkim", "dmarc"]
+      .iter()
+      .filter_map(|method| {
+        let result = combined_result(&results, method)?;
+        Some(format!("{} {}", method.to_uppercase(), result))
+      })
+      .collect();
+    let mut attachments = 0;
+    imp.service.for_each_attachment(|_| attachments += 1);
+    let summary = summary_line(&imp.service.from(), &imp.service.date(), attachments, &badges);
+    imp.summary_bar.set_text(&summary);
+    imp.summary_bar.set_tooltip_text(Some(&summary));
+    imp.summary_bar.set_visible(true);
+  }
+
+  /// Non blocking feedback, alert_error() is kept for real errors.
+  pub fn show_toast(&self, message: &str) {
+    self.imp().toast_overlay.add_toast(adw::Toast::new(message));
+  }
+
+  pub fn alert_error(&self, title: &str, message: &str, close_window: bool) -> adw::AlertDialog {
+    let alert = adw::AlertDialog::new(Some(title), Some(message));
+    alert.add_response("close", &gettext("Close"));
+    alert.set_response_appearance("close", adw::ResponseAppearance::Destructive);
+    alert.present(Some(self));
+    if close_window {
+      alert.connect_response(
+        Some("close"),
+        clone!(
+          #[strong(rename_to = window)]
+          self,
+          move |_, _| {
+            window.close_or_empty();
+          }
+        ),
+      );
+    }
+    alert
+  }
+
+  /// After a file failed to open (or none was chosen): closes the window, or
+  /// keeps it open on the empty state, waiting for a file.
+  fn close_or_empty(&self) {
+    let has_message = self.imp().service.message_count() > 0;
+    match should_close_window(self.get_close_on_error(), has_message) {
+      true => {
+        self.close();
+      }
+      false => self.show_empty_state(!has_message),
+    }
+  }
+
+  /// "No Message" page in place of the headers and the body
+  fn show_empty_state(&self, empty: bool) {
+    let imp = self.imp();
+    let mut child = imp.vertical_box.first_child();
+    while let Some(widget) = child {
+      let is_empty_state = widget == *imp.empty_state.upcast_ref::<gtk4::Widget>();
+      widget.set_visible(is_empty_state == empty);
+      child = widget.next_sibling();
+    }
+    // the rows with a visibility of their own
+    self.update_summary_bar();
+  }
+
+  fn get_text_when_sanitized(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_TEXT_WHEN_SANITIZED),
+      None => true,
+    }
+  }
+
+  fn get_text_for_bulk(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_TEXT_FOR_BULK),
+      None => false,
+    }
+  }
+
+  fn get_scroll_to_top(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_SCROLL_TO_TOP),
+      None => true,
+    }
+  }
+
+  fn get_media_preview(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_MEDIA_PREVIEW),
+      None => true,
+    }
+  }
+
+  fn get_type_icons(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_TYPE_ICONS),
+      None => true,
+    }
+  }
+
+  fn get_pdf_as_body(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_PDF_AS_BODY),
+      None => false,
+    }
+  }
+
+  fn get_close_on_error(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_CLOSE_ON_ERROR),
+      None => true,
+    }
+  }
+
+  fn get_collapse_quotes(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_COLLAPSE_QUOTES),
+      None => true,
+    }
+  }
+
+  fn get_image_proxy(&self) -> Option<String> {
+    let settings = self.imp().settings.get()?;
+    let proxy = settings.get::<String>(SETTINGS_IMAGE_PROXY);
+    match proxy.trim().is_empty() {
+      true => None,
+      false => Some(proxy),
+    }
+  }
+
+  /// Content of the custom force CSS stylesheet, read on each load so edits
+  /// apply on reload. None (the built-in one) when unset or unreadable.
+  fn get_custom_css(&self) -> Option<String> {
+    let settings = self.imp().settings.get()?;
+    let path = settings.get::<String>(SETTINGS_CUSTOM_CSS);
+    if path.trim().is_empty() {
+      return None;
+    }
+    match std::fs::read_to_string(path.trim()) {
+      Ok(css) => Some(css),
+      Err(e) => {
+        log::warn!("get_custom_css({}): {}", path, e);
+        None
+      }
+    }
+  }
+
+  fn get_image_placeholders(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_IMAGE_PLACEHOLDERS),
+      None => false,
+    }
+  }
+
+  fn get_strip_comments(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_STRIP_COMMENTS),
+      None => false,
+    }
+  }
+
+  /// Characters per line of the body, 0 for the full width
+  fn get_max_width(&self) -> u32 {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<u32>(SETTINGS_MAX_WIDTH),
+      None => 0,
+    }
+  }
+
+  /// Minutes without interaction before the message is cleared, 0 to never
+  fn get_idle_timeout(&self) -> u32 {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<u32>(SETTINGS_IDLE_TIMEOUT),
+      None => 0,
+    }
+  }
+
+  fn get_split_view(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_SPLIT_VIEW),
+      None => false,
+    }
+  }
+
+  fn get_compact_header(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_COMPACT_HEADER),
+      None => false,
+    }
+  }
+
+  fn get_load_avatars(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_LOAD_AVATARS),
+      None => false,
+    }
+  }
+
+  fn get_tint_text_view(&self) -> bool {
+    match self.imp().settings.get() {
+      Some(settings) => settings.get::<bool>(SETTINGS_TINT_TEXT_VIEW),
+      None => false,
+    }
+  }
+
+  fn get_show_file_name(&self) -> bool {
+    if let Some(settings) = self.imp().settings.get() {
+      settings.get::<bool>(SETTINGS_SHOW_FILE_NAME)
+    } else {
+      false
+    }
+  }
+
+  fn show_preferences(&self) {
+    log::debug!("show_preferences()");
+    match self.imp().settings.get() {
+      Some(settings) => {
+        let builder = gtk4::Builder::from_resource("/io/github/alescdb/mailviewer/preferences.ui");
+        let show_file_name: adw::SwitchRow = builder.object("show_file_name").unwrap();
+        settings
+          .bind(SETTINGS_SHOW_FILE_NAME, &show_file_name, "active")
+          .build();
+        let collapse_quotes: adw::SwitchRow = builder.object("collapse_quotes").unwrap();
+        settings
+          .bind(SETTINGS_COLLAPSE_QUOTES, &collapse_quotes, "active")
+          .build();
+        let strip_comments: adw::SwitchRow = builder.object("strip_comments").unwrap();
+        settings
+          .bind(SETTINGS_STRIP_COMMENTS, &strip_comments, "active")
+          .build();
+        let tint_text_view: adw::SwitchRow = builder.object("tint_text_view").unwrap();
+        settings
+          .bind(SETTINGS_TINT_TEXT_VIEW, &tint_text_view, "active")
+          .build();
+        let close_on_error: adw::SwitchRow = builder.object("close_on_error").unwrap();
+        settings
+          .bind(SETTINGS_CLOSE_ON_ERROR, &close_on_error, "active")
+          .build();
+        let load_avatars: adw::SwitchRow = builder.object("load_avatars").unwrap();
+        settings
+          .bind(SETTINGS_LOAD_AVATARS, &load_avatars, "active")
+          .build();
+        let line_ending: adw::ComboRow = builder.object("line_ending").unwrap();
+        settings
+          .bind(SETTINGS_LINE_ENDING, &line_ending, "selected")
+          .mapping(|variant, _| {
+            let ending = LineEnding::from_setting(&variant.get::<String>()?);
+            Some((LineEnding::ALL.iter().position(|e| *e == ending)? as u32).to_value())
+          })
+          .set_mapping(|value, _| {
+            let ending = LineEnding::ALL.get(value.get::<u32>().ok()? as usize)?;
+            Some(ending.as_setting().to_variant())
+          })
+          .build();
+        let link_action: adw::ComboRow = builder.object("link_action").unwrap();
+        settings
+          .bind(SETTINGS_LINK_ACTION, &link_action, "selected")
+          .mapping(|variant, _| {
+            let action = LinkAction::from_setting(&variant.get::<String>()?);
+            Some((LinkAction::ALL.iter().position(|a| *a == action)? as u32).to_value())
+          })
+          .set_mapping(|value, _| {
+            let action = LinkAction::ALL.get(value.get::<u32>().ok()? as usize)?;
+            Some(action.as_setting().to_variant())
+          })
+          .build();
+        let idle_timeout: adw::SpinRow = builder.object("idle_timeout").unwrap();
+        settings
+          .bind(SETTINGS_IDLE_TIMEOUT, &idle_timeout, "value")
+          .build();
+        let max_width: adw::SpinRow = builder.object("max_width").unwrap();
+        settings
+          .bind(SETTINGS_MAX_WIDTH, &max_width, "value")
+          .build();
+        let image_placeholders: adw::SwitchRow = builder.object("image_placeholders").unwrap();
+        settings
+          .bind(SETTINGS_IMAGE_PLACEHOLDERS, &image_placeholders, "active")
+          .build();
+        let text_when_sanitized: adw::SwitchRow = builder.object("text_when_sanitized").unwrap();
+        settings
+          .bind(SETTINGS_TEXT_WHEN_SANITIZED, &text_when_sanitized, "active")
+          .build();
+        let scroll_to_top: adw::SwitchRow = builder.object("scroll_to_top").unwrap();
+        settings
+          .bind(SETTINGS_SCROLL_TO_TOP, &scroll_to_top, "active")
+          .build();
+        let media_preview: adw::SwitchRow = builder.object("media_preview").unwrap();
+        settings
+          .bind(SETTINGS_MEDIA_PREVIEW, &media_preview, "active")
+          .build();
+        let type_icons: adw::SwitchRow = builder.object("type_icons").unwrap();
+        settings
+          .bind(SETTINGS_TYPE_ICONS, &type_icons, "active")
+          .build();
+        let text_for_bulk: adw::SwitchRow = builder.object("text_for_bulk").unwrap();
+        settings
+          .bind(SETTINGS_TEXT_FOR_BULK, &text_for_bulk, "active")
+          .build();
+        let pdf_as_body: adw::SwitchRow = builder.object("pdf_as_body").unwrap();
+        settings
+          .bind(SETTINGS_PDF_AS_BODY, &pdf_as_body, "active")
+          .build();
+        let zoom_per_sender: adw::SwitchRow = builder.object("zoom_per_sender").unwrap();
+        settings
+          .bind(SETTINGS_ZOOM_PER_SENDER, &zoom_per_sender, "active")
+          .build();
+        let html_types: adw::EntryRow = builder.object("html_types").unwrap();
+        settings
+          .bind(SETTINGS_HTML_TYPES, &html_types, "text")
+          .build();
+        let image_proxy: adw::EntryRow = builder.object("image_proxy").unwrap();
+        settings
+          .bind(SETTINGS_IMAGE_PROXY, &image_proxy, "text")
+          .build();
+        let custom_css: adw::EntryRow = builder.object("custom_css").unwrap();
+        settings
+          .bind(SETTINGS_CUSTOM_CSS, &custom_css, "text")
+          .build();
+        let search_url: adw::EntryRow = builder.object("search_url").unwrap();
+        settings
+          .bind(SETTINGS_SEARCH_URL, &search_url, "text")
+          .build();
+
+        let prefs: adw::PreferencesDialog = builder.object("preferences").unwrap();
+        prefs.present(Some(self));
+        prefs.connect_closed(clone!(
+          #[weak(rename_to = win)]
+          self,
+          move |_| {
+            log::debug!("show_preferences() => done");
+            win
+              .imp()
+              .service
+              .set_show_file_name(win.get_show_file_name());
+            win.update_text_tint();
+            win.update_avatar();
+            win.update_auto_load_images();
+            // custom CSS, image placeholders
+            win.load_html(win.imp().force_css.is_active());
+          }
+        ));
+      }
+      None => {
+        self.alert_error(
+          &gettext("Settings"),
+          &gettext("Failed to get settings"),
+          false,
         );
       }
     }
   }
 }
+
+/// Builds the web search URL, replacing `%s` in the template with the
+/// (whitespace collapsed) escaped selection.
+fn search_url(template: &str, selection: &str) -> String {
+  let query = selection.split_whitespace().collect::<Vec<&str>>().join(" ");
+  let query = glib::Uri::escape_string(&query, None, false);
+  if template.contains("%s") {
+    template.replace("%s", &query)
+  } else {
+    format!("{}{}", template, query)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LineEnding {
+  Lf,
+  CrLf,
+}
+
+impl LineEnding {
+  /// in the order of the preferences combo
+  const ALL: [LineEnding; 2] = [LineEnding::Lf, LineEnding::CrLf];
+
+  fn from_setting(value: &str) -> Self {
+    match value {
+      "crlf" => LineEnding::CrLf,
+      _ => LineEnding::Lf,
+    }
+  }
+
+  fn as_setting(&self) -> &'static str {
+    match self {
+      LineEnding::Lf => "lf",
+      LineEnding::CrLf => "crlf",
+    }
+  }
+}
+
+/// What a click on a link does
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LinkAction {
+  Open,
+  Copy,
+  Ask,
+}
+
+impl LinkAction {
+  /// in the order of the preferences combo
+  const ALL: [LinkAction; 3] = [LinkAction::Open, LinkAction::Copy, LinkAction::Ask];
+
+  fn from_setting(value: &str) -> Self {
+    match value {
+      "copy" => LinkAction::Copy,
+      "ask" => LinkAction::Ask,
+      _ => LinkAction::Open,
+    }
+  }
+
+  fn as_setting(&self) -> &'static str {
+    match self {
+      LinkAction::Open => "open",
+      LinkAction::Copy => "copy",
+      LinkAction::Ask => "ask",
+    }
+  }
+}
+
+fn normalize_line_endings(text: &str, ending: LineEnding) -> String {
+  let text = normalize_newlines(text);
+  match ending {
+    LineEnding::Lf => text,
+    LineEnding::CrLf => text.replace('\n', "\r\n"),
+  }
+}
+
+/// What "Save Body As" writes: sanitized HTML for .html/.htm, plain text
+/// (extracted from the HTML when needed) otherwise.
+fn body_content(extension: &str, html: Option<&str>, text: Option<&str>) -> Option<String> {
+  match extension.to_lowercase().as_str() {
+    "html" | "htm" => match (html, text) {
+      (Some(html), _) => Some(Html::new(html, false).safe()),
+      (None, Some(text)) => Some(format!("<pre>{}</pre>", glib::markup_escape_text(text))),
+      (None, None) => None,
+    },
+    _ => match (text, html) {
+      (Some(text), _) => Some(text.to_string()),
+      (None, Some(html)) => Some(Html::new(html, false).to_text()),
+      (None, None) => None,
+    },
+  }
+}
+
+fn webkit_available() -> bool {
+  std::env::var_os(ENV_DISABLE_WEBKIT).is_none()
+}
+
+/// Text view first for text-only messages, and for those whose HTML was
+/// mostly removed by the sanitizer when `text_fallback` (the option is on
+/// and there is a text part).
+fn prefer_text_view(text_only: bool, text_fallback: bool, sanitization: &Sanitization) -> bool {
+  text_only || (text_fallback && sanitization.is_heavy())
+}
+
+/// Whether the body is shown in the text view rather than the HTML one.
+fn show_text_view(webkit_available: bool, show_text: bool) -> bool {
+  show_text || !webkit_available
+}
+
+/// Content of the text view: the text part, or the HTML one as text when
+/// there is none, for when text/html is excluded or WebKit is missing.
+fn text_view_body(text: Option<String>, html: Option<&str>) -> String {
+  text.or_else(|| html.map(|html| Html::new(html, false).to_text())).unwrap_or_default()
+}
+
+/// Below that size files are read too fast for a progress bar
+const OPEN_PROGRESS_MIN: u64 = 16 * 1024 * 1024;
+
+/// Delay between two updates of the progress bar while a file is read
+const OPEN_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// The progress bar is shown while a large file is being read
+fn show_open_progress(read: u64, total: u64) -> bool {
+  total >= OPEN_PROGRESS_MIN && read < total
+}
+
+/// Only the built-in forced stylesheet depends on the style, messages with
+/// their own CSS or a custom stylesheet look the same either way.
+fn restyle_on_dark_change(force_css: bool, custom_css: bool, has_html: bool) -> bool {
+  force_css && !custom_css && has_html
+}
+
+/// Side margins of the text view centering `chars` characters of
+/// `char_width` pixels, none when they fit or for the full width (0).
+fn text_margin(available: i32, chars: u32, char_width: i32) -> i32 {
+  match chars {
+    0 => 0,
+    chars => ((available - chars as i32 * char_width) / 2).max(0),
+  }
+}
+
+/// Bytes of a part shown by "View Raw", large attachments are cut
+const RAW_PART_LIMIT: usize = 256 * 1024;
+
+/// The headers pane is never narrower than this
+const MIN_SPLIT_POSITION: i32 = 200;
+
+fn restored_split_position(saved: i32) -> i32 {
+  saved.max(MIN_SPLIT_POSITION)
+}
+
+/// Divider position worth saving: only moves of the visible pane (a hidden
+/// one collapses it), not below the minimum, and only when it changed.
+fn split_position_to_save(visible: bool, position: i32, saved: i32) -> Option<i32> {
+  (visible && position >= MIN_SPLIT_POSITION && position != saved).then_some(position)
+}
+
+/// Zoom remembered for a sender domain
+fn sender_zoom(zooms: &HashMap<String, f64>, domain: &str) -> Option<f64> {
+  zooms.get(&domain.to_lowercase()).copied()
+}
+
+/// Remembers the zoom of a sender domain; going back to the global zoom
+/// forgets it, so the setting only holds the senders that differ.
+fn record_sender_zoom(zooms: &mut HashMap<String, f64>, domain: &str, zoom: f64, default: f64) {
+  let domain = domain.to_lowercase();
+  match (zoom - default).abs() < 0.01 {
+    true => zooms.remove(&domain),
+    false => zooms.insert(domain, zoom),
+  };
+}
+
+/// How "Copy Image" gets the bytes of an image of the HTML view
+#[derive(Debug, PartialEq)]
+enum ImageCopy {
+  /// decoded from its data: URI (inline images), even offline
+  Data(Vec<u8>),
+  /// a remote image, copied by WebKit once loaded
+  Loaded,
+  /// a remote image that isn't loaded
+  Unavailable,
+}
+
+fn image_copy(uri: &str, remote_loaded: bool) -> ImageCopy {
+  let Some(data) = uri.strip_prefix("data:") else {
+    return match remote_loaded {
+      true => ImageCopy::Loaded,
+      false => ImageCopy::Unavailable,
+    };
+  };
+  let Some((meta, content)) = data.split_once(',') else {
+    return ImageCopy::Unavailable;
+  };
+  let bytes = match meta.to_lowercase().ends_with(";base64") {
+    true => {
+      let content: String = content.chars().filter(|c| !c.is_whitespace()).collect();
+      general_purpose::STANDARD.decode(content).ok()
+    }
+    false => glib::Uri::unescape_bytes(content, None).ok().map(|b| b.to_vec()),
+  };
+  match bytes {
+    Some(bytes) if !bytes.is_empty() => ImageCopy::Data(bytes),
+    _ => ImageCopy::Unavailable,
+  }
+}
+
+/// Only the last focused (`active`) of the application's `windows` persists
+/// its geometry; the last window to close always does.
+fn should_save_geometry<W: PartialEq>(window: &W, active: Option<&W>, windows: usize) -> bool {
+  active.map_or(true, |active| active == window) || windows <= 1
+}
+
+fn mail_file_patterns() -> Vec<String> {
+  MAIL_FILE_EXTENSIONS.iter().map(|extension| format!("*.{}", extension)).collect()
+}
+
+fn is_mail_file(path: &str) -> bool {
+  std::path::Path::new(path)
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .is_some_and(|extension| {
+      MAIL_FILE_EXTENSIONS.iter().any(|e| e.eq_ignore_ascii_case(extension))
+    })
+}
+
+/// Never closes a window still showing a message, whatever the setting.
+fn should_close_window(close_on_error: bool, has_message: bool) -> bool {
+  close_on_error && !has_message
+}
+
+/// The attachment shown as the body: the only one of a message without
+/// text, if it's a PDF and the option is on.
+fn pdf_as_body(enabled: bool, has_body: bool, attachments: &[Attachment]) -> Option<&Attachment> {
+  match attachments {
+    [attachment] if enabled && !has_body && attachment.is_pdf() => Some(attachment),
+    _ => None,
+  }
+}
+
+/// Banner summarizing a bounce, None unless delivery to a recipient failed.
+fn bounce_text(status: &[DeliveryStatus]) -> Option<String> {
+  let failures: Vec<&DeliveryStatus> = status.iter().filter(|s| s.is_failure()).collect();
+  let first = failures.first()?;
+  let mut text = match first.reason() {
+    Some(reason) => gettext("Message to {recipient} bounced: {reason}")
+      .replace("{reason}", reason)
+      .replace("{recipient}", &first.recipient),
+    None => gettext("Message to {recipient} bounced").replace("{recipient}", &first.recipient),
+  };
+  let others = failures.len() - 1;
+  if others > 0 {
+    text.push_str(
+      &ngettext(
+        " (and {count} other recipient)",
+        " (and {count} other recipients)",
+        others as u32,
+      )
+      .replace("{count}", &others.to_string()),
+    );
+  }
+  Some(text)
+}
+
+/// "(no subject) — 1 attachment" for a message made only of attachments,
+/// None when it has a subject or a body to show.
+fn attachments_only_summary(subject: &str, has_body: bool, attachments: usize) -> Option<String> {
+  if !subject.trim().is_empty() || has_body || attachments == 0 {
+    return None;
+  }
+  let count = ngettext("{total} attachment", "{total} attachments", attachments as u32)
+    .replace("{total}", &attachments.to_string());
+  Some(format!("{} — {}", gettext("(no subject)"), count))
+}
+
+/// "John Doe • 2024-10-23 12:27:21 • 1 attachment • SPF pass DKIM pass",
+/// the empty fields left out.
+fn summary_line(from: &str, date: &str, attachments: usize, badges: &[String]) -> String {
+  let mut fields: Vec<String> = vec![];
+  if !from.trim().is_empty() {
+    fields.push(avatar::display_name(from));
+  }
+  if !date.trim().is_empty() {
+    fields.push(date.trim().to_string());
+  }
+  if attachments > 0 {
+    fields.push(
+      ngettext("{total} attachment", "{total} attachments", attachments as u32)
+        .replace("{total}", &attachments.to_string()),
+    );
+  }
+  if !badges.is_empty() {
+    fields.push(badges.join(" "));
+  }
+  fields.join(" \u{2022} ")
+}
+
+/// Whether the next load of the HTML view scrolls back to the top: for
+/// another message unless the option is off, never when the same one is
+/// rendered again (loaded images, forced CSS).
+#[derive(Debug, Default)]
+pub struct ScrollReset(Cell<bool>);
+
+impl ScrollReset {
+  fn new_message(&self, enabled: bool) {
+    self.0.set(enabled);
+  }
+
+  fn render_again(&self) {
+    self.0.set(false);
+  }
+
+  /// A load finished, true for the first one after new_message() only
+  fn finished(&self) -> bool {
+    self.0.take()
+  }
+}
+
+/// Larger audio and video attachments are only opened externally
+const MEDIA_PREVIEW_LIMIT: usize = 50 * 1024 * 1024;
+
+/// Audio and video attachments get a Play button, the others are opened
+fn is_media_previewable(attachment: &Attachment) -> bool {
+  let mime_type = attachment.mime_type.as_deref().unwrap_or_default().to_lowercase();
+  sniff::is_media(&mime_type)
+    && attachment.size() <= MEDIA_PREVIEW_LIMIT
+    && sniff::sniff(&attachment.head(16)).is_some_and(sniff::is_media)
+}
+
+/// Icon theme name of an attachment of type `mime_type`
+fn attachment_icon(mime_type: &str) -> &'static str {
+  let mime_type = mime_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+  let (kind, subtype) = mime_type.split_once('/').unwrap_or((mime_type.as_str(), ""));
+  match (kind, subtype) {
+    ("image", _) => "image-x-generic-symbolic",
+    ("audio", _) => "audio-x-generic-symbolic",
+    ("video", _) => "video-x-generic-symbolic",
+    ("text", "calendar") => "x-office-calendar-symbolic",
+    ("text", "vcard" | "x-vcard" | "directory") => "x-office-address-book-symbolic",
+    ("text", "csv") => "x-office-spreadsheet-symbolic",
+    ("message", _) => "mail-unread-symbolic",
+    ("application", "pdf" | "msword" | "rtf" | "vnd.oasis.opendocument.text") => {
+      "x-office-document-symbolic"
+    }
+    ("application", s) if s.contains("wordprocessingml") => "x-office-document-symbolic",
+    ("application", s) if s.contains("spreadsheet") || s == "vnd.ms-excel" => {
+      "x-office-spreadsheet-symbolic"
+    }
+    ("application", s) if s.contains("presentation") || s == "vnd.ms-powerpoint" => {
+      "x-office-presentation-symbolic"
+    }
+    (
+      "application",
+      "zip" | "gzip" | "x-gzip" | "x-tar" | "x-bzip2" | "x-xz" | "x-7z-compressed"
+      | "x-rar-compressed" | "vnd.rar" | "zstd",
+    ) => "package-x-generic-symbolic",
+    _ if sniff::is_executable(&mime_type) => "application-x-executable-symbolic",
+    ("text", _) => "text-x-generic-symbolic",
+    _ => "document-open",
+  }
+}
+
+/// Lines of an attachment name before it's ellipsized
+const ATTACHMENT_TITLE_LINES: i32 = 2;
+
+/// Row of an attachment, without its buttons. Long names are cut to keep
+/// the save button in view, the tooltip has the whole name and the type.
+fn attachment_row(attachment: &Attachment, mime: &str, icon: &str) -> adw::ActionRow {
+  let row = adw::ActionRow::builder()
+    .title(attachment.filename.to_string())
+    .title_lines(ATTACHMENT_TITLE_LINES)
+    .subtitle(attachment.description.as_deref().unwrap_or(mime))
+    .subtitle_lines(ATTACHMENT_TITLE_LINES)
+    .use_markup(false)
+    .tooltip_text(format!("{}\n{}", attachment.filename, mime))
+    .activatable(true)
+    .build();
+  row.add_prefix(&gtk4::Image::from_icon_name(icon));
+  row
+}
+
+/// Indices to try after `current` in a focus chain of `len` widgets,
+/// wrapping around.
+fn focus_order(len: usize, current: usize, backward: bool) -> impl Iterator<Item = usize> {
+  (1..len).map(move |step| match backward {
+    true => (current + len - step) % len,
+    false => (current + step) % len,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn search_url_template() {
+    assert_eq!(
+      search_url("https://duckduckgo.com/?q=%s", "  hello\n big world "),
+      "https://duckduckgo.com/?q=hello%20big%20world"
+    );
+    assert_eq!(
+      search_url("https://www.google.com/search?q=", "a&b=c"),
+      "https://www.google.com/search?q=a%26b%3Dc"
+    );
+  }
+
+  #[test]
+  fn save_body_content() {
+    let html = Some("<p onclick=\"evil()\">Hello</p><script>evil()</script>");
+    let text = Some("Hello <you>");
+
+    let content = body_content("html", html, text).unwrap();
+    assert!(content.contains("<p>Hello</p>"));
+    assert!(!content.contains("<script"));
+    assert_eq!(body_content("HTM", None, text).unwrap(), "<pre>Hello &lt;you&gt;</pre>");
+    assert_eq!(body_content("txt", html, text).unwrap(), "Hello <you>");
+    assert_eq!(body_content("txt", html, None).unwrap(), "Hello");
+    assert_eq!(body_content("", None, None), None);
+  }
+
+  #[test]
+  fn line_endings() {
+    let text = "one\r\ntwo\nthree\rfour";
+    assert_eq!(normalize_line_endings(text, LineEnding::Lf), "one\ntwo\nthree\nfour");
+    assert_eq!(
+      normalize_line_endings(text, LineEnding::CrLf),
+      "one\r\ntwo\r\nthree\r\nfour"
+    );
+    assert_eq!(LineEnding::from_setting("crlf").as_setting(), "crlf");
+    assert_eq!(LineEnding::from_setting("unknown"), LineEnding::Lf);
+  }
+
+  #[test]
+  fn link_actions() {
+    assert_eq!(LinkAction::from_setting("open"), LinkAction::Open);
+    assert_eq!(LinkAction::from_setting("copy"), LinkAction::Copy);
+    assert_eq!(LinkAction::from_setting("ask"), LinkAction::Ask);
+    assert_eq!(LinkAction::from_setting("unknown"), LinkAction::Open);
+    for action in LinkAction::ALL {
+      assert_eq!(LinkAction::from_setting(action.as_setting()), action);
+    }
+  }
+
+  #[test]
+  fn webkit_fallback() {
+    assert!(!show_text_view(true, false));
+    assert!(show_text_view(true, true));
+    assert!(show_text_view(false, false));
+    assert!(show_text_view(false, true));
+  }
+
+  #[test]
+  fn text_view_from_html() {
+    assert_eq!(text_view_body(Some("Text".to_string()), Some("<p>HTML</p>")), "Text");
+    assert_eq!(text_view_body(None, Some("<p>Hello</p>\n<p>Lucas</p>")), "Hello\nLucas");
+    assert_eq!(text_view_body(None, None), "");
+  }
+
+  #[test]
+  fn dark_change_restyles() {
+    assert!(restyle_on_dark_change(true, false, true));
+    assert!(!restyle_on_dark_change(false, false, true));
+    assert!(!restyle_on_dark_change(true, true, true));
+    assert!(!restyle_on_dark_change(true, false, false));
+  }
+
+  #[test]
+  fn max_width_margins() {
+    assert_eq!(text_margin(1200, 80, 10), 200);
+    assert_eq!(text_margin(700, 80, 10), 0);
+    assert_eq!(text_margin(1200, 0, 10), 0);
+  }
+
+  #[test]
+  fn sender_zooms() {
+    let mut zooms = HashMap::new();
+    record_sender_zoom(&mut zooms, "News.Example", 1.5, 1.0);
+    record_sender_zoom(&mut zooms, "moon.space", 0.8, 1.0);
+    assert_eq!(sender_zoom(&zooms, "news.example"), Some(1.5));
+    assert_eq!(sender_zoom(&zooms, "moon.space"), Some(0.8));
+    assert_eq!(sender_zoom(&zooms, "other.org"), None);
+    record_sender_zoom(&mut zooms, "news.example", 1.2, 1.0);
+    assert_eq!(sender_zoom(&zooms, "news.example"), Some(1.2));
+    // back to the global zoom
+    record_sender_zoom(&mut zooms, "moon.space", 1.0, 1.0);
+    assert_eq!(sender_zoom(&zooms, "moon.space"), None);
+    assert_eq!(zooms.len(), 1);
+  }
+
+  #[test]
+  fn split_position_persistence() {
+    assert_eq!(split_position_to_save(true, 350, 320), Some(350));
+    assert_eq!(split_position_to_save(true, 320, 320), None);
+    assert_eq!(split_position_to_save(false, 0, 320), None);
+    assert_eq!(split_position_to_save(true, 50, 320), None);
+    assert_eq!(restored_split_position(350), 350);
+    assert_eq!(restored_split_position(0), MIN_SPLIT_POSITION);
+  }
+
+  #[test]
+  fn copy_image_bytes() {
+    assert_eq!(
+      image_copy("data:image/png;base64,iVBO\nRw0K", false),
+      ImageCopy::Data(b"\x89PNG\r\n".to_vec())
+    );
+    assert_eq!(
+      image_copy("data:image/svg+xml,%3Csvg%2F%3E", false),
+      ImageCopy::Data(b"<svg/>".to_vec())
+    );
+    assert_eq!(image_copy("data:image/png;base64,", true), ImageCopy::Unavailable);
+    assert_eq!(image_copy("https://cdn.example/logo.png", true), ImageCopy::Loaded);
+    assert_eq!(image_copy("https://cdn.example/logo.png", false), ImageCopy::Unavailable);
+  }
+
+  #[test]
+  fn attachments_only() {
+    assert_eq!(
+      attachments_only_summary("", false, 1).as_deref(),
+      Some("(no subject) — 1 attachment")
+    );
+    assert_eq!(
+      attachments_only_summary("  ", false, 3).as_deref(),
+      Some("(no subject) — 3 attachments")
+    );
+    assert_eq!(attachments_only_summary("Invoice", false, 1), None);
+    assert_eq!(attachments_only_summary("", true, 1), None);
+    assert_eq!(attachments_only_summary("", false, 0), None);
+  }
+
+  #[test]
+  fn bounce_banner() {
+    let failed = DeliveryStatus {
+      recipient: "x@y.com".to_string(),
+      action: "failed".to_string(),
+      status: "5.1.1".to_string(),
+      diagnostic: Some("550 No such user".to_string()),
+    };
+    let delayed = DeliveryStatus {
+      recipient: "lucas@mercure.space".to_string(),
+      action: "delayed".to_string(),
+      status: "4.4.1".to_string(),
+      diagnostic: None,
+    };
+    assert_eq!(
+      bounce_text(&[delayed.clone(), failed.clone()]).as_deref(),
+      Some("Message to x@y.com bounced: 550 No such user")
+    );
+    let unknown = DeliveryStatus {
+      recipient: "z@y.com".to_string(),
+      action: "failed".to_string(),
+      ..Default::default()
+    };
+    assert_eq!(
+      bounce_text(&[unknown, failed]).as_deref(),
+      Some("Message to z@y.com bounced (and 1 other recipient)")
+    );
+    assert_eq!(bounce_text(&[delayed]), None);
+    assert_eq!(bounce_text(&[]), None);
+  }
+
+  #[test]
+  fn geometry_selection() {
+    // windows 1 and 2 open, 2 focused last
+    assert!(should_save_geometry(&2, Some(&2), 2));
+    assert!(!should_save_geometry(&1, Some(&2), 2));
+    // the other one closed, or none focused yet
+    assert!(should_save_geometry(&1, Some(&2), 1));
+    assert!(should_save_geometry(&1, None, 2));
+  }
+
+  #[test]
+  fn mail_files() {
+    assert_eq!(mail_file_patterns(), ["*.eml", "*.emlx", "*.msg", "*.mbox"]);
+    assert!(is_mail_file("/tmp/Message.EML"));
+    assert!(is_mail_file("archive.mbox"));
+    assert!(is_mail_file("1234.emlx"));
+    assert!(!is_mail_file("photo.png"));
+    assert!(!is_mail_file("eml"));
+  }
+
+  #[test]
+  fn close_window_decision() {
+    assert!(should_close_window(true, false));
+    assert!(!should_close_window(false, false));
+    assert!(!should_close_window(true, true));
+    assert!(!should_close_window(false, true));
+  }
+
+  #[test]
+  fn open_progress_visibility() {
+    assert!(!show_open_progress(1024, 18025));
+    assert!(show_open_progress(OPEN_PROGRESS_MIN / 2, OPEN_PROGRESS_MIN));
+    assert!(!show_open_progress(OPEN_PROGRESS_MIN, OPEN_PROGRESS_MIN));
+  }
+
+  #[test]
+  fn focus_chain_order() {
+    assert_eq!(focus_order(4, 1, false).collect::<Vec<_>>(), vec![2, 3, 0]);
+    assert_eq!(focus_order(4, 1, true).collect::<Vec<_>>(), vec![0, 3, 2]);
+    assert_eq!(focus_order(1, 0, false).count(), 0);
+  }
+
+  #[test]
+  fn toggle_buttons_accessible_names() {
+    let ui = include_str!("window.ui");
+    for id in ["show_text", "show_images", "force_css", "reader_view", "zoom_minus", "zoom_plus"] {
+      let start = ui.find(&format!("id=\"{}\"", id)).unwrap();
+      let end = start + ui[start..].find("</object>").unwrap();
+      let object = &ui[start..end];
+      assert!(object.contains("<property name=\"tooltip-text\""), "{}", id);
+      let label = object
+        .split("<accessibility>")
+        .nth(1)
+        .and_then(|a| a.split("<property name=\"label\" translatable=\"yes\">").nth(1))
+        .and_then(|l| l.split('<').next())
+        .unwrap_or_default();
+      assert!(!label.trim().is_empty(), "{} has no accessible name", id);
+    }
+  }
+
+  #[test]
+  fn pdf_body_decision() {
+    let attachment = |filename: &str, mime_type: &str| Attachment {
+      filename: filename.to_string(),
+      content_id: "none".to_string(),
+      body: b"%PDF-1.4".to_vec(),
+      mime_type: Some(mime_type.to_string()),
+      description: None,
+      encoded: None,
+    };
+    let pdf = [attachment("invoice.pdf", "application/pdf")];
+    assert_eq!(
+      pdf_as_body(true, false, &pdf).map(|a| a.filename.as_str()),
+      Some("invoice.pdf")
+    );
+    assert!(pdf_as_body(false, false, &pdf).is_none());
+    assert!(pdf_as_body(true, true, &pdf).is_none());
+    let two = [pdf[0].clone(), attachment("terms.pdf", "application/pdf")];
+    assert!(pdf_as_body(true, false, &two).is_none());
+    let image = [attachment("scan.png", "image/png")];
+    assert!(pdf_as_body(true, false, &image).is_none());
+  }
+
+  #[test]
+  fn summary_bar_line() {
+    let service = MailService::new();
+    service.open_message("sample.eml").unwrap();
+    let mut attachments = 0;
+    service.for_each_attachment(|_| attachments += 1);
+    let badges = vec!["SPF pass".to_string(), "DKIM fail".to_string()];
+    assert_eq!(
+      summary_line(&service.from(), &service.date(), attachments, &badges),
+      "John Doe \u{2022} 2024-10-23 12:27:21 \u{2022} 1 attachment \u{2022} SPF pass DKIM fail"
+    );
+    assert_eq!(summary_line("lucas@mercure.space", "", 0, &[]), "lucas@mercure.space");
+    assert_eq!(summary_line("", "", 0, &[]), "");
+  }
+
+  #[test]
+  fn scroll_reset_flag() {
+    let reset = ScrollReset::default();
+    reset.new_message(true);
+    assert!(reset.finished());
+    assert!(!reset.finished());
+    // loaded images, forced CSS
+    reset.new_message(true);
+    reset.render_again();
+    assert!(!reset.finished());
+    reset.new_message(false);
+    assert!(!reset.finished());
+  }
+
+  #[test]
+  fn attachment_icons() {
+    assert_eq!(attachment_icon("application/pdf"), "x-office-document-symbolic");
+    assert_eq!(attachment_icon("application/zip"), "package-x-generic-symbolic");
+    assert_eq!(attachment_icon("audio/mpeg"), "audio-x-generic-symbolic");
+    assert_eq!(attachment_icon("Image/PNG; name=a.png"), "image-x-generic-symbolic");
+    assert_eq!(
+      attachment_icon("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+      "x-office-spreadsheet-symbolic"
+    );
+    assert_eq!(attachment_icon("text/x-shellscript"), "application-x-executable-symbolic");
+    assert_eq!(attachment_icon("application/octet-stream"), "document-open");
+    assert_eq!(attachment_icon("Unknown"), "document-open");
+  }
+
+  #[test]
+  fn media_preview_decision() {
+    let attachment = |mime_type: Option<&str>, size: usize| Attachment {
+      filename: "clip".to_string(),
+      content_id: "none".to_string(),
+      body: [&b"OggS"[..], &vec![0; size.saturating_sub(4)]].concat(),
+      mime_type: mime_type.map(|m| m.to_string()),
+      description: None,
+      encoded: None,
+    };
+    assert!(is_media_previewable(&attachment(Some("audio/ogg"), 1024)));
+    assert!(is_media_previewable(&attachment(Some("Video/MP4"), 1024)));
+    assert!(!is_media_previewable(&attachment(Some("image/png"), 1024)));
+    assert!(!is_media_previewable(&attachment(Some("application/ogg"), 1024)));
+    assert!(!is_media_previewable(&attachment(None, 1024)));
+    assert!(!is_media_previewable(&attachment(Some("video/mp4"), MEDIA_PREVIEW_LIMIT + 1)));
+    // an executable labelled as a video
+    let mut disguised = attachment(Some("video/mp4"), 1024);
+    disguised.body = b"\x7fELF\x02\x01".to_vec();
+    assert!(!is_media_previewable(&disguised));
+  }
+
+  #[test]
+  fn sanitized_html_falls_back_to_text() {
+    let (_, script_only) = Html::new("<script>location = 'https://evil.example'</script>", false)
+      .safe_report();
+    assert!(prefer_text_view(false, true, &script_only));
+    // no text part, or the option is off
+    assert!(!prefer_text_view(false, false, &script_only));
+    let (_, newsletter) = Html::new("<p>Hello Lucas</p>", false).safe_report();
+    assert!(!prefer_text_view(false, true, &newsletter));
+    assert!(prefer_text_view(true, false, &newsletter));
+  }
+
+  #[test]
+  fn attachment_row_tooltip() {
+    let filename = format!("{}.pdf", "Quarterly report with a very long name ".repeat(8));
+    let attachment = Attachment {
+      filename: filename.clone(),
+      content_id: "none".to_string(),
+      body: vec![],
+      mime_type: Some("application/pdf".to_string()),
+      description: None,
+      encoded: None,
+    };
+    // widgets need a display
+    if adw::init().is_err() {
+      return;
+    }
+    let row = attachment_row(&attachment, "application/pdf", "x-office-document-symbolic");
+    assert_eq!(row.title(), filename);
+    assert_eq!(row.tooltip_text().unwrap(), format!("{}\napplication/pdf", filename));
+  }
+}